@@ -0,0 +1,435 @@
+//! Archive of a group, a directory of JSONL files or SQLite databases each covering a window of
+//! time, named by when the window starts, such as `2024-05-01T00:00:00Z.jsonl`.
+
+use crate::config::Format;
+
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::task;
+
+const ATTACHMENTS: &str = "attachments";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY,
+    time TEXT NOT NULL,
+    type TEXT NOT NULL,
+    uid INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    old_name TEXT,
+    text TEXT
+);
+
+CREATE TABLE IF NOT EXISTS attachments (
+    event INTEGER NOT NULL REFERENCES events (id),
+    size INTEGER NOT NULL,
+    file TEXT
+);
+";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Line of an archive file.
+#[derive(Serialize, Debug)]
+pub struct Record {
+    pub time: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    InitUser {
+        uid: u32,
+        name: String,
+    },
+    DestroyUser {
+        uid: u32,
+        name: String,
+    },
+    Rename {
+        uid: u32,
+        old_name: String,
+        name: String,
+    },
+    Message {
+        uid: u32,
+        name: String,
+        text: String,
+        attachments: Vec<Attachment>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+pub struct Attachment {
+    pub size: u64,
+    /// Path relative to the directory of the group, if attachments are saved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+}
+
+pub struct Archive {
+    directory: PathBuf,
+    format: Format,
+    rotate_every: Duration,
+    keep_for: Option<Duration>,
+    // Start of the window being written and its file.
+    current: Option<(u64, Writer)>,
+}
+
+enum Writer {
+    Jsonl(File),
+    Sqlite(Arc<Mutex<Connection>>),
+}
+
+impl Archive {
+    pub async fn open(
+        root: &Path,
+        group: &str,
+        format: Format,
+        rotate_every: Duration,
+        keep_for: Option<Duration>,
+    ) -> Result<Self, Error> {
+        let directory = root.join(directory_name(group));
+        fs::create_dir_all(directory.join(ATTACHMENTS)).await?;
+
+        Ok(Self {
+            directory,
+            format,
+            rotate_every,
+            keep_for,
+            current: None,
+        })
+    }
+
+    pub async fn write(&mut self, time: SystemTime, event: Event) -> Result<(), Error> {
+        let start = window_start(time, self.rotate_every);
+
+        let writer = match &mut self.current {
+            Some((current, writer)) if *current == start => writer,
+            current => {
+                let path = self.directory.join(file_name(start, self.format));
+                let writer = match self.format {
+                    Format::Jsonl => Writer::Jsonl(
+                        OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .await?,
+                    ),
+                    Format::Sqlite => {
+                        let connection = task::spawn_blocking(move || {
+                            let connection = Connection::open(path)?;
+                            connection.execute_batch(SCHEMA)?;
+
+                            Ok::<_, rusqlite::Error>(connection)
+                        })
+                        .await
+                        .unwrap()?;
+
+                        Writer::Sqlite(Arc::new(Mutex::new(connection)))
+                    }
+                };
+
+                *current = Some((start, writer));
+
+                // Old files are only looked for once a new one is started.
+                self.prune(time).await?;
+                &mut self.current.as_mut().unwrap().1
+            }
+        };
+
+        let record = Record {
+            time: humantime::format_rfc3339_millis(time).to_string(),
+            event,
+        };
+
+        match writer {
+            Writer::Jsonl(file) => {
+                let mut line = serde_json::to_vec(&record).map_err(io::Error::from)?;
+                line.push(b'\n');
+                file.write_all(&line).await?;
+            }
+            Writer::Sqlite(connection) => {
+                let connection = connection.clone();
+                task::spawn_blocking(move || insert(&connection.lock().unwrap(), &record))
+                    .await
+                    .unwrap()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves an attachment named by its hash, returning its path relative to the archive.
+    pub async fn save_attachment(&self, data: &[u8]) -> Result<String, io::Error> {
+        let name = format!("{}/{}", ATTACHMENTS, hex::encode(Sha1::digest(data)));
+
+        // Written even if it exists, so that it's kept for as long as the newest message with it.
+        fs::write(self.directory.join(&name), data).await?;
+
+        Ok(name)
+    }
+
+    async fn prune(&self, now: SystemTime) -> Result<(), io::Error> {
+        let extension = extension(self.format);
+
+        let cutoff = match self.keep_for.and_then(|keep_for| now.checked_sub(keep_for)) {
+            Some(cutoff) => cutoff,
+            None => return Ok(()),
+        };
+
+        let mut entries = fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let start = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(extension))
+                .and_then(|start| humantime::parse_rfc3339(start).ok());
+
+            // A file ends when the next one starts.
+            if let Some(start) = start {
+                if start + self.rotate_every <= cutoff {
+                    tracing::info!("Deleting {}", entry.path().display());
+                    fs::remove_file(entry.path()).await?;
+                }
+            }
+        }
+
+        let mut entries = fs::read_dir(self.directory.join(ATTACHMENTS)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.metadata().await?.modified()? <= cutoff {
+                fs::remove_file(entry.path()).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// A record as a row of the events table, and its attachments as rows of their own.
+fn insert(connection: &Connection, record: &Record) -> Result<(), rusqlite::Error> {
+    let (kind, uid, name, old_name, text, attachments) = match &record.event {
+        Event::InitUser { uid, name } => ("init_user", uid, name, None, None, &[][..]),
+        Event::DestroyUser { uid, name } => ("destroy_user", uid, name, None, None, &[][..]),
+        Event::Rename {
+            uid,
+            old_name,
+            name,
+        } => ("rename", uid, name, Some(old_name), None, &[][..]),
+        Event::Message {
+            uid,
+            name,
+            text,
+            attachments,
+        } => ("message", uid, name, None, Some(text), &attachments[..]),
+    };
+
+    let transaction = connection.unchecked_transaction()?;
+    transaction.execute(
+        "INSERT INTO events (time, type, uid, name, old_name, text) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![record.time, kind, uid, name, old_name, text],
+    )?;
+
+    let event = transaction.last_insert_rowid();
+    for attachment in attachments {
+        transaction.execute(
+            "INSERT INTO attachments (event, size, file) VALUES (?1, ?2, ?3)",
+            params![event, attachment.size, attachment.file],
+        )?;
+    }
+
+    transaction.commit()
+}
+
+fn window_start(time: SystemTime, rotate_every: Duration) -> u64 {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let every = rotate_every.as_secs().max(1);
+
+    seconds - seconds % every
+}
+
+fn file_name(start: u64, format: Format) -> String {
+    let start = UNIX_EPOCH + Duration::from_secs(start);
+    format!(
+        "{}{}",
+        humantime::format_rfc3339_seconds(start),
+        extension(format)
+    )
+}
+
+fn extension(format: Format) -> &'static str {
+    match format {
+        Format::Jsonl => ".jsonl",
+        Format::Sqlite => ".sqlite",
+    }
+}
+
+// Group names may be anything, those which aren't safe as a file name are percent-encoded.
+fn directory_name(group: &str) -> String {
+    let mut name = String::with_capacity(group.len());
+
+    for (index, c) in group.chars().enumerate() {
+        let safe = c.is_alphanumeric() || matches!(c, '-' | '_' | ' ') || (c == '.' && index > 0);
+
+        match safe {
+            true => name.push(c),
+            false => {
+                let mut buffer = [0; 4];
+                for byte in c.encode_utf8(&mut buffer).bytes() {
+                    name.push_str(&format!("%{:02X}", byte));
+                }
+            }
+        }
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn names_files() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        assert_eq!(
+            file_name(window_start(time, day), Format::Jsonl),
+            "2023-11-14T00:00:00Z.jsonl"
+        );
+        assert_eq!(directory_name("fun"), "fun");
+        assert_eq!(directory_name("../a/b.c"), "%2E.%2Fa%2Fb.c");
+    }
+
+    #[tokio::test]
+    async fn rotates_and_prunes() {
+        let root = env::temp_dir().join(format!("multichat-archive-{}", std::process::id()));
+        let hour = Duration::from_secs(60 * 60);
+        let mut archive = Archive::open(&root, "fun", Format::Jsonl, hour, Some(2 * hour))
+            .await
+            .unwrap();
+
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000 - 1_700_000_000 % 3600);
+        let event = || Event::InitUser {
+            uid: 1,
+            name: "alice".to_owned(),
+        };
+
+        archive.write(start, event()).await.unwrap();
+        archive.write(start + hour / 2, event()).await.unwrap();
+
+        let path = archive
+            .directory
+            .join(file_name(window_start(start, hour), Format::Jsonl));
+        let data = fs::read_to_string(&path).await.unwrap();
+        assert_eq!(data.lines().count(), 2);
+        assert!(data.starts_with("{\"time\":\"2023-11-14T22:00:00.000Z\",\"type\":\"init_user\""));
+
+        // The first file ends three hours before this, it's deleted.
+        archive.write(start + 4 * hour, event()).await.unwrap();
+        let exists = fs::try_exists(&path).await.unwrap();
+        fs::remove_dir_all(&root).await.unwrap();
+
+        assert!(!exists);
+    }
+
+    #[tokio::test]
+    async fn writes_sqlite() {
+        let root = env::temp_dir().join(format!("multichat-archive-sqlite-{}", std::process::id()));
+        let hour = Duration::from_secs(60 * 60);
+        let mut archive = Archive::open(&root, "fun", Format::Sqlite, hour, None)
+            .await
+            .unwrap();
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let rename = Event::Rename {
+            uid: 1,
+            old_name: "alice".to_owned(),
+            name: "bob".to_owned(),
+        };
+
+        let message = Event::Message {
+            uid: 1,
+            name: "bob".to_owned(),
+            text: "hi".to_owned(),
+            attachments: vec![
+                Attachment {
+                    size: 3,
+                    file: Some("attachments/a".to_owned()),
+                },
+                Attachment {
+                    size: 5,
+                    file: None,
+                },
+            ],
+        };
+
+        archive.write(time, rename).await.unwrap();
+        archive.write(time, message).await.unwrap();
+
+        let path = archive
+            .directory
+            .join(file_name(window_start(time, hour), Format::Sqlite));
+        let connection = Connection::open(&path).unwrap();
+
+        let events: Vec<(String, String, Option<String>, Option<String>)> = connection
+            .prepare("SELECT time, type, old_name, text FROM events ORDER BY id")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        let attachments: Vec<(i64, u64, Option<String>)> = connection
+            .prepare("SELECT event, size, file FROM attachments ORDER BY size")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        drop(connection);
+        fs::remove_dir_all(&root).await.unwrap();
+
+        let time = "2023-11-14T22:13:20.000Z".to_owned();
+        assert_eq!(
+            events,
+            [
+                (
+                    time.clone(),
+                    "rename".to_owned(),
+                    Some("alice".to_owned()),
+                    None
+                ),
+                (time, "message".to_owned(), None, Some("hi".to_owned())),
+            ]
+        );
+        assert_eq!(
+            attachments,
+            [(2, 3, Some("attachments/a".to_owned())), (2, 5, None)]
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use multichat_client::proto::AccessToken;
+use serde::{Deserialize, Deserializer};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub directory: PathBuf,
+    #[serde(default)]
+    pub format: Format,
+    #[serde(
+        default = "default_rotate_every",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub rotate_every: Duration,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub keep_for: Option<Duration>,
+    pub multichat: Multichat,
+    pub groups: Vec<Group>,
+}
+
+/// What the files of the archive are.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    /// A line of JSON for every update.
+    #[default]
+    Jsonl,
+    /// SQLite databases with a table of updates and one of their attachments.
+    Sqlite,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Group {
+    pub multichat_group: String,
+    #[serde(default)]
+    pub attachments: bool,
+}
+
+fn default_rotate_every() -> Duration {
+    Duration::from_secs(24 * 60 * 60)
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(deserializer)?;
+
+    humantime::parse_duration(&duration).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        let config = toml::from_str::<Config>(config).unwrap();
+
+        assert_eq!(config.format, Format::Jsonl);
+        assert_eq!(config.rotate_every, default_rotate_every());
+        assert_eq!(config.keep_for, None);
+    }
+}
@@ -0,0 +1,98 @@
+use multichat_client::{MaybeTlsClient, UpdateKind};
+use std::collections::HashMap;
+use std::io;
+use std::time::SystemTime;
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::archive::{self, Archive, Attachment, Event};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Archive(#[from] archive::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Archive of a group and whether its attachments are saved.
+pub struct Group {
+    pub archive: Archive,
+    pub attachments: bool,
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    mut groups: HashMap<u32, Group>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), Error> {
+    // Names are only sent when users join or rename, but they're written with every message.
+    let mut names = HashMap::<u32, HashMap<u32, String>>::new();
+
+    loop {
+        let update = tokio::select! {
+            update = client.read_update() => update?,
+            _ = shutdown.changed() => break,
+        };
+
+        let group = match groups.get_mut(&update.gid) {
+            Some(group) => group,
+            None => continue,
+        };
+
+        let users = names.entry(update.gid).or_default();
+        let event = match update.kind {
+            UpdateKind::InitUser { uid, name } => {
+                users.insert(uid, name.clone());
+                Event::InitUser { uid, name }
+            }
+            UpdateKind::DestroyUser { uid } => Event::DestroyUser {
+                uid,
+                name: users.remove(&uid).unwrap_or_default(),
+            },
+            UpdateKind::Rename { uid, name } => Event::Rename {
+                uid,
+                old_name: users.insert(uid, name.clone()).unwrap_or_default(),
+                name,
+            },
+            UpdateKind::Message { uid, message } => {
+                let mut attachments = Vec::with_capacity(message.attachments.len());
+
+                for attachment in &message.attachments {
+                    let file = match group.attachments {
+                        true => {
+                            let data = client.download_attachment(attachment.id).await?;
+                            Some(group.archive.save_attachment(&data).await?)
+                        }
+                        false => {
+                            client.ignore_attachment(attachment.id).await?;
+                            None
+                        }
+                    };
+
+                    attachments.push(Attachment {
+                        size: attachment.size,
+                        file,
+                    });
+                }
+
+                Event::Message {
+                    uid,
+                    name: users.get(&uid).cloned().unwrap_or_default(),
+                    text: message.text,
+                    attachments,
+                }
+            }
+            UpdateKind::InitGroup { .. }
+            | UpdateKind::DestroyGroup
+            | UpdateKind::StartTyping { .. }
+            | UpdateKind::StopTyping { .. } => continue,
+        };
+
+        group.archive.write(SystemTime::now(), event).await?;
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
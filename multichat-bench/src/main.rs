@@ -0,0 +1,258 @@
+mod stats;
+mod tls;
+
+use clap::Parser;
+use multichat_client::proto::{AccessToken, Config as ProtoConfig};
+use multichat_client::{ClientBuilder, ConnectError, UpdateKind};
+use stats::{Snapshot, Stats};
+use std::borrow::Cow;
+use std::future;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::signal;
+use tokio::sync::watch;
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tokio_rustls::TlsConnector;
+
+#[derive(Parser)]
+#[clap(about = "Generates traffic with many clients and measures how the server copes")]
+struct Args {
+    #[clap(long, env = "MULTICHAT_SERVER", help = "Address of the server")]
+    server: String,
+    #[clap(long, env = "MULTICHAT_ACCESS_TOKEN", hide_env_values = true)]
+    access_token: AccessToken,
+    #[clap(
+        long,
+        env = "MULTICHAT_CERTIFICATE",
+        help = "Certificate of the server, connects over TLS if set"
+    )]
+    certificate: Option<PathBuf>,
+    #[clap(long, default_value_t = 10, help = "Number of concurrent clients")]
+    clients: usize,
+    #[clap(
+        long,
+        default_value_t = 1,
+        help = "Number of groups clients are spread over"
+    )]
+    groups: usize,
+    #[clap(long, default_value = "bench", help = "Prefix of names of the groups")]
+    group_prefix: String,
+    #[clap(
+        long,
+        default_value_t = 1.0,
+        help = "Messages sent per second by every client, 0 to only receive"
+    )]
+    rate: f64,
+    #[clap(long, default_value_t = 32, help = "Length of message text")]
+    text_size: usize,
+    #[clap(long, default_value_t = 0, help = "Size of attachments in bytes")]
+    attachment_size: usize,
+    #[clap(
+        long,
+        default_value_t = 0.0,
+        help = "Fraction of messages with an attachment, between 0 and 1"
+    )]
+    attachment_ratio: f64,
+    #[clap(long, default_value = "1m", value_parser = humantime::parse_duration)]
+    duration: Duration,
+    #[clap(long, default_value = "10s", value_parser = humantime::parse_duration)]
+    report_every: Duration,
+}
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error(transparent)]
+    Connect(#[from] ConnectError<io::Error>),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+// What every client needs to know.
+struct Settings {
+    args: Args,
+    connector: Option<TlsConnector>,
+    attachment: Vec<u8>,
+    // Messages carry the time they were sent at relative to this.
+    start: Instant,
+    stats: Stats,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    if args.groups == 0 || args.report_every.is_zero() {
+        eprintln!("There has to be at least one group and reports can't be continuous");
+        return ExitCode::FAILURE;
+    }
+
+    let connector = match &args.certificate {
+        Some(certificate) => match tls::configure(certificate).await {
+            Ok(connector) => Some(connector),
+            Err(err) => {
+                eprintln!("Error configuring TLS: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let settings = Arc::new(Settings {
+        attachment: vec![0; args.attachment_size],
+        args,
+        connector,
+        start: Instant::now(),
+        stats: Stats::default(),
+    });
+
+    let (shutdown_sender, shutdown) = watch::channel(());
+    let clients: Vec<_> = (0..settings.args.clients)
+        .map(|index| tokio::spawn(client(index, settings.clone(), shutdown.clone())))
+        .collect();
+
+    let mut last = Snapshot::default();
+    let mut total = Snapshot::default();
+    let mut report = time::interval_at(
+        settings.start + settings.args.report_every,
+        settings.args.report_every,
+    );
+
+    let end = time::sleep(settings.args.duration);
+    tokio::pin!(end);
+
+    loop {
+        tokio::select! {
+            _ = report.tick() => {
+                let interval = settings.stats.snapshot(&mut last);
+                println!("[{}s] {}", settings.start.elapsed().as_secs(), interval);
+                total.merge(interval);
+            }
+            _ = &mut end => break,
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    let _ = shutdown_sender.send(());
+    for client in clients {
+        client.await.unwrap();
+    }
+
+    total.merge(settings.stats.snapshot(&mut last));
+    println!("Total: {}", total);
+
+    ExitCode::SUCCESS
+}
+
+// Keeps a client connected until shut down, reconnecting whenever it's disconnected.
+async fn client(index: usize, settings: Arc<Settings>, mut shutdown: watch::Receiver<()>) {
+    loop {
+        let error = match session(index, &settings, &mut shutdown).await {
+            Ok(()) => return,
+            Err(err) => err,
+        };
+
+        let counter = match error {
+            Error::Connect(_) => &settings.stats.connect_failures,
+            Error::Io(_) => &settings.stats.disconnects,
+        };
+
+        // Only the first errors are printed, there may be thousands of them.
+        if counter.fetch_add(1, Ordering::Relaxed) < 10 {
+            eprintln!("Client {}: {}", index, error);
+        }
+
+        tokio::select! {
+            _ = time::sleep(Duration::from_secs(1)) => {}
+            _ = shutdown.changed() => return,
+        }
+    }
+}
+
+async fn session(
+    index: usize,
+    settings: &Settings,
+    shutdown: &mut watch::Receiver<()>,
+) -> Result<(), Error> {
+    let args = &settings.args;
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(args.attachment_size + 64 * 1024);
+
+    let mut builder = ClientBuilder::maybe_tls(settings.connector.clone());
+    builder.config(proto_config);
+
+    let mut client = tokio::select! {
+        client = builder.connect(&args.server, args.access_token) => client?,
+        _ = shutdown.changed() => return Ok(()),
+    };
+
+    let group = format!("{}-{}", args.group_prefix, index % args.groups);
+    let gid = client.join_group(&group).await?;
+    let uid = client.init_user(gid, &format!("bench-{}", index)).await?;
+
+    // Clients start at random points of their period, so that they don't all send at once.
+    let mut sends = match args.rate > 0.0 {
+        true => {
+            let period = Duration::from_secs_f64(1.0 / args.rate);
+            let mut sends =
+                time::interval_at(Instant::now() + period.mul_f64(rand::random()), period);
+            sends.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            Some(sends)
+        }
+        false => None,
+    };
+
+    loop {
+        let send = async {
+            match &mut sends {
+                Some(sends) => sends.tick().await,
+                None => future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            _ = send => {
+                let sent = settings.start.elapsed().as_micros();
+                let text = format!("{} {}", sent, "x".repeat(args.text_size));
+
+                let attachments = match rand::random::<f64>() < args.attachment_ratio {
+                    true => vec![Cow::Borrowed(&settings.attachment[..])],
+                    false => Vec::new(),
+                };
+
+                client.send_message(gid, uid, &text, &attachments).await?;
+                settings.stats.sent.fetch_add(1, Ordering::Relaxed);
+            }
+            update = client.read_update() => {
+                let message = match update?.kind {
+                    UpdateKind::Message { message, .. } => message,
+                    _ => continue,
+                };
+
+                for attachment in &message.attachments {
+                    client.ignore_attachment(attachment.id).await?;
+                    settings.stats.attachment_bytes.fetch_add(attachment.size, Ordering::Relaxed);
+                }
+
+                // Messages of anything else than the tool in the same groups are left out.
+                let sent = message.text.split(' ').next().and_then(|sent| sent.parse().ok());
+                if let Some(sent) = sent {
+                    let sent = Duration::from_micros(sent);
+                    settings.stats.latency(settings.start.elapsed().saturating_sub(sent));
+                }
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    client.destroy_user(gid, uid).await?;
+    client.shutdown().await?;
+
+    Ok(())
+}
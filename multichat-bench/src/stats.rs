@@ -0,0 +1,143 @@
+//! Counters shared by all clients, reported periodically and at the end.
+
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Stats {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+    pub attachment_bytes: AtomicU64,
+    pub disconnects: AtomicU64,
+    pub connect_failures: AtomicU64,
+    // Latencies since the last report, in microseconds.
+    latencies: Mutex<Vec<u64>>,
+}
+
+impl Stats {
+    pub fn latency(&self, latency: Duration) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+        self.latencies
+            .lock()
+            .unwrap()
+            .push(latency.as_micros() as u64);
+    }
+
+    /// Takes the counters and latencies since the last snapshot.
+    pub fn snapshot(&self, last: &mut Snapshot) -> Snapshot {
+        let mut latencies = mem::take(&mut *self.latencies.lock().unwrap());
+        latencies.sort_unstable();
+
+        let total = Snapshot {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            attachment_bytes: self.attachment_bytes.load(Ordering::Relaxed),
+            disconnects: self.disconnects.load(Ordering::Relaxed),
+            connect_failures: self.connect_failures.load(Ordering::Relaxed),
+            latencies: Vec::new(),
+        };
+
+        let interval = Snapshot {
+            sent: total.sent - last.sent,
+            received: total.received - last.received,
+            attachment_bytes: total.attachment_bytes - last.attachment_bytes,
+            disconnects: total.disconnects - last.disconnects,
+            connect_failures: total.connect_failures - last.connect_failures,
+            latencies,
+        };
+
+        *last = total;
+        interval
+    }
+}
+
+#[derive(Default)]
+pub struct Snapshot {
+    pub sent: u64,
+    pub received: u64,
+    pub attachment_bytes: u64,
+    pub disconnects: u64,
+    pub connect_failures: u64,
+    /// Sorted, in microseconds.
+    pub latencies: Vec<u64>,
+}
+
+impl Snapshot {
+    /// Adds up snapshots of consecutive intervals.
+    pub fn merge(&mut self, other: Snapshot) {
+        self.sent += other.sent;
+        self.received += other.received;
+        self.attachment_bytes += other.attachment_bytes;
+        self.disconnects += other.disconnects;
+        self.connect_failures += other.connect_failures;
+        self.latencies.extend(other.latencies);
+        self.latencies.sort_unstable();
+    }
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sent {}, received {}, attachments {} KiB, disconnects {}, failed connects {}",
+            self.sent,
+            self.received,
+            self.attachment_bytes / 1024,
+            self.disconnects,
+            self.connect_failures
+        )?;
+
+        if self.latencies.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, ", latency")?;
+        for (name, percentile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("max", 1.0)] {
+            let latency = Duration::from_micros(percentile_of(&self.latencies, percentile));
+            write!(f, " {} {:.1?}", name, latency)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Nearest rank percentile of sorted values.
+fn percentile_of(sorted: &[u64], percentile: f64) -> u64 {
+    let rank = (percentile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let values: Vec<_> = (1..=100).collect();
+
+        assert_eq!(percentile_of(&values, 0.5), 50);
+        assert_eq!(percentile_of(&values, 0.99), 99);
+        assert_eq!(percentile_of(&values, 1.0), 100);
+        assert_eq!(percentile_of(&values, 0.0), 1);
+        assert_eq!(percentile_of(&[7], 0.9), 7);
+    }
+
+    #[test]
+    fn snapshots_intervals() {
+        let stats = Stats::default();
+        let mut last = Snapshot::default();
+
+        stats.sent.fetch_add(3, Ordering::Relaxed);
+        stats.latency(Duration::from_millis(2));
+        assert_eq!(stats.snapshot(&mut last).sent, 3);
+
+        stats.sent.fetch_add(1, Ordering::Relaxed);
+        let interval = stats.snapshot(&mut last);
+        assert_eq!(interval.sent, 1);
+        assert_eq!(interval.received, 0);
+        assert!(interval.latencies.is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+mod tls;
+
+use clap::{Parser, Subcommand};
+use multichat_client::proto::{AccessToken, Config as ProtoConfig};
+use multichat_client::{ClientBuilder, ConnectError, MaybeTlsClient, UpdateKind};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use thiserror::Error;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use tokio::signal;
+
+#[derive(Parser)]
+#[clap(about = "Sends messages to and reads messages from Multichat, for scripts")]
+struct Args {
+    #[clap(long, env = "MULTICHAT_SERVER", help = "Address of the server")]
+    server: String,
+    #[clap(
+        long,
+        env = "MULTICHAT_ACCESS_TOKEN",
+        hide_env_values = true,
+        help = "Access token, better passed in the environment than on the command line"
+    )]
+    access_token: AccessToken,
+    #[clap(
+        long,
+        env = "MULTICHAT_CERTIFICATE",
+        help = "Certificate of the server, connects over TLS if set"
+    )]
+    certificate: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(about = "Sends a message, read from standard input if not given")]
+    Send {
+        #[clap(long, help = "Group to send to")]
+        group: String,
+        #[clap(long, help = "Name of the user to send as")]
+        name: String,
+        #[clap(long, help = "File to attach, can be given more than once")]
+        attach: Vec<PathBuf>,
+        #[clap(help = "Text of the message")]
+        text: Option<String>,
+    },
+    #[clap(about = "Prints messages of a group as they are sent, until interrupted")]
+    Tail {
+        #[clap(long, help = "Group to read")]
+        group: String,
+    },
+}
+
+#[derive(Error, Debug)]
+enum Error {
+    #[error("Error configuring TLS: {0}")]
+    Tls(#[from] tls::Error),
+    #[error("Error connecting to Multichat: {0}")]
+    Connect(#[from] ConnectError<io::Error>),
+    #[error("Error reading {0}: {1}")]
+    Read(PathBuf, io::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), Error> {
+    match args.command {
+        Command::Send {
+            group,
+            name,
+            attach,
+            text,
+        } => {
+            // Text and attachments are read first, so that nothing is sent if they can't be.
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    let mut text = String::new();
+                    tokio::io::stdin().read_to_string(&mut text).await?;
+                    text.trim_end().to_owned()
+                }
+            };
+
+            let mut attachments = Vec::new();
+            for path in attach {
+                let data = fs::read(&path)
+                    .await
+                    .map_err(|err| Error::Read(path, err))?;
+                attachments.push(Cow::Owned(data));
+            }
+
+            let mut client = connect(&args.server, args.access_token, args.certificate).await?;
+
+            let gid = client.join_group(&group).await?;
+            let uid = client.init_user(gid, &name).await?;
+            client.send_message(gid, uid, &text, &attachments).await?;
+            client.destroy_user(gid, uid).await?;
+            client.shutdown().await?;
+        }
+        Command::Tail { group } => {
+            let mut client = connect(&args.server, args.access_token, args.certificate).await?;
+            let gid = client.join_group(&group).await?;
+
+            tokio::select! {
+                result = tail(&mut client, gid) => result?,
+                _ = signal::ctrl_c() => {}
+            }
+
+            client.shutdown().await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn connect(
+    server: &str,
+    access_token: AccessToken,
+    certificate: Option<PathBuf>,
+) -> Result<MaybeTlsClient, Error> {
+    let connector = match certificate {
+        Some(certificate) => Some(tls::configure(&certificate).await?),
+        None => None,
+    };
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(512 * 1024 * 1024); // 512 MiB
+
+    let client = ClientBuilder::maybe_tls(connector)
+        .config(proto_config)
+        .connect(server, access_token)
+        .await?;
+
+    Ok(client)
+}
+
+// Prints messages as `name: text`, attachments are not downloaded.
+async fn tail(client: &mut MaybeTlsClient, gid: u32) -> Result<(), io::Error> {
+    let mut names = HashMap::new();
+
+    loop {
+        let update = client.read_update().await?;
+        if update.gid != gid {
+            continue;
+        }
+
+        match update.kind {
+            UpdateKind::InitUser { uid, name } | UpdateKind::Rename { uid, name } => {
+                names.insert(uid, name);
+            }
+            UpdateKind::DestroyUser { uid } => {
+                names.remove(&uid);
+            }
+            UpdateKind::Message { uid, message } => {
+                for attachment in &message.attachments {
+                    client.ignore_attachment(attachment.id).await?;
+                }
+
+                let name = names.get(&uid).map(String::as_str).unwrap_or("?");
+                match message.attachments.len() {
+                    0 => println!("{}: {}", name, message.text),
+                    count => println!("{}: {} ({} attachments)", name, message.text, count),
+                }
+            }
+            UpdateKind::InitGroup { .. }
+            | UpdateKind::DestroyGroup
+            | UpdateKind::StartTyping { .. }
+            | UpdateKind::StopTyping { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn args_are_valid() {
+        Args::command().debug_assert();
+    }
+}
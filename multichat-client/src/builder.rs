@@ -1,7 +1,7 @@
 use crate::client::{Client, InitError};
 use crate::net::{Addr, BasicConnector, Connector};
 
-use multichat_proto::{AccessToken, Config, Version};
+use multichat_proto::{AccessToken, Compression, Config, ResumeToken, Version};
 use std::convert::TryInto;
 use std::io::Error;
 use std::num::NonZeroUsize;
@@ -16,6 +16,8 @@ pub struct ClientBuilder<T> {
     connector: T,
     incoming_buffer: Result<Option<NonZeroUsize>, ()>,
     config: Config,
+    resume: Option<ResumeToken>,
+    compression: Option<Compression>,
 }
 
 impl<T: Connector> ClientBuilder<T> {
@@ -38,6 +40,23 @@ impl<T: Connector> ClientBuilder<T> {
         self
     }
 
+    /// Sets a session to resume, obtained from [`Client::resume_token`] of a previous connection.
+    ///
+    /// If the session can't be resumed, for example because it has expired, a new one is started.
+    /// Check [`Client::resumed`] to find out which happened.
+    pub fn resume(&mut self, value: Option<ResumeToken>) -> &mut Self {
+        self.resume = value;
+        self
+    }
+
+    /// Sets compression to request from the server.
+    ///
+    /// The server may decline it, in which case the connection is left uncompressed.
+    pub fn compression(&mut self, value: Option<Compression>) -> &mut Self {
+        self.compression = value;
+        self
+    }
+
     /// Connects to a Multichat server at the provided address.
     pub async fn connect(
         &self,
@@ -51,15 +70,24 @@ impl<T: Connector> ClientBuilder<T> {
             .unwrap_or(1);
 
         let stream = TcpStream::connect(addr).await?;
+        tracing::debug!(server = %addr.server_name(), "Connected");
+
         let stream = self
             .connector
             .connect(&addr.server_name(), stream)
             .await
             .map_err(ConnectError::Tls)?;
 
-        Client::from_io(incoming_buffer, stream, self.config, access_token)
-            .await
-            .map_err(From::from)
+        Client::from_io(
+            incoming_buffer,
+            stream,
+            self.config,
+            access_token,
+            self.resume,
+            self.compression,
+        )
+        .await
+        .map_err(From::from)
     }
 }
 
@@ -70,6 +98,8 @@ impl ClientBuilder<BasicConnector> {
             connector: BasicConnector,
             incoming_buffer: Ok(None),
             config: Config::default(),
+            resume: None,
+            compression: None,
         }
     }
 }
@@ -82,6 +112,8 @@ impl ClientBuilder<TlsConnector> {
             connector,
             incoming_buffer: Ok(None),
             config: Config::default(),
+            resume: None,
+            compression: None,
         }
     }
 }
@@ -96,6 +128,8 @@ impl ClientBuilder<Option<TlsConnector>> {
             connector,
             incoming_buffer: Ok(None),
             config: Config::default(),
+            resume: None,
+            compression: None,
         }
     }
 }
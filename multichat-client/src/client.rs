@@ -1,25 +1,68 @@
 use multichat_proto::{
-    AccessToken, Attachment, AuthRequest, AuthResponse, ClientMessage, Config, ServerMessage,
-    Version,
+    AccessToken, Attachment, AuthRequest, AuthResponse, ClientMessage, CompressedReader,
+    CompressedWriter, Compression, Config, ResumeToken, ServerMessage, Version,
 };
 use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::io::{Error, ErrorKind};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, WriteHalf};
+use std::task::{Context, Poll};
+use tokio::io::{
+    self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadBuf, WriteHalf,
+};
 use tokio::sync::mpsc::{self, Receiver};
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio::time;
 
+/// Number of bytes received over a connection, obtained from [`Client::received_bytes`].
+#[derive(Clone, Debug)]
+pub struct ReceivedBytes(Arc<AtomicU64>);
+
+impl ReceivedBytes {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Reader counting decompressed bytes.
+struct Counted<R> {
+    inner: R,
+    received: ReceivedBytes,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Counted<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+        let read = buf.filled().len() - filled;
+        this.received.0.fetch_add(read as u64, Ordering::Relaxed);
+
+        result
+    }
+}
+
 /// A client object representing a connection to a Multichat server.
 pub struct Client<T> {
-    stream_write: Arc<Mutex<BufWriter<WriteHalf<T>>>>,
+    stream_write: Arc<Mutex<CompressedWriter<BufWriter<WriteHalf<T>>>>>,
     receiver: Receiver<Result<ServerMessage<'static>, Error>>,
     // Updates queued while waiting for confirmations.
     updates: VecDeque<Update>,
+    // Attachments requested but not received yet, including those of cancelled downloads.
+    downloads: usize,
     config: Config,
     handle: JoinHandle<()>,
+    received: ReceivedBytes,
+    resume_token: ResumeToken,
+    resumed: bool,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
@@ -28,6 +71,8 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
         stream: T,
         config: Config,
         access_token: AccessToken,
+        resume: Option<ResumeToken>,
+        compression: Option<Compression>,
     ) -> Result<Self, InitError> {
         let (stream_read, stream_write) = io::split(stream);
 
@@ -45,19 +90,44 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
 
         // Write auth request.
         config
-            .write(&mut stream_write, &AuthRequest { access_token })
+            .write(
+                &mut stream_write,
+                &AuthRequest {
+                    access_token,
+                    resume,
+                    compression,
+                },
+            )
             .await?;
 
         // Read auth response.
-        let (ping_interval, ping_timeout) = match config.read(&mut stream_read).await? {
-            AuthResponse::Success {
-                ping_interval,
-                ping_timeout,
-            } => (ping_interval, ping_timeout),
-            AuthResponse::Failed => return Err(InitError::Auth),
+        let (ping_interval, ping_timeout, resume_token, resumed, compression) =
+            match config.read(&mut stream_read).await? {
+                AuthResponse::Success {
+                    ping_interval,
+                    ping_timeout,
+                    resume_token,
+                    resumed,
+                    compression,
+                } => (
+                    ping_interval,
+                    ping_timeout,
+                    resume_token,
+                    resumed,
+                    compression,
+                ),
+                AuthResponse::Failed => return Err(InitError::Auth),
+            };
+
+        tracing::debug!(resumed, ?compression, "Authenticated");
+
+        // Everything after the auth response is compressed if the server agreed to it.
+        let received = ReceivedBytes(Arc::new(AtomicU64::new(0)));
+        let mut stream_read = Counted {
+            inner: CompressedReader::new(stream_read, compression),
+            received: received.clone(),
         };
-
-        let stream_write = Arc::new(Mutex::new(stream_write));
+        let stream_write = Arc::new(Mutex::new(CompressedWriter::new(stream_write, compression)));
 
         // Spawn reading task.
         let (sender, receiver) = mpsc::channel(incoming_buffer);
@@ -76,6 +146,8 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
 
                     match result {
                         Ok(ServerMessage::Ping) => {
+                            tracing::trace!("Ping");
+
                             let mut stream_write = stream_write.lock().await;
 
                             let result =
@@ -90,6 +162,13 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
                             let _ = sender.send(Err(err)).await;
                             return;
                         }
+                        Ok(ServerMessage::Goodbye { reason }) => {
+                            tracing::info!(%reason, "Server said goodbye");
+
+                            let err = Error::new(ErrorKind::ConnectionAborted, reason);
+                            let _ = sender.send(Err(err)).await;
+                            return;
+                        }
                         Ok(message) => {
                             if sender.send(Ok(message)).await.is_err() {
                                 return;
@@ -108,11 +187,36 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
             stream_write,
             receiver,
             updates: VecDeque::new(),
+            downloads: 0,
             config,
             handle,
+            received,
+            resume_token,
+            resumed,
         })
     }
 
+    /// Returns a handle counting bytes received from the server, excluding the handshake.
+    ///
+    /// The handle stays usable while the client is busy, for example to report progress of
+    /// [`Client::download_attachment`].
+    pub fn received_bytes(&self) -> ReceivedBytes {
+        self.received.clone()
+    }
+
+    /// Returns the token for resuming this session with [`ClientBuilder::resume`](crate::ClientBuilder::resume).
+    pub fn resume_token(&self) -> ResumeToken {
+        self.resume_token
+    }
+
+    /// Returns whether a previous session was resumed.
+    ///
+    /// Users and joined groups of a resumed session are kept with the same IDs. The server sends
+    /// the current users of each resumed group again, updates sent while disconnected are lost.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
     /// Joins a group and returns its ID.
     /// If the group does not exist, it will be created.
     pub async fn join_group(&mut self, name: &str) -> Result<u32, Error> {
@@ -127,7 +231,10 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
             let message = self.receiver.recv().await.ok_or(ErrorKind::BrokenPipe)??;
             match translate_message(message) {
                 Ok(update) => self.updates.push_back(update),
-                Err(Reply::ConfirmGroup(gid)) => return Ok(gid),
+                Err(Reply::ConfirmGroup(gid)) => {
+                    tracing::debug!(name, gid, "Joined group");
+                    return Ok(gid);
+                }
                 Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Unexpected message")),
             }
         }
@@ -151,7 +258,10 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
             let message = self.receiver.recv().await.ok_or(ErrorKind::BrokenPipe)??;
             match translate_message(message) {
                 Ok(update) => self.updates.push_back(update),
-                Err(Reply::ConfirmClient(uid)) => return Ok(uid),
+                Err(Reply::ConfirmClient(uid)) => {
+                    tracing::debug!(gid, name, uid, "Created user");
+                    return Ok(uid);
+                }
                 Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Unexpected message")),
             }
         }
@@ -246,6 +356,8 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
     /// Downloads an attachment.
     ///
     /// Specifying a nonexistent attachment ID is considered an error and will result in client disconnection by server.
+    ///
+    /// This method is cancel-safe once the request is sent, the attachment of a cancelled download is discarded when it arrives.
     pub async fn download_attachment(&mut self, id: u32) -> Result<Vec<u8>, Error> {
         self.config
             .write(
@@ -254,11 +366,22 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
             )
             .await?;
 
+        self.downloads += 1;
+
         loop {
             let message = self.receiver.recv().await.ok_or(ErrorKind::BrokenPipe)??;
             match translate_message(message) {
                 Ok(update) => self.updates.push_back(update),
-                Err(Reply::Attachment(data)) => return Ok(data),
+                // Attachments arrive in the order they were requested.
+                Err(Reply::Attachment(data)) => {
+                    self.downloads -= 1;
+                    if self.downloads == 0 {
+                        tracing::debug!(id, size = data.len(), "Downloaded attachment");
+                        return Ok(data);
+                    }
+
+                    tracing::debug!("Discarded attachment of a cancelled download");
+                }
                 Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Unexpected message")),
             }
         }
@@ -291,6 +414,11 @@ impl<T: AsyncRead + AsyncWrite + Unpin + Send + 'static> Client<T> {
             let message = self.receiver.recv().await.ok_or(ErrorKind::BrokenPipe)??;
             match translate_message(message) {
                 Ok(update) => return Ok(update),
+                // Of a cancelled download.
+                Err(Reply::Attachment(_)) if self.downloads != 0 => {
+                    tracing::debug!("Discarded attachment of a cancelled download");
+                    self.downloads -= 1;
+                }
                 Err(_) => return Err(Error::new(ErrorKind::InvalidData, "Unexpected message")),
             }
         }
@@ -435,6 +563,7 @@ fn translate_message(message: ServerMessage<'static>) -> Result<Update, Reply> {
         ServerMessage::ConfirmUser { uid } => Err(Reply::ConfirmClient(uid)),
         ServerMessage::ConfirmGroup { gid } => Err(Reply::ConfirmGroup(gid)),
         ServerMessage::Attachment { data } => Err(Reply::Attachment(data.into_owned())),
-        ServerMessage::Ping => unreachable!(), // Filtered out by the reading task.
+        // Filtered out by the reading task.
+        ServerMessage::Ping | ServerMessage::Goodbye { .. } => unreachable!(),
     }
 }
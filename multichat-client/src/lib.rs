@@ -38,14 +38,19 @@
 
 mod builder;
 mod client;
+pub mod markup;
 mod net;
+mod signal;
 
 use std::convert::Infallible;
 
 pub use builder::{ClientBuilder, ConnectError};
-pub use client::{Client, Message, Update, UpdateKind};
+pub use client::{Client, Message, ReceivedBytes, Update, UpdateKind};
 pub use multichat_proto as proto;
+#[cfg(feature = "tls")]
+pub use multichat_proto::tls;
 pub use net::{Connector, EitherStream, Stream};
+pub use signal::shutdown_signal;
 
 use tokio::net::TcpStream;
 
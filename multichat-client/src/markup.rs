@@ -0,0 +1,152 @@
+//! Markup of message text shared by clients, `*bold*`, `_italic_` and `` `code` ``.
+//!
+//! The protocol carries messages as plain text, clients which can style text parse the markup
+//! and show it styled, others show it as it is.
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+}
+
+/// Part of a message with the same style.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Chunk<'a> {
+    pub text: &'a str,
+    pub style: Style,
+}
+
+/// Splits text into chunks styled by `*bold*`, `_italic_` and `` `code` `` markup.
+///
+/// Markers open at the start of a word and close at its end, those which are not closed are kept
+/// as they are, so that `snake_case` or `2 * 3` are left alone. Markup inside code is not
+/// recognized.
+pub fn parse(text: &str) -> Vec<Chunk<'_>> {
+    let mut chunks = Vec::new();
+    let mut style = Style::default();
+    let mut start = 0;
+
+    for (idx, c) in text.char_indices() {
+        let open = match c {
+            '*' => style.bold,
+            '_' => style.italic,
+            '`' => style.code,
+            _ => continue,
+        };
+
+        if style.code && c != '`' {
+            continue;
+        }
+
+        let toggles = match open {
+            true => closes(text, idx),
+            false => {
+                opens(text, idx)
+                    && text[idx + 1..]
+                        .char_indices()
+                        // Chunks are never empty.
+                        .skip(1)
+                        .any(|(i, d)| d == c && closes(text, idx + 1 + i))
+            }
+        };
+
+        if !toggles {
+            continue;
+        }
+
+        if start != idx {
+            chunks.push(Chunk {
+                text: &text[start..idx],
+                style,
+            });
+        }
+
+        match c {
+            '*' => style.bold = !open,
+            '_' => style.italic = !open,
+            _ => style.code = !open,
+        }
+
+        start = idx + 1;
+    }
+
+    if start != text.len() {
+        chunks.push(Chunk {
+            text: &text[start..],
+            style,
+        });
+    }
+
+    chunks
+}
+
+/// Whether text contains any markup.
+pub fn styled(text: &str) -> bool {
+    parse(text)
+        .iter()
+        .any(|chunk| chunk.style != Style::default())
+}
+
+// Markers open before a word, after anything but a letter or a digit.
+fn opens(text: &str, idx: usize) -> bool {
+    let prev = text[..idx].chars().next_back();
+    let next = text[idx + 1..].chars().next();
+
+    !prev.is_some_and(char::is_alphanumeric) && next.is_some_and(|c| !c.is_whitespace())
+}
+
+// Markers close after a word, before anything but a letter or a digit.
+fn closes(text: &str, idx: usize) -> bool {
+    let prev = text[..idx].chars().next_back();
+    let next = text[idx + 1..].chars().next();
+
+    prev.is_some_and(|c| !c.is_whitespace()) && !next.is_some_and(char::is_alphanumeric)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(text: &str) -> Vec<(&str, bool, bool, bool)> {
+        parse(text)
+            .into_iter()
+            .map(|chunk| {
+                let Style { bold, italic, code } = chunk.style;
+                (chunk.text, bold, italic, code)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn styles() {
+        assert_eq!(
+            chunks("a *bold* and _italic_ `code`"),
+            [
+                ("a ", false, false, false),
+                ("bold", true, false, false),
+                (" and ", false, false, false),
+                ("italic", false, true, false),
+                (" ", false, false, false),
+                ("code", false, false, true),
+            ]
+        );
+        assert_eq!(chunks("*_both_*"), [("both", true, true, false)]);
+        assert_eq!(chunks("`*not bold*`"), [("*not bold*", false, false, true)]);
+    }
+
+    #[test]
+    fn plain() {
+        for text in [
+            "snake_case_name",
+            "2 * 3 * 4",
+            "*unclosed",
+            "**",
+            "a * b*",
+            "*é",
+            "",
+        ] {
+            assert!(!styled(text), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::future::Future;
+use std::io::Error;
+use tokio::signal;
+
+/// Returns a future which completes once the process is asked to stop, with Ctrl+C or with
+/// SIGTERM, which is how systemd stops services.
+///
+/// The handlers are installed before returning, so that errors are reported before starting.
+pub fn shutdown_signal() -> Result<impl Future<Output = ()>, Error> {
+    #[cfg(unix)]
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+
+    Ok(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+
+        #[cfg(not(unix))]
+        let _ = signal::ctrl_c().await;
+    })
+}
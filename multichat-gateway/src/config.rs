@@ -0,0 +1,29 @@
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Address browsers connect to.
+    pub listen: SocketAddr,
+    pub multichat: Multichat,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub certificate: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        toml::from_str::<Config>(config).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use futures_util::{SinkExt, StreamExt};
+use multichat_client::proto::{AccessToken, Config as ProtoConfig};
+use multichat_client::{ClientBuilder, MaybeTlsClient};
+use std::borrow::Cow;
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::json::{Event, Request};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    WebSocket(#[from] tungstenite::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Server connections for browsers are made to.
+pub struct Upstream {
+    pub server: String,
+    pub connector: Option<TlsConnector>,
+    pub config: ProtoConfig,
+}
+
+impl Upstream {
+    async fn connect(&self, access_token: AccessToken) -> Result<MaybeTlsClient, String> {
+        ClientBuilder::maybe_tls(self.connector.clone())
+            .config(self.config)
+            .connect(&self.server, access_token)
+            .await
+            .map_err(|err| err.to_string())
+    }
+}
+
+/// Accepts browsers until shut down, then waits for their connections to close.
+pub async fn run(
+    listener: TcpListener,
+    upstream: Upstream,
+    shutdown: watch::Receiver<()>,
+) -> Result<(), io::Error> {
+    let upstream = Arc::new(upstream);
+    let mut connections = JoinSet::new();
+    let mut stop = shutdown.clone();
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            result = listener.accept() => result?,
+            // Reap connections which have closed.
+            Some(_) = connections.join_next() => continue,
+            _ = stop.changed() => break,
+        };
+
+        let upstream = upstream.clone();
+        let shutdown = shutdown.clone();
+
+        connections.spawn(async move {
+            tracing::debug!(%peer, "Browser connected");
+
+            if let Err(err) = handle(stream, &upstream, shutdown).await {
+                tracing::warn!(%peer, "Browser connection error: {}", err);
+            }
+        });
+    }
+
+    while connections.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+async fn handle(
+    stream: TcpStream,
+    upstream: &Upstream,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), Error> {
+    let mut socket = tokio_tungstenite::accept_async(stream).await?;
+
+    let access_token = loop {
+        let request = tokio::select! {
+            request = read(&mut socket) => request?,
+            _ = shutdown.changed() => None,
+        };
+
+        match request {
+            Some(Ok(Request::Auth { access_token })) => break access_token,
+            Some(Ok(_)) => {
+                let message = "Not authenticated".to_owned();
+                send(&mut socket, &Event::Error { message }).await?;
+            }
+            Some(Err(message)) => send(&mut socket, &Event::Error { message }).await?,
+            None => return Ok(()),
+        }
+    };
+
+    let mut client = match upstream.connect(access_token).await {
+        Ok(client) => client,
+        Err(message) => {
+            send(&mut socket, &Event::Error { message }).await?;
+            socket.close(None).await?;
+            return Ok(());
+        }
+    };
+
+    send(&mut socket, &Event::Ready).await?;
+
+    loop {
+        let event = tokio::select! {
+            request = read(&mut socket) => match request? {
+                Some(Ok(request)) => match perform(&mut client, request).await? {
+                    Some(event) => event,
+                    None => continue,
+                },
+                Some(Err(message)) => Event::Error { message },
+                None => break,
+            },
+            update = client.read_update() => update?.into(),
+            _ = shutdown.changed() => break,
+        };
+
+        send(&mut socket, &event).await?;
+    }
+
+    client.shutdown().await?;
+
+    // The browser may be gone already.
+    let _ = socket.close(None).await;
+
+    Ok(())
+}
+
+// Performs a request, returning its result if it has one.
+async fn perform(
+    client: &mut MaybeTlsClient,
+    request: Request,
+) -> Result<Option<Event>, io::Error> {
+    let event = match request {
+        Request::Auth { .. } => Event::Error {
+            message: "Already authenticated".to_owned(),
+        },
+        Request::JoinGroup { name } => {
+            let gid = client.join_group(&name).await?;
+            Event::GroupJoined { name, gid }
+        }
+        Request::InitUser { gid, name } => {
+            let uid = client.init_user(gid, &name).await?;
+            Event::UserCreated { gid, uid }
+        }
+        Request::DestroyUser { gid, uid } => {
+            client.destroy_user(gid, uid).await?;
+            return Ok(None);
+        }
+        Request::Rename { gid, uid, name } => {
+            client.rename_user(gid, uid, &name).await?;
+            return Ok(None);
+        }
+        Request::SendMessage {
+            gid,
+            uid,
+            text,
+            attachments,
+        } => {
+            let attachments: Vec<_> = attachments.into_iter().map(Cow::Owned).collect();
+            client.send_message(gid, uid, &text, &attachments).await?;
+            return Ok(None);
+        }
+        Request::StartTyping { gid, uid } => {
+            client.start_typing(gid, uid).await?;
+            return Ok(None);
+        }
+        Request::StopTyping { gid, uid } => {
+            client.stop_typing(gid, uid).await?;
+            return Ok(None);
+        }
+        Request::DownloadAttachment { id } => {
+            let data = client.download_attachment(id).await?;
+            Event::Attachment { id, data }
+        }
+        Request::IgnoreAttachment { id } => {
+            client.ignore_attachment(id).await?;
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(event))
+}
+
+// Reads the next request, or why it's invalid. Returns `None` once the browser disconnects.
+async fn read(
+    socket: &mut WebSocketStream<TcpStream>,
+) -> Result<Option<Result<Request, String>>, Error> {
+    loop {
+        let message = match socket.next().await {
+            Some(message) => message?,
+            None => return Ok(None),
+        };
+
+        let request = match message {
+            WsMessage::Text(text) => serde_json::from_str(&text),
+            WsMessage::Close(_) => return Ok(None),
+            WsMessage::Binary(_) => return Ok(Some(Err("Expected a text message".to_owned()))),
+            // Pings are answered by tungstenite.
+            WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => continue,
+        };
+
+        return Ok(Some(request.map_err(|err| err.to_string())));
+    }
+}
+
+async fn send(socket: &mut WebSocketStream<TcpStream>, event: &Event) -> Result<(), Error> {
+    let text = serde_json::to_string(event).unwrap();
+    socket.send(WsMessage::text(text)).await?;
+
+    Ok(())
+}
@@ -0,0 +1,231 @@
+//! JSON representation of the protocol used by browsers, sent in text messages.
+//!
+//! Every message is an object with a `type`, for example `{"type": "join_group", "name": "foo"}`.
+//! Requests are handled in the order they are sent in, and those with a result are replied to in
+//! the same order. Attachments are encoded in base64.
+//!
+//! As with the binary protocol, requests with IDs of groups, users or attachments which don't exist
+//! get the connection closed by the server.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use multichat_client::proto::{AccessToken, Attachment};
+use multichat_client::{Update, UpdateKind};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Message sent by a browser.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Has to be sent first, the access token is used to connect to the server.
+    Auth {
+        access_token: AccessToken,
+    },
+    JoinGroup {
+        name: String,
+    },
+    InitUser {
+        gid: u32,
+        name: String,
+    },
+    DestroyUser {
+        gid: u32,
+        uid: u32,
+    },
+    Rename {
+        gid: u32,
+        uid: u32,
+        name: String,
+    },
+    SendMessage {
+        gid: u32,
+        uid: u32,
+        text: String,
+        #[serde(default, deserialize_with = "deserialize_attachments")]
+        attachments: Vec<Vec<u8>>,
+    },
+    StartTyping {
+        gid: u32,
+        uid: u32,
+    },
+    StopTyping {
+        gid: u32,
+        uid: u32,
+    },
+    DownloadAttachment {
+        id: u32,
+    },
+    IgnoreAttachment {
+        id: u32,
+    },
+}
+
+/// Message sent to a browser, either a reply to a request or an update of a group.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Connected to the server, requests other than auth can be sent from now on.
+    Ready,
+    /// Reply to a request which could not be handled. The connection is closed if it was an auth
+    /// request.
+    Error {
+        message: String,
+    },
+    /// Reply to join group.
+    GroupJoined {
+        name: String,
+        gid: u32,
+    },
+    /// Reply to init user.
+    UserCreated {
+        gid: u32,
+        uid: u32,
+    },
+    /// Reply to download attachment.
+    Attachment {
+        id: u32,
+        #[serde(serialize_with = "serialize_data")]
+        data: Vec<u8>,
+    },
+    InitGroup {
+        gid: u32,
+        name: String,
+    },
+    DestroyGroup {
+        gid: u32,
+    },
+    InitUser {
+        gid: u32,
+        uid: u32,
+        name: String,
+    },
+    DestroyUser {
+        gid: u32,
+        uid: u32,
+    },
+    Rename {
+        gid: u32,
+        uid: u32,
+        name: String,
+    },
+    /// Attachments have to be either downloaded or ignored.
+    Message {
+        gid: u32,
+        uid: u32,
+        text: String,
+        attachments: Vec<Attachment>,
+    },
+    StartTyping {
+        gid: u32,
+        uid: u32,
+    },
+    StopTyping {
+        gid: u32,
+        uid: u32,
+    },
+}
+
+impl From<Update> for Event {
+    fn from(update: Update) -> Self {
+        let gid = update.gid;
+
+        match update.kind {
+            UpdateKind::InitGroup { name } => Self::InitGroup { gid, name },
+            UpdateKind::DestroyGroup => Self::DestroyGroup { gid },
+            UpdateKind::InitUser { uid, name } => Self::InitUser { gid, uid, name },
+            UpdateKind::DestroyUser { uid } => Self::DestroyUser { gid, uid },
+            UpdateKind::Rename { uid, name } => Self::Rename { gid, uid, name },
+            UpdateKind::Message { uid, message } => Self::Message {
+                gid,
+                uid,
+                text: message.text,
+                attachments: message.attachments,
+            },
+            UpdateKind::StartTyping { uid } => Self::StartTyping { gid, uid },
+            UpdateKind::StopTyping { uid } => Self::StopTyping { gid, uid },
+        }
+    }
+}
+
+fn deserialize_attachments<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|data| STANDARD.decode(data).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+fn serialize_data<S>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&STANDARD.encode(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use multichat_client::Message;
+
+    #[test]
+    fn parses_requests() {
+        let request = r#"{"type": "send_message", "gid": 1, "uid": 2, "text": "hi", "attachments": ["iVBORw=="]}"#;
+        assert_eq!(
+            serde_json::from_str::<Request>(request).unwrap(),
+            Request::SendMessage {
+                gid: 1,
+                uid: 2,
+                text: "hi".to_owned(),
+                attachments: vec![b"\x89PNG".to_vec()],
+            }
+        );
+
+        let request = r#"{"type": "send_message", "gid": 1, "uid": 2, "text": "hi"}"#;
+        assert!(matches!(
+            serde_json::from_str::<Request>(request).unwrap(),
+            Request::SendMessage { attachments, .. } if attachments.is_empty()
+        ));
+
+        let request = r#"{"type": "join_group", "name": "foo"}"#;
+        assert_eq!(
+            serde_json::from_str::<Request>(request).unwrap(),
+            Request::JoinGroup {
+                name: "foo".to_owned()
+            }
+        );
+
+        let request = r#"{"type": "auth", "access_token": "invalid"}"#;
+        assert!(serde_json::from_str::<Request>(request).is_err());
+    }
+
+    #[test]
+    fn serializes_events() {
+        let update = Update {
+            gid: 1,
+            kind: UpdateKind::Message {
+                uid: 2,
+                message: Message {
+                    text: "hi".to_owned(),
+                    attachments: vec![Attachment { id: 3, size: 4 }],
+                },
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_string(&Event::from(update)).unwrap(),
+            r#"{"type":"message","gid":1,"uid":2,"text":"hi","attachments":[{"id":3,"size":4}]}"#
+        );
+
+        let attachment = Event::Attachment {
+            id: 3,
+            data: b"\x89PNG".to_vec(),
+        };
+
+        assert_eq!(
+            serde_json::to_string(&attachment).unwrap(),
+            r#"{"type":"attachment","id":3,"data":"iVBORw=="}"#
+        );
+    }
+}
@@ -0,0 +1,119 @@
+mod config;
+mod gateway;
+mod json;
+mod tls;
+
+use clap::Parser;
+use config::Config;
+use gateway::Upstream;
+use multichat_client::proto::Config as ProtoConfig;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio::fs;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(help = "Path to config file")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().without_time().with_target(false));
+
+    subscriber::set_global_default(registry).unwrap();
+
+    let args = Args::parse();
+
+    tracing::info!("Reading config from {}", args.config.display());
+
+    let config = match fs::read_to_string(&args.config).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error reading config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = match toml::from_str::<Config>(&config) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error parsing config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let connector = match config.multichat.certificate {
+        Some(certificate) => match tls::configure(&certificate).await {
+            Ok(connector) => Some(connector),
+            Err(err) => {
+                tracing::error!("Error configuring TLS: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(512 * 1024 * 1024); // 512 MiB
+
+    let upstream = Upstream {
+        server: config.multichat.server,
+        connector,
+        config: proto_config,
+    };
+
+    let listener = match TcpListener::bind(config.listen).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("Error listening on {}: {}", config.listen, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing::info!("Listening for browsers on {}", config.listen);
+
+    let shutdown_signal = match multichat_client::shutdown_signal() {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("Error handling signals: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (shutdown_sender, shutdown) = watch::channel(());
+    let mut gateway = tokio::spawn(gateway::run(listener, upstream, shutdown));
+
+    let result = tokio::select! {
+        result = &mut gateway => Some(result.unwrap()),
+        _ = shutdown_signal => None,
+    };
+
+    tracing::info!("Shutting down");
+
+    let _ = shutdown_sender.send(());
+    let result = match result {
+        Some(result) => result,
+        None => gateway.await.unwrap(),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use multichat_client::proto::AccessToken;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub listen: SocketAddr,
+    /// Bearer token requests are authorized with.
+    pub token: String,
+    pub multichat: Multichat,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Group {
+    pub multichat_group: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        toml::from_str::<Config>(config).unwrap();
+    }
+}
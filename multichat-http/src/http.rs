@@ -0,0 +1,208 @@
+//! The HTTP API, `POST /groups/<name>/messages` to send a message and `GET /groups/<name>/events`
+//! for a stream of updates of the group as Server-Sent Events.
+
+use futures_util::stream;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Empty, Full, Limited, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{broadcast, oneshot};
+use tokio::time;
+
+use crate::json::{Event, Post};
+use crate::multichat::Command;
+
+// Larger messages wouldn't be taken by the Multichat server anyway.
+const MAX_POST_SIZE: usize = 64 * 1024 * 1024;
+// Proxies tend to close connections which are quiet for too long.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+type Body = UnsyncBoxBody<Bytes, Infallible>;
+
+pub struct Api {
+    pub token: String,
+    pub groups: HashMap<String, u32>,
+    pub commands: Sender<Command>,
+    pub events: broadcast::Sender<(u32, Event)>,
+}
+
+pub async fn serve(listener: TcpListener, api: Api) {
+    let api = Arc::new(api);
+
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                tracing::warn!("Error accepting HTTP connection: {}", err);
+                continue;
+            }
+        };
+
+        let api = api.clone();
+
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let api = api.clone();
+                async move { Ok::<_, Infallible>(api.handle(request).await) }
+            });
+
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!(%addr, "HTTP connection failed: {}", err);
+            }
+        });
+    }
+}
+
+impl Api {
+    async fn handle(&self, request: Request<Incoming>) -> Response<Body> {
+        let authorization = request.headers().get(AUTHORIZATION);
+        let expected = format!("Bearer {}", self.token);
+
+        // Compared in constant time, so that the token can't be guessed from how long it takes.
+        let authorized = authorization
+            .is_some_and(|value| bool::from(value.as_bytes().ct_eq(expected.as_bytes())));
+
+        if !authorized {
+            return status(StatusCode::UNAUTHORIZED);
+        }
+
+        let (name, endpoint) = match route(request.uri().path()) {
+            Some(route) => route,
+            None => return status(StatusCode::NOT_FOUND),
+        };
+
+        let gid = match self.groups.get(&name) {
+            Some(gid) => *gid,
+            None => return status(StatusCode::NOT_FOUND),
+        };
+
+        match (request.method(), endpoint) {
+            (&Method::POST, "messages") => self.post(gid, request).await,
+            (&Method::GET, "events") => self.events(gid),
+            (_, "messages" | "events") => status(StatusCode::METHOD_NOT_ALLOWED),
+            _ => status(StatusCode::NOT_FOUND),
+        }
+    }
+
+    async fn post(&self, gid: u32, request: Request<Incoming>) -> Response<Body> {
+        let body = match Limited::new(request.into_body(), MAX_POST_SIZE)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(_) => return status(StatusCode::PAYLOAD_TOO_LARGE),
+        };
+
+        let post = match serde_json::from_slice::<Post>(&body) {
+            Ok(post) => post,
+            Err(err) => {
+                let mut response = Response::new(Full::from(format!("{}\n", err)).boxed_unsync());
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                return response;
+            }
+        };
+
+        let (sent, result) = oneshot::channel();
+        let command = Command { gid, post, sent };
+
+        match self.commands.send(command).await.is_ok() && result.await.is_ok() {
+            true => status(StatusCode::NO_CONTENT),
+            false => status(StatusCode::SERVICE_UNAVAILABLE),
+        }
+    }
+
+    fn events(&self, gid: u32) -> Response<Body> {
+        let events = stream::unfold(self.events.subscribe(), move |mut receiver| async move {
+            loop {
+                let data = match time::timeout(KEEP_ALIVE, receiver.recv()).await {
+                    Ok(Ok((event_gid, event))) if event_gid == gid => {
+                        format!("data: {}\n\n", serde_json::to_string(&event).unwrap())
+                    }
+                    Ok(Ok(_)) => continue,
+                    // Events a slow client couldn't keep up with are skipped.
+                    Ok(Err(RecvError::Lagged(_))) => continue,
+                    Ok(Err(RecvError::Closed)) => return None,
+                    Err(_) => ": keep-alive\n\n".to_owned(),
+                };
+
+                return Some((Ok(Frame::data(Bytes::from(data))), receiver));
+            }
+        });
+
+        Response::builder()
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(StreamBody::new(events).boxed_unsync())
+            .unwrap()
+    }
+}
+
+// Splits `/groups/<name>/<endpoint>` into the name of the group and the endpoint.
+fn route(path: &str) -> Option<(String, &str)> {
+    let rest = path.strip_prefix("/groups/")?;
+    let (name, endpoint) = rest.rsplit_once('/')?;
+
+    Some((percent_decode(name)?, endpoint))
+}
+
+fn percent_decode(text: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            byte => decoded.push(byte),
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Empty::new().boxed_unsync());
+    *response.status_mut() = status;
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        assert_eq!(
+            route("/groups/foo/messages"),
+            Some(("foo".to_owned(), "messages"))
+        );
+
+        assert_eq!(
+            route("/groups/f%C3%B3o%20bar/events"),
+            Some(("f\u{f3}o bar".to_owned(), "events"))
+        );
+
+        assert_eq!(route("/groups/foo"), None);
+        assert_eq!(route("/groups/%ZZ/events"), None);
+        assert_eq!(route("/metrics"), None);
+    }
+}
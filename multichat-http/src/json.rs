@@ -0,0 +1,94 @@
+//! Bodies of requests and events of the event stream.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Body of `POST /groups/<name>/messages`.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Post {
+    /// Name of the user the message is sent as.
+    pub user: String,
+    pub text: String,
+    /// Attachments encoded in base64.
+    #[serde(default, deserialize_with = "deserialize_attachments")]
+    pub attachments: Vec<Vec<u8>>,
+}
+
+/// Update of a group, sent in the event stream of the group.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    InitUser {
+        uid: u32,
+        name: String,
+    },
+    DestroyUser {
+        uid: u32,
+        name: String,
+    },
+    Rename {
+        uid: u32,
+        old_name: String,
+        name: String,
+    },
+    /// Attachments are not downloaded, only their number is known.
+    Message {
+        uid: u32,
+        name: String,
+        text: String,
+        attachments: usize,
+    },
+}
+
+fn deserialize_attachments<'de, D>(deserializer: D) -> Result<Vec<Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|data| STANDARD.decode(data).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_posts() {
+        let post = r#"{"user": "ci", "text": "Build failed", "attachments": ["iVBORw=="]}"#;
+        assert_eq!(
+            serde_json::from_str::<Post>(post).unwrap(),
+            Post {
+                user: "ci".to_owned(),
+                text: "Build failed".to_owned(),
+                attachments: vec![b"\x89PNG".to_vec()],
+            }
+        );
+
+        let post = r#"{"user": "ci", "text": "Build passed"}"#;
+        assert!(serde_json::from_str::<Post>(post)
+            .unwrap()
+            .attachments
+            .is_empty());
+
+        let post = r#"{"user": "ci", "text": "Build failed", "attachments": ["?"]}"#;
+        assert!(serde_json::from_str::<Post>(post).is_err());
+    }
+
+    #[test]
+    fn serializes_events() {
+        let event = Event::Message {
+            uid: 1,
+            name: "alice".to_owned(),
+            text: "hi".to_owned(),
+            attachments: 0,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"message","uid":1,"name":"alice","text":"hi","attachments":0}"#
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use multichat_client::{MaybeTlsClient, Update, UpdateKind};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{broadcast, oneshot, watch};
+
+use crate::json::{self, Post};
+
+/// Message to send to a group, which is told once it's sent.
+pub struct Command {
+    pub gid: u32,
+    pub post: Post,
+    pub sent: oneshot::Sender<()>,
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    mut commands: Receiver<Command>,
+    events: broadcast::Sender<(u32, json::Event)>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), io::Error> {
+    let mut names = HashMap::<u32, HashMap<u32, String>>::new();
+    // Users are kept around for the next message, so that posting doesn't make them join and
+    // leave every time.
+    let mut users = HashMap::<(u32, String), u32>::new();
+
+    loop {
+        let event = tokio::select! {
+            command = commands.recv() => match command {
+                Some(command) => Event::Command(command),
+                None => break,
+            },
+            update = client.read_update() => Event::Multichat(update?),
+            _ = shutdown.changed() => break,
+        };
+
+        match event {
+            Event::Command(command) => {
+                let Command { gid, post, sent } = command;
+
+                let uid = match users.get(&(gid, post.user.clone())) {
+                    Some(uid) => *uid,
+                    None => {
+                        let uid = client.init_user(gid, &post.user).await?;
+                        users.insert((gid, post.user), uid);
+                        uid
+                    }
+                };
+
+                let attachments: Vec<_> = post.attachments.into_iter().map(Cow::Owned).collect();
+                client
+                    .send_message(gid, uid, &post.text, &attachments)
+                    .await?;
+
+                let _ = sent.send(());
+            }
+            Event::Multichat(update) => {
+                let gid = update.gid;
+                if let Some(event) =
+                    translate_update(&mut client, names.entry(gid).or_default(), update).await?
+                {
+                    // Nobody may be listening.
+                    let _ = events.send((gid, event));
+                }
+            }
+        }
+    }
+
+    for ((gid, _), uid) in users {
+        client.destroy_user(gid, uid).await?;
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
+
+// Keeps track of names of users, which events are sent with.
+async fn translate_update(
+    client: &mut MaybeTlsClient,
+    names: &mut HashMap<u32, String>,
+    update: Update,
+) -> Result<Option<json::Event>, io::Error> {
+    let event = match update.kind {
+        UpdateKind::InitUser { uid, name } => {
+            names.insert(uid, name.clone());
+            json::Event::InitUser { uid, name }
+        }
+        UpdateKind::DestroyUser { uid } => {
+            let name = names.remove(&uid).unwrap_or_default();
+            json::Event::DestroyUser { uid, name }
+        }
+        UpdateKind::Rename { uid, name } => {
+            let old_name = names.insert(uid, name.clone()).unwrap_or_default();
+            json::Event::Rename {
+                uid,
+                old_name,
+                name,
+            }
+        }
+        UpdateKind::Message { uid, message } => {
+            for attachment in &message.attachments {
+                client.ignore_attachment(attachment.id).await?;
+            }
+
+            json::Event::Message {
+                uid,
+                name: names.get(&uid).cloned().unwrap_or_default(),
+                text: message.text,
+                attachments: message.attachments.len(),
+            }
+        }
+        UpdateKind::InitGroup { .. }
+        | UpdateKind::DestroyGroup
+        | UpdateKind::StartTyping { .. }
+        | UpdateKind::StopTyping { .. } => return Ok(None),
+    };
+
+    Ok(Some(event))
+}
+
+enum Event {
+    Command(Command),
+    Multichat(Update),
+}
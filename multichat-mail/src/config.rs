@@ -0,0 +1,73 @@
+use multichat_client::proto::AccessToken;
+use serde::{Deserialize, Deserializer};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub multichat: Multichat,
+    /// Address mail is delivered to over LMTP.
+    pub lmtp: SocketAddr,
+    /// Relay digests are sent through, needed only if there are any.
+    pub smtp: Option<Smtp>,
+    pub groups: Vec<Group>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Smtp {
+    pub server: String,
+    /// Address digests are sent from.
+    pub from: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Group {
+    pub multichat_group: String,
+    /// Mail to this address is posted to the group.
+    pub address: Option<String>,
+    pub digest: Option<Digest>,
+}
+
+/// Mail summarizing what was said in a group.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Digest {
+    pub to: Vec<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub every: Duration,
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(deserializer)?;
+
+    humantime::parse_duration(&duration).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        let config = toml::from_str::<Config>(config).unwrap();
+
+        let digest = config.groups[0].digest.as_ref().unwrap();
+        assert_eq!(digest.every, Duration::from_secs(24 * 60 * 60));
+    }
+}
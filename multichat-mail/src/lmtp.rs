@@ -0,0 +1,201 @@
+//! LMTP server (RFC 2033) mail is delivered by, such as by Postfix with `lmtp:inet:...`.
+//!
+//! Unlike SMTP, every recipient gets a reply of their own once the mail is received, so that a
+//! mail is only accepted for the groups it was posted to.
+
+use std::collections::HashSet;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+// Mail larger than this is rejected, the Multichat server wouldn't take it anyway.
+const MAX_SIZE: usize = 64 * 1024 * 1024;
+
+/// Mail received for a recipient, which is told whether it was delivered.
+pub struct Delivery {
+    pub recipient: String,
+    pub data: Arc<Vec<u8>>,
+    pub delivered: oneshot::Sender<bool>,
+}
+
+pub async fn listen(
+    address: SocketAddr,
+    recipients: HashSet<String>,
+    sender: Sender<Delivery>,
+) -> Result<(), io::Error> {
+    let listener = TcpListener::bind(address).await?;
+    let recipients = Arc::new(recipients);
+
+    tracing::info!("Listening for LMTP on {}", address);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let recipients = recipients.clone();
+        let sender = sender.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, &recipients, &sender).await {
+                tracing::warn!(%peer, "LMTP error: {}", err);
+            }
+        });
+    }
+}
+
+async fn handle(
+    stream: TcpStream,
+    recipients: &HashSet<String>,
+    sender: &Sender<Delivery>,
+) -> Result<(), io::Error> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut accepted = Vec::new();
+    let mut line = String::new();
+
+    writer
+        .write_all(b"220 multichat-mail LMTP ready\r\n")
+        .await?;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+
+        let command = line.trim_end();
+        let verb = command
+            .split([' ', ':'])
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+
+        let reply: &[u8] = match verb.as_str() {
+            "LHLO" => b"250-multichat-mail\r\n250 PIPELINING\r\n",
+            "MAIL" => {
+                accepted.clear();
+                b"250 OK\r\n"
+            }
+            "RCPT" => match address(command) {
+                Some(recipient) if recipients.contains(&recipient) => {
+                    accepted.push(recipient);
+                    b"250 OK\r\n"
+                }
+                _ => b"550 No such group\r\n",
+            },
+            "DATA" if accepted.is_empty() => b"503 No recipients\r\n",
+            "DATA" => {
+                writer.write_all(b"354 Go ahead\r\n").await?;
+
+                let data = match read_data(&mut reader).await? {
+                    Some(data) => Arc::new(data),
+                    None => {
+                        for _ in &accepted {
+                            writer.write_all(b"552 Message too large\r\n").await?;
+                        }
+
+                        accepted.clear();
+                        continue;
+                    }
+                };
+
+                for recipient in accepted.drain(..) {
+                    let (delivered, result) = oneshot::channel();
+                    let delivery = Delivery {
+                        recipient,
+                        data: data.clone(),
+                        delivered,
+                    };
+
+                    let delivered = sender.send(delivery).await.is_ok() && result.await == Ok(true);
+                    let reply: &[u8] = match delivered {
+                        true => b"250 Delivered\r\n",
+                        false => b"451 Try again later\r\n",
+                    };
+
+                    writer.write_all(reply).await?;
+                }
+
+                continue;
+            }
+            "RSET" => {
+                accepted.clear();
+                b"250 OK\r\n"
+            }
+            "NOOP" => b"250 OK\r\n",
+            "QUIT" => {
+                writer.write_all(b"221 Bye\r\n").await?;
+                return Ok(());
+            }
+            _ => b"502 Unknown command\r\n",
+        };
+
+        writer.write_all(reply).await?;
+    }
+}
+
+// Reads the mail up to the line with a single dot, undoing dot stuffing, or skips it if it's too
+// large.
+async fn read_data<R: AsyncBufReadExt + Unpin>(
+    reader: &mut R,
+) -> Result<Option<Vec<u8>>, io::Error> {
+    let mut data = Vec::new();
+    let mut line = Vec::new();
+    let mut too_large = false;
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+
+        let line = line.strip_prefix(b".").unwrap_or(&line);
+        if data.len() + line.len() > MAX_SIZE {
+            too_large = true;
+        }
+
+        if !too_large {
+            data.extend_from_slice(line);
+        }
+    }
+
+    Ok((!too_large).then_some(data))
+}
+
+// The address of `RCPT TO:<group@example.com>`, in lowercase.
+fn address(command: &str) -> Option<String> {
+    let start = command.find('<')?;
+    let end = command[start..].find('>')? + start;
+
+    Some(command[start + 1..end].to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(
+            address("RCPT TO:<Foo@Lists.example.com> NOTIFY=NEVER"),
+            Some("foo@lists.example.com".to_owned())
+        );
+
+        assert_eq!(address("RCPT TO:foo"), None);
+    }
+
+    #[tokio::test]
+    async fn reads_data() {
+        let mut data = &b"Subject: hi\r\n\r\n..dot\r\nend\r\n.\r\nQUIT\r\n"[..];
+        assert_eq!(
+            read_data(&mut data).await.unwrap().unwrap(),
+            b"Subject: hi\r\n\r\n.dot\r\nend\r\n"
+        );
+    }
+}
@@ -0,0 +1,185 @@
+mod config;
+mod lmtp;
+mod multichat;
+mod smtp;
+mod tls;
+
+use clap::Parser;
+use config::Config;
+use multichat::Digest;
+use multichat_client::proto::Config as ProtoConfig;
+use multichat_client::ClientBuilder;
+use smtp::Relay;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::{mpsc, watch};
+use tracing::subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(help = "Path to config file")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().without_time().with_target(false));
+
+    subscriber::set_global_default(registry).unwrap();
+
+    let args = Args::parse();
+
+    tracing::info!("Reading config from {}", args.config.display());
+
+    let config = match fs::read_to_string(&args.config).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error reading config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = match toml::from_str::<Config>(&config) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error parsing config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let connector = match config.multichat.certificate {
+        Some(certificate) => match tls::configure(&certificate).await {
+            Ok(connector) => Some(connector),
+            Err(err) => {
+                tracing::error!("Error configuring TLS: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(512 * 1024 * 1024); // 512 MiB
+
+    let mut client = match ClientBuilder::maybe_tls(connector)
+        .config(proto_config)
+        .connect(&config.multichat.server, config.multichat.access_token)
+        .await
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("Error connecting to multichat: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing::info!("Connected to Multichat");
+
+    let relay = config.smtp.map(|smtp| {
+        Arc::new(Relay {
+            server: smtp.server,
+            from: smtp.from,
+        })
+    });
+
+    let mut address_to_group = HashMap::new();
+    let mut digests = HashMap::new();
+
+    for group in config.groups {
+        let gid = match client.join_group(&group.multichat_group).await {
+            Ok(gid) => gid,
+            Err(err) => {
+                tracing::error!("Error joining group: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if let Some(address) = group.address {
+            let address = address.to_ascii_lowercase();
+            if address_to_group.insert(address.clone(), gid).is_some() {
+                tracing::error!("Address {} is associated with more than one group", address);
+                return ExitCode::FAILURE;
+            }
+        }
+
+        if let Some(digest) = group.digest {
+            if relay.is_none() {
+                tracing::error!("Sending digests requires an SMTP relay");
+                return ExitCode::FAILURE;
+            }
+
+            let digest = Digest {
+                group: group.multichat_group,
+                to: digest.to,
+                every: digest.every,
+            };
+
+            if digests.insert(gid, digest).is_some() {
+                tracing::error!("Multichat group has more than one digest");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let shutdown_signal = match multichat_client::shutdown_signal() {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("Error handling signals: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel(1);
+    let (shutdown_sender, shutdown) = watch::channel(());
+
+    let recipients: HashSet<_> = address_to_group.keys().cloned().collect();
+    let mut listener = tokio::spawn(lmtp::listen(config.lmtp, recipients, sender));
+
+    let mut multichat = tokio::spawn(async move {
+        multichat::run(
+            client,
+            &address_to_group,
+            &digests,
+            relay,
+            receiver,
+            shutdown,
+        )
+        .await
+    });
+
+    let result = tokio::select! {
+        result = &mut multichat => Some(result.unwrap()),
+        result = &mut listener => result.unwrap().err().map(Err),
+        _ = shutdown_signal => None,
+    };
+
+    tracing::info!("Shutting down");
+
+    let _ = shutdown_sender.send(());
+    let result = match result {
+        Some(result) => result,
+        None => multichat.await.unwrap(),
+    };
+
+    listener.abort();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use mail_parser::MessageParser;
+use multichat_client::{MaybeTlsClient, Update, UpdateKind};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use tokio::time::{self, Instant};
+
+use crate::lmtp::Delivery;
+use crate::smtp::Relay;
+
+/// Where and how often a digest of a group is sent.
+pub struct Digest {
+    pub group: String,
+    pub to: Vec<String>,
+    pub every: Duration,
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    address_to_group: &HashMap<String, u32>,
+    digests: &HashMap<u32, Digest>,
+    relay: Option<Arc<Relay>>,
+    mut receiver: Receiver<Delivery>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), io::Error> {
+    let mut groups = HashMap::<u32, HashMap<u32, String>>::new();
+
+    // What was said in groups since their last digest, and when it's sent next.
+    let mut pending = digests
+        .iter()
+        .map(|(gid, digest)| (*gid, (Instant::now() + digest.every, Vec::<String>::new())))
+        .collect::<HashMap<_, _>>();
+
+    loop {
+        let next_digest = pending.values().map(|(next, _)| *next).min();
+        let digest_due = async {
+            match next_digest {
+                Some(next) => time::sleep_until(next).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let event = tokio::select! {
+            delivery = receiver.recv() => match delivery {
+                Some(delivery) => Event::Mail(delivery),
+                None => break,
+            },
+            update = client.read_update() => Event::Multichat(update?),
+            _ = digest_due => Event::Digest,
+            _ = shutdown.changed() => break,
+        };
+
+        match event {
+            Event::Mail(delivery) => {
+                let gid = address_to_group[&delivery.recipient];
+                let mail = parse_mail(&delivery.data);
+
+                let name = match mail.from.is_empty() {
+                    true => "mail",
+                    false => &mail.from,
+                };
+
+                let text = match mail.subject.is_empty() {
+                    true => mail.text,
+                    false => format!("*{}*\n{}", mail.subject, mail.text),
+                };
+
+                let attachments: Vec<_> = mail.attachments.into_iter().map(Cow::Owned).collect();
+
+                // Senders are only in the group for as long as it takes to post their mail.
+                let uid = client.init_user(gid, name).await?;
+                client.send_message(gid, uid, &text, &attachments).await?;
+                client.destroy_user(gid, uid).await?;
+
+                tracing::info!(recipient = %delivery.recipient, "Posted mail");
+                let _ = delivery.delivered.send(true);
+            }
+            Event::Multichat(update) => {
+                let users = groups.entry(update.gid).or_default();
+
+                match update.kind {
+                    UpdateKind::InitUser { uid, name } => {
+                        users.insert(uid, name);
+                    }
+                    UpdateKind::DestroyUser { uid } => {
+                        users.remove(&uid);
+                    }
+                    UpdateKind::Rename { uid, name } => {
+                        users.insert(uid, name);
+                    }
+                    UpdateKind::Message { uid, message } => {
+                        for attachment in &message.attachments {
+                            client.ignore_attachment(attachment.id).await?;
+                        }
+
+                        let (_, lines) = match pending.get_mut(&update.gid) {
+                            Some(pending) => pending,
+                            None => continue,
+                        };
+
+                        let mut line = format!("{}: {}", users[&uid], message.text);
+                        if !message.attachments.is_empty() {
+                            line.push_str(&format!(" ({} attachments)", message.attachments.len()));
+                        }
+
+                        lines.push(line);
+                    }
+                    UpdateKind::InitGroup { .. }
+                    | UpdateKind::DestroyGroup
+                    | UpdateKind::StartTyping { .. }
+                    | UpdateKind::StopTyping { .. } => {}
+                }
+            }
+            Event::Digest => {
+                let now = Instant::now();
+
+                for (gid, (next, lines)) in &mut pending {
+                    if *next > now {
+                        continue;
+                    }
+
+                    let digest = &digests[gid];
+                    *next = now + digest.every;
+
+                    if lines.is_empty() {
+                        continue;
+                    }
+
+                    let relay = relay.clone().unwrap();
+                    let to = digest.to.clone();
+                    let subject = format!("Digest of {}", digest.group);
+                    let text = mem::take(lines).join("\n");
+
+                    // Sending shouldn't hold up the bridge, nor should a relay being down.
+                    tokio::spawn(async move {
+                        if let Err(err) = relay.send(&to, &subject, &text).await {
+                            tracing::error!("Error sending digest: {}", err);
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
+
+enum Event {
+    Mail(Delivery),
+    Multichat(Update),
+    Digest,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Mail {
+    /// Display name of the sender, or their address if they have none.
+    from: String,
+    subject: String,
+    text: String,
+    attachments: Vec<Vec<u8>>,
+}
+
+// Anything that can't be parsed is left empty.
+fn parse_mail(data: &[u8]) -> Mail {
+    let message = match MessageParser::default().parse(data) {
+        Some(message) => message,
+        None => {
+            return Mail {
+                from: String::new(),
+                subject: String::new(),
+                text: String::new(),
+                attachments: Vec::new(),
+            }
+        }
+    };
+
+    let from = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|from| from.name().or(from.address()))
+        .unwrap_or_default()
+        .to_owned();
+
+    // Plain text is preferred to HTML, which is converted if it's all there is.
+    let text: Vec<_> = (0..message.text_body_count())
+        .filter_map(|idx| message.body_text(idx))
+        .map(|text| text.trim_end().to_owned())
+        .collect();
+
+    Mail {
+        from,
+        subject: message.subject().unwrap_or_default().to_owned(),
+        text: text.join("\n").trim_end().to_owned(),
+        attachments: message
+            .attachments()
+            .map(|attachment| attachment.contents().to_vec())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain() {
+        let mail = parse_mail(
+            b"From: =?utf-8?Q?Al=C3=ADce?= <alice@example.com>\r\n\
+              Subject: =?utf-8?B?SGVsbG8=?=\r\n =?utf-8?B?IHRoZXJl?=\r\n\
+              Content-Type: text/plain; charset=utf-8\r\n\
+              Content-Transfer-Encoding: quoted-printable\r\n\
+              \r\n\
+              Caf=C3=A9 is=\r\n open.\r\n",
+        );
+
+        assert_eq!(
+            mail,
+            Mail {
+                from: "Al\u{ed}ce".to_owned(),
+                subject: "Hello there".to_owned(),
+                text: "Caf\u{e9} is open.".to_owned(),
+                attachments: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_multipart() {
+        let mail = parse_mail(
+            b"From: bob@example.com\n\
+              Content-Type: multipart/mixed; boundary=\"outer\"\n\
+              \n\
+              preamble\n\
+              --outer\n\
+              Content-Type: multipart/alternative; boundary=inner\n\
+              \n\
+              --inner\n\
+              Content-Type: text/html\n\
+              \n\
+              <p>Hi</p>\n\
+              --inner\n\
+              Content-Type: text/plain\n\
+              \n\
+              Hi\n\
+              --inner--\n\
+              --outer\n\
+              Content-Type: image/png\n\
+              Content-Disposition: attachment; filename=\"a.png\"\n\
+              Content-Transfer-Encoding: base64\n\
+              \n\
+              iVBO\n\
+              Rw==\n\
+              --outer--\n",
+        );
+
+        assert_eq!(mail.from, "bob@example.com");
+        assert_eq!(mail.text, "Hi");
+        assert_eq!(mail.attachments, [b"\x89PNG".to_vec()]);
+    }
+}
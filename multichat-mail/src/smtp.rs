@@ -0,0 +1,131 @@
+//! Sending mail through an SMTP relay on a trusted network, such as a local Postfix, which takes
+//! care of delivering it. There is no TLS or authentication.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("SMTP server replied {0:?}")]
+    Rejected(String),
+}
+
+pub struct Relay {
+    pub server: String,
+    pub from: String,
+}
+
+impl Relay {
+    /// Sends a plain text mail to the recipients.
+    pub async fn send(&self, to: &[String], subject: &str, text: &str) -> Result<(), Error> {
+        let stream = TcpStream::connect(&self.server).await?;
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+
+        expect(&mut reader, 220).await?;
+
+        let commands = [
+            "HELO multichat-mail".to_owned(),
+            format!("MAIL FROM:<{}>", self.from),
+        ]
+        .into_iter()
+        .chain(to.iter().map(|to| format!("RCPT TO:<{}>", to)));
+
+        for command in commands {
+            writer
+                .write_all(format!("{}\r\n", command).as_bytes())
+                .await?;
+            expect(&mut reader, 250).await?;
+        }
+
+        writer.write_all(b"DATA\r\n").await?;
+        expect(&mut reader, 354).await?;
+
+        let message = format_message(&self.from, to, subject, text);
+        writer.write_all(message.as_bytes()).await?;
+        expect(&mut reader, 250).await?;
+
+        writer.write_all(b"QUIT\r\n").await?;
+
+        Ok(())
+    }
+}
+
+// The message with its headers, dot stuffed and ended for the DATA command. The relay adds the
+// date and message ID.
+fn format_message(from: &str, to: &[String], subject: &str, text: &str) -> String {
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        from,
+        to.join(", "),
+        encode_word(subject)
+    );
+
+    for line in text.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+
+    message.push_str(".\r\n");
+    message
+}
+
+// Headers can only be ASCII, anything else is encoded (RFC 2047).
+fn encode_word(text: &str) -> String {
+    match text.is_ascii() {
+        true => text.to_owned(),
+        false => format!("=?utf-8?B?{}?=", STANDARD.encode(text)),
+    }
+}
+
+// Reads a reply, which may span several lines, checking its code.
+async fn expect<R: AsyncBufReadExt + Unpin>(reader: &mut R, code: u16) -> Result<(), Error> {
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+
+        // Lines other than the last have a dash after the code.
+        if line.as_bytes().get(3) != Some(&b'-') {
+            break;
+        }
+    }
+
+    match line.get(..3).and_then(|c| c.parse::<u16>().ok()) {
+        Some(c) if c == code => Ok(()),
+        _ => Err(Error::Rejected(line.trim_end().to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_messages() {
+        let to = ["a@example.com".to_owned(), "b@example.com".to_owned()];
+        let message = format_message("m@example.com", &to, "Dig\u{e9}st", "hi\n.\nbye");
+
+        assert_eq!(
+            message,
+            "From: m@example.com\r\nTo: a@example.com, b@example.com\r\n\
+             Subject: =?utf-8?B?RGlnw6lzdA==?=\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n\
+             hi\r\n..\r\nbye\r\n.\r\n"
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use multichat_client::proto::AccessToken;
+use serde::Deserialize;
+use std::path::PathBuf;
+use url::Url;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub mattermost: Mattermost,
+    pub multichat: Multichat,
+    pub chats: Vec<Chat>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Mattermost {
+    /// Address of the server, the same as in the browser.
+    pub url: Url,
+    /// Access token of a bot account or a user.
+    pub token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Chat {
+    pub multichat_group: String,
+    /// ID of the channel, not its name, which can change.
+    pub mattermost_channel: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        toml::from_str::<Config>(config).unwrap();
+    }
+}
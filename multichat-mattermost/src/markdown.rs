@@ -0,0 +1,102 @@
+//! Conversion between Mattermost's Markdown and Multichat markup.
+
+use multichat_client::markup;
+
+// Characters which are escaped so that Mattermost shows them as they are.
+const SPECIAL: [char; 7] = ['\\', '*', '_', '`', '~', '[', ']'];
+
+/// Converts Markdown to markup, keeping bold, italic and code and dropping the rest of the
+/// styling. Links, lists and such are readable as they are.
+pub fn to_markup(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let (replacement, length) = match c {
+            '\\' => match rest[1..].chars().next() {
+                Some(escaped) if escaped.is_ascii_punctuation() => {
+                    (escaped.to_string(), 1 + escaped.len_utf8())
+                }
+                _ => ("\\".to_owned(), 1),
+            },
+            // Code is kept as it is, including code blocks.
+            '`' => {
+                let fence = if rest.starts_with("```") { "```" } else { "`" };
+                match rest[fence.len()..].find(fence) {
+                    Some(end) => {
+                        let length = 2 * fence.len() + end;
+                        (rest[..length].to_owned(), length)
+                    }
+                    None => ("`".to_owned(), 1),
+                }
+            }
+            _ if rest.starts_with("**") || rest.starts_with("__") => ("*".to_owned(), 2),
+            _ if rest.starts_with("~~") => (String::new(), 2),
+            '*' => ("_".to_owned(), 1),
+            c => (c.to_string(), c.len_utf8()),
+        };
+
+        converted.push_str(&replacement);
+        rest = &rest[length..];
+    }
+
+    converted
+}
+
+/// Converts markup to Markdown, escaping what would be taken for Markdown otherwise.
+pub fn from_markup(text: &str) -> String {
+    let mut converted = String::with_capacity(text.len());
+
+    for chunk in markup::parse(text) {
+        if chunk.style.code {
+            converted.push('`');
+            converted.push_str(chunk.text);
+            converted.push('`');
+            continue;
+        }
+
+        let mut escaped = String::with_capacity(chunk.text.len());
+        for c in chunk.text.chars() {
+            if SPECIAL.contains(&c) {
+                escaped.push('\\');
+            }
+
+            escaped.push(c);
+        }
+
+        let escaped = match chunk.style.italic {
+            true => format!("_{}_", escaped),
+            false => escaped,
+        };
+
+        match chunk.style.bold {
+            true => converted.push_str(&format!("**{}**", escaped)),
+            false => converted.push_str(&escaped),
+        }
+    }
+
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_markup() {
+        assert_eq!(
+            to_markup("**bold** *italic* __bold__ ~~gone~~ `**code**` \\*"),
+            "*bold* _italic_ *bold* gone `**code**` *"
+        );
+
+        assert_eq!(to_markup("```\nlet a = *b;\n```"), "```\nlet a = *b;\n```");
+    }
+
+    #[test]
+    fn converts_from_markup() {
+        assert_eq!(
+            from_markup("*bold* _italic_ `a*b` snake_case [link]"),
+            "**bold** _italic_ `a*b` snake\\_case \\[link\\]"
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use futures_util::{SinkExt, StreamExt};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+use tokio::time;
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+use url::Url;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    WebSocket(#[from] tungstenite::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error("Mattermost API error: {0}")]
+    Api(String),
+}
+
+/// Something that happened to a post in a channel.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Event {
+    pub kind: EventKind,
+    pub post: Post,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Posted,
+    Edited,
+    Deleted,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Post {
+    pub channel_id: String,
+    pub user_id: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub file_ids: Vec<String>,
+    /// Empty for posts of users, joins and such have a type of their own.
+    #[serde(default, rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Deserialize)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    #[serde(default)]
+    pub nickname: String,
+}
+
+impl User {
+    /// Nickname of the user if they have one, their username otherwise.
+    pub fn display_name(&self) -> &str {
+        match self.nickname.is_empty() {
+            true => &self.username,
+            false => &self.nickname,
+        }
+    }
+}
+
+/// Client of the Mattermost REST API.
+pub struct Mattermost {
+    http: reqwest::Client,
+    url: Url,
+    token: String,
+}
+
+impl Mattermost {
+    pub fn new(url: Url, token: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url,
+            token,
+        }
+    }
+
+    /// User the token belongs to, whose posts are those bridged from Multichat.
+    pub async fn me(&self) -> Result<User, Error> {
+        self.call(self.request(Method::GET, "users/me")?).await
+    }
+
+    pub async fn user(&self, id: &str) -> Result<User, Error> {
+        self.call(self.request(Method::GET, &format!("users/{}", id))?)
+            .await
+    }
+
+    pub async fn create_post(
+        &self,
+        channel_id: &str,
+        message: &str,
+        file_ids: &[String],
+    ) -> Result<(), Error> {
+        let body = serde_json::json!({
+            "channel_id": channel_id,
+            "message": message,
+            "file_ids": file_ids,
+        });
+
+        let request = self
+            .request(Method::POST, "posts")?
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+
+        self.call::<Value>(request).await?;
+
+        Ok(())
+    }
+
+    /// Uploads a file to a channel, it's shown once a post refers to it.
+    pub async fn upload(
+        &self,
+        channel_id: &str,
+        name: &str,
+        data: Vec<u8>,
+    ) -> Result<String, Error> {
+        #[derive(Deserialize)]
+        struct Uploaded {
+            file_infos: Vec<FileInfo>,
+        }
+
+        #[derive(Deserialize)]
+        struct FileInfo {
+            id: String,
+        }
+
+        let request = self
+            .request(Method::POST, "files")?
+            .query(&[("channel_id", channel_id), ("filename", name)])
+            .body(data);
+
+        let uploaded: Uploaded = self.call(request).await?;
+        let file = uploaded.file_infos.into_iter().next();
+
+        Ok(file
+            .ok_or_else(|| Error::Api("no file uploaded".to_owned()))?
+            .id)
+    }
+
+    pub async fn download(&self, file_id: &str) -> Result<Vec<u8>, Error> {
+        let response = self
+            .request(Method::GET, &format!("files/{}", file_id))?
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.bytes().await?.into())
+    }
+
+    /// Receives events through the WebSocket API until the receiver is gone, reconnecting
+    /// whenever the connection is lost.
+    pub async fn listen(&self, sender: Sender<Event>) -> Result<(), Error> {
+        let mut url = self.url.join("api/v4/websocket")?;
+        let scheme = match url.scheme() {
+            "https" => "wss",
+            _ => "ws",
+        };
+
+        url.set_scheme(scheme).unwrap();
+
+        loop {
+            let mut socket = match tokio_tungstenite::connect_async(url.as_str()).await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    tracing::warn!("Error connecting to Mattermost: {}", err);
+                    time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            let challenge = serde_json::json!({
+                "seq": 1,
+                "action": "authentication_challenge",
+                "data": { "token": self.token },
+            });
+
+            socket.send(WsMessage::text(challenge.to_string())).await?;
+
+            tracing::info!("Connected to Mattermost");
+
+            while let Some(message) = socket.next().await {
+                let text = match message {
+                    Ok(WsMessage::Text(text)) => text,
+                    Ok(WsMessage::Close(_)) => break,
+                    Ok(_) => continue,
+                    Err(err) => {
+                        tracing::warn!("Error receiving from Mattermost: {}", err);
+                        break;
+                    }
+                };
+
+                let event = match translate_event(&text) {
+                    Ok(Some(event)) => event,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        tracing::warn!("Invalid event from Mattermost: {}", err);
+                        continue;
+                    }
+                };
+
+                if sender.send(event).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            tracing::info!("Reconnecting to Mattermost");
+            time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder, Error> {
+        let url = self.url.join("api/v4/")?.join(path)?;
+
+        Ok(self.http.request(method, url).bearer_auth(&self.token))
+    }
+
+    async fn call<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        loop {
+            let response = request.try_clone().unwrap().send().await?;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let seconds = response
+                    .headers()
+                    .get("X-Ratelimit-Reset")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(1);
+
+                tracing::warn!("Rate limited, waiting {} seconds", seconds);
+                time::sleep(Duration::from_secs(seconds)).await;
+                continue;
+            }
+
+            let status = response.status();
+            let value = serde_json::from_slice::<Value>(&response.bytes().await?)?;
+
+            if !status.is_success() {
+                let message = value.get("message").and_then(Value::as_str);
+                return Err(Error::Api(message.unwrap_or(status.as_str()).to_owned()));
+            }
+
+            return Ok(serde_json::from_value(value)?);
+        }
+    }
+}
+
+// Posts come as JSON in a string, other events are not bridged.
+fn translate_event(text: &str) -> Result<Option<Event>, serde_json::Error> {
+    #[derive(Deserialize)]
+    struct RawEvent {
+        event: Option<String>,
+        data: Option<Data>,
+    }
+
+    #[derive(Deserialize)]
+    struct Data {
+        post: Option<String>,
+    }
+
+    let event = serde_json::from_str::<RawEvent>(text)?;
+    let kind = match event.event.as_deref() {
+        Some("posted") => EventKind::Posted,
+        Some("post_edited") => EventKind::Edited,
+        Some("post_deleted") => EventKind::Deleted,
+        _ => return Ok(None),
+    };
+
+    let post = match event.data.and_then(|data| data.post) {
+        Some(post) => serde_json::from_str::<Post>(&post)?,
+        None => return Ok(None),
+    };
+
+    if !post.kind.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(Event { kind, post }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translates_events() {
+        let posted = r#"{"event": "posted", "seq": 3, "data": {"channel_type": "O",
+            "post": "{\"id\":\"p1\",\"channel_id\":\"c1\",\"user_id\":\"u1\",\"message\":\"hi\",\"type\":\"\",\"file_ids\":[\"f1\"]}"}}"#;
+
+        assert_eq!(
+            translate_event(posted).unwrap(),
+            Some(Event {
+                kind: EventKind::Posted,
+                post: Post {
+                    channel_id: "c1".to_owned(),
+                    user_id: "u1".to_owned(),
+                    message: "hi".to_owned(),
+                    file_ids: vec!["f1".to_owned()],
+                    kind: String::new(),
+                },
+            })
+        );
+
+        let joined = r#"{"event": "posted", "data": {"post":
+            "{\"channel_id\":\"c1\",\"user_id\":\"u1\",\"message\":\"u1 joined\",\"type\":\"system_join_channel\"}"}}"#;
+        assert_eq!(translate_event(joined).unwrap(), None);
+
+        let typing = r#"{"event": "typing", "data": {"parent_id": ""}}"#;
+        assert_eq!(translate_event(typing).unwrap(), None);
+
+        let reply = r#"{"status": "OK", "seq_reply": 1}"#;
+        assert_eq!(translate_event(reply).unwrap(), None);
+    }
+}
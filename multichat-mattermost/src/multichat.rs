@@ -0,0 +1,237 @@
+use multichat_client::{MaybeTlsClient, Update, UpdateKind};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+
+use crate::markdown;
+use crate::mattermost::{self, Event as MattermostEvent, EventKind, Mattermost};
+
+// Names of Mattermost users are looked up again after this long, in case they changed.
+const NAME_TTL: Duration = Duration::from_secs(60 * 60);
+// Files a post can have.
+const MAX_FILES: usize = 5;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Mattermost(#[from] mattermost::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    mattermost: &Mattermost,
+    me: &str,
+    channel_to_group: &HashMap<String, u32>,
+    group_to_channel: &HashMap<u32, String>,
+    mut mattermost_receiver: Receiver<MattermostEvent>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), Error> {
+    let mut users = HashMap::<(String, u32), MattermostUser>::new();
+    let mut names = Names::default();
+    let mut groups = group_to_channel
+        .keys()
+        .map(|gid| (*gid, HashMap::new()))
+        .collect::<HashMap<_, HashMap<u32, MultichatUser>>>();
+
+    let mut owned = HashSet::new();
+
+    loop {
+        let event = tokio::select! {
+            event = mattermost_receiver.recv() => match event {
+                Some(event) => Event::Mattermost(event),
+                None => break,
+            },
+            update = client.read_update() => Event::Multichat(update?),
+            _ = shutdown.changed() => break,
+        };
+
+        match event {
+            Event::Mattermost(MattermostEvent { kind, post }) => {
+                // Posts bridged from Multichat.
+                if post.user_id == me {
+                    continue;
+                }
+
+                let gid = match channel_to_group.get(&post.channel_id) {
+                    Some(gid) => *gid,
+                    None => {
+                        tracing::warn!(channel = %post.channel_id, "Mattermost channel not found");
+                        continue;
+                    }
+                };
+
+                let text = markdown::to_markup(&post.message);
+
+                // There is no editing or deleting in the protocol, they are sent as new messages.
+                let text = match kind {
+                    EventKind::Posted => text,
+                    EventKind::Edited => format!("(edited) {}", text),
+                    EventKind::Deleted => format!("(deleted) {}", text),
+                };
+
+                let mut attachments = Vec::new();
+                if kind == EventKind::Posted {
+                    for file_id in &post.file_ids {
+                        match mattermost.download(file_id).await {
+                            Ok(data) => attachments.push(Cow::Owned(data)),
+                            Err(err) => {
+                                tracing::warn!(%file_id, "Error downloading file: {}", err)
+                            }
+                        }
+                    }
+                }
+
+                let name = names.get(mattermost, &post.user_id).await?;
+                let key = (post.user_id, gid);
+                let uid = mattermost_user(&mut client, &mut users, &mut owned, key, name).await?;
+
+                client.send_message(gid, uid, &text, &attachments).await?;
+            }
+            Event::Multichat(Update {
+                kind: UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup,
+                ..
+            }) => continue,
+            Event::Multichat(update) => {
+                let group = groups.get_mut(&update.gid).unwrap();
+                let channel = &group_to_channel[&update.gid];
+
+                match update.kind {
+                    UpdateKind::InitUser { uid, name } => {
+                        let owned = owned.remove(&(update.gid, uid));
+                        group.insert(uid, MultichatUser { name, owned });
+                    }
+                    UpdateKind::DestroyUser { uid } => {
+                        group.remove(&uid);
+                    }
+                    UpdateKind::Rename { uid, name } => {
+                        group.get_mut(&uid).unwrap().name = name;
+                    }
+                    UpdateKind::Message { uid, message } => {
+                        let user = &group[&uid];
+                        if user.owned {
+                            for attachment in message.attachments {
+                                client.ignore_attachment(attachment.id).await?;
+                            }
+
+                            continue;
+                        }
+
+                        let mut attachments = Vec::with_capacity(message.attachments.len());
+                        for attachment in message.attachments {
+                            attachments.push(client.download_attachment(attachment.id).await?);
+                        }
+
+                        // The protocol doesn't name attachments, Mattermost tells what they are
+                        // from their contents.
+                        let mut file_ids = Vec::with_capacity(attachments.len());
+                        for (idx, data) in attachments.into_iter().enumerate() {
+                            let name = format!("{}-{}", user.name, idx + 1);
+                            file_ids.push(mattermost.upload(channel, &name, data).await?);
+                        }
+
+                        let text = format!(
+                            "**{}**: {}",
+                            markdown::from_markup(&user.name),
+                            markdown::from_markup(&message.text)
+                        );
+
+                        let mut chunks = file_ids.chunks(MAX_FILES);
+                        mattermost
+                            .create_post(channel, &text, chunks.next().unwrap_or_default())
+                            .await?;
+
+                        for chunk in chunks {
+                            mattermost.create_post(channel, "", chunk).await?;
+                        }
+                    }
+                    UpdateKind::InitGroup { .. }
+                    | UpdateKind::DestroyGroup
+                    | UpdateKind::StartTyping { .. }
+                    | UpdateKind::StopTyping { .. } => {}
+                }
+            }
+        }
+    }
+
+    for ((_, gid), user) in users {
+        client.destroy_user(gid, user.uid).await?;
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
+
+async fn mattermost_user(
+    client: &mut MaybeTlsClient,
+    users: &mut HashMap<(String, u32), MattermostUser>,
+    owned: &mut HashSet<(u32, u32)>,
+    key: (String, u32),
+    name: String,
+) -> Result<u32, Error> {
+    let gid = key.1;
+    let user = match users.entry(key) {
+        Entry::Occupied(entry) => {
+            let user = entry.into_mut();
+            if user.name != name {
+                client.rename_user(gid, user.uid, &name).await?;
+                user.name = name;
+            }
+
+            user
+        }
+        Entry::Vacant(entry) => {
+            let uid = client.init_user(gid, &name).await?;
+            owned.insert((gid, uid));
+
+            entry.insert(MattermostUser { name, uid })
+        }
+    };
+
+    Ok(user.uid)
+}
+
+/// Names of Mattermost users by their IDs, which posts refer to them by.
+#[derive(Default)]
+struct Names {
+    names: HashMap<String, (Instant, String)>,
+}
+
+impl Names {
+    async fn get(&mut self, mattermost: &Mattermost, id: &str) -> Result<String, Error> {
+        if let Some((fetched, name)) = self.names.get(id) {
+            if fetched.elapsed() < NAME_TTL {
+                return Ok(name.clone());
+            }
+        }
+
+        let user = mattermost.user(id).await?;
+        let name = user.display_name().to_owned();
+        self.names
+            .insert(id.to_owned(), (Instant::now(), name.clone()));
+
+        Ok(name)
+    }
+}
+
+enum Event {
+    Mattermost(MattermostEvent),
+    Multichat(Update),
+}
+
+struct MattermostUser {
+    name: String,
+    uid: u32,
+}
+
+struct MultichatUser {
+    name: String,
+    owned: bool,
+}
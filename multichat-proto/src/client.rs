@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 
 use crate::access_token::AccessToken;
+use crate::compression::Compression;
+use crate::resume_token::ResumeToken;
 
 /// Message sent by client to server.
 #[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
@@ -45,4 +47,8 @@ pub enum ClientMessage<'a, 'b> {
 #[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct AuthRequest {
     pub access_token: AccessToken,
+    /// Token of a previous session to resume.
+    pub resume: Option<ResumeToken>,
+    /// Compression the client would like to use.
+    pub compression: Option<Compression>,
 }
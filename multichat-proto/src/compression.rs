@@ -0,0 +1,111 @@
+use async_compression::tokio::bufread::DeflateDecoder;
+use async_compression::tokio::write::DeflateEncoder;
+use serde::{Deserialize, Serialize};
+use std::io::Error;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncWrite, BufReader, ReadBuf};
+
+/// Compression of a connection, applied to everything sent after a successful authentication.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    Deflate,
+}
+
+/// Reader decompressing data if compression is enabled.
+pub enum CompressedReader<R> {
+    Plain(R),
+    Deflate(BufReader<DeflateDecoder<R>>),
+}
+
+impl<R: AsyncBufRead + Unpin> CompressedReader<R> {
+    /// Wraps a reader, any data it has already buffered is treated as compressed.
+    pub fn new(reader: R, compression: Option<Compression>) -> Self {
+        match compression {
+            Some(Compression::Deflate) => {
+                Self::Deflate(BufReader::new(DeflateDecoder::new(reader)))
+            }
+            None => Self::Plain(reader),
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for CompressedReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            Self::Plain(reader) => Pin::new(reader).poll_read(cx, buf),
+            Self::Deflate(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Writer compressing data if compression is enabled.
+///
+/// Flushing ends the current compressed block, so that the peer can decompress everything written so far.
+pub enum CompressedWriter<W> {
+    Plain(W),
+    Deflate(DeflateEncoder<W>),
+}
+
+impl<W: AsyncWrite + Unpin> CompressedWriter<W> {
+    pub fn new(writer: W, compression: Option<Compression>) -> Self {
+        match compression {
+            Some(Compression::Deflate) => Self::Deflate(DeflateEncoder::new(writer)),
+            None => Self::Plain(writer),
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CompressedWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        match self.get_mut() {
+            Self::Plain(writer) => Pin::new(writer).poll_write(cx, buf),
+            Self::Deflate(writer) => Pin::new(writer).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            Self::Plain(writer) => Pin::new(writer).poll_flush(cx),
+            Self::Deflate(writer) => Pin::new(writer).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        match self.get_mut() {
+            Self::Plain(writer) => Pin::new(writer).poll_shutdown(cx),
+            Self::Deflate(writer) => Pin::new(writer).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use tokio::io;
+
+    #[tokio::test]
+    async fn deflate_flush() {
+        let (client, server) = io::duplex(1024);
+        let config = Config::default();
+
+        let mut writer = CompressedWriter::new(client, Some(Compression::Deflate));
+        let mut reader =
+            CompressedReader::new(io::BufReader::new(server), Some(Compression::Deflate));
+
+        // Every message must be readable as soon as it's flushed.
+        for i in 0..100u32 {
+            config.write(&mut writer, &i).await.unwrap();
+            assert_eq!(config.read::<u32>(&mut reader).await.unwrap(), i);
+        }
+    }
+}
@@ -1,13 +1,23 @@
 //! Crate containing definitions and utilities for working with the Multichat protocol - a small and efficient
 //! protocol used for bridging chat communication from various sources over the internet.
+
+// The tests compare booleans with assert_eq!, newer clippy prefers assert!.
+#![cfg_attr(test, allow(clippy::bool_assert_comparison))]
+
 mod access_token;
 mod client;
+mod compression;
+mod resume_token;
 mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
 mod version;
 mod wire;
 
 pub use access_token::AccessToken;
 pub use client::{AuthRequest, ClientMessage};
+pub use compression::{CompressedReader, CompressedWriter, Compression};
+pub use resume_token::ResumeToken;
 pub use server::{Attachment, AuthResponse, ServerMessage};
 pub use version::Version;
 pub use wire::{read, write, Config};
@@ -0,0 +1,7 @@
+use serde::{Deserialize, Serialize};
+
+/// A token allowing a client to resume its session after reconnecting.
+///
+/// Issued by the server on every successful authentication.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResumeToken(pub [u8; 16]);
@@ -2,6 +2,9 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::time::Duration;
 
+use crate::compression::Compression;
+use crate::resume_token::ResumeToken;
+
 /// Message sent by server to client.
 #[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
 pub enum ServerMessage<'a> {
@@ -42,6 +45,8 @@ pub enum ServerMessage<'a> {
     Attachment { data: Cow<'a, [u8]> },
     /// Ping, used to keep the connection alive.
     Ping,
+    /// The server is terminating the connection.
+    Goodbye { reason: Cow<'a, str> },
 }
 
 /// Attachment to a message.
@@ -61,6 +66,12 @@ pub enum AuthResponse {
     Success {
         ping_interval: Duration,
         ping_timeout: Duration,
+        /// Token for resuming this session after reconnecting.
+        resume_token: ResumeToken,
+        /// Whether the session requested in the auth request was resumed.
+        resumed: bool,
+        /// Compression used from now on, if any.
+        compression: Option<Compression>,
     },
     /// The client could not be authenticated.
     Failed,
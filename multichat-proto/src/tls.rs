@@ -0,0 +1,19 @@
+//! Choice of the rustls crypto provider for the server, clients and bridges.
+//!
+//! Dependencies may enable both ring and aws-lc-rs, in which case rustls can't pick a provider by itself
+//! and building a config without one panics.
+
+use rustls::crypto::{aws_lc_rs, CryptoProvider};
+use std::sync::Arc;
+
+/// Returns the crypto provider TLS configs should be built with.
+pub fn provider() -> Arc<CryptoProvider> {
+    Arc::new(aws_lc_rs::default_provider())
+}
+
+/// Installs [`provider`] as the process default, for libraries building TLS configs of their own.
+///
+/// Does nothing if a default is already installed.
+pub fn install_default_provider() {
+    let _ = aws_lc_rs::default_provider().install_default();
+}
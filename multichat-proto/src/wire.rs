@@ -1,3 +1,5 @@
+use crate::server::ServerMessage;
+
 use bincode::{DefaultOptions, Options};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -54,10 +56,50 @@ impl Config {
         stream: &mut (impl AsyncWrite + Unpin),
         data: &impl Serialize,
     ) -> Result<(), Error> {
-        let data = options().serialize(data).map_err(|err| match *err {
-            bincode::ErrorKind::Io(err) => err,
-            err => Error::new(ErrorKind::InvalidData, err),
+        self.write_unflushed(stream, data).await?;
+        stream.flush().await?;
+
+        Ok(())
+    }
+
+    /// Writes a [`ServerMessage::Attachment`] to a stream without flushing it.
+    ///
+    /// Equivalent to writing the message with [`Config::write_unflushed`], but the data is written
+    /// directly to the stream instead of being copied into an intermediate buffer first.
+    pub async fn write_attachment(
+        &self,
+        stream: &mut (impl AsyncWrite + Unpin),
+        data: &[u8],
+    ) -> Result<(), Error> {
+        // Serialize the message without data and patch in the real length, which is encoded last.
+        let mut header = serialize(&ServerMessage::Attachment {
+            data: (&[][..]).into(),
         })?;
+        header.truncate(header.len() - serialize(&0u64)?.len());
+        header.extend(serialize(&(data.len() as u64))?);
+
+        let size = header.len() + data.len();
+        if size > self.max_size {
+            return Err(outgoing_limit());
+        }
+
+        let length = size.try_into().map_err(|_| outgoing_limit())?;
+        stream.write_u32(length).await?;
+        stream.write_all(&header).await?;
+        stream.write_all(data).await?;
+
+        Ok(())
+    }
+
+    /// Writes a message to a stream without flushing it.
+    ///
+    /// This allows batching multiple messages into a single flush, the stream has to be flushed manually afterwards.
+    pub async fn write_unflushed(
+        &self,
+        stream: &mut (impl AsyncWrite + Unpin),
+        data: &impl Serialize,
+    ) -> Result<(), Error> {
+        let data = serialize(data)?;
 
         if data.len() > self.max_size {
             return Err(outgoing_limit());
@@ -67,8 +109,6 @@ impl Config {
         stream.write_u32(length).await?;
         stream.write_all(&data).await?;
 
-        stream.flush().await?;
-
         Ok(())
     }
 }
@@ -104,6 +144,13 @@ fn outgoing_limit() -> Error {
     Error::new(ErrorKind::InvalidInput, "Outgoing data size exceeded limit")
 }
 
+fn serialize(data: &impl Serialize) -> Result<Vec<u8>, Error> {
+    options().serialize(data).map_err(|err| match *err {
+        bincode::ErrorKind::Io(err) => err,
+        err => Error::new(ErrorKind::InvalidData, err),
+    })
+}
+
 fn options() -> impl Options {
     DefaultOptions::new()
 }
@@ -112,7 +159,9 @@ fn options() -> impl Options {
 mod tests {
     use super::*;
     use crate::client::ClientMessage;
-    use crate::server::{AuthResponse, ServerMessage};
+    use crate::compression::Compression;
+    use crate::resume_token::ResumeToken;
+    use crate::server::AuthResponse;
 
     use std::fmt::Debug;
     use std::time::Duration;
@@ -134,6 +183,9 @@ mod tests {
         roundtrip_serialize(&AuthResponse::Success {
             ping_interval: Duration::from_secs(10),
             ping_timeout: Duration::from_secs(5),
+            resume_token: ResumeToken([7; 16]),
+            resumed: false,
+            compression: Some(Compression::Deflate),
         })
         .await;
 
@@ -154,6 +206,24 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn attachment_write() {
+        let config = Config::default();
+
+        for len in [0, 1, 300, 40000] {
+            let data = vec![7; len];
+
+            let mut buffer = Vec::new();
+            config.write_attachment(&mut buffer, &data).await.unwrap();
+
+            let mut buffer = buffer.as_slice();
+            let message: ServerMessage = config.read(&mut buffer).await.unwrap();
+
+            assert_eq!(buffer.len(), 0);
+            assert_eq!(message, ServerMessage::Attachment { data: data.into() });
+        }
+    }
+
     #[tokio::test]
     async fn length_write() {
         let config = *Config::default().max_size(10);
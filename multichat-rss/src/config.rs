@@ -0,0 +1,70 @@
+use multichat_client::proto::AccessToken;
+use serde::{Deserialize, Deserializer};
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub state: PathBuf,
+    #[serde(
+        default = "default_poll_every",
+        deserialize_with = "deserialize_duration"
+    )]
+    pub poll_every: Duration,
+    pub multichat: Multichat,
+    pub feeds: Vec<Feed>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Feed {
+    pub url: Url,
+    pub multichat_groups: Vec<String>,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub poll_every: Option<Duration>,
+}
+
+fn default_poll_every() -> Duration {
+    Duration::from_secs(15 * 60)
+}
+
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let duration = String::deserialize(deserializer)?;
+
+    humantime::parse_duration(&duration).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_duration(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        let config = toml::from_str::<Config>(config).unwrap();
+
+        assert_eq!(config.poll_every, default_poll_every());
+        assert_eq!(config.feeds[0].poll_every, None);
+    }
+}
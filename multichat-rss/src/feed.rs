@@ -0,0 +1,204 @@
+//! RSS, Atom and JSON feeds, reduced to what's posted of them.
+
+use feed_rs::model::{self, Link, Text};
+use feed_rs::parser::{self, ParseFeedError};
+use thiserror::Error;
+
+// Elements of HTML which are on lines of their own.
+const BLOCKS: &str = "p br div li ul ol tr td blockquote pre h1 h2 h3 h4 h5 h6 hr img";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Parse(#[from] ParseFeedError),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Feed {
+    pub title: String,
+    /// Newest first, as feeds list them.
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Identifies the entry when the feed is polled again.
+    pub id: String,
+    pub title: String,
+    pub link: Option<String>,
+    /// Plain text, without any HTML.
+    pub summary: String,
+}
+
+/// Parses an RSS, Atom or JSON feed, as it was received so that its declared encoding is used.
+pub fn parse(document: &[u8]) -> Result<Feed, Error> {
+    // Entries without an ID are known by their link or title, as they always were. Those
+    // without either are skipped, rather than getting a new random ID every time.
+    let feed = parser::Builder::new()
+        .id_generator(|links: &[Link], title: &Option<Text>, _: Option<&str>| {
+            links
+                .first()
+                .map(|link| link.href.clone())
+                .or_else(|| title.as_ref().map(|title| title.content.trim().to_owned()))
+                .unwrap_or_default()
+        })
+        .sanitize_content(false)
+        .build()
+        .parse(document)?;
+
+    let entries = feed
+        .entries
+        .into_iter()
+        .filter(|entry| !entry.id.is_empty())
+        .map(|entry| {
+            // Links to the entry itself have no relation or the alternate one.
+            let link = entry
+                .links
+                .iter()
+                .find(|link| link.rel.as_deref().is_none_or(|rel| rel == "alternate"))
+                .map(|link| link.href.trim().to_owned());
+
+            let summary = match (entry.summary, entry.content) {
+                (Some(summary), _) => summary.content,
+                (None, Some(content)) => content.body.unwrap_or_default(),
+                (None, None) => String::new(),
+            };
+
+            Entry {
+                id: entry.id.trim().to_owned(),
+                title: title_of(entry.title),
+                link,
+                summary: plain_text(&summary),
+            }
+        })
+        .collect();
+
+    Ok(Feed {
+        title: title_of(feed.title),
+        entries,
+    })
+}
+
+fn title_of(title: Option<model::Text>) -> String {
+    title
+        .map(|title| plain_text(&title.content))
+        .unwrap_or_default()
+}
+
+/// Text of HTML, with tags left out, references replaced and whitespace collapsed.
+pub fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        // Anything else is a less-than sign in sloppy HTML.
+        if !rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!') {
+            text.push('<');
+            continue;
+        }
+
+        let end = rest.find('>').map_or(rest.len(), |end| end + 1);
+        let name = rest[..end]
+            .trim_start_matches('/')
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap()
+            .to_ascii_lowercase();
+
+        // Blocks separate words, inline elements may be in the middle of one.
+        if BLOCKS.split(' ').any(|block| block == name) {
+            text.push(' ');
+        }
+
+        rest = &rest[end..];
+    }
+
+    text.push_str(rest);
+
+    let text = html_escape::decode_html_entities(&text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rss() {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+              <channel>
+                <title>Example &amp; co</title>
+                <item>
+                  <title>Second</title>
+                  <link>https://example.com/2</link>
+                  <guid isPermaLink="false">2</guid>
+                  <description>&lt;p&gt;Some &lt;b&gt;bold&lt;/b&gt; text&lt;/p&gt;&lt;p&gt;More&lt;/p&gt;</description>
+                </item>
+                <item>
+                  <title>First</title>
+                  <link>https://example.com/1</link>
+                  <content:encoded><![CDATA[<p>a < b</p>]]></content:encoded>
+                </item>
+              </channel>
+            </rss>"#;
+
+        let feed = parse(document.as_bytes()).unwrap();
+        assert_eq!(feed.title, "Example & co");
+        assert_eq!(
+            feed.entries,
+            [
+                Entry {
+                    id: "2".to_owned(),
+                    title: "Second".to_owned(),
+                    link: Some("https://example.com/2".to_owned()),
+                    summary: "Some bold text More".to_owned(),
+                },
+                Entry {
+                    id: "https://example.com/1".to_owned(),
+                    title: "First".to_owned(),
+                    link: Some("https://example.com/1".to_owned()),
+                    summary: "a < b".to_owned(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_atom() {
+        let document = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <title type="html">Example</title>
+              <link rel="self" href="https://example.com/feed.xml"/>
+              <entry>
+                <id>urn:uuid:1</id>
+                <title>Hello</title>
+                <link rel="edit" href="https://example.com/edit/1"/>
+                <link href="https://example.com/1"/>
+                <content type="xhtml"><div xmlns="http://www.w3.org/1999/xhtml">Hi <em>there</em></div></content>
+              </entry>
+            </feed>"#;
+
+        let feed = parse(document.as_bytes()).unwrap();
+        assert_eq!(feed.title, "Example");
+        assert_eq!(
+            feed.entries,
+            [Entry {
+                id: "urn:uuid:1".to_owned(),
+                title: "Hello".to_owned(),
+                link: Some("https://example.com/1".to_owned()),
+                summary: "Hi there".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(matches!(
+            parse(b"<html></html>"),
+            Err(Error::Parse(ParseFeedError::ParseError(_)))
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+mod config;
+mod feed;
+mod multichat;
+mod poll;
+mod state;
+mod tls;
+
+use clap::Parser;
+use config::Config;
+use multichat::Source;
+use multichat_client::proto::Config as ProtoConfig;
+use multichat_client::ClientBuilder;
+use state::State;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio::fs;
+use tokio::sync::{mpsc, watch};
+use tracing::subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(help = "Path to config file")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().without_time().with_target(false));
+
+    subscriber::set_global_default(registry).unwrap();
+
+    let args = Args::parse();
+
+    tracing::info!("Reading config from {}", args.config.display());
+
+    let config = match fs::read_to_string(&args.config).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error reading config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = match toml::from_str::<Config>(&config) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error parsing config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut urls = HashSet::new();
+    for feed in &config.feeds {
+        if !urls.insert(&feed.url) {
+            tracing::error!("Feed {} is configured more than once", feed.url);
+            return ExitCode::FAILURE;
+        }
+
+        if feed.poll_every.unwrap_or(config.poll_every).is_zero() {
+            tracing::error!("Feed {} would be polled continuously", feed.url);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let state = match State::load(&config.state).await {
+        Ok(state) => state,
+        Err(err) => {
+            tracing::error!("Error loading state: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let connector = match config.multichat.certificate {
+        Some(certificate) => match tls::configure(&certificate).await {
+            Ok(connector) => Some(connector),
+            Err(err) => {
+                tracing::error!("Error configuring TLS: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(512 * 1024 * 1024); // 512 MiB
+
+    let mut client = match ClientBuilder::maybe_tls(connector)
+        .config(proto_config)
+        .connect(&config.multichat.server, config.multichat.access_token)
+        .await
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("Error connecting to multichat: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing::info!("Connected to Multichat");
+
+    // Feeds may share groups, which are only joined once.
+    let mut joined = HashMap::new();
+    let mut sources = Vec::new();
+    let mut polls = Vec::new();
+
+    for feed in config.feeds {
+        let mut gids = Vec::new();
+
+        for group in &feed.multichat_groups {
+            let gid = match joined.get(group) {
+                Some(gid) => *gid,
+                None => match client.join_group(group).await {
+                    Ok(gid) => *joined.entry(group.clone()).or_insert(gid),
+                    Err(err) => {
+                        tracing::error!("Error joining group: {}", err);
+                        return ExitCode::FAILURE;
+                    }
+                },
+            };
+
+            gids.push(gid);
+        }
+
+        polls.push((
+            feed.url.clone(),
+            feed.poll_every.unwrap_or(config.poll_every),
+        ));
+        sources.push(Source {
+            url: feed.url,
+            name: feed.name,
+            gids,
+        });
+    }
+
+    let shutdown_signal = match multichat_client::shutdown_signal() {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("Error handling signals: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let http = reqwest::Client::builder()
+        .user_agent(concat!("multichat-rss/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap();
+
+    let (sender, receiver) = mpsc::channel(polls.len().max(1));
+    let (shutdown_sender, shutdown) = watch::channel(());
+
+    let pollers: Vec<_> = polls
+        .into_iter()
+        .enumerate()
+        .map(|(index, (url, every))| {
+            tokio::spawn(poll::run(http.clone(), index, url, every, sender.clone()))
+        })
+        .collect();
+
+    let mut multichat = tokio::spawn(async move {
+        multichat::run(client, &sources, state, &config.state, receiver, shutdown).await
+    });
+
+    let result = tokio::select! {
+        result = &mut multichat => Some(result.unwrap()),
+        _ = shutdown_signal => None,
+    };
+
+    tracing::info!("Shutting down");
+
+    let _ = shutdown_sender.send(());
+    let result = match result {
+        Some(result) => result,
+        None => multichat.await.unwrap(),
+    };
+
+    for poller in pollers {
+        poller.abort();
+    }
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use multichat_client::{MaybeTlsClient, UpdateKind};
+use std::io;
+use std::path::Path;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+use url::Url;
+
+use crate::feed::{Entry, Feed};
+use crate::state::{self, State};
+
+// Summaries are cut so that an entry doesn't take over the chat.
+const MAX_SUMMARY: usize = 400;
+
+/// Feed as configured, with the groups it's posted to joined.
+pub struct Source {
+    pub url: Url,
+    pub name: Option<String>,
+    pub gids: Vec<u32>,
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    sources: &[Source],
+    mut state: State,
+    state_path: &Path,
+    mut receiver: Receiver<(usize, Feed)>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), state::Error> {
+    loop {
+        tokio::select! {
+            polled = receiver.recv() => {
+                let (index, feed) = match polled {
+                    Some(polled) => polled,
+                    None => break,
+                };
+
+                let source = &sources[index];
+                let new = state.update(source.url.as_str(), &feed.entries);
+
+                if !new.is_empty() {
+                    let name = match (&source.name, feed.title.is_empty()) {
+                        (Some(name), _) => name.as_str(),
+                        (None, false) => feed.title.as_str(),
+                        (None, true) => source.url.host_str().unwrap_or("feed"),
+                    };
+
+                    post(&mut client, source, name, &new).await?;
+                    tracing::info!(url = %source.url, "Posted {} entries", new.len());
+                }
+
+                // Saved after posting, entries which weren't posted are posted after a restart.
+                state.save(state_path).await?;
+            }
+            update = client.read_update() => {
+                // Nothing is read from groups, attachments just have to be answered.
+                if let UpdateKind::Message { message, .. } = update?.kind {
+                    for attachment in &message.attachments {
+                        client.ignore_attachment(attachment.id).await?;
+                    }
+                }
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
+
+// The feed is only in the groups for as long as it takes to post its entries.
+async fn post(
+    client: &mut MaybeTlsClient,
+    source: &Source,
+    name: &str,
+    entries: &[&Entry],
+) -> Result<(), io::Error> {
+    for gid in &source.gids {
+        let uid = client.init_user(*gid, name).await?;
+
+        // Oldest first, feeds list the newest first.
+        for entry in entries.iter().rev() {
+            client.send_message(*gid, uid, &text(entry), &[]).await?;
+        }
+
+        client.destroy_user(*gid, uid).await?;
+    }
+
+    Ok(())
+}
+
+fn text(entry: &Entry) -> String {
+    let mut lines = Vec::new();
+
+    if !entry.title.is_empty() {
+        lines.push(format!("*{}*", entry.title));
+    }
+
+    if let Some(link) = &entry.link {
+        lines.push(link.clone());
+    }
+
+    if !entry.summary.is_empty() {
+        let mut summary: String = entry.summary.chars().take(MAX_SUMMARY).collect();
+        if summary.len() < entry.summary.len() {
+            summary.push('…');
+        }
+
+        lines.push(summary);
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_entries() {
+        let mut entry = Entry {
+            id: "1".to_owned(),
+            title: "Release".to_owned(),
+            link: Some("https://example.com/1".to_owned()),
+            summary: "x".repeat(MAX_SUMMARY + 1),
+        };
+
+        let expected = format!(
+            "*Release*\nhttps://example.com/1\n{}…",
+            "x".repeat(MAX_SUMMARY)
+        );
+        assert_eq!(text(&entry), expected);
+
+        entry.title.clear();
+        entry.summary = "Short".to_owned();
+        assert_eq!(text(&entry), "https://example.com/1\nShort");
+    }
+}
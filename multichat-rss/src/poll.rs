@@ -0,0 +1,55 @@
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{self, MissedTickBehavior};
+use url::Url;
+
+use crate::feed::{self, Feed};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Feed(#[from] feed::Error),
+}
+
+/// Polls a feed until the receiver is gone, sending it with its index every time it's fetched.
+pub async fn run(
+    http: reqwest::Client,
+    index: usize,
+    url: Url,
+    every: Duration,
+    sender: Sender<(usize, Feed)>,
+) {
+    let mut interval = time::interval(every);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        // Feeds are often down for a while, they're polled again later.
+        let feed = match fetch(&http, url.clone()).await {
+            Ok(feed) => feed,
+            Err(err) => {
+                tracing::warn!(%url, "Error polling feed: {}", err);
+                continue;
+            }
+        };
+
+        if sender.send((index, feed)).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn fetch(http: &reqwest::Client, url: Url) -> Result<Feed, Error> {
+    let document = http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    Ok(feed::parse(&document)?)
+}
@@ -0,0 +1,94 @@
+//! Entries of feeds which were already seen, kept on disk across restarts.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+use tokio::fs;
+
+use crate::feed::Entry;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// IDs of entries by the URL of their feed.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct State(BTreeMap<String, BTreeSet<String>>);
+
+impl State {
+    /// Loads the state, which is empty if it wasn't saved yet.
+    pub async fn load(path: &Path) -> Result<Self, Error> {
+        match fs::read(path).await {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Writes the state, replacing the old one at once so that it's never left half written.
+    pub async fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        fs::write(&temporary, serde_json::to_vec(self)?).await?;
+        fs::rename(&temporary, path).await?;
+
+        Ok(())
+    }
+
+    /// Remembers the entries a feed has now, returning those which weren't seen before.
+    ///
+    /// Only the entries still in the feed are remembered, so that the state doesn't grow forever.
+    /// None are new the first time a feed is seen, groups aren't flooded with its history.
+    pub fn update<'a>(&mut self, url: &str, entries: &'a [Entry]) -> Vec<&'a Entry> {
+        // An empty feed is more likely broken than emptied, forgetting everything would repost it.
+        if entries.is_empty() {
+            return Vec::new();
+        }
+
+        let ids = entries.iter().map(|entry| entry.id.clone()).collect();
+
+        match self.0.insert(url.to_owned(), ids) {
+            Some(seen) => entries
+                .iter()
+                .filter(|entry| !seen.contains(&entry.id))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str) -> Entry {
+        Entry {
+            id: id.to_owned(),
+            title: String::new(),
+            link: None,
+            summary: String::new(),
+        }
+    }
+
+    #[test]
+    fn finds_new_entries() {
+        let mut state = State::default();
+        let url = "https://example.com/feed.xml";
+
+        assert!(state.update(url, &[entry("1")]).is_empty());
+        assert!(state.update(url, &[]).is_empty());
+
+        let entries = [entry("3"), entry("2"), entry("1")];
+        let new = state.update(url, &entries);
+        assert_eq!(new, [&entries[0], &entries[1]]);
+
+        assert!(state.update(url, &entries[..2]).is_empty());
+    }
+}
@@ -0,0 +1,277 @@
+use crate::config::{
+    Client, DuplicateNames, GroupNames, GroupSettings, Idle, Limits, Moderation, SlowConsumers,
+    Tcp, Webhook,
+};
+use crate::server::{self, Settings};
+use crate::tls::{Acceptor, DefaultAcceptor, OptionalTlsAcceptor};
+
+use multichat_proto::Config;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// Configurable server builder.
+pub struct ServerBuilder<T> {
+    acceptor: T,
+    listeners: Vec<TcpListener>,
+    websocket_listeners: Vec<TcpListener>,
+    clients: Vec<Client>,
+    config: Config,
+    settings: Settings,
+}
+
+impl<T: Acceptor> ServerBuilder<T> {
+    /// Adds a listener to accept connections from.
+    pub fn listener(&mut self, value: TcpListener) -> &mut Self {
+        self.listeners.push(value);
+        self
+    }
+
+    /// Adds a listener to accept WebSocket connections from, using the same acceptor beneath WebSocket.
+    pub fn websocket_listener(&mut self, value: TcpListener) -> &mut Self {
+        self.websocket_listeners.push(value);
+        self
+    }
+
+    /// Adds a client allowed to connect with its access token.
+    pub fn client(&mut self, value: Client) -> &mut Self {
+        self.clients.push(value);
+        self
+    }
+
+    /// Sets Multichat protocol config.
+    pub fn config(&mut self, value: Config) -> &mut Self {
+        self.config = value;
+        self
+    }
+
+    /// Sets how many updates may be buffered for a connection, 256 by default.
+    pub fn update_buffer(&mut self, value: Option<NonZeroUsize>) -> &mut Self {
+        self.settings.update_buffer = value;
+        self
+    }
+
+    /// Adds settings of groups matching a pattern, the first match applies.
+    pub fn group_settings(&mut self, value: GroupSettings) -> &mut Self {
+        self.settings.group_settings.push(value);
+        self
+    }
+
+    /// Sets how often clients are pinged, 30 seconds by default.
+    pub fn ping_interval(&mut self, value: Option<Duration>) -> &mut Self {
+        self.settings.ping_interval = value;
+        self
+    }
+
+    /// Sets how long a client may take to respond to a ping, 5 seconds by default.
+    pub fn ping_timeout(&mut self, value: Option<Duration>) -> &mut Self {
+        self.settings.ping_timeout = value;
+        self
+    }
+
+    /// Sets how long a client may take to complete the TLS handshake and authenticate, 5 seconds by default.
+    pub fn handshake_timeout(&mut self, value: Option<Duration>) -> &mut Self {
+        self.settings.handshake_timeout = value;
+        self
+    }
+
+    /// Sets how long sessions of disconnected clients are kept for resumption, disabled by default.
+    pub fn resume_window(&mut self, value: Option<Duration>) -> &mut Self {
+        self.settings.resume_window = value;
+        self
+    }
+
+    /// Sets whether clients may enable compression, disabled by default.
+    pub fn compression(&mut self, value: bool) -> &mut Self {
+        self.settings.compression = value;
+        self
+    }
+
+    /// Adds a group that always exists.
+    pub fn persistent_group(&mut self, value: impl Into<String>) -> &mut Self {
+        self.settings.persistent_groups.push(value.into());
+        self
+    }
+
+    /// Sets normalization applied to group names.
+    pub fn group_names(&mut self, value: GroupNames) -> &mut Self {
+        self.settings.group_names = value;
+        self
+    }
+
+    /// Sets handling of users named the same as another user in their group.
+    pub fn duplicate_user_names(&mut self, value: DuplicateNames) -> &mut Self {
+        self.settings.duplicate_user_names = value;
+        self
+    }
+
+    /// Sets limits of groups and connections.
+    pub fn limits(&mut self, value: Limits) -> &mut Self {
+        self.settings.limits = value;
+        self
+    }
+
+    /// Sets when idle connections are disconnected.
+    pub fn idle(&mut self, value: Idle) -> &mut Self {
+        self.settings.idle = value;
+        self
+    }
+
+    /// Sets the default policy for connections falling behind on updates.
+    pub fn slow_consumers(&mut self, value: SlowConsumers) -> &mut Self {
+        self.settings.slow_consumers = value;
+        self
+    }
+
+    /// Sets socket options of accepted connections.
+    pub fn tcp(&mut self, value: Tcp) -> &mut Self {
+        self.settings.tcp = value;
+        self
+    }
+
+    /// Sets path of the Unix socket accepting administrative commands.
+    pub fn admin_socket(&mut self, value: Option<PathBuf>) -> &mut Self {
+        self.settings.admin_socket = value;
+        self
+    }
+
+    /// Sets whether state is logged when the process receives SIGUSR1, disabled by default.
+    ///
+    /// Installs a process-wide signal handler, so it's best left to the application owning the process.
+    pub fn dump_on_signal(&mut self, value: bool) -> &mut Self {
+        self.settings.dump_on_signal = value;
+        self
+    }
+
+    /// Sets filters and hooks applied to sent messages.
+    pub fn moderation(&mut self, value: Moderation) -> &mut Self {
+        self.settings.moderation = value;
+        self
+    }
+
+    /// Adds an endpoint notified about events.
+    pub fn webhook(&mut self, value: Webhook) -> &mut Self {
+        self.settings.webhooks.push(value);
+        self
+    }
+
+    /// Sets a function called once all listeners and sockets are bound, right before connections are accepted.
+    pub fn on_ready(&mut self, value: impl FnOnce() + Send + 'static) -> &mut Self {
+        self.settings.on_ready = Some(Box::new(value));
+        self
+    }
+
+    /// Runs the server until a listener fails.
+    ///
+    /// Connections are accepted on the calling task and handled on spawned tasks.
+    ///
+    /// Returns an error of kind [`ErrorKind::InvalidInput`] if the configuration is not valid.
+    pub async fn run(self) -> Result<(), Error> {
+        let Self {
+            acceptor,
+            listeners,
+            websocket_listeners,
+            clients,
+            config,
+            mut settings,
+        } = self;
+
+        if listeners.is_empty() && websocket_listeners.is_empty() {
+            return Err(invalid("No listeners".to_owned()));
+        }
+
+        let names = &settings.group_names;
+
+        let mut access_tokens = HashMap::new();
+        for mut client in clients {
+            client.groups = client.groups.normalize(names).map_err(|(name, err)| {
+                invalid(format!("Invalid group name {:?}: {}", name, err))
+            })?;
+
+            let access_token = client.access_token;
+            if access_tokens
+                .insert(access_token, Arc::new(client))
+                .is_some()
+            {
+                return Err(invalid(format!("Duplicate access token: {}", access_token)));
+            }
+        }
+
+        let mut persistent_groups = Vec::new();
+        for name in &settings.persistent_groups {
+            let name = names
+                .normalize(name)
+                .map_err(|err| invalid(format!("Invalid group name {:?}: {}", name, err)))?;
+
+            if persistent_groups.contains(&name) {
+                return Err(invalid(format!("Duplicate persistent group: {}", name)));
+            }
+
+            persistent_groups.push(name);
+        }
+
+        for webhook in &mut settings.webhooks {
+            if let Some(groups) = webhook.groups.take() {
+                webhook.groups = Some(groups.normalize(names).map_err(|(name, err)| {
+                    invalid(format!("Invalid group name {:?}: {}", name, err))
+                })?);
+            }
+        }
+
+        settings.persistent_groups = persistent_groups;
+
+        server::run(
+            listeners,
+            websocket_listeners,
+            acceptor,
+            access_tokens,
+            config,
+            settings,
+        )
+        .await
+    }
+}
+
+impl ServerBuilder<DefaultAcceptor> {
+    /// Creates a basic unencrypted server builder.
+    pub fn basic() -> Self {
+        Self::new(DefaultAcceptor)
+    }
+}
+
+impl ServerBuilder<TlsAcceptor> {
+    /// Creates a TLS builder using the provided acceptor.
+    pub fn tls(acceptor: TlsAcceptor) -> Self {
+        Self::new(acceptor)
+    }
+}
+
+impl ServerBuilder<OptionalTlsAcceptor> {
+    /// Creates a builder accepting both TLS and plaintext connections on the same listeners.
+    pub fn optional_tls(acceptor: TlsAcceptor) -> Self {
+        Self::new(OptionalTlsAcceptor(acceptor))
+    }
+}
+
+impl<T: Acceptor> ServerBuilder<T> {
+    /// Creates a builder using a custom acceptor.
+    pub fn new(acceptor: T) -> Self {
+        Self {
+            acceptor,
+            listeners: Vec::new(),
+            websocket_listeners: Vec::new(),
+            clients: Vec::new(),
+            config: Config::default(),
+            settings: Settings::default(),
+        }
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
@@ -1,4 +1,5 @@
 use multichat_proto::AccessToken;
+use regex::Regex;
 use serde::de::{Error, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer};
 use std::collections::HashSet;
@@ -8,34 +9,388 @@ use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::time::Duration;
+use thiserror::Error;
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Config {
     pub listen: SocketAddr,
+    pub websocket_listen: Option<SocketAddr>,
     pub tls: Option<Tls>,
     pub update_buffer: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub group_settings: Vec<GroupSettings>,
     #[serde(deserialize_with = "deserialize_size")]
     pub max_size: usize,
     #[serde(default, deserialize_with = "deserialize_duration")]
     pub ping_interval: Option<Duration>,
     #[serde(default, deserialize_with = "deserialize_duration")]
     pub ping_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub handshake_timeout: Option<Duration>,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub resume_window: Option<Duration>,
+    #[serde(default)]
+    pub compression: bool,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub runtime: Runtime,
+    #[serde(default)]
+    pub persistent_groups: Vec<String>,
+    #[serde(default)]
+    pub group_names: GroupNames,
+    #[serde(default)]
+    pub duplicate_user_names: DuplicateNames,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub idle: Idle,
+    #[serde(default)]
+    pub slow_consumers: SlowConsumers,
+    #[serde(default)]
+    pub tcp: Tcp,
+    pub admin_socket: Option<PathBuf>,
+    #[serde(default)]
+    pub moderation: Moderation,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
     pub clients: Vec<Client>,
 }
 
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Runtime {
+    #[serde(default)]
+    pub flavor: Flavor,
+    /// Number of worker threads of the multi-threaded runtime, defaults to the number of CPU cores.
+    pub worker_threads: Option<NonZeroUsize>,
+    /// Maximum number of threads for blocking operations.
+    pub max_blocking_threads: Option<NonZeroUsize>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flavor {
+    /// Connections are spread over a pool of worker threads.
+    #[default]
+    MultiThread,
+    /// Everything runs on a single thread.
+    CurrentThread,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct GroupNames {
+    #[serde(default)]
+    pub trim: bool,
+    #[serde(default)]
+    pub case_fold: bool,
+    pub max_length: Option<NonZeroUsize>,
+    #[serde(default)]
+    pub charset: Charset,
+}
+
+impl GroupNames {
+    /// Normalizes a group name, returning an error if it is not valid.
+    pub fn normalize(&self, name: &str) -> Result<String, InvalidName> {
+        let name = if self.trim { name.trim() } else { name };
+        let name = if self.case_fold {
+            name.to_lowercase()
+        } else {
+            name.to_owned()
+        };
+
+        if name.is_empty() {
+            return Err(InvalidName::Empty);
+        }
+
+        if let Some(max_length) = self.max_length {
+            if name.chars().count() > max_length.get() {
+                return Err(InvalidName::TooLong);
+            }
+        }
+
+        if !name.chars().all(|c| self.charset.allows(c)) {
+            return Err(InvalidName::Charset);
+        }
+
+        Ok(name)
+    }
+}
+
+/// Settings of groups with a name matching a pattern.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GroupSettings {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    pub update_buffer: Option<NonZeroUsize>,
+    /// Drop messages identical to one sent by the same user within this long.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub dedup_window: Option<Duration>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Limits {
+    pub group_users: Option<NonZeroUsize>,
+    pub group_subscribers: Option<NonZeroUsize>,
+    pub unauthenticated_per_ip: Option<NonZeroUsize>,
+    /// Maximum length of message text in characters.
+    pub message_length: Option<NonZeroUsize>,
+    /// Maximum size of a single attachment.
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    pub attachment_size: Option<usize>,
+    pub attachments_per_message: Option<usize>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Idle {
+    /// Disconnect clients that haven't joined any group for this long.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub without_groups: Option<Duration>,
+    /// Disconnect clients that haven't sent anything but pongs for this long.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub without_activity: Option<Duration>,
+}
+
+/// Handling of subscribers that receive updates slower than they are produced.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct SlowConsumers {
+    /// Drop typing updates superseded by a newer one of the same user.
+    #[serde(default)]
+    pub drop_typing: bool,
+    /// Drop renames superseded by a newer one of the same user.
+    #[serde(default)]
+    pub coalesce_renames: bool,
+    /// How long a subscriber may stay behind before being disconnected.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub grace_period: Option<Duration>,
+}
+
+/// Socket options of accepted connections.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Tcp {
+    /// Disable Nagle's algorithm.
+    #[serde(default)]
+    pub nodelay: bool,
+    /// Idle time before keepalive probes are sent, keepalive is disabled if unset.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub keepalive: Option<Duration>,
+    /// Time between keepalive probes.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub keepalive_interval: Option<Duration>,
+    /// Number of unanswered keepalive probes before the connection is dropped.
+    pub keepalive_retries: Option<u32>,
+    /// Length of the queue of pending connections.
+    pub backlog: Option<u32>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Charset {
+    /// Any character.
+    #[default]
+    Any,
+    /// Any character except control characters.
+    Printable,
+    /// Printable ASCII characters.
+    Ascii,
+    /// ASCII letters, digits, '-' and '_'.
+    Alphanumeric,
+}
+
+impl Charset {
+    fn allows(self, c: char) -> bool {
+        match self {
+            Charset::Any => true,
+            Charset::Printable => !c.is_control(),
+            Charset::Ascii => c.is_ascii_graphic() || c == ' ',
+            Charset::Alphanumeric => c.is_ascii_alphanumeric() || c == '-' || c == '_',
+        }
+    }
+}
+
+/// Handling of users named the same as another user in their group.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateNames {
+    #[default]
+    Allow,
+    /// Disconnect the client.
+    Reject,
+    /// Append the lowest free number, such as "name (2)".
+    Suffix,
+}
+
+impl DuplicateNames {
+    /// Resolves the name of a user, returning `None` if it should be rejected.
+    pub fn resolve(self, name: &str, taken: impl Fn(&str) -> bool) -> Option<String> {
+        if matches!(self, DuplicateNames::Allow) || !taken(name) {
+            return Some(name.to_owned());
+        }
+
+        match self {
+            DuplicateNames::Allow => unreachable!(),
+            DuplicateNames::Reject => None,
+            DuplicateNames::Suffix => (2..)
+                .map(|num| format!("{} ({})", name, num))
+                .find(|name| !taken(name)),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum InvalidName {
+    #[error("Group name is empty")]
+    Empty,
+    #[error("Group name is too long")]
+    TooLong,
+    #[error("Group name contains disallowed characters")]
+    Charset,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Moderation {
+    /// Filters applied to every message in order.
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    /// External hook consulted after all filters have been applied.
+    pub hook: Option<Hook>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Filter {
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Regex,
+    pub action: Action,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Drop the message.
+    Drop,
+    /// Replace matched text with asterisks.
+    Censor,
+    /// Log the message and deliver it unchanged.
+    Flag,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Hook {
+    #[serde(flatten)]
+    pub kind: HookKind,
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// Deliver messages when the hook fails or times out instead of dropping them.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum HookKind {
+    /// Program receiving the message as JSON on standard input, exiting successfully to accept it.
+    Command { command: Vec<String> },
+    /// URL receiving the message as a JSON POST request, responding with a success status to accept it.
+    Http { url: String },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Webhook {
+    pub url: String,
+    pub events: HashSet<Event>,
+    /// Groups whose events are delivered, all groups if not specified.
+    pub groups: Option<Groups>,
+    /// Number of times a failed delivery is retried.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    Message,
+    UserJoined,
+    GroupCreated,
+    GroupDestroyed,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Tls {
     pub certificate: PathBuf,
     pub key: PathBuf,
+    /// Also accept plaintext connections.
+    #[serde(default)]
+    pub allow_plaintext: bool,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct Client {
     pub access_token: AccessToken,
+    /// Name identifying the client in logs.
+    pub name: Option<String>,
     pub groups: Groups,
+    #[serde(default)]
+    pub scope: Scope,
+    #[serde(default)]
+    pub quota: Quota,
+    /// Overrides the server-wide slow consumer handling.
+    pub slow_consumers: Option<SlowConsumers>,
+}
+
+/// Persistent state written by the `export` admin command, using the same syntax as [`Config`].
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Export {
+    #[serde(default)]
+    pub persistent_groups: Vec<String>,
+    #[serde(default)]
+    pub clients: Vec<Client>,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Can only join groups and receive updates.
+    ReadOnly,
+    #[default]
+    ReadWrite,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Quota {
+    /// Maximum number of concurrent connections.
+    pub connections: Option<NonZeroUsize>,
+    /// Maximum number of users owned across all connections.
+    pub users: Option<NonZeroUsize>,
+    /// Maximum number of attachment bytes sent per hour.
+    #[serde(default, deserialize_with = "deserialize_optional_size")]
+    pub attachment_bytes: Option<usize>,
 }
 
 pub enum Groups {
@@ -50,6 +405,18 @@ impl Groups {
             Groups::Some(groups) => groups.contains(group),
         }
     }
+
+    /// Normalizes all contained group names.
+    pub fn normalize(self, names: &GroupNames) -> Result<Self, (String, InvalidName)> {
+        match self {
+            Groups::All => Ok(Groups::All),
+            Groups::Some(groups) => groups
+                .into_iter()
+                .map(|group| names.normalize(&group).map_err(|err| (group, err)))
+                .collect::<Result<_, _>>()
+                .map(Groups::Some),
+        }
+    }
 }
 
 impl<'a> Deserialize<'a> for Groups {
@@ -111,30 +478,38 @@ where
         where
             E: Error,
         {
-            let (size, unit) = value.split_once(char::is_whitespace).ok_or_else(|| {
-                E::custom("size must be a number followed by a unit (e.g. 1 KiB)")
-            })?;
-
-            let size: usize = size.parse().map_err(E::custom)?;
-            let mul = match unit {
-                "B" => 1,
-                "KiB" => 1024,
-                "MiB" => 1024 * 1024,
-                "GiB" => 1024 * 1024 * 1024,
-                _ => return Err(E::custom("unknown unit")),
-            };
-
-            let size = size
-                .checked_mul(mul)
-                .ok_or_else(|| E::custom("size is too large"))?;
-
-            Ok(size)
+            parse_size(value)
         }
     }
 
     deserializer.deserialize_str(SizeVisitor)
 }
 
+fn deserialize_optional_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_size(deserializer).map(Some)
+}
+
+fn parse_size<E: Error>(value: &str) -> Result<usize, E> {
+    let (size, unit) = value
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| E::custom("size must be a number followed by a unit (e.g. 1 KiB)"))?;
+
+    let size: usize = size.parse().map_err(E::custom)?;
+    let mul = match unit {
+        "B" => 1,
+        "KiB" => 1024,
+        "MiB" => 1024 * 1024,
+        "GiB" => 1024 * 1024 * 1024,
+        _ => return Err(E::custom("unknown unit")),
+    };
+
+    size.checked_mul(mul)
+        .ok_or_else(|| E::custom("size is too large"))
+}
+
 fn deserialize_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
 where
     D: Deserializer<'de>,
@@ -169,6 +544,14 @@ where
     deserializer.deserialize_str(DurationVisitor)
 }
 
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    Regex::new(&pattern).map_err(D::Error::custom)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,4 +561,63 @@ mod tests {
         let config = include_str!("../example/config.toml");
         toml::from_str::<Config>(config).unwrap();
     }
+
+    #[test]
+    fn group_name_normalize() {
+        let names = GroupNames {
+            trim: true,
+            case_fold: true,
+            max_length: NonZeroUsize::new(8),
+            charset: Charset::Alphanumeric,
+        };
+
+        assert_eq!(names.normalize(" Fun ").unwrap(), "fun");
+        assert!(matches!(names.normalize("   "), Err(InvalidName::Empty)));
+        assert!(matches!(
+            names.normalize("verylongname"),
+            Err(InvalidName::TooLong)
+        ));
+        assert!(matches!(names.normalize("a b"), Err(InvalidName::Charset)));
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let taken = |name: &str| ["a", "a (2)", "b"].contains(&name);
+
+        assert_eq!(DuplicateNames::Allow.resolve("a", taken).unwrap(), "a");
+        assert_eq!(DuplicateNames::Reject.resolve("c", taken).unwrap(), "c");
+        assert!(DuplicateNames::Reject.resolve("a", taken).is_none());
+        assert_eq!(DuplicateNames::Suffix.resolve("a", taken).unwrap(), "a (3)");
+        assert_eq!(DuplicateNames::Suffix.resolve("b", taken).unwrap(), "b (2)");
+    }
+
+    #[test]
+    fn group_name_default() {
+        let names = GroupNames::default();
+
+        assert_eq!(names.normalize(" Fun ").unwrap(), " Fun ");
+        assert!(matches!(names.normalize(""), Err(InvalidName::Empty)));
+    }
+
+    #[test]
+    fn moderation_parses() {
+        let moderation: Moderation = toml::from_str(
+            r#"
+            [[filters]]
+            pattern = "(?i)spam"
+            action = "censor"
+
+            [hook]
+            url = "http://127.0.0.1:8080/moderate"
+            timeout = "2s"
+            "#,
+        )
+        .unwrap();
+
+        assert!(moderation.filters[0].pattern.is_match("SPAM"));
+
+        let hook = moderation.hook.unwrap();
+        assert!(matches!(hook.kind, HookKind::Http { .. }));
+        assert_eq!(hook.timeout, Some(Duration::from_secs(2)));
+    }
 }
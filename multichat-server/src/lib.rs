@@ -0,0 +1,35 @@
+//! Crate containing the Multichat server, which can be embedded in other applications.
+//!
+//! # Example
+//! ```rust
+//! use multichat_server::config::{Client, Groups, Quota, Scope};
+//! use multichat_server::ServerBuilder;
+//! use std::error::Error;
+//! use tokio::net::TcpListener;
+//!
+//! async fn serve() -> Result<(), Box<dyn Error>> {
+//!     let mut builder = ServerBuilder::basic();
+//!     builder
+//!         .listener(TcpListener::bind("127.0.0.1:8585").await?)
+//!         .client(Client {
+//!             // This is a dummy access token for demonstration purposes.
+//!             access_token: "52f0395327987f07f805c3ac54fe38ac123303fcdb62a61fdfc9b8082195486c".parse()?,
+//!             name: Some("example".to_owned()),
+//!             groups: Groups::All,
+//!             scope: Scope::ReadWrite,
+//!             quota: Quota::default(),
+//!             slow_consumers: None,
+//!         });
+//!
+//!     builder.run().await?;
+//!     Ok(())
+//! }
+//! ```
+
+mod builder;
+pub mod config;
+mod server;
+pub mod tls;
+pub mod websocket;
+
+pub use builder::ServerBuilder;
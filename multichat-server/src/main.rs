@@ -1,15 +1,18 @@
-mod config;
-mod server;
-mod tls;
+#[cfg(unix)]
+mod systemd;
 
 use clap::Parser;
-use config::Config;
 use multichat_proto::Config as ProtoConfig;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use multichat_server::config::{Config, Flavor, LogFormat};
+use multichat_server::tls::{self, Acceptor};
+use multichat_server::ServerBuilder;
+use std::fs;
+use std::io::Error;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
-use tls::DefaultAcceptor;
-use tokio::fs;
+use tokio::net::{TcpListener, TcpSocket};
+use tokio::runtime;
 use tracing::subscriber;
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use tracing_subscriber::fmt;
@@ -22,51 +25,92 @@ struct Args {
     config: PathBuf,
 }
 
-#[tokio::main]
-async fn main() -> ExitCode {
-    let filter = EnvFilter::builder()
-        .with_default_directive(LevelFilter::INFO.into())
-        .from_env_lossy();
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let config = read_config(&args.config);
 
-    let registry = tracing_subscriber::registry()
-        .with(filter)
-        .with(fmt::layer().without_time().with_target(false));
+    // Logging is configured by the config file, so fall back to the default format if it can't be read.
+    let log_format = match &config {
+        Ok(config) => config.log_format,
+        Err(_) => LogFormat::default(),
+    };
 
-    subscriber::set_global_default(registry).unwrap();
+    init_tracing(log_format);
 
-    let args = Args::parse();
-    let config = match fs::read_to_string(&args.config).await {
+    let config = match config {
         Ok(config) => config,
         Err(err) => {
-            tracing::error!("Error reading config: {}", err);
+            tracing::error!("{}", err);
             return ExitCode::FAILURE;
         }
     };
 
-    let config = match toml::from_str::<Config>(&config) {
-        Ok(config) => config,
+    // Taken before the runtime spawns any threads.
+    #[cfg(unix)]
+    let activated = match systemd::listener() {
+        Ok(listener) => listener,
         Err(err) => {
-            tracing::error!("Error parsing config: {}", err);
+            tracing::error!("Error taking socket from systemd: {}", err);
             return ExitCode::FAILURE;
         }
     };
 
-    let mut access_tokens = HashMap::new();
-    for client in config.clients {
-        let exists = access_tokens
-            .insert(client.access_token, client.groups)
-            .is_some();
+    #[cfg(not(unix))]
+    let activated = None;
+
+    let mut builder = match config.runtime.flavor {
+        Flavor::MultiThread => runtime::Builder::new_multi_thread(),
+        Flavor::CurrentThread => runtime::Builder::new_current_thread(),
+    };
+
+    if let Some(worker_threads) = config.runtime.worker_threads {
+        builder.worker_threads(worker_threads.get());
+    }
+
+    if let Some(max_blocking_threads) = config.runtime.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads.get());
+    }
+
+    let runtime = match builder.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            tracing::error!("Error creating runtime: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
 
-        if exists {
-            tracing::error!("Duplicate access token: {}", client.access_token);
+    // The accept loop runs on the main thread, connections are spawned onto the workers.
+    runtime.block_on(run(config, activated))
+}
+
+async fn run(mut config: Config, activated: Option<StdTcpListener>) -> ExitCode {
+    let listener = match activated {
+        Some(listener) => TcpListener::from_std(listener),
+        None => bind(config.listen, config.tcp.backlog.unwrap_or(1024)),
+    };
+
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(err) => {
+            tracing::error!("Error listening on {}: {}", config.listen, err);
             return ExitCode::FAILURE;
         }
-    }
+    };
 
-    let mut proto_config = ProtoConfig::default();
-    proto_config.max_size(config.max_size);
+    let websocket_listener = match config.websocket_listen {
+        Some(addr) => match bind(addr, config.tcp.backlog.unwrap_or(1024)) {
+            Ok(listener) => Some(listener),
+            Err(err) => {
+                tracing::error!("Error listening on {}: {}", addr, err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
 
-    let result = match config.tls {
+    let listeners = (listener, websocket_listener);
+
+    let result = match config.tls.take() {
         Some(tls) => {
             let acceptor = match tls::configure(&tls.certificate, &tls.key).await {
                 Ok(acceptor) => acceptor,
@@ -76,29 +120,13 @@ async fn main() -> ExitCode {
                 }
             };
 
-            server::run(
-                config.listen,
-                acceptor,
-                config.update_buffer,
-                access_tokens,
-                proto_config,
-                config.ping_interval,
-                config.ping_timeout,
-            )
-            .await
-        }
-        None => {
-            server::run(
-                config.listen,
-                DefaultAcceptor,
-                config.update_buffer,
-                access_tokens,
-                proto_config,
-                config.ping_interval,
-                config.ping_timeout,
-            )
-            .await
+            if tls.allow_plaintext {
+                serve(ServerBuilder::optional_tls(acceptor), listeners, config).await
+            } else {
+                serve(ServerBuilder::tls(acceptor), listeners, config).await
+            }
         }
+        None => serve(ServerBuilder::basic(), listeners, config).await,
     };
 
     match result {
@@ -109,3 +137,104 @@ async fn main() -> ExitCode {
         }
     }
 }
+
+fn bind(addr: SocketAddr, backlog: u32) -> Result<TcpListener, Error> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    // Same as TcpListener::bind, allows restarting while old connections are in TIME_WAIT.
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+
+    socket.bind(addr)?;
+    socket.listen(backlog)
+}
+
+/// Configures the server according to the config file and runs it.
+async fn serve(
+    mut builder: ServerBuilder<impl Acceptor>,
+    (listener, websocket_listener): (TcpListener, Option<TcpListener>),
+    config: Config,
+) -> Result<(), Error> {
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(config.max_size);
+
+    builder
+        .listener(listener)
+        .config(proto_config)
+        .update_buffer(config.update_buffer)
+        .ping_interval(config.ping_interval)
+        .ping_timeout(config.ping_timeout)
+        .handshake_timeout(config.handshake_timeout)
+        .resume_window(config.resume_window)
+        .compression(config.compression)
+        .group_names(config.group_names)
+        .duplicate_user_names(config.duplicate_user_names)
+        .limits(config.limits)
+        .idle(config.idle)
+        .slow_consumers(config.slow_consumers)
+        .tcp(config.tcp)
+        .admin_socket(config.admin_socket)
+        .dump_on_signal(true)
+        .moderation(config.moderation);
+
+    if let Some(listener) = websocket_listener {
+        builder.websocket_listener(listener);
+    }
+
+    for settings in config.group_settings {
+        builder.group_settings(settings);
+    }
+
+    for client in config.clients {
+        builder.client(client);
+    }
+
+    for name in config.persistent_groups {
+        builder.persistent_group(name);
+    }
+
+    for webhook in config.webhooks {
+        builder.webhook(webhook);
+    }
+
+    #[cfg(unix)]
+    builder.on_ready(systemd::ready);
+
+    builder.run().await
+}
+
+fn init_tracing(format: LogFormat) {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let (text, json) = match format {
+        LogFormat::Text => (Some(fmt::layer().without_time().with_target(false)), None),
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(false),
+            ),
+        ),
+    };
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json);
+
+    subscriber::set_global_default(registry).unwrap();
+}
+
+fn read_config(path: &Path) -> Result<Config, String> {
+    let config =
+        fs::read_to_string(path).map_err(|err| format!("Error reading config: {}", err))?;
+
+    toml::from_str(&config).map_err(|err| format!("Error parsing config: {}", err))
+}
@@ -1,81 +1,253 @@
-use crate::config::Groups;
+#[cfg(unix)]
+mod admin;
+mod moderation;
+mod webhooks;
+
+use crate::config::{
+    Client, DuplicateNames, GroupNames, GroupSettings, Idle, Limits, Moderation, Scope,
+    SlowConsumers, Tcp, Webhook,
+};
 use crate::tls::Acceptor;
+use crate::websocket::WebSocketAcceptor;
 
+use bytes::Bytes;
+use futures_util::future::try_join_all;
+use moderation::{Moderator, Pending, Queue};
 use multichat_proto::{
-    AccessToken, Attachment, AuthRequest, AuthResponse, ClientMessage, Config, ServerMessage,
-    Version,
+    AccessToken, Attachment, AuthRequest, AuthResponse, ClientMessage, CompressedReader,
+    CompressedWriter, Config, ResumeToken, ServerMessage, Version,
 };
 use slab::Slab;
+use socket2::{SockRef, TcpKeepalive};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::future;
-use std::io::{Error, ErrorKind};
-use std::net::SocketAddr;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Error;
+use std::net::{IpAddr, SocketAddr};
 use std::num::NonZeroUsize;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::{self, AsyncRead, AsyncWrite, BufReader, BufWriter};
-use tokio::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{self, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::{self, Sender};
 use tokio::sync::{mpsc, RwLock};
 use tokio::task::JoinHandle;
 use tokio::time;
-use tracing::Instrument;
+use tracing::{field, Instrument};
+use webhooks::{Event, Webhooks};
+
+/// Server settings.
+#[derive(Default)]
+pub struct Settings {
+    pub update_buffer: Option<NonZeroUsize>,
+    /// Settings of groups matching a pattern.
+    pub group_settings: Vec<GroupSettings>,
+    pub ping_interval: Option<Duration>,
+    pub ping_timeout: Option<Duration>,
+    pub handshake_timeout: Option<Duration>,
+    /// How long sessions of disconnected clients are kept for resumption.
+    pub resume_window: Option<Duration>,
+    /// Whether clients may enable compression.
+    pub compression: bool,
+    /// Groups that always exist and are never garbage collected.
+    pub persistent_groups: Vec<String>,
+    /// Normalization applied to names of joined groups.
+    pub group_names: GroupNames,
+    pub duplicate_user_names: DuplicateNames,
+    pub limits: Limits,
+    pub idle: Idle,
+    pub slow_consumers: SlowConsumers,
+    /// Socket options of accepted connections.
+    pub tcp: Tcp,
+    /// Path of the Unix socket accepting administrative commands.
+    pub admin_socket: Option<PathBuf>,
+    /// Whether state is logged on SIGUSR1.
+    pub dump_on_signal: bool,
+    /// Filters and hooks applied to sent messages.
+    pub moderation: Moderation,
+    /// Endpoints notified about events.
+    pub webhooks: Vec<Webhook>,
+    /// Called once everything is bound and connections are about to be accepted.
+    pub on_ready: Option<Box<dyn FnOnce() + Send>>,
+}
 
 pub async fn run(
-    listen_addr: SocketAddr,
+    listeners: Vec<TcpListener>,
+    websocket_listeners: Vec<TcpListener>,
     acceptor: impl Acceptor,
-    update_buffer: Option<NonZeroUsize>,
-    access_tokens: HashMap<AccessToken, Groups>,
+    access_tokens: HashMap<AccessToken, Arc<Client>>,
     config: Config,
-    ping_timeout: Option<Duration>,
-    ping_interval: Option<Duration>,
+    settings: Settings,
 ) -> Result<(), Error> {
-    let listener = TcpListener::bind(&listen_addr).await?;
-
-    tracing::info!("Listening on {}", listen_addr);
-
-    let update_buffer = update_buffer.map(|num| num.get()).unwrap_or(256);
+    let update_buffer = settings.update_buffer.map(|num| num.get()).unwrap_or(256);
 
     let state = Arc::new(State {
         update_buffer,
+        group_settings: settings.group_settings,
         groups: RwLock::new(Slab::new()),
-        access_tokens,
+        access_tokens: RwLock::new(access_tokens),
+        connections: Mutex::new(HashMap::new()),
+        unauthenticated: Mutex::new(HashMap::new()),
         sender: broadcast::channel(update_buffer).0,
+        group_names: settings.group_names,
+        duplicate_user_names: settings.duplicate_user_names,
+        limits: settings.limits,
+        idle: settings.idle,
+        slow_consumers: settings.slow_consumers,
+        tcp: settings.tcp,
+        ping_interval: settings.ping_interval.unwrap_or(Duration::from_secs(30)),
+        ping_timeout: settings.ping_timeout.unwrap_or(Duration::from_secs(5)),
+        handshake_timeout: settings.handshake_timeout.unwrap_or(Duration::from_secs(5)),
+        resume_window: settings.resume_window,
+        compression: settings.compression,
+        sessions: Mutex::new(HashMap::new()),
+        next_owner: AtomicU64::new(0),
+        usage: Mutex::new(HashMap::new()),
+        bans: Mutex::new(HashMap::new()),
+        moderator: Arc::new(Moderator::new(settings.moderation)),
+        webhooks: Webhooks::new(settings.webhooks),
     });
 
-    let ping_interval = ping_interval.unwrap_or(Duration::from_secs(30));
-    let ping_timeout = ping_timeout.unwrap_or(Duration::from_secs(5));
+    {
+        let mut groups = state.groups.write().await;
+
+        for name in settings.persistent_groups {
+            let gid = groups.insert(Arc::new(state.new_group(name, true)));
+
+            tracing::debug!(%gid, name = ?groups[gid].name, "Created persistent group");
+        }
+    }
+
+    if let Some(path) = settings.admin_socket {
+        #[cfg(unix)]
+        admin::spawn(&path, state.clone())?;
+
+        #[cfg(not(unix))]
+        tracing::warn!(path = %path.display(), "Admin socket is only supported on Unix");
+    }
+
+    if settings.dump_on_signal {
+        #[cfg(unix)]
+        admin::spawn_signal(state.clone())?;
+
+        #[cfg(not(unix))]
+        tracing::warn!("Dumping state on signal is only supported on Unix");
+    }
+
+    let mut accepts = Vec::new();
+    for listener in listeners {
+        tracing::info!("Listening on {}", listener.local_addr()?);
+        accepts.push(accept(listener, acceptor.clone(), state.clone(), config));
+    }
+
+    let mut websocket_accepts = Vec::new();
+    for listener in websocket_listeners {
+        tracing::info!(
+            "Listening for WebSocket connections on {}",
+            listener.local_addr()?
+        );
+
+        let acceptor = WebSocketAcceptor(acceptor.clone());
+        websocket_accepts.push(accept(listener, acceptor, state.clone(), config));
+    }
+
+    if let Some(on_ready) = settings.on_ready {
+        on_ready();
+    }
+
+    // Accepted on the calling task rather than spawned, so the caller decides which thread accepts.
+    // Runs until any listener fails.
+    tokio::try_join!(try_join_all(accepts), try_join_all(websocket_accepts))?;
+
+    Ok(())
+}
+
+fn configure_socket(stream: &TcpStream, tcp: &Tcp) -> Result<(), Error> {
+    stream.set_nodelay(tcp.nodelay)?;
+
+    let Some(time) = tcp.keepalive else {
+        return Ok(());
+    };
+
+    #[allow(unused_mut)]
+    let mut keepalive = TcpKeepalive::new().with_time(time);
+
+    #[cfg(any(target_os = "linux", target_os = "macos", windows))]
+    if let Some(interval) = tcp.keepalive_interval {
+        keepalive = keepalive.with_interval(interval);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    if let Some(retries) = tcp.keepalive_retries {
+        keepalive = keepalive.with_retries(retries);
+    }
+
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
 
+async fn accept(
+    listener: TcpListener,
+    acceptor: impl Acceptor,
+    state: Arc<State>,
+    config: Config,
+) -> Result<(), Error> {
     loop {
         let (stream, addr) = listener.accept().await?;
         let acceptor = acceptor.clone();
         let state = state.clone();
-        let span = tracing::info_span!("connection", %addr);
+        let owner = state.next_owner.fetch_add(1, Ordering::Relaxed);
+        // The client is recorded once authenticated.
+        let span = tracing::info_span!("connection", id = owner, %addr, client = field::Empty);
+
+        if state.is_banned(&BanTarget::Ip(addr.ip())) {
+            span.in_scope(|| tracing::info!("Rejected banned address"));
+            continue;
+        }
+
+        let Some(unauthenticated) = Unauthenticated::new(&state, addr.ip()) else {
+            span.in_scope(|| tracing::info!("Rejected, too many unauthenticated connections"));
+            continue;
+        };
+
+        if let Err(err) = configure_socket(&stream, &state.tcp) {
+            span.in_scope(|| tracing::warn!("Error setting socket options: {}", err));
+        }
+
+        let deadline = time::Instant::now() + state.handshake_timeout;
 
         tokio::spawn(
             async move {
                 tracing::info!("Connected");
 
-                let stream = match acceptor.accept(stream).await {
-                    Ok(stream) => stream,
-                    Err(err) => {
-                        tracing::error!("TLS error: {}", err);
+                let stream = match time::timeout_at(deadline, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(err)) => {
+                        tracing::error!("Handshake error: {}", err);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::error!("Handshake timeout");
                         return;
                     }
                 };
 
                 let mut memberships = HashMap::new();
+                let mut access_token = None;
+                let mut resume_token = None;
 
                 let result = connection(
                     stream,
                     addr,
+                    owner,
                     &state,
                     config,
-                    ping_interval,
-                    ping_timeout,
+                    (unauthenticated, deadline),
                     &mut memberships,
+                    (&mut access_token, &mut resume_token),
                 )
                 .await;
 
@@ -84,93 +256,184 @@ pub async fn run(
                     Err(err) => tracing::error!("Disconnected: {}", err),
                 }
 
-                // Garbage collect users and groups.
-                for (_, membership) in memberships {
-                    membership.handle.abort();
-                    let _ = membership.handle.await;
-                }
-
-                let mut groups = state.groups.write().await;
-                groups.retain(|gid, group| {
-                    group.cleanup_users(addr);
+                state.connections.lock().unwrap().remove(&addr);
 
-                    if group.sender.receiver_count() == 0 {
-                        tracing::debug!(%gid, name = ?group.name, "Destroying group");
+                let Some(access_token) = access_token else {
+                    return;
+                };
 
-                        let _ = state.sender.send(GlobalUpdate {
-                            gid: gid.try_into().unwrap(),
-                            kind: GlobalUpdateKind::DestroyGroup,
-                        });
+                state.with_usage(&access_token, |usage| usage.connections -= 1);
 
-                        return false;
+                match (resume_token, state.resume_window) {
+                    (Some(resume_token), Some(window)) => {
+                        state
+                            .detach(resume_token, access_token, owner, memberships, window)
+                            .await
                     }
+                    _ => {
+                        for (_, membership) in memberships {
+                            membership.handle.abort();
+                            let _ = membership.handle.await;
+                        }
 
-                    true
-                });
+                        state.cleanup(access_token, owner).await;
+                    }
+                }
             }
             .instrument(span),
         );
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn connection(
     stream: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
     addr: SocketAddr,
+    owner: u64,
     state: &State,
     config: Config,
-    ping_interval: Duration,
-    ping_timeout: Duration,
+    (unauthenticated, deadline): (Unauthenticated, time::Instant),
     memberships: &mut HashMap<u32, Membership>,
+    (access_token, resume_token): (&mut Option<AccessToken>, &mut Option<ResumeToken>),
 ) -> Result<(), Error> {
     let (stream_read, stream_write) = io::split(stream);
 
     let mut stream_read = BufReader::new(stream_read);
     let mut stream_write = BufWriter::new(stream_write);
 
-    // Send our version.
-    // Intentionally bypass config write because Version does not implement Serialize.
-    Version::CURRENT.write(&mut stream_write).await?;
+    let handshake = async {
+        // Send our version.
+        // Intentionally bypass config write because Version does not implement Serialize.
+        Version::CURRENT.write(&mut stream_write).await?;
 
-    let version = Version::read(&mut stream_read).await?;
-    if version != Version::CURRENT {
-        return Err(Error::new(ErrorKind::Other, "Incompatible version"));
-    }
+        let version = Version::read(&mut stream_read).await?;
+        if version != Version::CURRENT {
+            return Err(Error::other("Incompatible version"));
+        }
+
+        // Read the client's auth request.
+        config.read::<AuthRequest>(&mut stream_read).await
+    };
+
+    let auth_request = time::timeout_at(deadline, handshake)
+        .await
+        .map_err(|_| Error::other("Handshake timeout"))??;
+
+    drop(unauthenticated);
 
-    // Read the client's auth request.
-    let auth_request = config.read::<AuthRequest>(&mut stream_read).await?;
+    let (terminate_sender, mut terminate_receiver) = mpsc::channel(1);
+    let pending_attachments = Arc::new(AtomicUsize::new(0));
 
-    let groups = match state.access_tokens.get(&auth_request.access_token) {
-        Some(groups) => groups,
-        None => {
+    // Register the connection while holding the locks so that it's not possible to miss
+    // a revocation or a ban.
+    let client = {
+        let access_tokens = state.access_tokens.read().await;
+        let mut connections = state.connections.lock().unwrap();
+
+        let banned = state.is_banned(&BanTarget::Ip(addr.ip()))
+            || state.is_banned(&BanTarget::AccessToken(auth_request.access_token));
+
+        match access_tokens.get(&auth_request.access_token) {
+            Some(_) if banned => Err("Banned"),
+            Some(client) => {
+                connections.insert(
+                    addr,
+                    ConnectionHandle {
+                        access_token: auth_request.access_token,
+                        owner,
+                        pending_attachments: pending_attachments.clone(),
+                        terminate: terminate_sender,
+                    },
+                );
+
+                Ok(client.clone())
+            }
+            None => Err("Invalid access token"),
+        }
+    };
+
+    let client = match client {
+        Ok(client) => client,
+        Err(err) => {
             config
                 .write(&mut stream_write, &AuthResponse::Failed)
                 .await?;
 
-            return Err(Error::new(ErrorKind::Other, "Invalid access token"));
+            return Err(Error::other(err));
+        }
+    };
+
+    let quota_exceeded = {
+        let mut usage = state.usage.lock().unwrap();
+        let usage = usage.entry(client.access_token).or_default();
+
+        match client.quota.connections {
+            Some(limit) if usage.connections >= limit.get() => true,
+            _ => {
+                usage.connections += 1;
+                false
+            }
         }
     };
 
+    if quota_exceeded {
+        config
+            .write(&mut stream_write, &AuthResponse::Failed)
+            .await?;
+
+        return Err(Error::other("Connection quota exceeded"));
+    }
+
+    *access_token = Some(client.access_token);
+
+    if let Some(name) = &client.name {
+        tracing::Span::current().record("client", field::display(name));
+    }
+
+    let groups = &client.groups;
+
+    // Only the access token which created a session may resume it.
+    let session = auth_request.resume.and_then(|token| {
+        let mut sessions = state.sessions.lock().unwrap();
+
+        match sessions.get(&token) {
+            Some(session) if session.access_token == client.access_token => sessions.remove(&token),
+            _ => None,
+        }
+    });
+
+    *resume_token = Some(ResumeToken(rand::random()));
+
+    let compression = auth_request.compression.filter(|_| state.compression);
+
     // Auth successful.
     config
         .write(
             &mut stream_write,
             &AuthResponse::Success {
-                ping_interval,
-                ping_timeout,
+                ping_interval: state.ping_interval,
+                ping_timeout: state.ping_timeout,
+                resume_token: resume_token.unwrap(),
+                resumed: session.is_some(),
+                compression,
             },
         )
         .await?;
 
+    let mut stream_read = CompressedReader::new(stream_read, compression);
+    let mut stream_write = CompressedWriter::new(stream_write, compression);
+
     // C2S.
+    // The task is aborted once the connection ends so that the stream gets closed.
     let (server_sender, mut server_receiver) = mpsc::channel(1);
-    tokio::spawn(async move {
+    let _reader = AbortOnDrop(tokio::spawn(async move {
         loop {
             let result = config.read(&mut stream_read).await;
             if result.is_err() | server_sender.send(result).await.is_err() {
                 break;
             }
         }
-    });
+    }));
 
     let init_groups = state
         .groups
@@ -184,7 +447,7 @@ async fn connection(
     // Send intitial updates.
     for (gid, name) in init_groups {
         config
-            .write(
+            .write_unflushed(
                 &mut stream_write,
                 &ServerMessage::InitGroup {
                     gid: gid.try_into().unwrap(),
@@ -196,20 +459,107 @@ async fn connection(
 
     let (update_sender, mut update_receiver) = mpsc::channel(state.update_buffer);
 
-    let mut attachments = Slab::<Arc<Vec<u8>>>::new();
-    let mut ping_interval = time::interval(ping_interval);
-    let mut pong_interval = time::interval(ping_timeout);
+    if let Some(session) = session {
+        for (gid, group, keep_alive) in session.groups {
+            let (receiver, users) = {
+                let mut users = group.users.lock().unwrap();
+
+                for (_, user) in users.iter_mut() {
+                    if user.owner == session.owner {
+                        user.owner = owner;
+                    }
+                }
+
+                (group.sender.subscribe(), snapshot_users(&users))
+            };
+
+            drop(keep_alive);
+
+            let handle = tokio::spawn(forward_updates(
+                gid,
+                receiver,
+                update_sender.clone(),
+                state.group_update_buffer(&group.name),
+                client.slow_consumers.unwrap_or(state.slow_consumers),
+            ));
+
+            memberships.insert(
+                gid,
+                Membership {
+                    handle,
+                    newly_joined: false,
+                },
+            );
+
+            write_users(&mut stream_write, config, gid, users).await?;
+        }
+
+        tracing::debug!(groups = %memberships.len(), "Resumed session");
+    }
+
+    stream_write.flush().await?;
+
+    let mut attachments = Slab::<Bytes>::new();
+    let mut ping_interval = time::interval(state.ping_interval);
+    let mut pong_interval = time::interval(state.ping_timeout);
     let mut waiting_pong = false;
     let mut receiver = state.sender.subscribe();
+    // Time of the first write since the last flush.
+    let mut unflushed = None;
+    // Time since which the connection has no group memberships.
+    let mut unjoined_since = Instant::now();
+    let mut last_activity = Instant::now();
+    let mut moderation = state
+        .moderator
+        .has_hook()
+        .then(|| Queue::spawn(state.moderator.clone()));
 
     loop {
+        // Messages are written unflushed and flushed once there is nothing else to do, but
+        // don't delay them indefinitely under constant load.
+        if unflushed.is_some_and(|since: Instant| since.elapsed() >= MAX_FLUSH_DELAY) {
+            stream_write.flush().await?;
+            unflushed = None;
+        }
+
         enum LocalUpdate {
             Client(ClientMessage<'static, 'static>),
             Global(GlobalUpdate),
             Group((u32, GroupUpdate)),
+            Moderated(Pending),
             Ping,
+            Terminate(String),
         }
 
+        let idle_deadline = [
+            state
+                .idle
+                .without_groups
+                .filter(|_| memberships.is_empty())
+                .map(|timeout| unjoined_since + timeout),
+            state
+                .idle
+                .without_activity
+                .map(|timeout| last_activity + timeout),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        let idle = async {
+            match idle_deadline {
+                Some(deadline) => time::sleep_until(deadline.into()).await,
+                None => future::pending().await,
+            }
+        };
+
+        let moderated = async {
+            match &mut moderation {
+                Some(queue) => queue.next().await,
+                None => future::pending().await,
+            }
+        };
+
         let pong = async {
             if waiting_pong {
                 pong_interval.tick().await
@@ -220,24 +570,44 @@ async fn connection(
 
         // It's not possible for the unwraps to fail unless either task panics and at that
         // point we can just bring the whole thing down.
-        let update = tokio::select! {
-            result = server_receiver.recv() => LocalUpdate::Client(result.unwrap()?),
-            result = update_receiver.recv() => {
-                match result.unwrap() {
-                    Ok(update) => LocalUpdate::Group(update),
-                    Err(num) => return Err(Error::new(ErrorKind::Other, format!("Skipped {} group update(s)", num))),
+        let next = async {
+            let update = tokio::select! {
+                result = server_receiver.recv() => LocalUpdate::Client(result.unwrap()?),
+                result = update_receiver.recv() => {
+                    match result.unwrap() {
+                        Ok(update) => LocalUpdate::Group(update),
+                        Err(reason) => return Err(Error::other(reason)),
+                    }
                 }
-            }
-            result = receiver.recv() => {
-                match result {
-                    Ok(update) => LocalUpdate::Global(update),
-                    Err(num) => return Err(Error::new(ErrorKind::Other, format!("Skipped {} global update(s)", num))),
+                result = receiver.recv() => {
+                    match result {
+                        Ok(update) => LocalUpdate::Global(update),
+                        Err(num) => return Err(Error::other(format!("Skipped {} global update(s)", num))),
+                    }
                 }
+                message = moderated => LocalUpdate::Moderated(message),
+                _ = ping_interval.tick() => LocalUpdate::Ping,
+                _ = pong => return Err(Error::other("Pong timeout")),
+                _ = idle => LocalUpdate::Terminate("Idle timeout".to_owned()),
+                reason = terminate_receiver.recv() => LocalUpdate::Terminate(reason.unwrap()),
+            };
+
+            Ok(update)
+        };
+
+        let update = tokio::select! {
+            biased;
+
+            update = next => update?,
+            result = stream_write.flush(), if unflushed.is_some() => {
+                result?;
+                unflushed = None;
+                continue;
             }
-            _ = ping_interval.tick() => LocalUpdate::Ping,
-            _ = pong => return Err(Error::new(ErrorKind::Other, "Pong timeout")),
         };
 
+        unflushed.get_or_insert_with(Instant::now);
+
         match update {
             LocalUpdate::Client(message) => {
                 ping_interval.reset();
@@ -245,52 +615,92 @@ async fn connection(
 
                 waiting_pong = false;
 
+                if message != ClientMessage::Pong {
+                    last_activity = Instant::now();
+                }
+
                 match message {
                     ClientMessage::JoinGroup { name } => {
+                        let name = state.group_names.normalize(&name).map_err(Error::other)?;
+
                         if !groups.contains(&name) {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to join a forbidden group",
-                            ));
+                            return Err(Error::other("Attempted to join a forbidden group"));
                         }
 
-                        let mut groups = state.groups.write().await;
+                        let subscribe = |gid: usize, group: &Group| {
+                            if let Some(limit) = state.limits.group_subscribers {
+                                if group.sender.receiver_count() >= limit.get() {
+                                    return Err(Error::other("Group subscriber limit reached"));
+                                }
+                            }
 
-                        let find = groups.iter_mut().find(|(_, group)| group.name == name);
-                        let (gid, group, new) = match find {
-                            Some((gid, group)) => (gid, group, false),
-                            None => {
-                                let (sender, _) = broadcast::channel(state.update_buffer);
-                                let gid = groups.insert(Group {
-                                    name: name.clone().into(),
-                                    users: Slab::new(),
-                                    sender,
-                                });
+                            // Take the snapshot of users under the same lock as subscribing, so
+                            // that no update is missed or applied twice.
+                            let users = group.users.lock().unwrap();
+                            let mut direct = group.direct.lock().unwrap();
+                            let receiver = group.sender.subscribe();
 
-                                (gid, groups.get_mut(gid).unwrap(), true)
+                            if group.sender.receiver_count() == 1 {
+                                *direct = Some(Direct {
+                                    gid: gid.try_into().unwrap(),
+                                    sender: update_sender.clone(),
+                                });
                             }
+
+                            Ok((receiver, snapshot_users(&users)))
                         };
 
-                        let gid = gid.try_into().unwrap();
-                        let sender = group.sender.clone();
-                        let mut receiver = sender.subscribe();
-                        let update_sender = update_sender.clone();
-
-                        let handle = tokio::spawn(async move {
-                            loop {
-                                let result = match receiver.recv().await {
-                                    Ok(update) => Ok((gid, update)),
-                                    Err(RecvError::Lagged(num)) => Err(num),
-                                    Err(RecvError::Closed) => return,
+                        // Groups can only be destroyed under the write lock, so it's enough to
+                        // hold the read lock while subscribing to an existing group.
+                        let groups_read = state.groups.read().await;
+                        let find = groups_read.iter().find(|(_, group)| group.name == name);
+
+                        let (gid, receiver, users, new) = match find {
+                            Some((gid, group)) => {
+                                let (receiver, users) = subscribe(gid, group)?;
+                                drop(groups_read);
+
+                                (gid, receiver, users, false)
+                            }
+                            None => {
+                                drop(groups_read);
+
+                                let mut groups = state.groups.write().await;
+
+                                // Somebody else might have created the group in the meantime.
+                                let find = groups.iter().find(|(_, group)| group.name == name);
+                                let (gid, new) = match find {
+                                    Some((gid, _)) => (gid, false),
+                                    None => {
+                                        let gid = groups
+                                            .insert(Arc::new(state.new_group(name.clone(), false)));
+
+                                        // Announce while still holding the lock, so that the
+                                        // order with destruction of the same ID is preserved.
+                                        let _ = state.sender.send(GlobalUpdate {
+                                            gid: gid.try_into().unwrap(),
+                                            kind: GlobalUpdateKind::InitGroup {
+                                                name: name.clone(),
+                                            },
+                                        });
+
+                                        (gid, true)
+                                    }
                                 };
 
-                                // The binary or is intentional, we want the result to be
-                                // sent regardless of being an error.
-                                if result.is_err() | update_sender.send(result).await.is_err() {
-                                    return;
-                                }
+                                let (receiver, users) = subscribe(gid, &groups[gid])?;
+                                (gid, receiver, users, new)
                             }
-                        });
+                        };
+
+                        let gid = gid.try_into().unwrap();
+                        let handle = tokio::spawn(forward_updates(
+                            gid,
+                            receiver,
+                            update_sender.clone(),
+                            state.group_update_buffer(&name),
+                            client.slow_consumers.unwrap_or(state.slow_consumers),
+                        ));
 
                         let membership = Membership {
                             handle,
@@ -298,173 +708,166 @@ async fn connection(
                         };
 
                         if memberships.insert(gid, membership).is_some() {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to join a group twice",
-                            ));
+                            return Err(Error::other("Attempted to join a group twice"));
                         }
 
                         if new {
-                            let _ = state.sender.send(GlobalUpdate {
-                                gid,
-                                kind: GlobalUpdateKind::InitGroup {
-                                    name: name.clone().into(),
-                                },
-                            });
+                            state.webhooks.emit(Event::GroupCreated { group: &name });
                         } else {
-                            let users = group
-                                .users
-                                .iter()
-                                .map(|(uid, user)| (uid, user.name.clone(), user.typing))
-                                .collect::<Vec<_>>();
-
-                            drop(groups);
-
-                            for (uid, name, typing) in users {
-                                config
-                                    .write(
-                                        &mut stream_write,
-                                        &ServerMessage::InitUser {
-                                            gid,
-                                            uid: uid.try_into().unwrap(),
-                                            name: name.clone().into(),
-                                        },
-                                    )
-                                    .await?;
-
-                                if typing {
-                                    config
-                                        .write(
-                                            &mut stream_write,
-                                            &ServerMessage::StartTyping {
-                                                gid,
-                                                uid: uid.try_into().unwrap(),
-                                            },
-                                        )
-                                        .await?;
-                                }
-                            }
+                            write_users(&mut stream_write, config, gid, users).await?;
                         }
 
                         config
-                            .write(&mut stream_write, &ServerMessage::ConfirmGroup { gid })
+                            .write_unflushed(
+                                &mut stream_write,
+                                &ServerMessage::ConfirmGroup { gid },
+                            )
                             .await?;
 
                         tracing::debug!(%gid, ?name, "Join group");
                     }
                     ClientMessage::LeaveGroup { gid } => {
-                        let mut groups = state.groups.write().await;
-
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get_mut(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to leave a nonexistent group",
-                                )
-                            })?;
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to leave a nonexistent group")
+                        })?;
 
                         let handle = memberships
                             .remove(&gid)
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to leave a non-joined group",
-                                )
-                            })?
+                            .ok_or_else(|| Error::other("Attempted to leave a non-joined group"))?
                             .handle;
 
                         // Wait for the task to finish.
                         handle.abort();
                         let _ = handle.await;
 
-                        group.cleanup_users(addr);
+                        group.remove_direct(&update_sender);
 
-                        if group.sender.receiver_count() == 0 {
-                            let group = groups.remove(gid.try_into().unwrap());
-                            let _ = state.sender.send(GlobalUpdate {
-                                gid,
-                                kind: GlobalUpdateKind::DestroyGroup,
-                            });
+                        if memberships.is_empty() {
+                            unjoined_since = Instant::now();
+                        }
+
+                        let removed_users = group.cleanup_users(owner);
+                        state.with_usage(&client.access_token, |usage| {
+                            usage.users -= removed_users;
+                        });
 
-                            tracing::debug!(%gid, name = ?group.name, "Destroyed group");
+                        if group.is_unused() {
+                            let mut groups = state.groups.write().await;
+
+                            // Check again, the group might have been joined in the meantime.
+                            let index = gid.try_into().unwrap();
+                            if group.is_unused()
+                                && groups
+                                    .get(index)
+                                    .is_some_and(|other| Arc::ptr_eq(other, &group))
+                            {
+                                groups.remove(index);
+                                state.destroy_group(gid, &group);
+                            }
                         }
 
                         tracing::debug!(%gid, "Leave group");
                     }
                     ClientMessage::InitUser { gid, name } => {
-                        let mut groups = state.groups.write().await;
+                        if client.scope == Scope::ReadOnly {
+                            return Err(Error::other(
+                                "Attempted to init a user with a read-only access token",
+                            ));
+                        }
 
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get_mut(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to init a user in a nonexistent group",
-                                )
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to init a user in a nonexistent group")
+                        })?;
+
+                        let (uid, name) = {
+                            let mut users = group.users.lock().unwrap();
+
+                            if let Some(limit) = state.limits.group_users {
+                                if users.len() >= limit.get() {
+                                    return Err(Error::other("Group user limit reached"));
+                                }
+                            }
+
+                            let name = state
+                                .duplicate_user_names
+                                .resolve(&name, |name| {
+                                    users.iter().any(|(_, user)| user.name == name)
+                                })
+                                .ok_or_else(|| {
+                                    Error::other("Attempted to init a duplicate user")
+                                })?;
+
+                            state.with_usage(&client.access_token, |usage| {
+                                if let Some(limit) = client.quota.users {
+                                    if usage.users >= limit.get() {
+                                        return Err(Error::other("User quota exceeded"));
+                                    }
+                                }
+
+                                usage.users += 1;
+                                Ok(())
                             })?;
 
-                        let uid = group
-                            .users
-                            .insert(User {
-                                name: name.clone().into(),
-                                typing: false,
-                                owner: addr,
-                            })
-                            .try_into()
-                            .unwrap();
+                            let uid: u32 = users
+                                .insert(User {
+                                    name: name.clone(),
+                                    typing: false,
+                                    recent: VecDeque::new(),
+                                    owner,
+                                })
+                                .try_into()
+                                .unwrap();
+
+                            group.send(GroupUpdate {
+                                uid,
+                                kind: GroupUpdateKind::InitUser { name: name.clone() },
+                            });
+
+                            let mut stats = group.stats.lock().unwrap();
+                            stats.peak_users = stats.peak_users.max(users.len());
+                            stats.last_activity = Instant::now();
+
+                            (uid, name)
+                        };
 
                         config
-                            .write(&mut stream_write, &ServerMessage::ConfirmUser { uid })
+                            .write_unflushed(&mut stream_write, &ServerMessage::ConfirmUser { uid })
                             .await?;
 
-                        let _ = group.sender.send(GroupUpdate {
-                            uid,
-                            kind: GroupUpdateKind::InitUser {
-                                name: name.clone().into(),
-                            },
+                        state.webhooks.emit(Event::UserJoined {
+                            group: &group.name,
+                            user: &name,
                         });
 
                         tracing::debug!(%gid, ?name, %uid, "Init user");
                     }
                     ClientMessage::DestroyUser { gid, uid } => {
-                        let mut groups = state.groups.write().await;
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to destroy a user from a nonexistent group")
+                        })?;
 
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get_mut(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to destroy a user from a nonexistent group",
-                                )
-                            })?;
+                        {
+                            let mut users = group.users.lock().unwrap();
 
-                        let err = || {
-                            Error::new(ErrorKind::Other, "Attempted to destroy a nonexistent user")
-                        };
+                            let err = || Error::other("Attempted to destroy a nonexistent user");
 
-                        let uid = uid.try_into().map_err(|_| err())?;
-                        let user = group.users.get(uid).ok_or_else(err)?;
+                            let uid = uid.try_into().map_err(|_| err())?;
+                            let user = users.get(uid).ok_or_else(err)?;
 
-                        if user.owner != addr {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to destroy a non owned user",
-                            ));
-                        }
+                            if user.owner != owner {
+                                return Err(Error::other("Attempted to destroy a non owned user"));
+                            }
 
-                        group.users.remove(uid);
+                            users.remove(uid);
+                            state.with_usage(&client.access_token, |usage| usage.users -= 1);
 
-                        let _ = group.sender.send(GroupUpdate {
-                            uid: uid.try_into().unwrap(),
-                            kind: GroupUpdateKind::DestroyUser,
-                        });
+                            group.send(GroupUpdate {
+                                uid: uid.try_into().unwrap(),
+                                kind: GroupUpdateKind::DestroyUser,
+                            });
+                        }
+
+                        group.record_activity();
 
                         tracing::debug!(%gid, %uid, "Leave user");
                     }
@@ -474,187 +877,213 @@ async fn connection(
                         message,
                         attachments,
                     } => {
-                        let groups = state.groups.read().await;
+                        if client.scope == Scope::ReadOnly {
+                            return Err(Error::other(
+                                "Attempted to send a message with a read-only access token",
+                            ));
+                        }
 
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to send a message to a nonexistent group",
-                                )
-                            })?;
+                        if let Some(limit) = state.limits.message_length {
+                            if message.chars().count() > limit.get() {
+                                return Err(Error::other("Attempted to send a too long message"));
+                            }
+                        }
 
-                        let err = || {
-                            Error::new(
-                                ErrorKind::Other,
-                                "Attempted to send a message as a nonexistent user",
-                            )
-                        };
+                        if let Some(limit) = state.limits.attachments_per_message {
+                            if attachments.len() > limit {
+                                return Err(Error::other(
+                                    "Attempted to send a message with too many attachments",
+                                ));
+                            }
+                        }
+
+                        if let Some(limit) = state.limits.attachment_size {
+                            if attachments
+                                .iter()
+                                .any(|attachment| attachment.len() > limit)
+                            {
+                                return Err(Error::other(
+                                    "Attempted to send a too large attachment",
+                                ));
+                            }
+                        }
+
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to send a message to a nonexistent group")
+                        })?;
+
+                        let err =
+                            || Error::other("Attempted to send a message as a nonexistent user");
 
                         let uid = uid.try_into().map_err(|_| err())?;
-                        let user = group.users.get(uid).ok_or_else(err)?;
+                        let (user_name, duplicate) = {
+                            let mut users = group.users.lock().unwrap();
+                            let user = users.get_mut(uid).ok_or_else(err)?;
+
+                            if user.owner != owner {
+                                return Err(Error::other(
+                                    "Attempted to send a message as a non owned user",
+                                ));
+                            }
 
-                        if user.owner != addr {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to send a message as a non owned user",
-                            ));
+                            let duplicate = group.dedup_window.is_some_and(|window| {
+                                user.is_duplicate(message_hash(&message, &attachments), window)
+                            });
+
+                            (user.name.clone(), duplicate)
+                        };
+
+                        if duplicate {
+                            tracing::debug!(%gid, %uid, "Dropped duplicate message");
+                            continue;
                         }
 
-                        let message_clone = message.clone();
-
-                        let _ = group.sender.send(GroupUpdate {
-                            uid: uid.try_into().unwrap(),
-                            kind: GroupUpdateKind::Message {
-                                message: message.into_owned(),
-                                attachments: attachments
-                                    .into_owned() // Already owned.
-                                    .into_iter()
-                                    .map(Cow::into_owned) // Already owned.
-                                    .map(Arc::new)
-                                    .collect(),
-                            },
-                        });
+                        let pending = Pending {
+                            gid,
+                            uid,
+                            group,
+                            user: user_name,
+                            message: message.into_owned(),
+                            attachments: attachments
+                                .into_owned() // Already owned.
+                                .into_iter()
+                                .map(Cow::into_owned) // Already owned.
+                                .map(Bytes::from)
+                                .collect(),
+                        };
+
+                        match &moderation {
+                            Some(queue) => queue.push(pending).await,
+                            None => {
+                                // Filters alone don't wait for anything.
+                                let Some(message) = state
+                                    .moderator
+                                    .moderate(
+                                        &pending.group.name,
+                                        &pending.user,
+                                        pending.message,
+                                        pending.attachments.len(),
+                                    )
+                                    .await
+                                else {
+                                    continue;
+                                };
 
-                        tracing::debug!(%gid, %uid, msg = ?message_clone, "Send message");
+                                deliver(state, &client, owner, Pending { message, ..pending })?;
+                            }
+                        }
                     }
                     ClientMessage::Rename { gid, uid, name } => {
-                        let mut groups = state.groups.write().await;
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to rename a user from a nonexistent group")
+                        })?;
 
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get_mut(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to rename a user from a nonexistent group",
-                                )
-                            })?;
+                        let name = {
+                            let mut users = group.users.lock().unwrap();
 
-                        let user = uid
-                            .try_into()
-                            .ok()
-                            .and_then(|uid: usize| group.users.get_mut(uid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to rename a nonexistent user",
-                                )
-                            })?;
+                            let err = || Error::other("Attempted to rename a nonexistent user");
 
-                        if user.owner != addr {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to rename a non owned user",
-                            ));
-                        }
+                            let index = uid.try_into().map_err(|_| err())?;
+                            let user = users.get(index).ok_or_else(err)?;
 
-                        user.name = name.clone().into();
+                            if user.owner != owner {
+                                return Err(Error::other("Attempted to rename a non owned user"));
+                            }
 
-                        let _ = group.sender.send(GroupUpdate {
-                            uid,
-                            kind: GroupUpdateKind::Rename {
-                                name: name.clone().into(),
-                            },
-                        });
+                            let name = state
+                                .duplicate_user_names
+                                .resolve(&name, |name| {
+                                    users
+                                        .iter()
+                                        .any(|(other, user)| other != index && user.name == name)
+                                })
+                                .ok_or_else(|| {
+                                    Error::other("Attempted to rename to a duplicate name")
+                                })?;
+
+                            users[index].name = name.clone();
+
+                            group.send(GroupUpdate {
+                                uid,
+                                kind: GroupUpdateKind::Rename { name: name.clone() },
+                            });
+
+                            name
+                        };
+
+                        group.record_activity();
 
                         tracing::debug!(%gid, %uid, ?name, "Rename");
                     }
                     ClientMessage::StartTyping { gid, uid } => {
-                        let mut groups = state.groups.write().await;
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to start typing in a nonexistent group")
+                        })?;
 
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get_mut(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to start typing in a nonexistent group",
-                                )
-                            })?;
+                        {
+                            let mut users = group.users.lock().unwrap();
 
-                        let err = || {
-                            Error::new(
-                                ErrorKind::Other,
-                                "Attempted to start typing as a nonexistent user",
-                            )
-                        };
+                            let err =
+                                || Error::other("Attempted to start typing as a nonexistent user");
 
-                        let uid = uid.try_into().map_err(|_| err())?;
-                        let user = group.users.get_mut(uid).ok_or_else(err)?;
+                            let uid = uid.try_into().map_err(|_| err())?;
+                            let user = users.get_mut(uid).ok_or_else(err)?;
 
-                        if user.owner != addr {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to start typing as a non owned user",
-                            ));
-                        }
+                            if user.owner != owner {
+                                return Err(Error::other(
+                                    "Attempted to start typing as a non owned user",
+                                ));
+                            }
 
-                        if user.typing {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to start typing while already typing",
-                            ));
-                        }
+                            if user.typing {
+                                return Err(Error::other(
+                                    "Attempted to start typing while already typing",
+                                ));
+                            }
 
-                        user.typing = true;
+                            user.typing = true;
 
-                        let _ = group.sender.send(GroupUpdate {
-                            uid: uid.try_into().unwrap(),
-                            kind: GroupUpdateKind::StartTyping,
-                        });
+                            group.send(GroupUpdate {
+                                uid: uid.try_into().unwrap(),
+                                kind: GroupUpdateKind::StartTyping,
+                            });
+                        }
 
                         tracing::debug!(%gid, %uid, "Stop typing");
                     }
                     ClientMessage::TypingStop { gid, uid } => {
-                        let mut groups = state.groups.write().await;
+                        let group = state.group(gid).await.ok_or_else(|| {
+                            Error::other("Attempted to stop typing in a nonexistent group")
+                        })?;
 
-                        let group = gid
-                            .try_into()
-                            .ok()
-                            .and_then(|gid: usize| groups.get_mut(gid))
-                            .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to stop typing in a nonexistent group",
-                                )
-                            })?;
+                        {
+                            let mut users = group.users.lock().unwrap();
 
-                        let err = || {
-                            Error::new(
-                                ErrorKind::Other,
-                                "Attempted to stop typing as a nonexistent user",
-                            )
-                        };
+                            let err =
+                                || Error::other("Attempted to stop typing as a nonexistent user");
 
-                        let uid = uid.try_into().map_err(|_| err())?;
-                        let user = group.users.get_mut(uid).ok_or_else(err)?;
+                            let uid = uid.try_into().map_err(|_| err())?;
+                            let user = users.get_mut(uid).ok_or_else(err)?;
 
-                        if user.owner != addr {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to stop typing as a non owned user",
-                            ));
-                        }
+                            if user.owner != owner {
+                                return Err(Error::other(
+                                    "Attempted to stop typing as a non owned user",
+                                ));
+                            }
 
-                        if !user.typing {
-                            return Err(Error::new(
-                                ErrorKind::Other,
-                                "Attempted to stop typing while not typing",
-                            ));
-                        }
+                            if !user.typing {
+                                return Err(Error::other(
+                                    "Attempted to stop typing while not typing",
+                                ));
+                            }
 
-                        user.typing = false;
+                            user.typing = false;
 
-                        let _ = group.sender.send(GroupUpdate {
-                            uid: uid.try_into().unwrap(),
-                            kind: GroupUpdateKind::TypingStop,
-                        });
+                            group.send(GroupUpdate {
+                                uid: uid.try_into().unwrap(),
+                                kind: GroupUpdateKind::TypingStop,
+                            });
+                        }
 
                         tracing::debug!(%gid, %uid, "Stop typing");
                     }
@@ -664,40 +1093,34 @@ async fn connection(
                             .ok()
                             .and_then(|id: usize| attachments.try_remove(id))
                             .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to download a nonexistent attachment",
-                                )
+                                Error::other("Attempted to download a nonexistent attachment")
                             })?;
 
+                        pending_attachments.fetch_sub(attachment.len(), Ordering::Relaxed);
+
                         config
-                            .write(
-                                &mut stream_write,
-                                &ServerMessage::Attachment {
-                                    data: attachment.as_slice().into(),
-                                },
-                            )
+                            .write_attachment(&mut stream_write, &attachment)
                             .await?;
 
                         tracing::debug!(%id, "Download attachment");
                     }
                     ClientMessage::IgnoreAttachment { id } => {
-                        let _ = id
+                        let attachment = id
                             .try_into()
                             .ok()
                             .and_then(|id: usize| attachments.try_remove(id))
                             .ok_or_else(|| {
-                                Error::new(
-                                    ErrorKind::Other,
-                                    "Attempted to ignore a nonexistent attachment",
-                                )
+                                Error::other("Attempted to ignore a nonexistent attachment")
                             })?;
 
+                        pending_attachments.fetch_sub(attachment.len(), Ordering::Relaxed);
+
                         tracing::debug!(%id, "Ignore attachment");
                     }
                     ClientMessage::Pong => tracing::trace!("Pong"),
                     ClientMessage::Shutdown => {
                         tracing::debug!("Shutdown");
+                        *resume_token = None;
                         return Ok(());
                     }
                 }
@@ -723,7 +1146,7 @@ async fn connection(
                     }
                 };
 
-                config.write(&mut stream_write, &message).await?;
+                config.write_unflushed(&mut stream_write, &message).await?;
 
                 if !init {
                     continue;
@@ -740,48 +1163,21 @@ async fn connection(
 
                 membership.newly_joined = false;
 
-                let groups = state.groups.read().await;
-                let users = groups[update.gid.try_into().unwrap()]
-                    .users
-                    .iter()
-                    .map(|(uid, user)| (uid, user.name.clone(), user.typing))
-                    .collect::<Vec<_>>();
-
-                drop(groups);
-
-                for (uid, name, typing) in users {
-                    config
-                        .write(
-                            &mut stream_write,
-                            &ServerMessage::InitUser {
-                                gid: update.gid,
-                                uid: uid.try_into().unwrap(),
-                                name: name.clone().into(),
-                            },
-                        )
-                        .await?;
+                let users = match state.group(update.gid).await {
+                    Some(group) => snapshot_users(&group.users.lock().unwrap()),
+                    None => continue,
+                };
 
-                    if typing {
-                        config
-                            .write(
-                                &mut stream_write,
-                                &ServerMessage::StartTyping {
-                                    gid: update.gid,
-                                    uid: uid.try_into().unwrap(),
-                                },
-                            )
-                            .await?;
-                    }
-                }
+                write_users(&mut stream_write, config, update.gid, users).await?;
             }
             LocalUpdate::Group((gid, update)) => {
                 ping_interval.reset();
 
-                let message = match update.kind {
+                let message = match &update.kind {
                     GroupUpdateKind::InitUser { name } => ServerMessage::InitUser {
                         gid,
                         uid: update.uid,
-                        name: name.into(),
+                        name: name.as_str().into(),
                     },
                     GroupUpdateKind::DestroyUser => ServerMessage::DestroyUser {
                         gid,
@@ -790,7 +1186,7 @@ async fn connection(
                     GroupUpdateKind::Rename { name } => ServerMessage::Rename {
                         gid,
                         uid: update.uid,
-                        name: name.into(),
+                        name: name.as_str().into(),
                     },
                     GroupUpdateKind::Message {
                         message,
@@ -799,7 +1195,8 @@ async fn connection(
                         let mut message_attachments = Vec::new();
                         for attachment in update_attachments {
                             let len = attachment.len();
-                            let id = attachments.insert(attachment);
+                            let id = attachments.insert(attachment.clone());
+                            pending_attachments.fetch_add(len, Ordering::Relaxed);
 
                             message_attachments.push(Attachment {
                                 id: id.try_into().unwrap(),
@@ -810,7 +1207,7 @@ async fn connection(
                         ServerMessage::Message {
                             gid,
                             uid: update.uid,
-                            message: message.into(),
+                            message: (&**message).into(),
                             attachments: message_attachments,
                         }
                     }
@@ -824,13 +1221,14 @@ async fn connection(
                     },
                 };
 
-                config.write(&mut stream_write, &message).await?;
+                config.write_unflushed(&mut stream_write, &message).await?;
             }
+            LocalUpdate::Moderated(message) => deliver(state, &client, owner, message)?,
             LocalUpdate::Ping => {
                 tracing::trace!("Sending ping");
 
                 config
-                    .write(&mut stream_write, &ServerMessage::Ping)
+                    .write_unflushed(&mut stream_write, &ServerMessage::Ping)
                     .await?;
 
                 ping_interval.reset();
@@ -838,28 +1236,480 @@ async fn connection(
 
                 waiting_pong = true;
             }
+            LocalUpdate::Terminate(reason) => {
+                // Terminated sessions must not be resumed.
+                *resume_token = None;
+
+                config
+                    .write(
+                        &mut stream_write,
+                        &ServerMessage::Goodbye {
+                            reason: (&*reason).into(),
+                        },
+                    )
+                    .await?;
+
+                return Err(Error::other(reason));
+            }
         }
     }
 }
 
+/// Sends a message which passed moderation to its group, charging it to the quota of the sender.
+fn deliver(state: &State, client: &Client, owner: u64, message: Pending) -> Result<(), Error> {
+    // The user may have been destroyed while the message was being moderated.
+    let owned = message
+        .group
+        .users
+        .lock()
+        .unwrap()
+        .get(message.uid)
+        .is_some_and(|user| user.owner == owner);
+
+    if !owned {
+        tracing::debug!(gid = %message.gid, uid = %message.uid, "Dropped message of a destroyed user");
+        return Ok(());
+    }
+
+    let size = message
+        .attachments
+        .iter()
+        .map(|attachment| attachment.len() as u64)
+        .sum::<u64>();
+
+    state.with_usage(&client.access_token, |usage| {
+        let attachment_bytes = usage.attachment_bytes();
+
+        if let Some(limit) = client.quota.attachment_bytes {
+            if *attachment_bytes + size > limit as u64 {
+                return Err(Error::other("Attachment quota exceeded"));
+            }
+        }
+
+        *attachment_bytes += size;
+        Ok(())
+    })?;
+
+    state.webhooks.emit(Event::Message {
+        group: &message.group.name,
+        user: &message.user,
+        message: &message.message,
+        attachments: message.attachments.len(),
+    });
+
+    tracing::debug!(gid = %message.gid, uid = %message.uid, msg = ?message.message, "Send message");
+
+    message
+        .group
+        .record_message(message.message.len() as u64 + size);
+
+    message.group.send(GroupUpdate {
+        uid: message.uid.try_into().unwrap(),
+        kind: GroupUpdateKind::Message {
+            message: message.message.into(),
+            attachments: message.attachments,
+        },
+    });
+
+    Ok(())
+}
+
+/// Maximum time a written message may wait for a flush.
+const MAX_FLUSH_DELAY: Duration = Duration::from_millis(10);
+
+/// Returns the ID, name and typing status of all users in a group.
+fn snapshot_users(users: &Slab<User>) -> Vec<(u32, String, bool)> {
+    users
+        .iter()
+        .map(|(uid, user)| (uid.try_into().unwrap(), user.name.clone(), user.typing))
+        .collect()
+}
+
+/// Writes the initial state of users in a group.
+async fn write_users(
+    stream: &mut (impl AsyncWrite + Unpin),
+    config: Config,
+    gid: u32,
+    users: Vec<(u32, String, bool)>,
+) -> Result<(), Error> {
+    for (uid, name, typing) in users {
+        config
+            .write_unflushed(
+                stream,
+                &ServerMessage::InitUser {
+                    gid,
+                    uid,
+                    name: name.into(),
+                },
+            )
+            .await?;
+
+        if typing {
+            config
+                .write_unflushed(stream, &ServerMessage::StartTyping { gid, uid })
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
 struct State {
     update_buffer: usize,
-    access_tokens: HashMap<AccessToken, Groups>,
-    groups: RwLock<Slab<Group>>,
+    group_settings: Vec<GroupSettings>,
+    group_names: GroupNames,
+    duplicate_user_names: DuplicateNames,
+    limits: Limits,
+    idle: Idle,
+    slow_consumers: SlowConsumers,
+    tcp: Tcp,
+    ping_interval: Duration,
+    ping_timeout: Duration,
+    handshake_timeout: Duration,
+    resume_window: Option<Duration>,
+    compression: bool,
+    // Detached sessions of disconnected clients awaiting resumption.
+    sessions: Mutex<HashMap<ResumeToken, Session>>,
+    next_owner: AtomicU64,
+    access_tokens: RwLock<HashMap<AccessToken, Arc<Client>>>,
+    connections: Mutex<HashMap<SocketAddr, ConnectionHandle>>,
+    // Number of connections per IP address which haven't finished the handshake yet.
+    unauthenticated: Mutex<HashMap<IpAddr, usize>>,
+    // Only locked for writing when creating or destroying groups.
+    groups: RwLock<Slab<Arc<Group>>>,
     sender: Sender<GlobalUpdate>,
+    usage: Mutex<HashMap<AccessToken, Usage>>,
+    // Expiration of bans.
+    bans: Mutex<HashMap<BanTarget, Instant>>,
+    moderator: Arc<Moderator>,
+    webhooks: Webhooks,
+}
+
+impl State {
+    fn is_banned(&self, target: &BanTarget) -> bool {
+        let mut bans = self.bans.lock().unwrap();
+
+        match bans.get(target) {
+            Some(expiration) if *expiration > Instant::now() => true,
+            Some(_) => {
+                bans.remove(target);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Returns settings of a group.
+    fn group_settings(&self, name: &str) -> Option<&GroupSettings> {
+        self.group_settings
+            .iter()
+            .find(|settings| settings.pattern.is_match(name))
+    }
+
+    /// Returns the update buffer capacity of a group.
+    fn group_update_buffer(&self, name: &str) -> usize {
+        self.group_settings(name)
+            .and_then(|settings| settings.update_buffer)
+            .map(NonZeroUsize::get)
+            .unwrap_or(self.update_buffer)
+    }
+
+    fn new_group(&self, name: String, persistent: bool) -> Group {
+        Group {
+            sender: broadcast::channel(self.group_update_buffer(&name)).0,
+            dedup_window: self
+                .group_settings(&name)
+                .and_then(|settings| settings.dedup_window),
+            name,
+            users: Mutex::new(Slab::new()),
+            persistent: AtomicBool::new(persistent),
+            stats: Mutex::new(GroupStats {
+                messages: 0,
+                bytes: 0,
+                peak_users: 0,
+                last_activity: Instant::now(),
+            }),
+            direct: Mutex::new(None),
+        }
+    }
+
+    /// Returns a group, the groups lock is not held afterwards.
+    async fn group(&self, gid: u32) -> Option<Arc<Group>> {
+        let gid = gid.try_into().ok()?;
+        self.groups.read().await.get(gid).cloned()
+    }
+
+    /// Announces destruction of a group, must be called with the groups write lock held.
+    fn destroy_group(&self, gid: u32, group: &Group) {
+        let _ = self.sender.send(GlobalUpdate {
+            gid,
+            kind: GlobalUpdateKind::DestroyGroup,
+        });
+
+        self.webhooks
+            .emit(Event::GroupDestroyed { group: &group.name });
+
+        tracing::debug!(%gid, name = ?group.name, "Destroyed group");
+    }
+
+    /// Removes users owned by a connection and garbage collects unused groups.
+    async fn cleanup(&self, access_token: AccessToken, owner: u64) {
+        let removed_users = self
+            .groups
+            .read()
+            .await
+            .iter()
+            .map(|(_, group)| group.cleanup_users(owner))
+            .sum::<usize>();
+
+        let mut groups = self.groups.write().await;
+        groups.retain(|gid, group| {
+            if group.is_unused() {
+                self.destroy_group(gid.try_into().unwrap(), group);
+                return false;
+            }
+
+            true
+        });
+
+        drop(groups);
+
+        self.with_usage(&access_token, |usage| usage.users -= removed_users);
+    }
+
+    /// Keeps users and memberships of a disconnected connection until resumed or the window expires.
+    async fn detach(
+        self: &Arc<Self>,
+        resume_token: ResumeToken,
+        access_token: AccessToken,
+        owner: u64,
+        memberships: HashMap<u32, Membership>,
+        window: Duration,
+    ) {
+        // Subscribe before the memberships are gone, so that the groups can't be garbage collected.
+        let groups = {
+            let groups = self.groups.read().await;
+
+            memberships
+                .keys()
+                .map(|&gid| {
+                    let group = groups[gid.try_into().unwrap()].clone();
+                    let receiver = group.sender.subscribe();
+
+                    (gid, group, receiver)
+                })
+                .collect()
+        };
+
+        for (_, membership) in memberships {
+            membership.handle.abort();
+            let _ = membership.handle.await;
+        }
+
+        self.sessions.lock().unwrap().insert(
+            resume_token,
+            Session {
+                access_token,
+                owner,
+                groups,
+            },
+        );
+
+        tracing::debug!("Detached session");
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            time::sleep(window).await;
+
+            let session = state.sessions.lock().unwrap().remove(&resume_token);
+            if let Some(session) = session {
+                drop(session.groups);
+                state.cleanup(session.access_token, session.owner).await;
+
+                tracing::debug!(%owner, "Expired session");
+            }
+        });
+    }
+
+    /// Runs a closure with the usage of an authenticated access token.
+    fn with_usage<T>(&self, access_token: &AccessToken, f: impl FnOnce(&mut Usage) -> T) -> T {
+        f(self.usage.lock().unwrap().get_mut(access_token).unwrap())
+    }
+}
+
+/// Accounts an unauthenticated connection of an IP address until dropped.
+struct Unauthenticated {
+    state: Arc<State>,
+    ip: IpAddr,
+}
+
+impl Unauthenticated {
+    /// Returns `None` if the IP address has too many unauthenticated connections.
+    fn new(state: &Arc<State>, ip: IpAddr) -> Option<Self> {
+        let mut unauthenticated = state.unauthenticated.lock().unwrap();
+        let count = unauthenticated.entry(ip).or_insert(0);
+
+        if let Some(limit) = state.limits.unauthenticated_per_ip {
+            if *count >= limit.get() {
+                return None;
+            }
+        }
+
+        *count += 1;
+
+        Some(Self {
+            state: state.clone(),
+            ip,
+        })
+    }
+}
+
+impl Drop for Unauthenticated {
+    fn drop(&mut self) {
+        let mut unauthenticated = self.state.unauthenticated.lock().unwrap();
+        let count = unauthenticated.get_mut(&self.ip).unwrap();
+
+        *count -= 1;
+        if *count == 0 {
+            unauthenticated.remove(&self.ip);
+        }
+    }
+}
+
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum BanTarget {
+    Ip(IpAddr),
+    AccessToken(AccessToken),
+}
+
+/// Handle to an authenticated connection.
+struct ConnectionHandle {
+    access_token: AccessToken,
+    owner: u64,
+    // Bytes of attachments received but not yet downloaded or ignored.
+    pending_attachments: Arc<AtomicUsize>,
+    // Terminates the connection with a reason.
+    terminate: mpsc::Sender<String>,
+}
+
+impl ConnectionHandle {
+    fn terminate(&self, reason: &str) {
+        // The connection is already terminating if the channel is full or closed.
+        let _ = self.terminate.try_send(reason.to_owned());
+    }
+}
+
+/// Resource usage accounted to an access token.
+#[derive(Default)]
+struct Usage {
+    connections: usize,
+    users: usize,
+    attachment_bytes: u64,
+    // Start of the current attachment accounting window.
+    window: Option<Instant>,
+}
+
+impl Usage {
+    const WINDOW: Duration = Duration::from_secs(60 * 60);
+
+    /// Attachment bytes sent in the current window.
+    fn attachment_bytes(&mut self) -> &mut u64 {
+        let now = Instant::now();
+
+        match self.window {
+            Some(window) if now.duration_since(window) < Self::WINDOW => {}
+            _ => {
+                self.window = Some(now);
+                self.attachment_bytes = 0;
+            }
+        }
+
+        &mut self.attachment_bytes
+    }
 }
 
 struct Group {
     name: String,
-    users: Slab<User>,
+    // Locked while sending updates about users, so that they are ordered.
+    users: Mutex<Slab<User>>,
     sender: Sender<GroupUpdate>,
+    // Declared in config or imported, never garbage collected.
+    persistent: AtomicBool,
+    dedup_window: Option<Duration>,
+    stats: Mutex<GroupStats>,
+    // Set while the group has a single subscriber since it joined, which is sent updates
+    // directly instead of going through the broadcast channel and its relay task.
+    direct: Mutex<Option<Direct>>,
 }
 
 impl Group {
-    fn cleanup_users(&mut self, addr: SocketAddr) {
-        self.users.retain(|uid, user| {
-            if user.owner == addr {
-                let _ = self.sender.send(GroupUpdate {
+    fn send(&self, mut update: GroupUpdate) {
+        let mut direct = self.direct.lock().unwrap();
+
+        if let Some(subscriber) = &*direct {
+            if self.sender.receiver_count() == 1 {
+                let Err(err) = subscriber.sender.try_send(Ok((subscriber.gid, update))) else {
+                    return;
+                };
+
+                let Ok((_, returned)) = err.into_inner() else {
+                    unreachable!();
+                };
+
+                update = returned;
+            }
+
+            // Either another subscriber appeared or the connection fell behind, from now on
+            // updates must go through the relay task so that they are not reordered.
+            *direct = None;
+        }
+
+        let _ = self.sender.send(update);
+    }
+
+    /// Stops sending updates directly to a connection which left the group.
+    fn remove_direct(&self, sender: &mpsc::Sender<Result<(u32, GroupUpdate), String>>) {
+        let mut direct = self.direct.lock().unwrap();
+
+        if direct
+            .as_ref()
+            .is_some_and(|subscriber| subscriber.sender.same_channel(sender))
+        {
+            *direct = None;
+        }
+    }
+
+    fn record_activity(&self) {
+        self.stats.lock().unwrap().last_activity = Instant::now();
+    }
+
+    fn record_message(&self, bytes: u64) {
+        let mut stats = self.stats.lock().unwrap();
+        stats.messages += 1;
+        stats.bytes += bytes;
+        stats.last_activity = Instant::now();
+    }
+
+    fn is_unused(&self) -> bool {
+        !self.persistent.load(Ordering::Relaxed) && self.sender.receiver_count() == 0
+    }
+
+    /// Removes users owned by a connection, returning how many were removed.
+    fn cleanup_users(&self, owner: u64) -> usize {
+        let mut users = self.users.lock().unwrap();
+        let len = users.len();
+
+        users.retain(|uid, user| {
+            if user.owner == owner {
+                self.send(GroupUpdate {
                     uid: uid.try_into().unwrap(),
                     kind: GroupUpdateKind::DestroyUser,
                 });
@@ -869,14 +1719,146 @@ impl Group {
 
             true
         });
+
+        len - users.len()
     }
 }
 
+/// Counters of a group reported by the admin interface.
+struct GroupStats {
+    messages: u64,
+    // Message text and attachments.
+    bytes: u64,
+    peak_users: usize,
+    // Last message or change of users.
+    last_activity: Instant,
+}
+
 struct User {
     name: String,
     typing: bool,
-    // Owning connection.
-    owner: SocketAddr,
+    // Hashes of messages sent within the dedup window, oldest first.
+    recent: VecDeque<(Instant, u64)>,
+    // Owning connection, or a detached session.
+    owner: u64,
+}
+
+impl User {
+    /// Returns whether a message was already sent within the window, remembering it otherwise.
+    fn is_duplicate(&mut self, hash: u64, window: Duration) -> bool {
+        let now = Instant::now();
+
+        while self
+            .recent
+            .front()
+            .is_some_and(|(sent, _)| now.duration_since(*sent) >= window)
+        {
+            self.recent.pop_front();
+        }
+
+        if self.recent.iter().any(|(_, recent)| *recent == hash) {
+            return true;
+        }
+
+        self.recent.push_back((now, hash));
+        false
+    }
+}
+
+fn message_hash(message: &str, attachments: &[Cow<[u8]>]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    message.hash(&mut hasher);
+    attachments.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Users and memberships of a disconnected connection which may be resumed.
+struct Session {
+    access_token: AccessToken,
+    owner: u64,
+    // Keeps the groups alive while detached.
+    groups: Vec<(u32, Arc<Group>, broadcast::Receiver<GroupUpdate>)>,
+}
+
+/// Forwards updates of a group to a connection, applying the slow consumer policy when it falls behind.
+async fn forward_updates(
+    gid: u32,
+    mut receiver: broadcast::Receiver<GroupUpdate>,
+    sender: mpsc::Sender<Result<(u32, GroupUpdate), String>>,
+    capacity: usize,
+    policy: SlowConsumers,
+) {
+    let mut queue = VecDeque::new();
+    let mut behind_since = None;
+
+    loop {
+        tokio::select! {
+            result = receiver.recv() => {
+                match result {
+                    Ok(update) => queue.push_back(update),
+                    Err(RecvError::Lagged(num)) => {
+                        let _ = sender.send(Err(format!("Skipped {} group update(s)", num))).await;
+                        return;
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+
+                if queue.len() > capacity {
+                    shed_updates(&mut queue, &policy);
+                }
+
+                if queue.len() <= capacity {
+                    behind_since = None;
+                    continue;
+                }
+
+                let behind_since = *behind_since.get_or_insert_with(Instant::now);
+                let grace_period = policy.grace_period.unwrap_or(Duration::ZERO);
+
+                // Don't let the queue grow without bounds during the grace period.
+                if behind_since.elapsed() >= grace_period || queue.len() > 4 * capacity {
+                    let reason = format!("Fell behind by {} group update(s)", queue.len() - capacity);
+                    let _ = sender.send(Err(reason)).await;
+                    return;
+                }
+            }
+            permit = sender.reserve(), if !queue.is_empty() => {
+                let Ok(permit) = permit else {
+                    return;
+                };
+
+                permit.send(Ok((gid, queue.pop_front().unwrap())));
+            }
+        }
+    }
+}
+
+/// Removes updates superseded by a newer update of the same user, as allowed by the policy.
+fn shed_updates(queue: &mut VecDeque<GroupUpdate>, policy: &SlowConsumers) {
+    let mut typing = HashSet::new();
+    let mut renames = HashSet::new();
+
+    // Walk backwards so that the newest update of each user is kept.
+    let keep = queue
+        .iter()
+        .rev()
+        .map(|update| match update.kind {
+            GroupUpdateKind::StartTyping | GroupUpdateKind::TypingStop if policy.drop_typing => {
+                typing.insert(update.uid)
+            }
+            GroupUpdateKind::Rename { .. } if policy.coalesce_renames => renames.insert(update.uid),
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+
+    let mut keep = keep.into_iter().rev();
+    queue.retain(|_| keep.next().unwrap());
+}
+
+struct Direct {
+    gid: u32,
+    sender: mpsc::Sender<Result<(u32, GroupUpdate), String>>,
 }
 
 struct Membership {
@@ -913,8 +1895,9 @@ enum GroupUpdateKind {
     },
     DestroyUser,
     Message {
-        message: String,
-        attachments: Vec<Arc<Vec<u8>>>,
+        // Shared between all subscribers.
+        message: Arc<str>,
+        attachments: Vec<Bytes>,
     },
     StartTyping,
     TypingStop,
@@ -922,3 +1905,44 @@ enum GroupUpdateKind {
         name: String,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shed_keeps_newest() {
+        let update = |uid, kind| GroupUpdate { uid, kind };
+        let rename = |name: &str| GroupUpdateKind::Rename { name: name.into() };
+
+        let mut queue = VecDeque::from([
+            update(0, GroupUpdateKind::StartTyping),
+            update(0, rename("a")),
+            update(1, GroupUpdateKind::StartTyping),
+            update(0, GroupUpdateKind::TypingStop),
+            update(0, rename("b")),
+            update(0, GroupUpdateKind::DestroyUser),
+        ]);
+
+        let policy = SlowConsumers {
+            drop_typing: true,
+            coalesce_renames: true,
+            grace_period: None,
+        };
+
+        shed_updates(&mut queue, &policy);
+
+        let kept = queue
+            .iter()
+            .map(|update| match &update.kind {
+                GroupUpdateKind::StartTyping => format!("{} start", update.uid),
+                GroupUpdateKind::TypingStop => format!("{} stop", update.uid),
+                GroupUpdateKind::Rename { name } => format!("{} rename {}", update.uid, name),
+                GroupUpdateKind::DestroyUser => format!("{} destroy", update.uid),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(kept, ["1 start", "0 stop", "0 rename b", "0 destroy"]);
+    }
+}
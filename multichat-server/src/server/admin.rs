@@ -0,0 +1,704 @@
+//! Administrative interface exposed over a Unix socket.
+//!
+//! Each line received is a command, which is answered with a single line of JSON.
+//! The state dump is also logged when SIGUSR1 is received.
+use super::webhooks::Event;
+use super::{BanTarget, ConnectionHandle, GlobalUpdate, GlobalUpdateKind, State};
+use crate::config::{Client, Export, Groups, Quota, Scope};
+
+use multichat_proto::AccessToken;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{self, SignalKind};
+use toml::value::Table;
+use tracing::Instrument;
+
+pub fn spawn(path: &Path, state: Arc<State>) -> Result<(), Error> {
+    // Remove a stale socket left behind by a previous instance.
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    tracing::info!("Admin socket listening on {}", path.display());
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(err) => {
+                    tracing::error!("Admin socket error: {}", err);
+                    continue;
+                }
+            };
+
+            let span = tracing::info_span!("admin");
+            tokio::spawn(connection(stream, state.clone()).instrument(span));
+        }
+    });
+
+    Ok(())
+}
+
+/// Logs the state dump whenever SIGUSR1 is received.
+pub fn spawn_signal(state: Arc<State>) -> Result<(), Error> {
+    let mut signal = unix::signal(SignalKind::user_defined1())?;
+
+    tokio::spawn(async move {
+        while signal.recv().await.is_some() {
+            let dump = dump(&state).await;
+            tracing::info!(state = %dump, "State dump");
+        }
+    });
+
+    Ok(())
+}
+
+async fn connection(stream: UnixStream, state: Arc<State>) {
+    let (stream_read, mut stream_write) = stream.into_split();
+    let mut lines = BufReader::new(stream_read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        tracing::debug!(?line, "Admin command");
+
+        let response = match command(&line, &state).await {
+            Ok(response) => response,
+            Err(err) => json!({ "error": err }),
+        };
+
+        let mut response = response.to_string();
+        response.push('\n');
+
+        if stream_write.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+async fn command(line: &str, state: &State) -> Result<Value, String> {
+    let mut args = line.split_whitespace();
+    let command = args.next().ok_or("Empty command")?;
+
+    let response = match command {
+        "usage" => usage(state).await,
+        "token" => match args.next().ok_or("Missing argument")? {
+            "list" => token_list(state).await,
+            "add" => {
+                let access_token = parse_token(args.next())?;
+                let groups = args.next().ok_or("Missing argument")?;
+                let scope = match args.next() {
+                    Some("read-only") => Scope::ReadOnly,
+                    Some("read-write") | None => Scope::ReadWrite,
+                    Some(_) => return Err("Invalid scope".into()),
+                };
+
+                token_add(state, access_token, groups, scope).await?
+            }
+            "revoke" => token_revoke(state, parse_token(args.next())?).await?,
+            _ => return Err("Invalid argument".into()),
+        },
+        "kick" => {
+            let target = parse_target(args.next())?;
+            let reason = parse_reason(&mut args, "Kicked");
+
+            json!({ "terminated": terminate(state, target, &reason) })
+        }
+        "ban" => {
+            let target = parse_target(args.next())?;
+            let duration = humantime::parse_duration(args.next().ok_or("Missing argument")?)
+                .map_err(|err| err.to_string())?;
+            let reason = parse_reason(&mut args, "Banned");
+
+            ban(state, target, duration, &reason)
+        }
+        "unban" => {
+            let target = parse_target(args.next())?.ban_target();
+            if state.bans.lock().unwrap().remove(&target).is_none() {
+                return Err("Not banned".into());
+            }
+
+            json!({})
+        }
+        "bans" => bans(state),
+        "dump" => dump(state).await,
+        "export" => export(state, Path::new(args.next().ok_or("Missing argument")?)).await?,
+        "import" => import(state, Path::new(args.next().ok_or("Missing argument")?)).await?,
+        "stats" => {
+            let idle = match args.next() {
+                Some(arg) => Some(humantime::parse_duration(arg).map_err(|err| err.to_string())?),
+                None => None,
+            };
+
+            stats(state, idle).await
+        }
+        _ => return Err(format!("Unknown command: {}", command)),
+    };
+
+    if args.next().is_some() {
+        return Err("Extra argument".into());
+    }
+
+    Ok(response)
+}
+
+/// Connections targeted by a command.
+enum Target {
+    Addr(SocketAddr),
+    Ip(IpAddr),
+    AccessToken(AccessToken),
+}
+
+impl Target {
+    fn matches(&self, addr: &SocketAddr, connection: &ConnectionHandle) -> bool {
+        match self {
+            Target::Addr(target) => target == addr,
+            Target::Ip(ip) => *ip == addr.ip(),
+            Target::AccessToken(access_token) => *access_token == connection.access_token,
+        }
+    }
+
+    fn ban_target(&self) -> BanTarget {
+        match self {
+            // Ports change between connections, so ban the whole address.
+            Target::Addr(addr) => BanTarget::Ip(addr.ip()),
+            Target::Ip(ip) => BanTarget::Ip(*ip),
+            Target::AccessToken(access_token) => BanTarget::AccessToken(*access_token),
+        }
+    }
+}
+
+fn parse_target(arg: Option<&str>) -> Result<Target, String> {
+    let arg = arg.ok_or("Missing argument")?;
+
+    if let Ok(addr) = arg.parse() {
+        return Ok(Target::Addr(addr));
+    }
+
+    if let Ok(ip) = arg.parse() {
+        return Ok(Target::Ip(ip));
+    }
+
+    arg.parse()
+        .map(Target::AccessToken)
+        .map_err(|_| "Invalid target, expected an address or an access token".into())
+}
+
+/// Rest of the arguments, joined by spaces.
+fn parse_reason<'a>(args: &mut impl Iterator<Item = &'a str>, default: &str) -> String {
+    let reason = args.collect::<Vec<_>>().join(" ");
+    if reason.is_empty() {
+        return default.to_owned();
+    }
+
+    reason
+}
+
+fn parse_token(arg: Option<&str>) -> Result<AccessToken, String> {
+    arg.ok_or("Missing argument")?
+        .parse()
+        .map_err(|_| "Invalid access token".into())
+}
+
+async fn usage(state: &State) -> Value {
+    let access_tokens = state.access_tokens.read().await;
+    let mut usage = state.usage.lock().unwrap();
+
+    let usage = access_tokens
+        .iter()
+        .map(|(access_token, client)| {
+            let usage = usage.entry(*access_token).or_default();
+            let quota = &client.quota;
+
+            json!({
+                "access_token": access_token.to_string(),
+                "name": client.name,
+                "connections": usage.connections,
+                "users": usage.users,
+                "attachment_bytes": *usage.attachment_bytes(),
+                "quota": {
+                    "connections": quota.connections,
+                    "users": quota.users,
+                    "attachment_bytes": quota.attachment_bytes,
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "usage": usage })
+}
+
+async fn token_list(state: &State) -> Value {
+    let access_tokens = state
+        .access_tokens
+        .read()
+        .await
+        .values()
+        .map(|client| {
+            let groups = match &client.groups {
+                Groups::All => json!("*"),
+                Groups::Some(groups) => json!(groups),
+            };
+
+            let scope = match client.scope {
+                Scope::ReadOnly => "read-only",
+                Scope::ReadWrite => "read-write",
+            };
+
+            json!({
+                "access_token": client.access_token.to_string(),
+                "name": client.name,
+                "groups": groups,
+                "scope": scope,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "access_tokens": access_tokens })
+}
+
+/// Adds an access token until the server is restarted.
+async fn token_add(
+    state: &State,
+    access_token: AccessToken,
+    groups: &str,
+    scope: Scope,
+) -> Result<Value, String> {
+    let groups = match groups {
+        "*" => Groups::All,
+        groups => Groups::Some(groups.split(',').map(str::to_owned).collect()),
+    };
+
+    let groups = groups
+        .normalize(&state.group_names)
+        .map_err(|(name, err)| format!("Invalid group name {:?}: {}", name, err))?;
+
+    let client = Client {
+        access_token,
+        name: None,
+        groups,
+        scope,
+        quota: Quota::default(),
+        slow_consumers: None,
+    };
+
+    let mut access_tokens = state.access_tokens.write().await;
+    if access_tokens.contains_key(&access_token) {
+        return Err("Duplicate access token".into());
+    }
+
+    access_tokens.insert(access_token, Arc::new(client));
+
+    tracing::info!(%access_token, "Added access token");
+
+    Ok(json!({}))
+}
+
+/// Revokes an access token and terminates all connections authenticated with it.
+async fn token_revoke(state: &State, access_token: AccessToken) -> Result<Value, String> {
+    let mut access_tokens = state.access_tokens.write().await;
+    if access_tokens.remove(&access_token).is_none() {
+        return Err("Unknown access token".into());
+    }
+
+    tracing::info!(%access_token, "Revoked access token");
+
+    let terminated = terminate(
+        state,
+        Target::AccessToken(access_token),
+        "Access token revoked",
+    );
+
+    Ok(json!({ "terminated": terminated }))
+}
+
+/// Terminates all matching connections, returning their count.
+fn terminate(state: &State, target: Target, reason: &str) -> usize {
+    terminate_matching(&state.connections.lock().unwrap(), &target, reason)
+}
+
+fn terminate_matching(
+    connections: &HashMap<SocketAddr, ConnectionHandle>,
+    target: &Target,
+    reason: &str,
+) -> usize {
+    let mut terminated = 0;
+    for (addr, connection) in connections {
+        if target.matches(addr, connection) {
+            connection.terminate(reason);
+            terminated += 1;
+        }
+    }
+
+    tracing::info!(%terminated, ?reason, "Terminated connections");
+
+    terminated
+}
+
+fn ban(state: &State, target: Target, duration: Duration, reason: &str) -> Value {
+    // Lock connections first so that the ban can't be missed by a connection that is just authenticating.
+    let connections = state.connections.lock().unwrap();
+
+    let expiration = Instant::now() + duration;
+    state
+        .bans
+        .lock()
+        .unwrap()
+        .insert(target.ban_target(), expiration);
+
+    // Kicking by address would leave other connections from the banned IP address alive.
+    let target = match target {
+        Target::Addr(addr) => Target::Ip(addr.ip()),
+        target => target,
+    };
+
+    tracing::info!(?duration, "Banned");
+
+    let terminated = terminate_matching(&connections, &target, reason);
+
+    json!({ "terminated": terminated })
+}
+
+fn bans(state: &State) -> Value {
+    let now = Instant::now();
+
+    let mut bans = state.bans.lock().unwrap();
+    bans.retain(|_, expiration| *expiration > now);
+
+    let bans = bans
+        .iter()
+        .map(|(target, expiration)| {
+            let target = match target {
+                BanTarget::Ip(ip) => ip.to_string(),
+                BanTarget::AccessToken(access_token) => access_token.to_string(),
+            };
+
+            json!({
+                "target": target,
+                "remaining_secs": (*expiration - now).as_secs(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "bans": bans })
+}
+
+/// Reports statistics of groups, optionally only of those idle for at least the given duration.
+async fn stats(state: &State, idle: Option<Duration>) -> Value {
+    let groups = state
+        .groups
+        .read()
+        .await
+        .iter()
+        .filter_map(|(gid, group)| {
+            let users = group.users.lock().unwrap().len();
+            let stats = group.stats.lock().unwrap();
+            let idle_for = stats.last_activity.elapsed();
+
+            if idle.is_some_and(|idle| idle_for < idle) {
+                return None;
+            }
+
+            Some(json!({
+                "gid": gid,
+                "name": group.name,
+                "persistent": group.persistent.load(Ordering::Relaxed),
+                "subscribers": group.sender.receiver_count(),
+                "users": users,
+                "peak_users": stats.peak_users,
+                "messages": stats.messages,
+                "bytes": stats.bytes,
+                "idle_secs": idle_for.as_secs(),
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    json!({ "groups": groups })
+}
+
+/// Writes persistent groups and access tokens to a file, using the syntax of the config file.
+async fn export(state: &State, path: &Path) -> Result<Value, String> {
+    let mut persistent_groups = state
+        .groups
+        .read()
+        .await
+        .iter()
+        .filter(|(_, group)| group.persistent.load(Ordering::Relaxed))
+        .map(|(_, group)| group.name.clone())
+        .collect::<Vec<_>>();
+
+    persistent_groups.sort();
+
+    let clients = state
+        .access_tokens
+        .read()
+        .await
+        .values()
+        .map(|client| export_client(client))
+        .collect::<Vec<_>>();
+
+    let response = json!({
+        "persistent_groups": persistent_groups.len(),
+        "access_tokens": clients.len(),
+    });
+
+    let mut export = Table::new();
+    export.insert("persistent-groups".into(), persistent_groups.into());
+    export.insert("clients".into(), clients.into());
+
+    let export = toml::to_string(&toml::Value::Table(export)).map_err(|err| err.to_string())?;
+    write_private(path, &export)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    tracing::info!(?path, "Exported state");
+
+    Ok(response)
+}
+
+// Exports hold access tokens, so they are only readable by the owner, even while being written.
+async fn write_private(path: &Path, data: &str) -> Result<(), Error> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    // Left behind by an export which failed before renaming it.
+    match tokio::fs::remove_file(&temporary).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&temporary)
+        .await?;
+
+    file.write_all(data.as_bytes()).await?;
+    file.sync_all().await?;
+
+    tokio::fs::rename(&temporary, path).await
+}
+
+fn export_client(client: &Client) -> toml::Value {
+    let mut table = Table::new();
+    table.insert(
+        "access-token".into(),
+        client.access_token.to_string().into(),
+    );
+
+    if let Some(name) = &client.name {
+        table.insert("name".into(), name.clone().into());
+    }
+
+    let groups = match &client.groups {
+        Groups::All => "*".into(),
+        Groups::Some(groups) => {
+            let mut groups = groups.iter().cloned().collect::<Vec<_>>();
+            groups.sort();
+            groups.into()
+        }
+    };
+
+    table.insert("groups".into(), groups);
+
+    let scope = match client.scope {
+        Scope::ReadOnly => "read-only",
+        Scope::ReadWrite => "read-write",
+    };
+
+    table.insert("scope".into(), scope.into());
+
+    let quota = &client.quota;
+    let mut table_quota = Table::new();
+
+    if let Some(connections) = quota.connections {
+        table_quota.insert("connections".into(), (connections.get() as i64).into());
+    }
+
+    if let Some(users) = quota.users {
+        table_quota.insert("users".into(), (users.get() as i64).into());
+    }
+
+    if let Some(attachment_bytes) = quota.attachment_bytes {
+        table_quota.insert(
+            "attachment-bytes".into(),
+            format!("{} B", attachment_bytes).into(),
+        );
+    }
+
+    if !table_quota.is_empty() {
+        table.insert("quota".into(), table_quota.into());
+    }
+
+    if let Some(slow_consumers) = &client.slow_consumers {
+        let mut table_slow = Table::new();
+        table_slow.insert("drop-typing".into(), slow_consumers.drop_typing.into());
+        table_slow.insert(
+            "coalesce-renames".into(),
+            slow_consumers.coalesce_renames.into(),
+        );
+
+        if let Some(grace_period) = slow_consumers.grace_period {
+            table_slow.insert(
+                "grace-period".into(),
+                humantime::format_duration(grace_period).to_string().into(),
+            );
+        }
+
+        table.insert("slow-consumers".into(), table_slow.into());
+    }
+
+    table.into()
+}
+
+/// Adds persistent groups and access tokens from a file written by `export`.
+///
+/// Access tokens already present are replaced, which only affects new connections.
+async fn import(state: &State, path: &Path) -> Result<Value, String> {
+    let export = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| err.to_string())?;
+    let export: Export = toml::from_str(&export).map_err(|err| err.to_string())?;
+
+    // Validate everything before changing anything.
+    let persistent_groups = export
+        .persistent_groups
+        .iter()
+        .map(|name| {
+            state
+                .group_names
+                .normalize(name)
+                .map_err(|err| format!("Invalid group name {:?}: {}", name, err))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let clients = export
+        .clients
+        .into_iter()
+        .map(|mut client| {
+            client.groups = client
+                .groups
+                .normalize(&state.group_names)
+                .map_err(|(name, err)| format!("Invalid group name {:?}: {}", name, err))?;
+
+            Ok(client)
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let response = json!({
+        "persistent_groups": persistent_groups.len(),
+        "access_tokens": clients.len(),
+    });
+
+    {
+        let mut groups = state.groups.write().await;
+
+        for name in persistent_groups {
+            if let Some((_, group)) = groups.iter().find(|(_, group)| group.name == name) {
+                group.persistent.store(true, Ordering::Relaxed);
+                continue;
+            }
+
+            let gid = groups.insert(Arc::new(state.new_group(name.clone(), true)));
+
+            let _ = state.sender.send(GlobalUpdate {
+                gid: gid.try_into().unwrap(),
+                kind: GlobalUpdateKind::InitGroup { name: name.clone() },
+            });
+
+            state.webhooks.emit(Event::GroupCreated { group: &name });
+        }
+    }
+
+    let mut access_tokens = state.access_tokens.write().await;
+    for client in clients {
+        access_tokens.insert(client.access_token, Arc::new(client));
+    }
+
+    tracing::info!(?path, "Imported state");
+
+    Ok(response)
+}
+
+/// Dumps groups, connections and detached sessions.
+///
+/// Users are owned by connections or sessions with the same owner ID.
+async fn dump(state: &State) -> Value {
+    let groups = state
+        .groups
+        .read()
+        .await
+        .iter()
+        .map(|(gid, group)| {
+            let users = group
+                .users
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(uid, user)| {
+                    json!({
+                        "uid": uid,
+                        "name": user.name,
+                        "typing": user.typing,
+                        "owner": user.owner,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            json!({
+                "gid": gid,
+                "name": group.name,
+                "persistent": group.persistent.load(Ordering::Relaxed),
+                "subscribers": group.sender.receiver_count(),
+                "users": users,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let connections = state
+        .connections
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(addr, connection)| {
+            json!({
+                "addr": addr.to_string(),
+                "owner": connection.owner,
+                "access_token": connection.access_token.to_string(),
+                "pending_attachment_bytes": connection.pending_attachments.load(Ordering::Relaxed),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let sessions = state
+        .sessions
+        .lock()
+        .unwrap()
+        .values()
+        .map(|session| {
+            json!({
+                "owner": session.owner,
+                "access_token": session.access_token.to_string(),
+                "groups": session.groups.iter().map(|(gid, _, _)| gid).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "groups": groups,
+        "connections": connections,
+        "sessions": sessions,
+    })
+}
@@ -0,0 +1,199 @@
+use super::{AbortOnDrop, Group};
+use crate::config::{Action, Hook, HookKind, Moderation};
+
+use bytes::Bytes;
+use serde_json::json;
+use std::io::Error;
+use std::mem;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time;
+
+// Messages of a connection waiting for the hook before the connection stops reading more.
+const QUEUE_SIZE: usize = 16;
+
+pub struct Moderator {
+    moderation: Moderation,
+    http: reqwest::Client,
+}
+
+impl Moderator {
+    pub fn new(moderation: Moderation) -> Self {
+        Self {
+            moderation,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn has_hook(&self) -> bool {
+        self.moderation.hook.is_some()
+    }
+
+    /// Applies filters and the hook to a message, returning the message to deliver or `None` if it should be dropped.
+    pub async fn moderate(
+        &self,
+        group: &str,
+        user: &str,
+        mut message: String,
+        attachments: usize,
+    ) -> Option<String> {
+        for filter in &self.moderation.filters {
+            if !filter.pattern.is_match(&message) {
+                continue;
+            }
+
+            match filter.action {
+                Action::Drop => {
+                    tracing::info!(?group, ?user, pattern = %filter.pattern, "Dropped message");
+                    return None;
+                }
+                Action::Censor => {
+                    message = filter
+                        .pattern
+                        .replace_all(&message, |captures: &regex::Captures| {
+                            "*".repeat(captures[0].chars().count())
+                        })
+                        .into_owned();
+                }
+                Action::Flag => {
+                    tracing::warn!(?group, ?user, text = ?message, pattern = %filter.pattern, "Flagged message");
+                }
+            }
+        }
+
+        let Some(hook) = &self.moderation.hook else {
+            return Some(message);
+        };
+
+        let payload = json!({
+            "group": group,
+            "user": user,
+            "message": message,
+            "attachments": attachments,
+        });
+
+        let timeout = hook.timeout.unwrap_or(Duration::from_secs(5));
+        let result = match time::timeout(timeout, self.run_hook(hook, payload.to_string())).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::other("Timed out")),
+        };
+
+        match result {
+            Ok(true) => Some(message),
+            Ok(false) => {
+                tracing::info!(?group, ?user, "Message vetoed by hook");
+                None
+            }
+            Err(err) => {
+                tracing::warn!(?group, ?user, "Moderation hook failed: {}", err);
+                hook.fail_open.then_some(message)
+            }
+        }
+    }
+
+    /// Runs the hook, returning whether it accepted the message.
+    async fn run_hook(&self, hook: &Hook, payload: String) -> Result<bool, Error> {
+        match &hook.kind {
+            HookKind::Command { command } => {
+                let (program, args) = command
+                    .split_first()
+                    .ok_or_else(|| Error::other("Empty command"))?;
+
+                let mut child = Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()?;
+
+                let mut stdin = child.stdin.take().unwrap();
+                stdin.write_all(payload.as_bytes()).await?;
+                drop(stdin);
+
+                Ok(child.wait().await?.success())
+            }
+            HookKind::Http { url } => {
+                let response = self
+                    .http
+                    .post(url)
+                    .header("Content-Type", "application/json")
+                    .body(payload)
+                    .send()
+                    .await
+                    .map_err(Error::other)?;
+
+                Ok(response.status().is_success())
+            }
+        }
+    }
+}
+
+/// Message which passed the checks done on receipt and waits for moderation.
+pub struct Pending {
+    pub gid: u32,
+    pub uid: usize,
+    pub group: Arc<Group>,
+    pub user: String,
+    pub message: String,
+    pub attachments: Vec<Bytes>,
+}
+
+/// Moderates messages of a connection in the order they were sent, in a task of its own so
+/// that the connection keeps being served while the hook runs.
+pub struct Queue {
+    sender: mpsc::Sender<Pending>,
+    receiver: mpsc::UnboundedReceiver<Pending>,
+    _task: AbortOnDrop,
+}
+
+impl Queue {
+    pub fn spawn(moderator: Arc<Moderator>) -> Self {
+        let (sender, mut pending) = mpsc::channel::<Pending>(QUEUE_SIZE);
+        // Unbounded so that the task never waits for the connection while the connection waits
+        // for room in the queue, it holds at most as many messages as the queue.
+        let (accepted, receiver) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(async move {
+            while let Some(mut message) = pending.recv().await {
+                let text = moderator
+                    .moderate(
+                        &message.group.name,
+                        &message.user,
+                        mem::take(&mut message.message),
+                        message.attachments.len(),
+                    )
+                    .await;
+
+                if let Some(text) = text {
+                    message.message = text;
+                    if accepted.send(message).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender,
+            receiver,
+            _task: AbortOnDrop(task),
+        }
+    }
+
+    pub async fn push(&self, message: Pending) {
+        // The task only ends when the queue is dropped.
+        let _ = self.sender.send(message).await;
+    }
+
+    /// Next message accepted by moderation, cancel safe.
+    pub async fn next(&mut self) -> Pending {
+        match self.receiver.recv().await {
+            Some(message) => message,
+            None => std::future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use super::AbortOnDrop;
+use crate::config::{self, Webhook};
+
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time;
+
+// Events waiting for delivery to a single webhook, newer ones are dropped when it can't keep up.
+const QUEUE_SIZE: usize = 256;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    Message {
+        group: &'a str,
+        user: &'a str,
+        message: &'a str,
+        attachments: usize,
+    },
+    UserJoined {
+        group: &'a str,
+        user: &'a str,
+    },
+    GroupCreated {
+        group: &'a str,
+    },
+    GroupDestroyed {
+        group: &'a str,
+    },
+}
+
+impl Event<'_> {
+    fn kind(&self) -> config::Event {
+        match self {
+            Event::Message { .. } => config::Event::Message,
+            Event::UserJoined { .. } => config::Event::UserJoined,
+            Event::GroupCreated { .. } => config::Event::GroupCreated,
+            Event::GroupDestroyed { .. } => config::Event::GroupDestroyed,
+        }
+    }
+
+    fn group(&self) -> &str {
+        match self {
+            Event::Message { group, .. }
+            | Event::UserJoined { group, .. }
+            | Event::GroupCreated { group }
+            | Event::GroupDestroyed { group } => group,
+        }
+    }
+}
+
+pub struct Webhooks {
+    workers: Vec<Worker>,
+}
+
+struct Worker {
+    webhook: Arc<Webhook>,
+    sender: mpsc::Sender<String>,
+    _task: AbortOnDrop,
+}
+
+impl Webhooks {
+    pub fn new(webhooks: Vec<Webhook>) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .unwrap();
+
+        let workers = webhooks
+            .into_iter()
+            .map(|webhook| {
+                let webhook = Arc::new(webhook);
+                let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+                let task = tokio::spawn(work(http.clone(), webhook.clone(), receiver));
+
+                Worker {
+                    webhook,
+                    sender,
+                    _task: AbortOnDrop(task),
+                }
+            })
+            .collect();
+
+        Self { workers }
+    }
+
+    /// Queues an event for delivery to all interested webhooks in the background.
+    pub fn emit(&self, event: Event) {
+        let mut body = None;
+
+        for worker in &self.workers {
+            let webhook = &worker.webhook;
+            if !webhook.events.contains(&event.kind()) {
+                continue;
+            }
+
+            if let Some(groups) = &webhook.groups {
+                if !groups.contains(event.group()) {
+                    continue;
+                }
+            }
+
+            let body = body
+                .get_or_insert_with(|| serde_json::to_string(&event).unwrap())
+                .clone();
+
+            match worker.sender.try_send(body) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!(url = %webhook.url, "Webhook queue full, dropping event")
+                }
+                Err(TrySendError::Closed(_)) => unreachable!(),
+            }
+        }
+    }
+}
+
+/// Delivers events to a webhook one by one, in the order they were emitted.
+async fn work(http: reqwest::Client, webhook: Arc<Webhook>, mut receiver: mpsc::Receiver<String>) {
+    while let Some(body) = receiver.recv().await {
+        deliver(&http, &webhook, body).await;
+    }
+}
+
+async fn deliver(http: &reqwest::Client, webhook: &Webhook, body: String) {
+    let mut backoff = Duration::from_secs(1);
+
+    for attempt in 0..=webhook.retries {
+        if attempt != 0 {
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_secs(60));
+        }
+
+        let result = http
+            .post(&webhook.url)
+            .header("Content-Type", "application/json")
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        match result {
+            Ok(_) => return,
+            Err(err) => {
+                tracing::warn!(url = %webhook.url, %attempt, "Webhook delivery failed: {}", err)
+            }
+        }
+    }
+
+    tracing::error!(url = %webhook.url, "Giving up webhook delivery");
+}
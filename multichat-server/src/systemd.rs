@@ -0,0 +1,57 @@
+//! Integration with systemd socket activation and service notifications.
+//!
+//! Everything here does nothing when the server isn't started by systemd.
+use sd_notify::NotifyState;
+use std::io::Error;
+use std::net::TcpListener;
+use std::os::fd::FromRawFd;
+use std::time::Duration;
+use tokio::time;
+
+/// Takes the listening socket passed by socket activation.
+///
+/// Must be called before any threads are spawned, because it modifies the environment.
+pub fn listener() -> Result<Option<TcpListener>, Error> {
+    let mut fds = sd_notify::listen_fds()?;
+
+    let Some(fd) = fds.next() else {
+        return Ok(None);
+    };
+
+    if fds.next().is_some() {
+        return Err(Error::other("Expected a single socket from systemd"));
+    }
+
+    // SAFETY: The file descriptor was passed to this process and nothing else owns it.
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    // Fails if the socket isn't a TCP socket.
+    listener.local_addr()?;
+    listener.set_nonblocking(true)?;
+
+    Ok(Some(listener))
+}
+
+/// Notifies systemd that the server is ready and keeps its watchdog fed if enabled.
+pub fn ready() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        tracing::warn!("Error notifying systemd: {}", err);
+    }
+
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+
+    // Feed the watchdog from the runtime, so that it fires if the runtime gets stuck.
+    let mut interval = time::interval(Duration::from_micros(usec) / 2);
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                tracing::warn!("Error notifying systemd watchdog: {}", err);
+            }
+        }
+    });
+}
@@ -3,11 +3,15 @@ use std::fmt::Display;
 use std::future::Future;
 use std::io;
 use std::path::Path;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
 use thiserror::Error;
 use tokio::fs;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::TcpStream;
+use tokio::time;
 use tokio_rustls::rustls::{self, ServerConfig};
 use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
@@ -43,6 +47,81 @@ impl Acceptor for DefaultAcceptor {
     }
 }
 
+/// Accepts both TLS and plaintext connections, telling them apart by the first byte sent by the client.
+#[derive(Clone)]
+pub struct OptionalTlsAcceptor(pub TlsAcceptor);
+
+impl OptionalTlsAcceptor {
+    // Clients start with either a TLS handshake or their protocol version, this only covers clients waiting for the server.
+    const DETECTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+    // Content type of a TLS handshake record.
+    const HANDSHAKE: u8 = 0x16;
+}
+
+impl Acceptor for OptionalTlsAcceptor {
+    type Stream = MaybeTlsStream;
+    type Error = io::Error;
+
+    async fn accept(&self, stream: TcpStream) -> Result<Self::Stream, Self::Error> {
+        let mut byte = [0; 1];
+
+        match time::timeout(Self::DETECTION_TIMEOUT, stream.peek(&mut byte)).await {
+            Ok(Ok(1)) if byte[0] == Self::HANDSHAKE => {
+                let stream = self.0.accept(stream).await?;
+                Ok(MaybeTlsStream::Tls(Box::new(stream)))
+            }
+            Ok(Err(err)) => Err(err),
+            _ => Ok(MaybeTlsStream::Plain(stream)),
+        }
+    }
+}
+
+pub enum MaybeTlsStream {
+    Tls(Box<TlsStream<TcpStream>>),
+    Plain(TcpStream),
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            MaybeTlsStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error(transparent)]
@@ -60,7 +139,8 @@ pub async fn configure(certificate: &Path, key: &Path) -> Result<TlsAcceptor, Er
     let key = fs::read(key).await?;
     let key = rustls_pemfile::private_key(&mut &*key)?.ok_or(Error::NoKeys)?;
 
-    let config = ServerConfig::builder()
+    let config = ServerConfig::builder_with_provider(multichat_proto::tls::provider())
+        .with_safe_default_protocol_versions()?
         .with_no_client_auth()
         .with_single_cert(certificates, key)?;
 
@@ -0,0 +1,119 @@
+//! WebSocket transport for browser clients.
+//!
+//! The protocol is carried unchanged in binary messages, which form a byte stream. Message boundaries are
+//! irrelevant, a frame of the protocol may be split across several messages or share one with other frames.
+use crate::tls::Acceptor;
+
+use bytes::{Buf, Bytes};
+use futures_util::{Sink, Stream};
+use std::io::{self, Error, ErrorKind};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+/// Accepts WebSocket connections on top of another acceptor, which may for example provide TLS.
+#[derive(Clone)]
+pub struct WebSocketAcceptor<T>(pub T);
+
+impl<T: Acceptor> Acceptor for WebSocketAcceptor<T> {
+    type Stream = WebSocketStream<T::Stream>;
+    type Error = Error;
+
+    async fn accept(&self, stream: TcpStream) -> Result<Self::Stream, Self::Error> {
+        let stream = self
+            .0
+            .accept(stream)
+            .await
+            .map_err(|err| Error::other(err.to_string()))?;
+
+        let inner = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(into_io)?;
+
+        Ok(WebSocketStream {
+            inner,
+            read: Bytes::new(),
+        })
+    }
+}
+
+/// Byte stream carried in binary messages of a WebSocket connection.
+pub struct WebSocketStream<S> {
+    inner: tokio_tungstenite::WebSocketStream<S>,
+    // Unread part of the last received message.
+    read: Bytes,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        while this.read.is_empty() {
+            let message = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(message) => message.map_err(into_io)?,
+                None => return Poll::Ready(Ok(())),
+            };
+
+            match message {
+                Message::Binary(data) => this.read = data,
+                Message::Close(_) => return Poll::Ready(Ok(())),
+                // Pings are answered by tungstenite.
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+                Message::Text(_) => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Received a text message",
+                    )))
+                }
+            }
+        }
+
+        let len = this.read.len().min(buf.remaining());
+        buf.put_slice(&this.read[..len]);
+        this.read.advance(len);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut inner = Pin::new(&mut self.get_mut().inner);
+
+        ready!(inner.as_mut().poll_ready(cx)).map_err(into_io)?;
+        inner
+            .start_send(Message::Binary(Bytes::copy_from_slice(buf)))
+            .map_err(into_io)?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_flush(cx)
+            .map_err(into_io)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_close(cx)
+            .map_err(into_io)
+    }
+}
+
+fn into_io(err: tungstenite::Error) -> Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => Error::other(err),
+    }
+}
@@ -0,0 +1,64 @@
+//! Support for running the server in-process.
+use multichat_client::proto::{AccessToken, Config};
+use multichat_client::{Client, ClientBuilder, Update};
+use multichat_server::config::{Client as ClientConfig, Groups, Quota, Scope};
+use multichat_server::tls::DefaultAcceptor;
+use multichat_server::ServerBuilder;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+
+/// Maximum message size used by both sides, large enough to fill socket buffers quickly.
+pub const MAX_SIZE: usize = 1024 * 1024;
+
+pub fn access_token() -> AccessToken {
+    "52f0395327987f07f805c3ac54fe38ac123303fcdb62a61fdfc9b8082195486c"
+        .parse()
+        .unwrap()
+}
+
+/// Spawns a server on an ephemeral port, accepting [`access_token`] for all groups.
+pub async fn spawn(configure: impl FnOnce(&mut ServerBuilder<DefaultAcceptor>)) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut builder = ServerBuilder::basic();
+    builder
+        .listener(listener)
+        .config(config())
+        .client(ClientConfig {
+            access_token: access_token(),
+            name: None,
+            groups: Groups::All,
+            scope: Scope::ReadWrite,
+            quota: Quota::default(),
+            slow_consumers: None,
+        });
+
+    configure(&mut builder);
+
+    tokio::spawn(async move { builder.run().await.unwrap() });
+
+    addr
+}
+
+pub fn config() -> Config {
+    *Config::default().max_size(MAX_SIZE)
+}
+
+pub async fn connect(addr: SocketAddr) -> Client<TcpStream> {
+    ClientBuilder::basic()
+        .config(config())
+        .connect(addr, access_token())
+        .await
+        .unwrap()
+}
+
+/// Reads an update, failing the test if none arrives in time.
+pub async fn read_update(client: &mut Client<TcpStream>) -> Update {
+    time::timeout(Duration::from_secs(5), client.read_update())
+        .await
+        .expect("Timed out waiting for an update")
+        .unwrap()
+}
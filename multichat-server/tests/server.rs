@@ -0,0 +1,495 @@
+mod common;
+
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use multichat_client::proto::{AuthRequest, AuthResponse, Compression, Version};
+use multichat_client::{ClientBuilder, ConnectError, UpdateKind};
+use multichat_server::config::{
+    Client as ClientConfig, DuplicateNames, GroupSettings, Groups, Hook, HookKind, Limits,
+    Moderation, Quota, Scope,
+};
+use multichat_server::ServerBuilder;
+use regex::Regex;
+use std::borrow::Cow;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn invalid_access_token() {
+    let addr = common::spawn(|_| {}).await;
+
+    let access_token = "0000000000000000000000000000000000000000000000000000000000000000"
+        .parse()
+        .unwrap();
+
+    let result = ClientBuilder::basic().connect(addr, access_token).await;
+    assert!(matches!(result, Err(ConnectError::Auth)));
+}
+
+#[tokio::test]
+async fn message_fan_out() {
+    let addr = common::spawn(|_| {}).await;
+
+    let mut alice = common::connect(addr).await;
+    let mut bob = common::connect(addr).await;
+
+    let gid = alice.join_group("fun").await.unwrap();
+    assert_eq!(bob.join_group("fun").await.unwrap(), gid);
+
+    let uid = alice.init_user(gid, "alice").await.unwrap();
+    alice.send_message(gid, uid, "hello", &[]).await.unwrap();
+
+    for client in [&mut alice, &mut bob] {
+        let mut updates = Vec::new();
+        while updates.len() < 2 {
+            // The group is announced to both, depending on timing.
+            match common::read_update(client).await.kind {
+                UpdateKind::InitGroup { .. } => {}
+                kind => updates.push(kind),
+            }
+        }
+
+        assert!(
+            matches!(&updates[0], UpdateKind::InitUser { uid: id, name } if *id == uid && name == "alice")
+        );
+        assert!(
+            matches!(&updates[1], UpdateKind::Message { uid: id, message } if *id == uid && message.text == "hello")
+        );
+    }
+}
+
+#[tokio::test]
+async fn attachment_download() {
+    let addr = common::spawn(|_| {}).await;
+
+    let mut alice = common::connect(addr).await;
+    let mut bob = common::connect(addr).await;
+
+    let gid = alice.join_group("fun").await.unwrap();
+    bob.join_group("fun").await.unwrap();
+
+    let uid = alice.init_user(gid, "alice").await.unwrap();
+    let attachments = [Cow::Borrowed(&b"first"[..]), Cow::Borrowed(&b"second"[..])];
+    alice
+        .send_message(gid, uid, "files", &attachments)
+        .await
+        .unwrap();
+
+    let message = loop {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut bob).await.kind {
+            break message;
+        }
+    };
+
+    assert_eq!(message.attachments.len(), 2);
+    assert_eq!(message.attachments[1].size, 6);
+
+    // Attachments may be downloaded in any order, but only once.
+    let second = message.attachments[1].id;
+    assert_eq!(bob.download_attachment(second).await.unwrap(), b"second");
+    bob.ignore_attachment(message.attachments[0].id)
+        .await
+        .unwrap();
+
+    assert!(bob.download_attachment(second).await.is_err());
+}
+
+#[tokio::test]
+async fn slow_consumer_disconnected() {
+    let addr = common::spawn(|builder| {
+        builder.update_buffer(NonZeroUsize::new(4));
+    })
+    .await;
+
+    // Reads everything, including its own messages.
+    let mut sender = ClientBuilder::basic()
+        .config(common::config())
+        .incoming_buffer(4096)
+        .connect(addr, common::access_token())
+        .await
+        .unwrap();
+
+    // Never reads, so that the socket buffers fill up.
+    let mut slow = common::connect(addr).await;
+
+    let gid = sender.join_group("fun").await.unwrap();
+    slow.join_group("fun").await.unwrap();
+
+    let uid = sender.init_user(gid, "sender").await.unwrap();
+    let text = "x".repeat(common::MAX_SIZE / 2);
+
+    for _ in 0..256 {
+        sender.send_message(gid, uid, &text, &[]).await.unwrap();
+    }
+
+    let result = time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Err(err) = slow.read_update().await {
+                break err;
+            }
+        }
+    })
+    .await;
+
+    assert!(result.is_ok(), "Slow consumer was not disconnected");
+}
+
+#[tokio::test]
+async fn duplicate_user_names_suffixed() {
+    let addr = common::spawn(|builder| {
+        builder.duplicate_user_names(DuplicateNames::Suffix);
+    })
+    .await;
+
+    let mut client = common::connect(addr).await;
+    let gid = client.join_group("fun").await.unwrap();
+
+    client.init_user(gid, "bridge").await.unwrap();
+    client.init_user(gid, "bridge").await.unwrap();
+
+    let mut names = Vec::new();
+    while names.len() < 2 {
+        if let UpdateKind::InitUser { name, .. } = common::read_update(&mut client).await.kind {
+            names.push(name);
+        }
+    }
+
+    assert_eq!(names, ["bridge", "bridge (2)"]);
+}
+
+#[tokio::test]
+async fn message_limits() {
+    let addr = common::spawn(|builder| {
+        builder.limits(Limits {
+            message_length: NonZeroUsize::new(5),
+            attachment_size: Some(4),
+            ..Limits::default()
+        });
+    })
+    .await;
+
+    let mut client = common::connect(addr).await;
+    let gid = client.join_group("fun").await.unwrap();
+    let uid = client.init_user(gid, "user").await.unwrap();
+
+    client
+        .send_message(gid, uid, "short", &[Cow::Borrowed(&b"tiny"[..])])
+        .await
+        .unwrap();
+
+    loop {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut client).await.kind {
+            assert_eq!(message.text, "short");
+            break;
+        }
+    }
+
+    // Violations are protocol errors, so the client gets disconnected.
+    client
+        .send_message(gid, uid, "too long", &[])
+        .await
+        .unwrap();
+
+    let result = time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Err(err) = client.read_update().await {
+                break err;
+            }
+        }
+    })
+    .await;
+
+    assert!(result.is_ok(), "Client was not disconnected");
+}
+
+#[tokio::test]
+async fn vetoed_messages_not_charged() {
+    let access_token = "07e6a978bbed823e85e51b9702a73b5e1fe5599b01628a7cc076fadc737d071f"
+        .parse()
+        .unwrap();
+
+    let addr = common::spawn(|builder| {
+        builder
+            .moderation(Moderation {
+                filters: Vec::new(),
+                hook: Some(Hook {
+                    kind: HookKind::Command {
+                        command: ["sh", "-c", "! grep -q vetoed"].map(From::from).into(),
+                    },
+                    timeout: None,
+                    fail_open: false,
+                }),
+            })
+            .client(ClientConfig {
+                access_token,
+                name: None,
+                groups: Groups::All,
+                scope: Scope::ReadWrite,
+                quota: Quota {
+                    attachment_bytes: Some(6),
+                    ..Quota::default()
+                },
+                slow_consumers: None,
+            });
+    })
+    .await;
+
+    let mut client = ClientBuilder::basic()
+        .config(common::config())
+        .connect(addr, access_token)
+        .await
+        .unwrap();
+    let gid = client.join_group("fun").await.unwrap();
+    let uid = client.init_user(gid, "user").await.unwrap();
+
+    // Both attachments together would exceed the quota.
+    for text in ["vetoed", "accepted"] {
+        client
+            .send_message(gid, uid, text, &[Cow::Borrowed(&b"data"[..])])
+            .await
+            .unwrap();
+    }
+
+    loop {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut client).await.kind {
+            assert_eq!(message.text, "accepted");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn duplicate_messages_dropped() {
+    let addr = common::spawn(|builder| {
+        builder.group_settings(GroupSettings {
+            pattern: Regex::new("^fun$").unwrap(),
+            update_buffer: None,
+            dedup_window: Some(Duration::from_secs(60)),
+        });
+    })
+    .await;
+
+    let mut client = common::connect(addr).await;
+    let gid = client.join_group("fun").await.unwrap();
+    let uid = client.init_user(gid, "bridge").await.unwrap();
+
+    for text in ["retried", "retried", "next"] {
+        client.send_message(gid, uid, text, &[]).await.unwrap();
+    }
+
+    let mut messages = Vec::new();
+    while messages.len() < 2 {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut client).await.kind {
+            messages.push(message.text);
+        }
+    }
+
+    assert_eq!(messages, ["retried", "next"]);
+}
+
+#[tokio::test]
+async fn compressed_connection() {
+    let addr = common::spawn(|builder| {
+        builder.compression(true);
+    })
+    .await;
+
+    let mut alice = common::connect(addr).await;
+    let mut bob = ClientBuilder::basic()
+        .config(common::config())
+        .compression(Some(Compression::Deflate))
+        .connect(addr, common::access_token())
+        .await
+        .unwrap();
+
+    let gid = bob.join_group("fun").await.unwrap();
+    assert_eq!(alice.join_group("fun").await.unwrap(), gid);
+
+    let uid = alice.init_user(gid, "alice").await.unwrap();
+    let text = "compressible ".repeat(1000);
+    let attachment = vec![7; 100_000];
+    alice
+        .send_message(gid, uid, &text, &[Cow::Borrowed(&attachment[..])])
+        .await
+        .unwrap();
+
+    let message = loop {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut bob).await.kind {
+            break message;
+        }
+    };
+
+    assert_eq!(message.text, text);
+    assert_eq!(
+        bob.download_attachment(message.attachments[0].id)
+            .await
+            .unwrap(),
+        attachment
+    );
+}
+
+#[tokio::test]
+async fn websocket_handshake() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    common::spawn(|builder| {
+        builder.websocket_listener(listener);
+    })
+    .await;
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut websocket, _) = tokio_tungstenite::client_async(format!("ws://{}/", addr), stream)
+        .await
+        .unwrap();
+
+    // The byte stream may be split into messages arbitrarily.
+    let mut request = Vec::new();
+    Version::CURRENT.write(&mut request).await.unwrap();
+    let auth = AuthRequest {
+        access_token: common::access_token(),
+        resume: None,
+        compression: None,
+    };
+    common::config().write(&mut request, &auth).await.unwrap();
+
+    let (first, second) = request.split_at(1);
+    for part in [first, second] {
+        websocket
+            .send(Message::Binary(Bytes::copy_from_slice(part)))
+            .await
+            .unwrap();
+    }
+
+    let mut response = Vec::new();
+    let (version, auth) = loop {
+        match websocket.next().await.unwrap().unwrap() {
+            Message::Binary(data) => response.extend_from_slice(&data),
+            _ => continue,
+        }
+
+        let mut reader = &response[..];
+        let Ok(version) = Version::read(&mut reader).await else {
+            continue;
+        };
+
+        if let Ok(auth) = common::config().read::<AuthResponse>(&mut reader).await {
+            break (version, auth);
+        }
+    };
+
+    assert_eq!(version, Version::CURRENT);
+    assert!(matches!(auth, AuthResponse::Success { .. }));
+}
+
+#[tokio::test]
+async fn single_subscriber_order() {
+    let addr = common::spawn(|_| {}).await;
+
+    let mut alice = common::connect(addr).await;
+    let gid = alice.join_group("fun").await.unwrap();
+    let uid = alice.init_user(gid, "alice").await.unwrap();
+
+    // Sent directly while alice is the only subscriber, then through the broadcast channel.
+    alice.send_message(gid, uid, "first", &[]).await.unwrap();
+
+    let mut bob = common::connect(addr).await;
+    bob.join_group("fun").await.unwrap();
+    alice.send_message(gid, uid, "second", &[]).await.unwrap();
+
+    let mut messages = Vec::new();
+    while messages.len() < 2 {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut alice).await.kind {
+            messages.push(message.text);
+        }
+    }
+
+    assert_eq!(messages, ["first", "second"]);
+
+    let message = loop {
+        if let UpdateKind::Message { message, .. } = common::read_update(&mut bob).await.kind {
+            break message;
+        }
+    };
+
+    assert_eq!(message.text, "second");
+}
+
+#[tokio::test]
+async fn ready_after_binding() {
+    let (sender, receiver) = oneshot::channel();
+    let addr = common::spawn(|builder| {
+        builder.on_ready(|| sender.send(()).unwrap());
+    })
+    .await;
+
+    time::timeout(Duration::from_secs(5), receiver)
+        .await
+        .unwrap()
+        .unwrap();
+
+    common::connect(addr).await;
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn not_ready_on_failure() {
+    let (sender, receiver) = oneshot::channel::<()>();
+
+    let mut builder = ServerBuilder::basic();
+    builder
+        .listener(TcpListener::bind("127.0.0.1:0").await.unwrap())
+        .admin_socket(Some("/nonexistent/admin.sock".into()))
+        .on_ready(|| sender.send(()).unwrap());
+
+    assert!(builder.run().await.is_err());
+    assert!(receiver.await.is_err());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn export_private() {
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixStream;
+
+    let directory = std::env::temp_dir().join(format!("multichat-export-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let socket = directory.join("admin.sock");
+    let path = directory.join("export.toml");
+
+    // Readable by everyone before the export replaces it.
+    std::fs::write(&path, "").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    common::spawn(|builder| {
+        builder.admin_socket(Some(socket.clone()));
+    })
+    .await;
+
+    let stream = loop {
+        match UnixStream::connect(&socket).await {
+            Ok(stream) => break stream,
+            Err(_) => time::sleep(Duration::from_millis(10)).await,
+        }
+    };
+
+    let (read, mut write) = stream.into_split();
+    let command = format!("export {}\n", path.display());
+    write.write_all(command.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    BufReader::new(read).read_line(&mut response).await.unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    let export = std::fs::read_to_string(&path).unwrap();
+    let _ = std::fs::remove_dir_all(&directory);
+
+    assert!(!response.contains("error"), "{}", response);
+    assert_eq!(mode & 0o777, 0o600);
+    assert!(export.contains(&common::access_token().to_string()));
+}
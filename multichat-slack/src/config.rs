@@ -0,0 +1,48 @@
+use multichat_client::proto::AccessToken;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub slack: Slack,
+    pub multichat: Multichat,
+    pub chats: Vec<Chat>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Slack {
+    /// App-level token with the `connections:write` scope, events are received through Socket
+    /// Mode with it.
+    pub app_token: String,
+    /// Bot token the Web API is called with.
+    pub bot_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Chat {
+    pub multichat_group: String,
+    /// ID of the channel, not its name, which can change.
+    pub slack_channel: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        toml::from_str::<Config>(config).unwrap();
+    }
+}
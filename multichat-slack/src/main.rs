@@ -0,0 +1,186 @@
+mod config;
+mod mrkdwn;
+mod multichat;
+mod slack;
+mod tls;
+
+use clap::Parser;
+use config::Config;
+use multichat_client::proto::Config as ProtoConfig;
+use multichat_client::ClientBuilder;
+use slack::Slack;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::{mpsc, watch};
+use tracing::subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(help = "Path to config file")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().without_time().with_target(false));
+
+    subscriber::set_global_default(registry).unwrap();
+
+    // WebSocket connections build their TLS config with the default provider.
+    multichat_client::tls::install_default_provider();
+
+    let args = Args::parse();
+
+    tracing::info!("Reading config from {}", args.config.display());
+
+    let config = match fs::read_to_string(&args.config).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error reading config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = match toml::from_str::<Config>(&config) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error parsing config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let slack = Arc::new(Slack::new(config.slack.app_token, config.slack.bot_token));
+    if let Err(err) = slack.check().await {
+        tracing::error!("Error checking Slack bot token: {}", err);
+        return ExitCode::FAILURE;
+    }
+
+    let connector = match config.multichat.certificate {
+        Some(certificate) => match tls::configure(&certificate).await {
+            Ok(connector) => Some(connector),
+            Err(err) => {
+                tracing::error!("Error configuring TLS: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(512 * 1024 * 1024); // 512 MiB
+
+    let mut client = match ClientBuilder::maybe_tls(connector)
+        .config(proto_config)
+        .connect(&config.multichat.server, config.multichat.access_token)
+        .await
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("Error connecting to multichat: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing::info!("Connected to Multichat");
+
+    let mut channel_to_group = HashMap::new();
+    let mut group_to_channel = HashMap::new();
+
+    for chat in config.chats {
+        let gid = match client.join_group(&chat.multichat_group).await {
+            Ok(gid) => gid,
+            Err(err) => {
+                tracing::error!("Error joining group: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if channel_to_group.contains_key(&chat.slack_channel) {
+            tracing::error!(
+                "Slack channel {} is already associated with a Multichat group",
+                chat.slack_channel
+            );
+
+            return ExitCode::FAILURE;
+        }
+
+        if group_to_channel.contains_key(&gid) {
+            tracing::error!(
+                "Multichat group {} is already associated with a Slack channel",
+                chat.multichat_group
+            );
+
+            return ExitCode::FAILURE;
+        }
+
+        channel_to_group.insert(chat.slack_channel.clone(), gid);
+        group_to_channel.insert(gid, chat.slack_channel);
+    }
+
+    let shutdown_signal = match multichat_client::shutdown_signal() {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("Error handling signals: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (sender, receiver) = mpsc::channel(1);
+    let (shutdown_sender, shutdown) = watch::channel(());
+
+    let mut listener = tokio::spawn({
+        let slack = slack.clone();
+        async move { slack.listen(sender).await }
+    });
+
+    let mut multichat = tokio::spawn(async move {
+        multichat::run(
+            client,
+            &slack,
+            &channel_to_group,
+            &group_to_channel,
+            receiver,
+            shutdown,
+        )
+        .await
+    });
+
+    let result = tokio::select! {
+        result = &mut multichat => Some(result.unwrap()),
+        result = &mut listener => match result.unwrap() {
+            Ok(()) => None,
+            Err(err) => Some(Err(err.into())),
+        },
+        _ = shutdown_signal => None,
+    };
+
+    tracing::info!("Shutting down");
+
+    let _ = shutdown_sender.send(());
+    let result = match result {
+        Some(result) => result,
+        None => multichat.await.unwrap(),
+    };
+
+    listener.abort();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
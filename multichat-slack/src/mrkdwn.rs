@@ -0,0 +1,111 @@
+//! Conversion between Slack's mrkdwn and Multichat markup.
+//!
+//! Both style text with `*bold*`, `_italic_` and `` `code` ``, so only Slack's escaping and its
+//! `<...>` references to users, channels and links need converting.
+
+use std::collections::HashMap;
+
+/// IDs of users mentioned in a message, whose names are needed to convert it.
+pub fn mentions(text: &str) -> Vec<&str> {
+    references(text)
+        .filter_map(|reference| reference.strip_prefix('@'))
+        .map(|reference| reference.split('|').next().unwrap())
+        .collect()
+}
+
+/// Converts the text of a Slack message, mentioned users are named by `names` or left as IDs.
+pub fn from_slack(text: &str, names: &HashMap<&str, String>) -> String {
+    let mut converted = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        converted.push_str(&unescape(&rest[..start]));
+
+        let reference = &rest[start + 1..end];
+        let (target, label) = match reference.split_once('|') {
+            Some((target, label)) => (target, Some(label)),
+            None => (reference, None),
+        };
+
+        match (target.chars().next(), label) {
+            (Some('@'), _) => {
+                let id = &target[1..];
+                let name = names.get(id).map(String::as_str).or(label).unwrap_or(id);
+
+                converted.push('@');
+                converted.push_str(&unescape(name));
+            }
+            (Some('#'), Some(label)) => {
+                converted.push('#');
+                converted.push_str(&unescape(label));
+            }
+            // Special mentions such as `<!here>`, or user groups labeled with their handle.
+            (Some('!'), Some(label)) => converted.push_str(&unescape(label)),
+            (Some('!'), None) => {
+                converted.push('@');
+                converted.push_str(target[1..].split('^').next().unwrap());
+            }
+            (_, Some(label)) if unescape(label) != unescape(target) => {
+                converted.push_str(&unescape(label));
+                converted.push_str(" (");
+                converted.push_str(&unescape(target));
+                converted.push(')');
+            }
+            _ => converted.push_str(&unescape(target)),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    converted.push_str(&unescape(rest));
+    converted
+}
+
+/// Escapes text sent to Slack, so that it's not taken for references.
+pub fn to_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn references(text: &str) -> impl Iterator<Item = &str> {
+    text.split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(reference, _)| reference)
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_from_slack() {
+        let text = "<@U01> &lt;3 <#C01|general> <!here> <https://example.com|site> <https://example.com> <@U02>";
+        assert_eq!(mentions(text), ["U01", "U02"]);
+
+        let names = HashMap::from([("U01", "alice".to_owned())]);
+        assert_eq!(
+            from_slack(text, &names),
+            "@alice <3 #general @here site (https://example.com) https://example.com @U02"
+        );
+
+        assert_eq!(from_slack("a < b", &names), "a < b");
+    }
+
+    #[test]
+    fn converts_to_slack() {
+        assert_eq!(to_slack("<@U01> & co"), "&lt;@U01&gt; &amp; co");
+    }
+}
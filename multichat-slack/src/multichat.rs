@@ -0,0 +1,225 @@
+use multichat_client::{MaybeTlsClient, Update, UpdateKind};
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::watch;
+
+use crate::mrkdwn;
+use crate::slack::{self, Event as SlackEvent, Slack};
+
+// Names of Slack users are looked up again after this long, in case they changed.
+const NAME_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Slack(#[from] slack::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    slack: &Slack,
+    channel_to_group: &HashMap<String, u32>,
+    group_to_channel: &HashMap<u32, String>,
+    mut slack_receiver: Receiver<SlackEvent>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), Error> {
+    let mut users = HashMap::<(String, u32), SlackUser>::new();
+    let mut names = Names::default();
+    let mut groups = group_to_channel
+        .keys()
+        .map(|gid| (*gid, HashMap::new()))
+        .collect::<HashMap<_, HashMap<u32, MultichatUser>>>();
+
+    let mut owned = HashSet::new();
+
+    loop {
+        let event = tokio::select! {
+            event = slack_receiver.recv() => match event {
+                Some(event) => Event::Slack(event),
+                None => break,
+            },
+            update = client.read_update() => Event::Multichat(update?),
+            _ = shutdown.changed() => break,
+        };
+
+        match event {
+            Event::Slack(event) => {
+                let gid = match channel_to_group.get(&event.channel) {
+                    Some(gid) => *gid,
+                    None => {
+                        tracing::warn!(channel = %event.channel, "Slack channel not found");
+                        continue;
+                    }
+                };
+
+                let mut mentioned = HashMap::new();
+                for id in mrkdwn::mentions(&event.text) {
+                    // Users of other workspaces can be mentioned in shared channels.
+                    match names.get(slack, id).await {
+                        Ok(name) => {
+                            mentioned.insert(id, name);
+                        }
+                        Err(err) => tracing::warn!(%id, "Error getting mentioned user: {}", err),
+                    }
+                }
+
+                let text = mrkdwn::from_slack(&event.text, &mentioned);
+
+                // There is no editing in the protocol, edits are sent as new messages.
+                let text = match event.edited {
+                    true => Cow::Owned(format!("(edited) {}", text)),
+                    false => Cow::Borrowed(&*text),
+                };
+
+                let mut attachments = Vec::with_capacity(event.files.len());
+                for file in &event.files {
+                    match slack.download(&file.url).await {
+                        Ok(data) => attachments.push(Cow::Owned(data)),
+                        Err(err) => {
+                            tracing::warn!(name = %file.name, "Error downloading file: {}", err)
+                        }
+                    }
+                }
+
+                let name = names.get(slack, &event.user).await?;
+                let key = (event.user, gid);
+                let uid = slack_user(&mut client, &mut users, &mut owned, key, name).await?;
+
+                client.send_message(gid, uid, &text, &attachments).await?;
+            }
+            Event::Multichat(Update {
+                kind: UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup,
+                ..
+            }) => continue,
+            Event::Multichat(update) => {
+                let group = groups.get_mut(&update.gid).unwrap();
+                let channel = &group_to_channel[&update.gid];
+
+                match update.kind {
+                    UpdateKind::InitUser { uid, name } => {
+                        let owned = owned.remove(&(update.gid, uid));
+                        group.insert(uid, MultichatUser { name, owned });
+                    }
+                    UpdateKind::DestroyUser { uid } => {
+                        group.remove(&uid);
+                    }
+                    UpdateKind::Rename { uid, name } => {
+                        group.get_mut(&uid).unwrap().name = name;
+                    }
+                    UpdateKind::Message { uid, message } => {
+                        let user = &group[&uid];
+                        if user.owned {
+                            for attachment in message.attachments {
+                                client.ignore_attachment(attachment.id).await?;
+                            }
+
+                            continue;
+                        }
+
+                        let mut attachments = Vec::with_capacity(message.attachments.len());
+                        for attachment in message.attachments {
+                            attachments.push(client.download_attachment(attachment.id).await?);
+                        }
+
+                        if !message.text.is_empty() {
+                            let text = mrkdwn::to_slack(&message.text);
+                            slack.post_message(channel, &user.name, &text).await?;
+                        }
+
+                        // The protocol doesn't name attachments, Slack tells what they are from
+                        // their contents.
+                        for (idx, data) in attachments.into_iter().enumerate() {
+                            let name = format!("{}-{}", user.name, idx + 1);
+                            slack.upload(channel, &name, data).await?;
+                        }
+                    }
+                    UpdateKind::InitGroup { .. }
+                    | UpdateKind::DestroyGroup
+                    | UpdateKind::StartTyping { .. }
+                    | UpdateKind::StopTyping { .. } => {}
+                }
+            }
+        }
+    }
+
+    for ((_, gid), user) in users {
+        client.destroy_user(gid, user.uid).await?;
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
+
+async fn slack_user(
+    client: &mut MaybeTlsClient,
+    users: &mut HashMap<(String, u32), SlackUser>,
+    owned: &mut HashSet<(u32, u32)>,
+    key: (String, u32),
+    name: String,
+) -> Result<u32, Error> {
+    let gid = key.1;
+    let user = match users.entry(key) {
+        Entry::Occupied(entry) => {
+            let user = entry.into_mut();
+            if user.name != name {
+                client.rename_user(gid, user.uid, &name).await?;
+                user.name = name;
+            }
+
+            user
+        }
+        Entry::Vacant(entry) => {
+            let uid = client.init_user(gid, &name).await?;
+            owned.insert((gid, uid));
+
+            entry.insert(SlackUser { name, uid })
+        }
+    };
+
+    Ok(user.uid)
+}
+
+/// Names of Slack users by their IDs, which messages refer to them by.
+#[derive(Default)]
+struct Names {
+    names: HashMap<String, (Instant, String)>,
+}
+
+impl Names {
+    async fn get(&mut self, slack: &Slack, id: &str) -> Result<String, Error> {
+        if let Some((fetched, name)) = self.names.get(id) {
+            if fetched.elapsed() < NAME_TTL {
+                return Ok(name.clone());
+            }
+        }
+
+        let name = slack.user_name(id).await?;
+        self.names
+            .insert(id.to_owned(), (Instant::now(), name.clone()));
+
+        Ok(name)
+    }
+}
+
+enum Event {
+    Slack(SlackEvent),
+    Multichat(Update),
+}
+
+struct SlackUser {
+    name: String,
+    uid: u32,
+}
+
+struct MultichatUser {
+    name: String,
+    owned: bool,
+}
@@ -0,0 +1,397 @@
+use futures_util::{SinkExt, StreamExt};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc::Sender;
+use tokio::time;
+use tokio_tungstenite::tungstenite::{self, Message as WsMessage};
+
+const API_URL: &str = "https://slack.com/api/";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    WebSocket(#[from] tungstenite::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Slack API error: {0}")]
+    Api(String),
+}
+
+/// A message posted in a Slack channel.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Event {
+    pub channel: String,
+    pub user: String,
+    pub text: String,
+    pub files: Vec<File>,
+    pub edited: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct File {
+    pub name: String,
+    pub url: String,
+}
+
+/// Client of the Slack Web API.
+pub struct Slack {
+    http: reqwest::Client,
+    app_token: String,
+    bot_token: String,
+}
+
+impl Slack {
+    pub fn new(app_token: String, bot_token: String) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            app_token,
+            bot_token,
+        }
+    }
+
+    /// Checks that the bot token is valid, the app token is checked when connecting.
+    pub async fn check(&self) -> Result<(), Error> {
+        self.call::<Value>(&self.bot_token, "auth.test", &[])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Display name of a user, falling back to their full name and then their username.
+    pub async fn user_name(&self, user: &str) -> Result<String, Error> {
+        #[derive(Deserialize)]
+        struct UserInfo {
+            user: User,
+        }
+
+        #[derive(Deserialize)]
+        struct User {
+            name: String,
+            #[serde(default)]
+            profile: Profile,
+        }
+
+        #[derive(Deserialize, Default)]
+        struct Profile {
+            #[serde(default)]
+            display_name: String,
+            #[serde(default)]
+            real_name: String,
+        }
+
+        let info: UserInfo = self
+            .call(&self.bot_token, "users.info", &[("user", user)])
+            .await?;
+
+        let User { name, profile } = info.user;
+        let name = [profile.display_name, profile.real_name, name]
+            .into_iter()
+            .find(|name| !name.is_empty())
+            .unwrap_or_else(|| user.to_owned());
+
+        Ok(name)
+    }
+
+    /// Posts a message on behalf of a Multichat user, which is shown with their name.
+    pub async fn post_message(
+        &self,
+        channel: &str,
+        user_name: &str,
+        text: &str,
+    ) -> Result<(), Error> {
+        let params = [
+            ("channel", channel),
+            ("username", user_name),
+            ("text", text),
+        ];
+        self.call::<Value>(&self.bot_token, "chat.postMessage", &params)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Uploads a file and shares it in a channel.
+    pub async fn upload(&self, channel: &str, name: &str, data: Vec<u8>) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Upload {
+            upload_url: String,
+            file_id: String,
+        }
+
+        let length = data.len().to_string();
+        let upload: Upload = self
+            .call(
+                &self.bot_token,
+                "files.getUploadURLExternal",
+                &[("filename", name), ("length", &length)],
+            )
+            .await?;
+
+        self.http
+            .post(&upload.upload_url)
+            .body(data)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let files = serde_json::json!([{ "id": upload.file_id, "title": name }]).to_string();
+        self.call::<Value>(
+            &self.bot_token,
+            "files.completeUploadExternal",
+            &[("files", &files), ("channel_id", channel)],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Downloads a private file shared in a channel the bot is in.
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>, Error> {
+        let response = self
+            .http
+            .get(url)
+            .bearer_auth(&self.bot_token)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.bytes().await?.into())
+    }
+
+    /// Receives messages through Socket Mode until the receiver is gone, reconnecting whenever
+    /// Slack closes the connection.
+    pub async fn listen(&self, sender: Sender<Event>) -> Result<(), Error> {
+        #[derive(Deserialize)]
+        struct Connection {
+            url: String,
+        }
+
+        loop {
+            let connection: Connection = self
+                .call(&self.app_token, "apps.connections.open", &[])
+                .await?;
+
+            let (mut socket, _) = match tokio_tungstenite::connect_async(&connection.url).await {
+                Ok(socket) => socket,
+                Err(err) => {
+                    tracing::warn!("Error connecting to Slack: {}", err);
+                    time::sleep(Duration::from_secs(5)).await;
+                    continue;
+                }
+            };
+
+            tracing::info!("Connected to Slack");
+
+            while let Some(message) = socket.next().await {
+                let text = match message {
+                    Ok(WsMessage::Text(text)) => text,
+                    Ok(WsMessage::Close(_)) => break,
+                    Ok(_) => continue,
+                    Err(err) => {
+                        tracing::warn!("Error receiving from Slack: {}", err);
+                        break;
+                    }
+                };
+
+                let envelope = serde_json::from_str::<Envelope>(&text)?;
+
+                // Slack sends events again unless they are acknowledged in time.
+                if let Some(envelope_id) = &envelope.envelope_id {
+                    let ack = serde_json::json!({ "envelope_id": envelope_id });
+                    socket.send(WsMessage::text(ack.to_string())).await?;
+                }
+
+                if envelope.kind == "disconnect" {
+                    break;
+                }
+
+                let event = envelope
+                    .payload
+                    .and_then(|payload| payload.event)
+                    .and_then(translate_event);
+
+                if let Some(event) = event {
+                    if sender.send(event).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+
+            tracing::info!("Reconnecting to Slack");
+        }
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        method: &str,
+        params: &[(&str, &str)],
+    ) -> Result<T, Error> {
+        let url = format!("{}{}", API_URL, method);
+
+        loop {
+            let response = self
+                .http
+                .post(&url)
+                .bearer_auth(token)
+                .form(params)
+                .send()
+                .await?;
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                let seconds = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(1);
+
+                tracing::warn!(method, "Rate limited, waiting {} seconds", seconds);
+                time::sleep(Duration::from_secs(seconds)).await;
+                continue;
+            }
+
+            let value =
+                serde_json::from_slice::<Value>(&response.error_for_status()?.bytes().await?)?;
+
+            return match value.get("ok").and_then(Value::as_bool) {
+                Some(true) => Ok(serde_json::from_value(value)?),
+                _ => {
+                    let error = value
+                        .get("error")
+                        .and_then(Value::as_str)
+                        .unwrap_or("unknown");
+                    Err(Error::Api(error.to_owned()))
+                }
+            };
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(rename = "type")]
+    kind: String,
+    envelope_id: Option<String>,
+    payload: Option<Payload>,
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    event: Option<MessageEvent>,
+}
+
+#[derive(Deserialize)]
+struct MessageEvent {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    subtype: Option<String>,
+    channel: Option<String>,
+    user: Option<String>,
+    bot_id: Option<String>,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    files: Vec<FileInfo>,
+    /// The message after editing, when the subtype is `message_changed`.
+    message: Option<Box<MessageEvent>>,
+}
+
+#[derive(Deserialize)]
+struct FileInfo {
+    name: Option<String>,
+    url_private_download: Option<String>,
+}
+
+// Only messages of users are bridged, not those of bots, which include the ones bridged to Slack,
+// nor joins, topic changes and the like.
+fn translate_event(event: MessageEvent) -> Option<Event> {
+    if event.kind.as_deref() != Some("message") {
+        return None;
+    }
+
+    let channel = event.channel.clone()?;
+    let (message, edited) = match event.subtype.as_deref() {
+        None | Some("file_share" | "thread_broadcast" | "me_message") => (event, false),
+        Some("message_changed") => (*event.message?, true),
+        Some(_) => return None,
+    };
+
+    if message.bot_id.is_some() {
+        return None;
+    }
+
+    let files = message
+        .files
+        .into_iter()
+        .filter_map(|file| {
+            Some(File {
+                name: file.name.unwrap_or_default(),
+                url: file.url_private_download?,
+            })
+        })
+        .collect();
+
+    Some(Event {
+        channel,
+        user: message.user?,
+        text: message.text,
+        // Files aren't shared again on edits.
+        files: if edited { Vec::new() } else { files },
+        edited,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(json: &str) -> Option<Event> {
+        translate_event(serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn translates_events() {
+        let event = translate(
+            r#"{"type": "message", "channel": "C01", "user": "U01", "text": "hi",
+                "files": [{"name": "a.png", "url_private_download": "https://files.slack.com/a.png"}],
+                "subtype": "file_share"}"#,
+        );
+
+        assert_eq!(
+            event,
+            Some(Event {
+                channel: "C01".to_owned(),
+                user: "U01".to_owned(),
+                text: "hi".to_owned(),
+                files: vec![File {
+                    name: "a.png".to_owned(),
+                    url: "https://files.slack.com/a.png".to_owned(),
+                }],
+                edited: false,
+            })
+        );
+
+        let edited = translate(
+            r#"{"type": "message", "subtype": "message_changed", "channel": "C01",
+                "message": {"type": "message", "user": "U01", "text": "hello"}}"#,
+        );
+
+        assert!(edited.is_some_and(|event| event.edited && event.text == "hello"));
+
+        let bridged = r#"{"type": "message", "subtype": "bot_message", "channel": "C01",
+                          "bot_id": "B01", "username": "alice", "text": "hi"}"#;
+        assert_eq!(translate(bridged), None);
+
+        let joined = r#"{"type": "message", "subtype": "channel_join", "channel": "C01",
+                         "user": "U01", "text": "<@U01> has joined the channel"}"#;
+        assert_eq!(translate(joined), None);
+    }
+}
@@ -0,0 +1,100 @@
+use crate::telegram::Topic;
+
+use teloxide::prelude::Requester;
+use teloxide::types::{
+    Chat, ChatKind, ChatMemberKind, ChatPermissions, PublicChatKind, PublicChatSupergroup, UserId,
+};
+use teloxide::{Bot, RequestError};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Error getting chat, check that the bot was added to it: {0}")]
+    Chat(RequestError),
+    #[error("Error getting the bot's membership: {0}")]
+    Member(RequestError),
+    #[error("The bot is not a member, add it to the chat")]
+    NotMember,
+    #[error("The bot was banned, unban it and add it to the chat again")]
+    Banned,
+    #[error("The bot can't send messages, allow it to or make it an administrator")]
+    CannotSend,
+    #[error("The bot can't post to the channel, make it an administrator allowed to post")]
+    CannotPost,
+    #[error("Topics are configured, but the chat is not a forum")]
+    NotForum,
+}
+
+/// Checks that the bot can send messages to a chat, so that a misconfigured chat is reported on
+/// startup rather than at the first message bridged to it.
+pub async fn chat(bot: &Bot, bot_id: UserId, topic: Topic) -> Result<(), Error> {
+    let chat = bot.get_chat(topic.chat_id).await.map_err(Error::Chat)?;
+    if topic.thread_id.is_some() && !is_forum(&chat) {
+        return Err(Error::NotForum);
+    }
+
+    let member = bot
+        .get_chat_member(topic.chat_id, bot_id)
+        .await
+        .map_err(Error::Member)?;
+
+    can_send(chat.is_channel(), chat.permissions(), &member.kind)
+}
+
+fn is_forum(chat: &Chat) -> bool {
+    matches!(
+        &chat.kind,
+        ChatKind::Public(public) if matches!(
+            public.kind,
+            PublicChatKind::Supergroup(PublicChatSupergroup { is_forum: true, .. })
+        )
+    )
+}
+
+// Members of groups are allowed what the group allows everyone, members of channels can't post.
+fn can_send(
+    channel: bool,
+    permissions: Option<ChatPermissions>,
+    member: &ChatMemberKind,
+) -> Result<(), Error> {
+    match member {
+        ChatMemberKind::Left => Err(Error::NotMember),
+        ChatMemberKind::Banned(_) => Err(Error::Banned),
+        _ if channel && !member.can_post_messages() => Err(Error::CannotPost),
+        ChatMemberKind::Restricted(restricted) if !restricted.can_send_messages => {
+            Err(Error::CannotSend)
+        }
+        ChatMemberKind::Member
+            if !permissions.is_none_or(|permissions| permissions.can_send_messages()) =>
+        {
+            Err(Error::CannotSend)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_membership() {
+        let everyone = Some(ChatPermissions::all());
+        let nobody = Some(ChatPermissions::empty());
+
+        assert!(can_send(false, everyone, &ChatMemberKind::Member).is_ok());
+        assert!(can_send(false, None, &ChatMemberKind::Member).is_ok());
+        assert!(matches!(
+            can_send(false, nobody, &ChatMemberKind::Member),
+            Err(Error::CannotSend)
+        ));
+        assert!(matches!(
+            can_send(false, everyone, &ChatMemberKind::Left),
+            Err(Error::NotMember)
+        ));
+        assert!(matches!(
+            can_send(true, None, &ChatMemberKind::Member),
+            Err(Error::CannotPost)
+        ));
+    }
+}
@@ -1,17 +1,101 @@
 use multichat_client::proto::AccessToken;
-use serde::Deserialize;
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Formatter};
+use std::marker::PhantomData;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use url::Url;
 
 #[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Config {
-    pub telegram: Telegram,
+    #[serde(deserialize_with = "one_or_many")]
+    pub telegram: Vec<Telegram>,
     pub multichat: Multichat,
     pub chats: Vec<Chat>,
+    /// SQLite database bridged messages are kept in, so that reactions refer to them after restarting.
+    pub message_store: Option<PathBuf>,
+    #[serde(default)]
+    pub templates: Templates,
+    #[serde(default)]
+    pub large_attachments: LargeAttachments,
+    /// Address metrics for Prometheus are served on.
+    pub metrics: Option<SocketAddr>,
+    #[serde(default)]
+    pub names: Names,
 }
 
 #[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct Telegram {
+    /// Referred to by chats when there are several bots.
+    pub name: Option<String>,
     pub token: String,
+    /// Local Bot API server, which allows uploading larger files.
+    pub api_url: Option<Url>,
+    /// Receive updates through a webhook instead of long polling.
+    pub webhook: Option<Webhook>,
+    #[serde(default)]
+    pub filter: Filter,
+}
+
+/// Telegram messages and reactions which are not bridged.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Filter {
+    /// Messages starting with `/`.
+    pub ignore_commands: bool,
+    pub ignore_bots: bool,
+    pub ignored_users: Vec<u64>,
+    /// Only these users are bridged if given.
+    pub allowed_users: Option<Vec<u64>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+pub struct Webhook {
+    pub address: SocketAddr,
+    /// Public URL Telegram posts updates to, its path is the one listened on.
+    pub url: String,
+    /// Sent by Telegram with every update, so that nobody else can post them.
+    pub secret_token: String,
+    /// Serve HTTPS, not needed behind a reverse proxy doing so.
+    pub tls: Option<Tls>,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+pub struct Tls {
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+}
+
+/// How Telegram users are named in Multichat.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Names {
+    /// Add `@username` to names of users who have one.
+    pub usernames: bool,
+    /// Names used instead of those of users with these IDs, as they are.
+    #[serde(deserialize_with = "user_ids")]
+    pub overrides: HashMap<u64, String>,
+}
+
+/// What is done with attachments from Multichat too large to upload to Telegram.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case", tag = "action")]
+pub enum LargeAttachments {
+    /// Dropped, they're only logged.
+    #[default]
+    Skip,
+    /// Dropped with a note in the message.
+    Notice,
+    /// Saved to a directory served over HTTP at the URL, with a link in the message.
+    Link { directory: PathBuf, url: String },
 }
 
 #[derive(Deserialize)]
@@ -27,6 +111,82 @@ pub struct Multichat {
 pub struct Chat {
     pub multichat_group: String,
     pub telegram_chat: i64,
+    /// Thread of a forum topic, only the topic is bridged if given.
+    pub telegram_topic: Option<i32>,
+    /// Name of the bot bridging the chat, needed only if there are several.
+    pub telegram_bot: Option<String>,
+    /// Used instead of the global templates.
+    pub templates: Option<Templates>,
+    /// Convert voice notes to MP3 before bridging them.
+    #[serde(default)]
+    pub transcode_voice: bool,
+    /// Create Multichat users for Telegram users joining, not only once they speak.
+    #[serde(default)]
+    pub bridge_joins: bool,
+    /// Multichat users whose messages are pinned.
+    #[serde(default)]
+    pub pin_from: HashSet<String>,
+}
+
+/// MarkdownV2 sent to Telegram for what happens in Multichat, with `{name}` and `{group}` replaced.
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Templates {
+    /// Also has `{text}`.
+    pub message: String,
+    pub join: String,
+    pub leave: String,
+    /// Also has `{old-name}`.
+    pub rename: String,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            message: "*{name}*: {text}".to_owned(),
+            join: "*{name}*: joined".to_owned(),
+            leave: "*{name}*: left".to_owned(),
+            rename: "*{old-name}*: renamed to *{name}*".to_owned(),
+        }
+    }
+}
+
+// A table or an array of tables.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrMany<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+            f.write_str("a table or an array of tables")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            T::deserialize(MapAccessDeserializer::new(map)).map(|one| vec![one])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec::deserialize(SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany(PhantomData))
+}
+
+// Keys of TOML tables are strings.
+fn user_ids<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<u64, String>, D::Error> {
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(id, name)| match id.parse() {
+            Ok(id) => Ok((id, name)),
+            Err(_) => Err(de::Error::custom(format!("invalid user ID {}", id))),
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -38,4 +198,57 @@ mod tests {
         let config = include_str!("../example/config.toml");
         toml::from_str::<Config>(config).unwrap();
     }
+
+    #[test]
+    fn several_bots() {
+        let config = r#"
+            chats = []
+
+            [[telegram]]
+            name = "a"
+            token = "1"
+
+            [[telegram]]
+            name = "b"
+            token = "2"
+
+            [multichat]
+            server = "example.com:8585"
+            access-token = "52f0395327987f07f805c3ac54fe38ac123303fcdb62a61fdfc9b8082195486c"
+        "#;
+
+        let config = toml::from_str::<Config>(config).unwrap();
+        assert_eq!(config.telegram.len(), 2);
+        assert_eq!(config.telegram[1].name.as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn names() {
+        let config = r#"
+            usernames = true
+            overrides = { 6398471025 = "Alice" }
+        "#;
+
+        let names = toml::from_str::<Names>(config).unwrap();
+        assert!(names.usernames);
+        assert_eq!(names.overrides[&6398471025], "Alice");
+    }
+
+    #[test]
+    fn large_attachments() {
+        let config = r#"
+            action = "link"
+            directory = "/var/www/multichat"
+            url = "https://example.com/multichat"
+        "#;
+
+        assert!(matches!(
+            toml::from_str::<LargeAttachments>(config).unwrap(),
+            LargeAttachments::Link { .. }
+        ));
+        assert!(matches!(
+            toml::from_str::<LargeAttachments>(r#"action = "notice""#).unwrap(),
+            LargeAttachments::Notice
+        ));
+    }
 }
@@ -0,0 +1,133 @@
+use crate::metrics;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use teloxide::net::Download;
+use teloxide::prelude::Requester;
+use teloxide::types::FileMeta;
+use teloxide::{Bot, RequestError};
+use tokio::sync::Semaphore;
+
+// Files downloaded at once.
+const MAX_DOWNLOADS: usize = 4;
+// Bytes of recently downloaded files kept.
+const CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Downloads files from Telegram a few at once, keeping those downloaded recently so that files
+/// sent again are not downloaded again.
+pub struct Downloader {
+    bot: Bot,
+    permits: Semaphore,
+    cache: Mutex<Cache>,
+}
+
+impl Downloader {
+    pub fn new(bot: Bot) -> Self {
+        Self {
+            bot,
+            permits: Semaphore::new(MAX_DOWNLOADS),
+            cache: Mutex::new(Cache::new(CACHE_SIZE)),
+        }
+    }
+
+    pub async fn download(&self, file: &FileMeta) -> Result<Vec<u8>, RequestError> {
+        if let Some(data) = self.cache.lock().unwrap().get(&file.unique_id) {
+            return Ok(data);
+        }
+
+        // Never closed.
+        let _permit = self.permits.acquire().await.unwrap();
+
+        // It may have been downloaded while waiting.
+        if let Some(data) = self.cache.lock().unwrap().get(&file.unique_id) {
+            return Ok(data);
+        }
+
+        let mut data = Vec::new();
+
+        let result = async {
+            let file = self.bot.get_file(&file.id).await?;
+            self.bot.download_file(&file.path, &mut data).await?;
+
+            Ok(())
+        };
+
+        if let Err(err) = result.await {
+            metrics::API_ERRORS.add(1);
+            return Err(err);
+        }
+
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(file.unique_id.clone(), data.clone());
+
+        Ok(data)
+    }
+}
+
+// Least recently used files are forgotten first.
+struct Cache {
+    capacity: usize,
+    size: usize,
+    files: HashMap<String, Vec<u8>>,
+    // Least recently used first.
+    order: VecDeque<String>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            size: 0,
+            files: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&mut self, id: &str) -> Option<Vec<u8>> {
+        let data = self.files.get(id)?.clone();
+
+        let idx = self.order.iter().position(|used| used == id).unwrap();
+        let id = self.order.remove(idx).unwrap();
+        self.order.push_back(id);
+
+        Some(data)
+    }
+
+    fn insert(&mut self, id: String, data: Vec<u8>) {
+        if data.len() > self.capacity || self.files.contains_key(&id) {
+            return;
+        }
+
+        self.size += data.len();
+        self.files.insert(id.clone(), data);
+        self.order.push_back(id);
+
+        while self.size > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.size -= self.files.remove(&oldest).unwrap().len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_least_recently_used() {
+        let mut cache = Cache::new(3);
+        cache.insert("a".to_owned(), vec![1]);
+        cache.insert("b".to_owned(), vec![2]);
+        cache.insert("too large".to_owned(), vec![0; 4]);
+
+        assert_eq!(cache.get("a"), Some(vec![1]));
+
+        cache.insert("c".to_owned(), vec![3, 3]);
+        assert_eq!(cache.get("a"), Some(vec![1]));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(vec![3, 3]));
+        assert_eq!(cache.get("too large"), None);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Messages recently bridged to one side, so that those coming back from it through another bridge
+/// of the same chat and group are not bridged again, over and over.
+///
+/// There's no way to tell where a message comes from, so it's an echo if it has the same author
+/// and text as one bridged shortly before, or if its text is exactly the text of one sent.
+pub struct Echoes {
+    ttl: Duration,
+    // Oldest first.
+    recent: VecDeque<(Instant, u64)>,
+}
+
+impl Echoes {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, text: &str) {
+        self.expire();
+        self.recent
+            .push_back((Instant::now(), fingerprint(Some(name), text)));
+    }
+
+    /// Remembers the text of a message as it was sent, such as with the name rendered into it.
+    pub fn insert_sent(&mut self, text: &str) {
+        self.expire();
+        self.recent
+            .push_back((Instant::now(), fingerprint(None, text)));
+    }
+
+    pub fn is_echo(&mut self, name: &str, text: &str) -> bool {
+        self.expire();
+        self.contains(Some(name), text) || self.contains(None, text)
+    }
+
+    fn contains(&self, name: Option<&str>, text: &str) -> bool {
+        let fingerprint = fingerprint(name, text);
+        self.recent.iter().any(|(_, recent)| *recent == fingerprint)
+    }
+
+    fn expire(&mut self) {
+        while let Some((time, _)) = self.recent.front() {
+            if time.elapsed() < self.ttl {
+                break;
+            }
+
+            self.recent.pop_front();
+        }
+    }
+}
+
+fn fingerprint(name: Option<&str>, text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (name, text).hash(&mut hasher);
+
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn echoes() {
+        let mut echoes = Echoes::new(Duration::from_secs(30));
+        echoes.insert("alice", "hi");
+
+        assert!(echoes.is_echo("alice", "hi"));
+        assert!(!echoes.is_echo("bot", "*alice*: hi"));
+        assert!(!echoes.is_echo("bob", "hi"));
+        assert!(!echoes.is_echo("alice", "hi all"));
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+        assert!(!echoes.is_echo("alice", "hi"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sent_with_template() {
+        let mut echoes = Echoes::new(Duration::from_secs(30));
+
+        // Sent with the template `{name} says {text}`, as Telegram returned it.
+        echoes.insert("alice", "hi");
+        echoes.insert_sent("*alice* says hi");
+
+        assert!(echoes.is_echo("bot", "*alice* says hi"));
+        assert!(!echoes.is_echo("bot", "*alice*: hi"));
+        assert!(!echoes.is_echo("bot", "*bob* says hi"));
+    }
+}
@@ -1,25 +1,46 @@
+mod check;
 mod config;
+mod download;
+mod echoes;
 mod markdown_safe;
+mod markup;
+mod messages;
+mod metrics;
 mod multichat;
 mod telegram;
+mod template;
 mod tls;
+#[cfg(feature = "transcode")]
+mod transcode;
+#[cfg(feature = "webhook")]
+mod webhook;
 
 use clap::Parser;
 use config::Config;
+use messages::Messages;
+use multichat::ChatOptions;
 use multichat_client::proto::Config as ProtoConfig;
 use multichat_client::ClientBuilder;
 use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::process::ExitCode;
-use teloxide::types::ChatId;
+use std::sync::Arc;
+use telegram::Topic;
+use teloxide::prelude::Requester;
+use teloxide::types::{ChatId, MessageId, ThreadId};
+use teloxide::update_listeners;
 use teloxide::Bot;
 use tokio::fs;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
 use tracing::subscriber;
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
 use tracing_subscriber::fmt;
 use tracing_subscriber::prelude::*;
 
+// Bridged messages remembered for reactions to refer to.
+const MAX_MESSAGES: usize = 4096;
+
 #[derive(Parser)]
 struct Args {
     #[clap(help = "Path to config file")]
@@ -58,7 +79,64 @@ async fn main() -> ExitCode {
         }
     };
 
-    let bot = Bot::new(config.telegram.token);
+    metrics::STARTED.set_now();
+
+    match config.metrics {
+        #[cfg(feature = "metrics")]
+        Some(address) => {
+            if let Err(err) = metrics::serve(address).await {
+                tracing::error!("Error serving metrics: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        Some(_) => {
+            tracing::error!("Serving metrics requires the metrics feature");
+            return ExitCode::FAILURE;
+        }
+        None => {}
+    }
+
+    let messages = match config.message_store {
+        Some(path) => match Messages::open(path, MAX_MESSAGES).await {
+            Ok(messages) => messages,
+            Err(err) => {
+                tracing::error!("Error opening message store: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Messages::new(MAX_MESSAGES),
+    };
+
+    if config.telegram.is_empty() {
+        tracing::error!("No Telegram bots configured");
+        return ExitCode::FAILURE;
+    }
+
+    // Files up to 50 MiB can be uploaded to Telegram, more to a local Bot API server.
+    let bots: Vec<_> = config
+        .telegram
+        .iter()
+        .map(|telegram| {
+            let bot = Bot::new(&telegram.token);
+
+            match &telegram.api_url {
+                Some(url) => (bot.set_api_url(url.clone()), 2000 * 1024 * 1024),
+                None => (bot, 50 * 1024 * 1024),
+            }
+        })
+        .collect();
+
+    let mut bot_ids = Vec::with_capacity(bots.len());
+    for (bot, _) in &bots {
+        match bot.get_me().await {
+            Ok(me) => bot_ids.push(me.id),
+            Err(err) => {
+                tracing::error!("Error getting Telegram bot, check its token: {}", err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
 
     let connector = match config.multichat.certificate {
         Some(certificate) => match tls::configure(&certificate).await {
@@ -90,8 +168,56 @@ async fn main() -> ExitCode {
 
     let mut chat_to_group = HashMap::new();
     let mut group_to_chat = HashMap::new();
+    let mut options = HashMap::new();
+    let mut chat_bots = HashMap::new();
 
     for chat in config.chats {
+        let bot = match &chat.telegram_bot {
+            Some(name) => config
+                .telegram
+                .iter()
+                .position(|telegram| telegram.name.as_ref() == Some(name)),
+            None if bots.len() == 1 => Some(0),
+            None => None,
+        };
+
+        let bot = match bot {
+            Some(bot) => bot,
+            None => {
+                tracing::error!(
+                    "Telegram chat {} has to name one of the configured bots",
+                    chat.telegram_chat
+                );
+
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if *chat_bots.entry(chat.telegram_chat).or_insert(bot) != bot {
+            tracing::error!(
+                "Telegram chat {} is bridged by more than one bot",
+                chat.telegram_chat
+            );
+
+            return ExitCode::FAILURE;
+        }
+
+        let topic = Topic {
+            chat_id: ChatId(chat.telegram_chat),
+            thread_id: chat.telegram_topic.map(|id| ThreadId(MessageId(id))),
+        };
+
+        if let Err(err) = check::chat(&bots[bot].0, bot_ids[bot], topic).await {
+            tracing::error!(
+                "Telegram chat {}{}: {}",
+                chat.telegram_chat,
+                topic_suffix(chat.telegram_topic),
+                err
+            );
+
+            return ExitCode::FAILURE;
+        }
+
         let gid = match client.join_group(&chat.multichat_group).await {
             Ok(gid) => gid,
             Err(err) => {
@@ -101,14 +227,15 @@ async fn main() -> ExitCode {
         };
 
         let inserted = chat_to_group
-            .entry(ChatId(chat.telegram_chat))
+            .entry(topic)
             .or_insert_with(HashSet::new)
             .insert(gid);
 
         if !inserted {
             tracing::error!(
-                "Telegram chat {} is already associated with Multichat group {}",
+                "Telegram chat {}{} is already associated with Multichat group {}",
                 chat.telegram_chat,
+                topic_suffix(chat.telegram_topic),
                 chat.multichat_group
             );
 
@@ -118,34 +245,127 @@ async fn main() -> ExitCode {
         let inserted = group_to_chat
             .entry(gid)
             .or_insert_with(HashSet::new)
-            .insert(ChatId(chat.telegram_chat));
+            .insert(topic);
 
         if !inserted {
             tracing::error!(
-                "Multichat group {} is already associated with Telegram chat {}",
+                "Multichat group {} is already associated with Telegram chat {}{}",
                 chat.multichat_group,
-                chat.telegram_chat
+                chat.telegram_chat,
+                topic_suffix(chat.telegram_topic)
             );
 
             return ExitCode::FAILURE;
         }
+
+        if cfg!(not(feature = "transcode")) && chat.transcode_voice {
+            tracing::error!("Transcoding voice notes requires the transcode feature");
+            return ExitCode::FAILURE;
+        }
+
+        let templates = chat.templates.as_ref().unwrap_or(&config.templates);
+        options.insert(
+            topic,
+            ChatOptions {
+                bot: bots[bot].0.clone(),
+                max_upload: bots[bot].1,
+                templates: templates.for_group(&chat.multichat_group),
+                transcode_voice: chat.transcode_voice,
+                bridge_joins: chat.bridge_joins,
+                pin_from: chat.pin_from,
+            },
+        );
     }
 
+    let shutdown_signal = match multichat_client::shutdown_signal() {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("Error handling signals: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
     let (sender, receiver) = mpsc::channel(1);
+    let (shutdown_sender, shutdown) = watch::channel(());
+
+    let names = Arc::new(config.names);
+    let mut telegram = JoinSet::new();
+
+    for (bot_config, (bot, _)) in config.telegram.into_iter().zip(bots) {
+        let sender = sender.clone();
+
+        match bot_config.webhook {
+            #[cfg(feature = "webhook")]
+            Some(webhook) => match webhook::listen(bot.clone(), webhook).await {
+                Ok(listener) => {
+                    telegram.spawn(telegram::run(
+                        bot,
+                        listener,
+                        bot_config.filter,
+                        names.clone(),
+                        sender,
+                        shutdown.clone(),
+                    ));
+                }
+                Err(err) => {
+                    tracing::error!("Error listening for webhook updates: {}", err);
+                    return ExitCode::FAILURE;
+                }
+            },
+            #[cfg(not(feature = "webhook"))]
+            Some(_) => {
+                tracing::error!("Receiving updates through a webhook requires the webhook feature");
+                return ExitCode::FAILURE;
+            }
+            None => {
+                let listener = update_listeners::polling_default(bot.clone()).await;
+                telegram.spawn(telegram::run(
+                    bot,
+                    listener,
+                    bot_config.filter,
+                    names.clone(),
+                    sender,
+                    shutdown.clone(),
+                ));
+            }
+        }
+    }
 
-    let telegram = tokio::spawn(telegram::run(bot.clone(), sender));
-    let multichat = tokio::spawn(async move {
-        multichat::run(client, bot, &chat_to_group, &group_to_chat, receiver).await
+    let large_attachments = config.large_attachments;
+    let mut multichat = tokio::spawn(async move {
+        multichat::run(
+            client,
+            &chat_to_group,
+            &group_to_chat,
+            &options,
+            &large_attachments,
+            messages,
+            receiver,
+            shutdown,
+        )
+        .await
     });
 
     let result = tokio::select! {
-        result = telegram => {
-            result.unwrap();
-            Ok(())
-        },
-        result = multichat => result.unwrap(),
+        result = &mut multichat => Some(result.unwrap()),
+        // Only stops if the webhook couldn't be set.
+        _ = telegram.join_next() => None,
+        _ = shutdown_signal => None,
     };
 
+    tracing::info!("Shutting down");
+
+    // Multichat users are destroyed before disconnecting.
+    let _ = shutdown_sender.send(());
+    let result = match result {
+        Some(result) => result,
+        None => multichat.await.unwrap(),
+    };
+
+    while let Some(result) = telegram.join_next().await {
+        result.unwrap();
+    }
+
     match result {
         Ok(()) => ExitCode::SUCCESS,
         Err(err) => {
@@ -154,3 +374,10 @@ async fn main() -> ExitCode {
         }
     }
 }
+
+fn topic_suffix(topic: Option<i32>) -> String {
+    match topic {
+        Some(topic) => format!(" topic {}", topic),
+        None => String::new(),
+    }
+}
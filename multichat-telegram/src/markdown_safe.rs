@@ -6,14 +6,10 @@ impl<T: AsRef<str>> Display for MarkdownSafe<T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         let mut last_char = ' ';
         for c in self.0.as_ref().chars() {
-            match c {
-                '*' | '_' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '='
-                | '|' | '{' | '}' | '.' | '!'
-                    if last_char != '\\' =>
-                {
-                    write!(f, "\\{}", c)?
-                }
-                _ => write!(f, "{}", c)?,
+            if SPECIAL.contains(&c) && last_char != '\\' {
+                write!(f, "\\{}", c)?
+            } else {
+                write!(f, "{}", c)?
             }
             last_char = c;
         }
@@ -22,6 +18,11 @@ impl<T: AsRef<str>> Display for MarkdownSafe<T> {
     }
 }
 
+/// Characters which have to be escaped in MarkdownV2.
+pub const SPECIAL: [char; 18] = [
+    '*', '_', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.', '!',
+];
+
 pub trait MarkdownSafeExt: AsRef<str> {
     fn markdown_safe(&self) -> MarkdownSafe<&Self> {
         MarkdownSafe(self)
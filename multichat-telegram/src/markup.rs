@@ -0,0 +1,140 @@
+use crate::markdown_safe::MarkdownSafeExt;
+
+use multichat_client::markup;
+use teloxide::types::{MessageEntity, MessageEntityKind};
+
+/// Makes text safe for MarkdownV2, with its markup turned into formatting.
+pub fn to_markdown(text: &str) -> String {
+    let mut markdown = String::with_capacity(text.len());
+
+    for chunk in markup::parse(text) {
+        let mut markers = String::new();
+        if chunk.style.bold {
+            markers.push('*');
+        }
+
+        if chunk.style.italic {
+            markers.push('_');
+        }
+
+        markdown.push_str(&markers);
+
+        match chunk.style.code {
+            // Only these have to be escaped in code.
+            true => {
+                markdown.push('`');
+                for c in chunk.text.chars() {
+                    if c == '`' || c == '\\' {
+                        markdown.push('\\');
+                    }
+
+                    markdown.push(c);
+                }
+                markdown.push('`');
+            }
+            false => markdown.push_str(&chunk.text.markdown_safe().to_string()),
+        }
+
+        markdown.extend(markers.chars().rev());
+    }
+
+    markdown
+}
+
+/// Adds markup for the bold, italic and code entities of a Telegram message to its text.
+pub fn from_entities(text: &str, entities: &[MessageEntity]) -> String {
+    // Closing markers go first, those of inner entities before outer ones, then opening
+    // markers of outer entities before inner ones.
+    let mut markers = Vec::new();
+
+    for entity in entities {
+        let marker = match entity.kind {
+            MessageEntityKind::Bold => '*',
+            MessageEntityKind::Italic => '_',
+            MessageEntityKind::Code | MessageEntityKind::Pre { .. } => '`',
+            _ => continue,
+        };
+
+        let (start, end) = match (
+            byte_index(text, entity.offset),
+            byte_index(text, entity.offset + entity.length),
+        ) {
+            (Some(start), Some(end)) => (start, end),
+            _ => continue,
+        };
+
+        // Markup doesn't start or end with whitespace.
+        let inner = &text[start..end];
+        let start = start + inner.len() - inner.trim_start().len();
+        let end = end - (inner.len() - inner.trim_end().len());
+
+        if start >= end {
+            continue;
+        }
+
+        markers.push(((start, 1, usize::MAX - end), marker));
+        markers.push(((end, 0, usize::MAX - start), marker));
+    }
+
+    markers.sort_unstable();
+
+    let mut marked = String::with_capacity(text.len() + markers.len());
+    let mut last = 0;
+
+    for ((idx, _, _), marker) in markers {
+        marked.push_str(&text[last..idx]);
+        marked.push(marker);
+        last = idx;
+    }
+
+    marked.push_str(&text[last..]);
+    marked
+}
+
+// Entities are in UTF-16 code units.
+fn byte_index(text: &str, offset: usize) -> Option<usize> {
+    let mut units = 0;
+
+    for (idx, c) in text.char_indices() {
+        if units == offset {
+            return Some(idx);
+        }
+
+        units += c.len_utf16();
+    }
+
+    (units == offset).then_some(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown() {
+        assert_eq!(to_markdown("a *bold* 2.5"), "a *bold* 2\\.5");
+        assert_eq!(to_markdown("*_both_*"), "*_both_*");
+        assert_eq!(to_markdown("`a.b\\`"), "`a.b\\\\`");
+        assert_eq!(to_markdown("snake_case"), "snake\\_case");
+    }
+
+    #[test]
+    fn entities() {
+        let entity = |kind, offset, length| MessageEntity {
+            kind,
+            offset,
+            length,
+        };
+
+        let text = "😀 bold and italic ";
+        let entities = [
+            entity(MessageEntityKind::Bold, 3, 16),
+            entity(MessageEntityKind::Italic, 12, 7),
+            entity(MessageEntityKind::Underline, 0, 2),
+        ];
+        assert_eq!(from_entities(text, &entities), "😀 *bold and _italic_* ");
+
+        let entities = [entity(MessageEntityKind::Code, 0, 4)];
+        assert_eq!(from_entities("a *b", &entities), "`a *b`");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::telegram::Topic;
+
+use rusqlite::{params, Connection};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use teloxide::types::{ChatId, MessageId, ThreadId};
+use tokio::task;
+
+/// Texts of recently bridged messages in either direction, so that updates referring to them
+/// can say which one they are about.
+pub struct Messages {
+    capacity: usize,
+    messages: HashMap<(ChatId, MessageId), Message>,
+    // Oldest first, forgotten once there's more than the capacity.
+    order: VecDeque<(ChatId, MessageId)>,
+    store: Option<Arc<Mutex<Connection>>>,
+}
+
+impl Messages {
+    /// Messages kept only in memory, forgotten on restart.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            messages: HashMap::new(),
+            order: VecDeque::new(),
+            store: None,
+        }
+    }
+
+    /// Messages kept in an SQLite database too, so that they are still known after restarting.
+    pub async fn open(path: PathBuf, capacity: usize) -> Result<Self, rusqlite::Error> {
+        let (connection, rows) = task::spawn_blocking(move || {
+            let connection = Connection::open(path)?;
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS messages (
+                    chat_id INTEGER NOT NULL,
+                    message_id INTEGER NOT NULL,
+                    thread_id INTEGER,
+                    text TEXT NOT NULL,
+                    PRIMARY KEY (chat_id, message_id)
+                )",
+            )?;
+
+            // Oldest first, as they were inserted.
+            let rows = connection
+                .prepare(
+                    "SELECT chat_id, message_id, thread_id, text FROM messages ORDER BY rowid",
+                )?
+                .query_map([], |row| {
+                    let topic = Topic {
+                        chat_id: ChatId(row.get(0)?),
+                        thread_id: row
+                            .get::<_, Option<i32>>(2)?
+                            .map(|id| ThreadId(MessageId(id))),
+                    };
+
+                    Ok((topic, MessageId(row.get(1)?), row.get(3)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok::<_, rusqlite::Error>((connection, rows))
+        })
+        .await
+        .unwrap()?;
+
+        let mut messages = Self::new(capacity);
+        for (topic, message_id, text) in rows {
+            messages.remember(topic, message_id, text);
+        }
+
+        messages.store = Some(Arc::new(Mutex::new(connection)));
+
+        Ok(messages)
+    }
+
+    /// Remembers the text of a message, which is replaced if it's already known.
+    ///
+    /// The message is still remembered until restarting if it couldn't be stored.
+    pub async fn insert(&mut self, topic: Topic, message_id: MessageId, text: String) {
+        let store = match &self.store {
+            Some(store) => store.clone(),
+            None => return self.remember(topic, message_id, text),
+        };
+
+        let capacity = self.capacity;
+        let stored = text.clone();
+        let result = task::spawn_blocking(move || {
+            let connection = store.lock().unwrap();
+
+            // Replacing the text keeps the row, and with it the message's age.
+            connection.execute(
+                "INSERT INTO messages (chat_id, message_id, thread_id, text) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (chat_id, message_id) DO UPDATE SET text = excluded.text",
+                params![
+                    topic.chat_id.0,
+                    message_id.0,
+                    topic.thread_id.map(|ThreadId(MessageId(id))| id),
+                    stored
+                ],
+            )?;
+
+            connection.execute(
+                "DELETE FROM messages WHERE rowid NOT IN
+                 (SELECT rowid FROM messages ORDER BY rowid DESC LIMIT ?1)",
+                [capacity as i64],
+            )
+        })
+        .await
+        .unwrap();
+
+        if let Err(err) = result {
+            tracing::warn!("Error storing message: {}", err);
+        }
+
+        self.remember(topic, message_id, text);
+    }
+
+    pub fn get(&self, chat_id: ChatId, message_id: MessageId) -> Option<&Message> {
+        self.messages.get(&(chat_id, message_id))
+    }
+
+    fn remember(&mut self, topic: Topic, message_id: MessageId, text: String) {
+        let key = (topic.chat_id, message_id);
+        if self.messages.insert(key, Message { topic, text }).is_some() {
+            return;
+        }
+
+        self.order.push_back(key);
+
+        if self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.messages.remove(&oldest);
+        }
+    }
+}
+
+pub struct Message {
+    pub topic: Topic,
+    pub text: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(thread_id: Option<i32>) -> Topic {
+        Topic {
+            chat_id: ChatId(-1001847508954),
+            thread_id: thread_id.map(|id| ThreadId(MessageId(id))),
+        }
+    }
+
+    #[test]
+    fn forgets_oldest() {
+        let mut messages = Messages::new(2);
+        messages.remember(topic(None), MessageId(1), "a".to_owned());
+        messages.remember(topic(None), MessageId(2), "b".to_owned());
+        messages.remember(topic(None), MessageId(1), "edited".to_owned());
+        messages.remember(topic(None), MessageId(3), "c".to_owned());
+
+        let chat_id = topic(None).chat_id;
+        let text = |id| {
+            messages
+                .get(chat_id, MessageId(id))
+                .map(|m| m.text.as_str())
+        };
+        assert_eq!(text(1), None);
+        assert_eq!(text(2), Some("b"));
+        assert_eq!(text(3), Some("c"));
+    }
+
+    #[tokio::test]
+    async fn reopened() {
+        let path = std::env::temp_dir().join(format!("multichat-messages-{}", std::process::id()));
+
+        let mut messages = Messages::open(path.clone(), 2).await.unwrap();
+        for id in 0..10 {
+            messages
+                .insert(topic(None), MessageId(id), id.to_string())
+                .await;
+        }
+
+        let messages = Messages::open(path.clone(), 2).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let chat_id = topic(None).chat_id;
+        assert!(messages.get(chat_id, MessageId(7)).is_none());
+        assert_eq!(messages.get(chat_id, MessageId(9)).unwrap().text, "9");
+    }
+
+    #[tokio::test]
+    async fn reopened_keeps_topics() {
+        let path =
+            std::env::temp_dir().join(format!("multichat-messages-topics-{}", std::process::id()));
+
+        let mut messages = Messages::open(path.clone(), 10).await.unwrap();
+        messages
+            .insert(topic(None), MessageId(1), "alice: hi".to_owned())
+            .await;
+        messages
+            .insert(topic(Some(4)), MessageId(2), "a\\n\nb ".to_owned())
+            .await;
+        messages
+            .insert(topic(None), MessageId(1), "edited".to_owned())
+            .await;
+
+        let messages = Messages::open(path.clone(), 10).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let chat_id = topic(None).chat_id;
+        let first = messages.get(chat_id, MessageId(1)).unwrap();
+        assert_eq!(first.topic, topic(None));
+        assert_eq!(first.text, "edited");
+
+        let second = messages.get(chat_id, MessageId(2)).unwrap();
+        assert_eq!(second.topic, topic(Some(4)));
+        assert_eq!(second.text, "a\\n\nb ");
+    }
+}
@@ -0,0 +1,206 @@
+//! Counters of what the bridge does, which can be served for Prometheus to scrape.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub static TO_MULTICHAT: Metric = Metric::new();
+pub static TO_TELEGRAM: Metric = Metric::new();
+pub static API_ERRORS: Metric = Metric::new();
+pub static RATE_LIMITED: Metric = Metric::new();
+/// In milliseconds.
+pub static RATE_LIMITED_TIME: Metric = Metric::new();
+/// Unix timestamps, in seconds.
+pub static STARTED: Metric = Metric::new();
+pub static LAST_TELEGRAM_EVENT: Metric = Metric::new();
+pub static LAST_MULTICHAT_UPDATE: Metric = Metric::new();
+
+pub struct Metric(AtomicU64);
+
+impl Metric {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn add_duration(&self, duration: Duration) {
+        self.add(duration.as_millis() as u64);
+    }
+
+    pub fn set_now(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        self.0.store(now.as_secs(), Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Metrics in the Prometheus text format.
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub fn render() -> String {
+    let mut rendered = String::new();
+
+    write_metric(
+        &mut rendered,
+        "messages_total",
+        "counter",
+        "Messages bridged.",
+        &[
+            (
+                "{direction=\"to_multichat\"}",
+                TO_MULTICHAT.get().to_string(),
+            ),
+            ("{direction=\"to_telegram\"}", TO_TELEGRAM.get().to_string()),
+        ],
+    );
+    write_metric(
+        &mut rendered,
+        "api_errors_total",
+        "counter",
+        "Failed Telegram API requests.",
+        &[("", API_ERRORS.get().to_string())],
+    );
+    write_metric(
+        &mut rendered,
+        "rate_limited_total",
+        "counter",
+        "Telegram API requests which had to wait because of rate limiting.",
+        &[("", RATE_LIMITED.get().to_string())],
+    );
+    write_metric(
+        &mut rendered,
+        "rate_limited_seconds_total",
+        "counter",
+        "Time spent waiting because of rate limiting.",
+        &[("", (RATE_LIMITED_TIME.get() as f64 / 1000.0).to_string())],
+    );
+    write_metric(
+        &mut rendered,
+        "start_time_seconds",
+        "gauge",
+        "When the bridge started, it exits on losing the Multichat connection.",
+        &[("", STARTED.get().to_string())],
+    );
+    write_metric(
+        &mut rendered,
+        "last_event_timestamp_seconds",
+        "gauge",
+        "When the last event was received.",
+        &[
+            (
+                "{source=\"telegram\"}",
+                LAST_TELEGRAM_EVENT.get().to_string(),
+            ),
+            (
+                "{source=\"multichat\"}",
+                LAST_MULTICHAT_UPDATE.get().to_string(),
+            ),
+        ],
+    );
+
+    rendered
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+fn write_metric(
+    rendered: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: &[(&str, String)],
+) {
+    let _ = writeln!(rendered, "# HELP multichat_telegram_{} {}", name, help);
+    let _ = writeln!(rendered, "# TYPE multichat_telegram_{} {}", name, kind);
+
+    for (labels, value) in values {
+        let _ = writeln!(rendered, "multichat_telegram_{}{} {}", name, labels, value);
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub use server::serve;
+
+#[cfg(feature = "metrics")]
+mod server {
+    use http_body_util::Full;
+    use hyper::body::{Bytes, Incoming};
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Method, Request, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use std::convert::Infallible;
+    use std::io;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    /// Serves metrics on `/metrics`, and `/health` which is OK as long as the bridge runs.
+    pub async fn serve(address: SocketAddr) -> Result<(), io::Error> {
+        let listener = TcpListener::bind(address).await?;
+        tracing::info!("Serving metrics on {}", address);
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::warn!("Error accepting metrics connection: {}", err);
+                        continue;
+                    }
+                };
+
+                tokio::spawn(async move {
+                    let service =
+                        service_fn(|request| async move { Ok::<_, Infallible>(handle(request)) });
+
+                    if let Err(err) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        tracing::debug!(%addr, "Metrics connection failed: {}", err);
+                    }
+                });
+            }
+        });
+
+        Ok(())
+    }
+
+    fn handle(request: Request<Incoming>) -> Response<Full<Bytes>> {
+        let (status, body) = match (request.method(), request.uri().path()) {
+            (&Method::GET, "/metrics") => (StatusCode::OK, super::render()),
+            (&Method::GET, "/health") => (StatusCode::OK, "OK\n".to_owned()),
+            _ => (StatusCode::NOT_FOUND, String::new()),
+        };
+
+        let mut response = Response::new(Full::new(Bytes::from(body)));
+        *response.status_mut() = status;
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders() {
+        TO_TELEGRAM.add(2);
+        RATE_LIMITED_TIME.add_duration(Duration::from_millis(1500));
+
+        let rendered = render();
+        assert!(
+            rendered.contains("\nmultichat_telegram_messages_total{direction=\"to_telegram\"} 2\n")
+        );
+        assert!(rendered.contains("\nmultichat_telegram_rate_limited_seconds_total 1.5\n"));
+        assert!(rendered.contains("# TYPE multichat_telegram_api_errors_total counter\n"));
+    }
+}
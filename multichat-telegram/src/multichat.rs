@@ -5,20 +5,47 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::future::Future;
 use std::time::Duration;
 use std::{io, mem, slice};
-use teloxide::payloads::SendMessageSetters;
+use teloxide::payloads::{PinChatMessageSetters, SendMessageSetters};
 use teloxide::prelude::Requester;
 use teloxide::types::{
     ChatAction, ChatId, InputFile, InputMedia, InputMediaAudio, InputMediaDocument,
-    InputMediaPhoto, InputMediaVideo, ParseMode, UserId,
+    InputMediaPhoto, InputMediaVideo, MessageId, ParseMode, UserId,
 };
 use teloxide::{Bot, RequestError};
 use thiserror::Error;
+use tokio::fs;
 use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use tokio::time;
 
-use crate::markdown_safe::MarkdownSafeExt;
-use crate::telegram::{Event as TelegramEvent, EventKind};
+use crate::config::{LargeAttachments, Templates};
+use crate::echoes::Echoes;
+use crate::messages::Messages;
+use crate::metrics;
+use crate::telegram::{self, Event as TelegramEvent, EventKind, Topic};
+use crate::template;
+#[cfg(feature = "transcode")]
+use crate::transcode;
+
+// Longest text of a message and caption of an attachment allowed by Telegram.
+const MAX_MESSAGE_LENGTH: usize = 4096;
+const MAX_CAPTION_LENGTH: usize = 1024;
+// How long bridged messages are remembered for recognizing echoes.
+const ECHO_TTL: Duration = Duration::from_secs(30);
+
+/// How a Telegram chat or topic is bridged.
+pub struct ChatOptions {
+    /// Bot the chat is bridged by.
+    pub bot: Bot,
+    /// Largest attachment the bot can upload.
+    pub max_upload: u64,
+    pub templates: Templates,
+    pub transcode_voice: bool,
+    pub bridge_joins: bool,
+    /// Multichat users whose messages are pinned.
+    pub pin_from: HashSet<String>,
+}
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -28,14 +55,21 @@ pub enum Error {
     Io(#[from] io::Error),
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     mut client: MaybeTlsClient,
-    bot: Bot,
-    chat_to_group: &HashMap<ChatId, HashSet<u32>>,
-    group_to_chat: &HashMap<u32, HashSet<ChatId>>,
+    chat_to_group: &HashMap<Topic, HashSet<u32>>,
+    group_to_chat: &HashMap<u32, HashSet<Topic>>,
+    options: &HashMap<Topic, ChatOptions>,
+    large_attachments: &LargeAttachments,
+    mut messages: Messages,
     mut telegram_receiver: Receiver<TelegramEvent>,
+    mut shutdown: watch::Receiver<()>,
 ) -> Result<(), Error> {
-    let mut users = HashMap::<(UserId, ChatId), TelegramUser>::new();
+    let mut users = HashMap::<(UserId, Topic), TelegramUser>::new();
+    // Only messages sent to where they come from are echoes, not users repeating themselves.
+    let mut to_multichat = Echoes::new(ECHO_TTL);
+    let mut to_telegram = Echoes::new(ECHO_TTL);
     let mut groups = group_to_chat
         .keys()
         .map(|gid| {
@@ -69,55 +103,75 @@ pub async fn run(
             },
             update = client.read_update() => Event::Multichat(update?),
             gid = typing => Event::Typing(gid),
+            _ = shutdown.changed() => break,
         };
 
+        match &event {
+            Event::Telegram(_) => metrics::LAST_TELEGRAM_EVENT.set_now(),
+            Event::Multichat(_) => metrics::LAST_MULTICHAT_UPDATE.set_now(),
+            Event::Typing(_) => {}
+        }
+
         match event {
             Event::Telegram(event) => match event.kind {
                 EventKind::Message {
+                    message_id,
                     user_name,
                     text,
+                    reply,
                     attachment,
+                    voice,
+                    edited,
                 } => {
-                    let gids = match chat_to_group.get(&event.chat_id) {
-                        Some(gids) => gids,
+                    let topic = Topic {
+                        chat_id: event.chat_id,
+                        thread_id: event.thread_id,
+                    };
+
+                    let (bridged, gids) = match find_topic(chat_to_group, topic) {
+                        Some(found) => found,
                         None => {
-                            tracing::warn!(chat_id = %event.chat_id, "Telegram chat not found");
+                            tracing::warn!(?topic, "Telegram chat not found");
                             continue;
                         }
                     };
 
-                    let entry = users.entry((event.user_id, event.chat_id));
-                    let user = match entry {
-                        Entry::Occupied(entry) => {
-                            let user = entry.into_mut();
-                            if user.name != user_name {
-                                for (gid, uid) in &user.gid_uid {
-                                    client.rename_user(*gid, *uid, &user_name).await?;
-                                }
+                    if to_telegram.is_echo(&user_name, &text) {
+                        tracing::debug!(?topic, "Ignoring echoed message");
+                        continue;
+                    }
 
-                                user.name = user_name;
-                            }
+                    let key = (event.user_id, bridged);
+                    let user =
+                        telegram_user(&mut client, &mut users, &mut owned, gids, key, user_name)
+                            .await?;
 
-                            user
-                        }
-                        Entry::Vacant(_) => {
-                            let mut gid_uid = Vec::new();
+                    messages
+                        .insert(topic, message_id, format!("{}: {}", user.name, text))
+                        .await;
 
-                            for gid in gids {
-                                let uid = client.init_user(*gid, &user_name).await?;
+                    to_multichat.insert(&user.name, &text);
 
-                                gid_uid.push((*gid, uid));
-                                owned.insert((*gid, uid));
-                            }
+                    // There is no editing in the protocol, edits are sent as new messages.
+                    let text = match edited {
+                        true => Cow::Owned(format!("(edited) {}", text)),
+                        false => Cow::Borrowed(&*text),
+                    };
 
-                            entry.or_insert(TelegramUser {
-                                name: user_name,
-                                gid_uid,
-                            })
+                    // Nor are there replies, the replied to message is quoted instead.
+                    let text = match reply {
+                        Some(reply) => Cow::Owned(format!("{}\n{}", reply, text)),
+                        None => text,
+                    };
+
+                    let attachment = match attachment {
+                        Some(data) if voice && options[&bridged].transcode_voice => {
+                            Some(transcode_voice(data).await)
                         }
+                        attachment => attachment,
                     };
 
-                    let attachment = attachment.map(|data| Cow::Owned(data));
+                    let attachment = attachment.map(Cow::Owned);
 
                     let attachments = match &attachment {
                         Some(attachment) => slice::from_ref(attachment),
@@ -127,25 +181,97 @@ pub async fn run(
                     for (gid, uid) in &user.gid_uid {
                         client.send_message(*gid, *uid, &text, attachments).await?;
                     }
+
+                    metrics::TO_MULTICHAT.add(1);
                 }
-                EventKind::Leave => {
-                    let user = match users.remove(&(event.user_id, event.chat_id)) {
-                        Some(user) => user,
-                        None => continue,
+                EventKind::Reaction {
+                    message_id,
+                    user_name,
+                    reactions,
+                } => {
+                    // Reactions are sent as messages, quoting the message if it's still known.
+                    let (topic, text) = match messages.get(event.chat_id, message_id) {
+                        Some(message) => (
+                            message.topic,
+                            format!(
+                                "reacted with {}\n{}",
+                                reactions.join(""),
+                                telegram::quote(None, &message.text)
+                            ),
+                        ),
+                        None => (
+                            Topic {
+                                chat_id: event.chat_id,
+                                thread_id: None,
+                            },
+                            format!("reacted with {}", reactions.join("")),
+                        ),
                     };
 
-                    for (gid, uid) in user.gid_uid {
-                        client.destroy_user(gid, uid).await?;
+                    let (bridged, gids) = match find_topic(chat_to_group, topic) {
+                        Some(found) => found,
+                        None => {
+                            tracing::warn!(?topic, "Telegram chat not found");
+                            continue;
+                        }
+                    };
+
+                    let key = (event.user_id, bridged);
+                    let user =
+                        telegram_user(&mut client, &mut users, &mut owned, gids, key, user_name)
+                            .await?;
+
+                    for (gid, uid) in &user.gid_uid {
+                        client.send_message(*gid, *uid, &text, &[]).await?;
+                    }
+
+                    metrics::TO_MULTICHAT.add(1);
+                }
+                EventKind::Join { user_name } => {
+                    // Users join the whole chat, so they do in every bridged topic of it.
+                    let topics = chat_to_group.iter().filter(|(topic, _)| {
+                        topic.chat_id == event.chat_id && options[*topic].bridge_joins
+                    });
+
+                    for (topic, gids) in topics {
+                        let key = (event.user_id, *topic);
+                        telegram_user(
+                            &mut client,
+                            &mut users,
+                            &mut owned,
+                            gids,
+                            key,
+                            user_name.clone(),
+                        )
+                        .await?;
+                    }
+                }
+                EventKind::Leave => {
+                    // Users were created for every bridged topic of the chat they spoke in.
+                    let keys: Vec<_> = users
+                        .keys()
+                        .filter(|(user_id, topic)| {
+                            *user_id == event.user_id && topic.chat_id == event.chat_id
+                        })
+                        .copied()
+                        .collect();
+
+                    for key in keys {
+                        let user = users.remove(&key).unwrap();
+
+                        for (gid, uid) in user.gid_uid {
+                            client.destroy_user(gid, uid).await?;
+                        }
                     }
                 }
             },
             Event::Multichat(Update {
-                kind: UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup { .. },
+                kind: UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup,
                 ..
             }) => continue,
             Event::Multichat(update) => {
                 let group = groups.get_mut(&update.gid).unwrap();
-                let chat_ids = group_to_chat.get(&update.gid).unwrap();
+                let topics = group_to_chat.get(&update.gid).unwrap();
 
                 match update.kind {
                     UpdateKind::InitUser { uid, name } => {
@@ -160,14 +286,21 @@ pub async fn run(
                             continue;
                         }
 
-                        let message = format!("*{}*: joined", user.name.markdown_safe());
+                        for topic in topics {
+                            let bot = &options[topic].bot;
+                            let message = template::render(
+                                &options[topic].templates.join,
+                                &[("name", &user.name)],
+                            );
 
-                        for chat_id in chat_ids {
                             rate_limit(|| async {
-                                bot.send_message(*chat_id, &message)
+                                let mut request = bot
+                                    .send_message(topic.chat_id, &message)
                                     .parse_mode(ParseMode::MarkdownV2)
-                                    .disable_notification(true)
-                                    .await
+                                    .disable_notification(true);
+
+                                request.message_thread_id = topic.thread_id;
+                                request.await
                             })
                             .await?;
                         }
@@ -182,14 +315,21 @@ pub async fn run(
                             continue;
                         }
 
-                        let message = format!("*{}*: left", user.name.markdown_safe());
+                        for topic in topics {
+                            let bot = &options[topic].bot;
+                            let message = template::render(
+                                &options[topic].templates.leave,
+                                &[("name", &user.name)],
+                            );
 
-                        for chat_id in chat_ids {
                             rate_limit(|| async {
-                                bot.send_message(*chat_id, &message)
+                                let mut request = bot
+                                    .send_message(topic.chat_id, &message)
                                     .parse_mode(ParseMode::MarkdownV2)
-                                    .disable_notification(true)
-                                    .await
+                                    .disable_notification(true);
+
+                                request.message_thread_id = topic.thread_id;
+                                request.await
                             })
                             .await?;
                         }
@@ -209,7 +349,7 @@ pub async fn run(
                     }
                     UpdateKind::Message { uid, message } => {
                         let user = group.users.get(&uid).unwrap();
-                        if user.owned {
+                        if user.owned || to_multichat.is_echo(&user.name, &message.text) {
                             for attachment in message.attachments {
                                 client.ignore_attachment(attachment.id).await?;
                             }
@@ -217,61 +357,140 @@ pub async fn run(
                             continue;
                         }
 
-                        let text = format!(
-                            "*{}*: {}",
-                            user.name.markdown_safe(),
-                            message.text.markdown_safe()
-                        );
+                        // Attachments are downloaded once for all chats, so they have to fit every bot.
+                        let max_upload = topics
+                            .iter()
+                            .map(|topic| options[topic].max_upload)
+                            .min()
+                            .unwrap();
 
-                        if !message.attachments.is_empty() {
-                            let mut attachments = Vec::with_capacity(message.attachments.len());
+                        to_telegram.insert(&user.name, &message.text);
 
-                            for attachment in message.attachments {
-                                if attachment.size > 50 * 1024 * 1024 {
+                        let mut text = message.text;
+                        let mut attachments = Vec::with_capacity(message.attachments.len());
+
+                        for attachment in message.attachments {
+                            if attachment.size <= max_upload {
+                                attachments.push(client.download_attachment(attachment.id).await?);
+                                continue;
+                            }
+
+                            match large_attachments {
+                                LargeAttachments::Skip => {
                                     tracing::warn!(id = %attachment.id, "Attachment is too large, ignoring");
-                                    continue;
+                                    client.ignore_attachment(attachment.id).await?;
                                 }
+                                LargeAttachments::Notice => {
+                                    client.ignore_attachment(attachment.id).await?;
+
+                                    let size = attachment.size / (1024 * 1024);
+                                    push_line(
+                                        &mut text,
+                                        &format!("(attachment too large, {} MiB)", size),
+                                    );
+                                }
+                                LargeAttachments::Link { directory, url } => {
+                                    let data = client.download_attachment(attachment.id).await?;
 
-                                attachments.push(client.download_attachment(attachment.id).await?);
+                                    // Random so that the links can't be guessed.
+                                    let name = format!("{:032x}", rand::random::<u128>());
+                                    fs::write(directory.join(&name), data).await?;
+
+                                    push_line(
+                                        &mut text,
+                                        &format!("{}/{}", url.trim_end_matches('/'), name),
+                                    );
+                                }
                             }
+                        }
 
-                            // Split the attachments into chunks of 10, which is the maximum allowed by Telegram.
-                            let len = attachments.len();
-                            let chat_ids = group_to_chat.get(&update.gid).unwrap();
-
-                            let mut media_group = Vec::new();
-                            for (i, attachment) in attachments.into_iter().enumerate() {
-                                let text = if media_group.is_empty() {
-                                    Some(text.clone())
-                                } else {
-                                    None
-                                };
-
-                                media_group.push(into_input_media(attachment, text));
-
-                                if media_group.len() == 10 || i == len - 1 {
-                                    for chat_id in chat_ids {
-                                        rate_limit(|| async {
-                                            bot.send_media_group(*chat_id, media_group.clone())
-                                                .await
-                                        })
-                                        .await?;
-                                    }
+                        let plain = format!("{}: {}", user.name, text);
+
+                        for topic in topics {
+                            let bot = &options[topic].bot;
+                            let mut parts = template::render_split(
+                                &options[topic].templates.message,
+                                &[("name", &user.name)],
+                                &text,
+                                MAX_MESSAGE_LENGTH,
+                            );
+
+                            // The text is the caption of the first attachment if it's short
+                            // enough, otherwise it's sent before them.
+                            let caption = match &parts[..] {
+                                [part]
+                                    if !attachments.is_empty()
+                                        && template::length(part) <= MAX_CAPTION_LENGTH =>
+                                {
+                                    parts.pop()
+                                }
+                                _ => None,
+                            };
+
+                            let mut first = None;
 
-                                    media_group.clear();
+                            for part in &parts {
+                                let sent = rate_limit(|| async {
+                                    let mut request = bot
+                                        .send_message(topic.chat_id, part)
+                                        .parse_mode(ParseMode::MarkdownV2);
+
+                                    request.message_thread_id = topic.thread_id;
+                                    request.await
+                                })
+                                .await?;
+
+                                first.get_or_insert(sent.id);
+                                messages.insert(*topic, sent.id, plain.clone()).await;
+
+                                if let Some(text) = telegram::bridged_text(&sent) {
+                                    to_telegram.insert_sent(&text);
                                 }
                             }
-                        } else {
-                            for chat_id in chat_ids {
-                                rate_limit(|| async {
-                                    bot.send_message(*chat_id, &text)
-                                        .parse_mode(ParseMode::MarkdownV2)
-                                        .await
+
+                            // Split the attachments into chunks of 10, which is the maximum allowed by Telegram.
+                            for (i, chunk) in attachments.chunks(10).enumerate() {
+                                let media_group: Vec<_> = chunk
+                                    .iter()
+                                    .enumerate()
+                                    .map(|(j, attachment)| {
+                                        let caption = match i == 0 && j == 0 {
+                                            true => caption.clone(),
+                                            false => None,
+                                        };
+
+                                        into_input_media(attachment.clone(), caption)
+                                    })
+                                    .collect();
+
+                                let sent = rate_limit(|| async {
+                                    let mut request =
+                                        bot.send_media_group(topic.chat_id, media_group.clone());
+
+                                    request.message_thread_id = topic.thread_id;
+                                    request.await
                                 })
                                 .await?;
+
+                                for sent in sent {
+                                    first.get_or_insert(sent.id);
+                                    messages.insert(*topic, sent.id, plain.clone()).await;
+
+                                    if let Some(text) = telegram::bridged_text(&sent) {
+                                        to_telegram.insert_sent(&text);
+                                    }
+                                }
+                            }
+
+                            if let Some(first) = first {
+                                if options[topic].pin_from.contains(&user.name) {
+                                    pin(bot, topic.chat_id, first).await;
+                                }
                             }
                         }
 
+                        metrics::TO_TELEGRAM.add(1);
+
                         if group.typing.is_some() {
                             force_typing.push_back(update.gid);
                         }
@@ -287,18 +506,21 @@ pub async fn run(
                             continue;
                         }
 
-                        let message = format!(
-                            "*{}*: renamed to *{}*",
-                            old_name.markdown_safe(),
-                            user.name.markdown_safe()
-                        );
+                        for topic in topics {
+                            let bot = &options[topic].bot;
+                            let message = template::render(
+                                &options[topic].templates.rename,
+                                &[("name", &user.name), ("old-name", &old_name)],
+                            );
 
-                        for chat_id in chat_ids {
                             rate_limit(|| async {
-                                bot.send_message(*chat_id, &message)
+                                let mut request = bot
+                                    .send_message(topic.chat_id, &message)
                                     .parse_mode(ParseMode::MarkdownV2)
-                                    .disable_notification(true)
-                                    .await
+                                    .disable_notification(true);
+
+                                request.message_thread_id = topic.thread_id;
+                                request.await
                             })
                             .await?;
                         }
@@ -345,7 +567,7 @@ pub async fn run(
                         typing.abort();
                         let _ = typing.await;
                     }
-                    UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup { .. } => {
+                    UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup => {
                         // Handled above.
                         unreachable!()
                     }
@@ -358,10 +580,14 @@ pub async fn run(
                     continue;
                 }
 
-                let chat_ids = group_to_chat.get(&gid).unwrap();
-                for chat_id in chat_ids {
+                let topics = group_to_chat.get(&gid).unwrap();
+                for topic in topics {
+                    let bot = &options[topic].bot;
                     rate_limit(|| async {
-                        bot.send_chat_action(*chat_id, ChatAction::Typing).await
+                        let mut request = bot.send_chat_action(topic.chat_id, ChatAction::Typing);
+
+                        request.message_thread_id = topic.thread_id;
+                        request.await
                     })
                     .await?;
                 }
@@ -369,9 +595,58 @@ pub async fn run(
         }
     }
 
+    // They would be left until the server notices the connection is gone otherwise.
+    for user in users.into_values() {
+        for (gid, uid) in user.gid_uid {
+            client.destroy_user(gid, uid).await?;
+        }
+    }
+
+    client.shutdown().await?;
+
     Ok(())
 }
 
+#[cfg(feature = "transcode")]
+async fn transcode_voice(data: Vec<u8>) -> Vec<u8> {
+    // Bridged as it is if it can't be converted.
+    match transcode::to_mp3(&data).await {
+        Ok(mp3) => mp3,
+        Err(err) => {
+            tracing::warn!("Error transcoding voice note: {}", err);
+            data
+        }
+    }
+}
+
+// Chats are never configured to transcode without the feature.
+#[cfg(not(feature = "transcode"))]
+async fn transcode_voice(data: Vec<u8>) -> Vec<u8> {
+    data
+}
+
+// Pinning needs rights the bot may not have, which is not worth stopping for.
+async fn pin(bot: &Bot, chat_id: ChatId, message_id: MessageId) {
+    let result = rate_limit(|| async {
+        bot.pin_chat_message(chat_id, message_id)
+            .disable_notification(true)
+            .await
+    })
+    .await;
+
+    if let Err(err) = result {
+        tracing::warn!(%chat_id, "Error pinning message: {}", err);
+    }
+}
+
+fn push_line(text: &mut String, line: &str) {
+    if !text.is_empty() {
+        text.push('\n');
+    }
+
+    text.push_str(line);
+}
+
 fn into_input_media(data: Vec<u8>, caption: Option<String>) -> InputMedia {
     // Match on the first bytes to determine if it's a photo, video, or a generic document.
     match &data[..] {
@@ -414,6 +689,56 @@ fn into_input_media(data: Vec<u8>, caption: Option<String>) -> InputMedia {
     }
 }
 
+// Topic bridged for messages in a topic, with its groups. The whole chat is bridged if the topic
+// itself is not.
+fn find_topic(
+    chat_to_group: &HashMap<Topic, HashSet<u32>>,
+    topic: Topic,
+) -> Option<(Topic, &HashSet<u32>)> {
+    [topic, topic.chat()]
+        .into_iter()
+        .find_map(|topic| Some((topic, chat_to_group.get(&topic)?)))
+}
+
+// Multichat users of a Telegram user, which are created or renamed if needed.
+async fn telegram_user<'a>(
+    client: &mut MaybeTlsClient,
+    users: &'a mut HashMap<(UserId, Topic), TelegramUser>,
+    owned: &mut HashSet<(u32, u32)>,
+    gids: &HashSet<u32>,
+    key: (UserId, Topic),
+    name: String,
+) -> Result<&'a TelegramUser, Error> {
+    let user = match users.entry(key) {
+        Entry::Occupied(entry) => {
+            let user = entry.into_mut();
+            if user.name != name {
+                for (gid, uid) in &user.gid_uid {
+                    client.rename_user(*gid, *uid, &name).await?;
+                }
+
+                user.name = name;
+            }
+
+            user
+        }
+        Entry::Vacant(entry) => {
+            let mut gid_uid = Vec::new();
+
+            for gid in gids {
+                let uid = client.init_user(*gid, &name).await?;
+
+                gid_uid.push((*gid, uid));
+                owned.insert((*gid, uid));
+            }
+
+            entry.insert(TelegramUser { name, gid_uid })
+        }
+    };
+
+    Ok(user)
+}
+
 async fn rate_limit<T, C: Fn() -> F, F: Future<Output = Result<T, RequestError>>>(
     c: C,
 ) -> Result<T, RequestError> {
@@ -423,11 +748,16 @@ async fn rate_limit<T, C: Fn() -> F, F: Future<Output = Result<T, RequestError>>
             Err(RequestError::RetryAfter(duration)) => {
                 let duration = duration.duration();
                 tracing::warn!(?duration, "Rate limited, waiting");
+                metrics::RATE_LIMITED.add(1);
+                metrics::RATE_LIMITED_TIME.add_duration(duration);
 
                 time::sleep(duration).await;
                 continue;
             }
-            Err(err) => return Err(err),
+            Err(err) => {
+                metrics::API_ERRORS.add(1);
+                return Err(err);
+            }
         }
     }
 }
@@ -1,131 +1,355 @@
-use teloxide::net::Download;
-use teloxide::prelude::Requester;
-use teloxide::types::{ChatId, MediaKind, MediaText, Message, MessageCommon, MessageKind, UserId};
+use crate::config::{Filter, Names};
+use crate::download::Downloader;
+use crate::markup;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use teloxide::dispatching::{Dispatcher, UpdateFilterExt};
+use teloxide::dptree;
+use teloxide::error_handlers::LoggingErrorHandler;
+use teloxide::types::{
+    AllowedUpdate, ChatId, Location, MaybeInaccessibleMessage, Me, MediaContact, MediaKind,
+    MediaLocation, MediaPoll, MediaText, MediaVenue, Message, MessageCommon, MessageEntity,
+    MessageId, MessageKind, MessageReactionUpdated, ReactionType, ThreadId, Update, User, UserId,
+};
+use teloxide::update_listeners::UpdateListener;
 use teloxide::{Bot, RequestError};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
+use tokio::time;
+
+// Longest part of a replied to message quoted, in characters.
+const MAX_QUOTE_LENGTH: usize = 64;
+
+/// Updates handled by [`run`], for listeners which can't tell Telegram themselves.
+#[cfg_attr(not(feature = "webhook"), allow(dead_code))]
+pub const ALLOWED_UPDATES: &[AllowedUpdate] = &[
+    AllowedUpdate::Message,
+    AllowedUpdate::EditedMessage,
+    AllowedUpdate::MessageReaction,
+];
+
+/// Chat, or one of the topics of a forum.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Topic {
+    pub chat_id: ChatId,
+    /// Not given for chats which are not forums, or for the whole chat.
+    pub thread_id: Option<ThreadId>,
+}
+
+impl Topic {
+    /// The whole chat the topic is in.
+    pub fn chat(self) -> Self {
+        Self {
+            chat_id: self.chat_id,
+            thread_id: None,
+        }
+    }
+}
 
 pub struct Event {
     pub chat_id: ChatId,
+    /// Topic of the message, not known for reactions.
+    pub thread_id: Option<ThreadId>,
     pub user_id: UserId,
     pub kind: EventKind,
 }
 
 pub enum EventKind {
     Message {
+        message_id: MessageId,
         user_name: String,
         text: String,
+        /// Quote of the message replied to.
+        reply: Option<String>,
         attachment: Option<Vec<u8>>,
+        /// The attachment is a voice note, in OGG/Opus.
+        voice: bool,
+        /// Sent again with new text, attachments of edits are not downloaded again.
+        edited: bool,
+    },
+    Reaction {
+        message_id: MessageId,
+        user_name: String,
+        /// Only those added, as emoji.
+        reactions: Vec<String>,
+    },
+    /// Only bridged in chats configured to.
+    Join {
+        user_name: String,
     },
     Leave,
 }
 
-pub async fn run(bot: Bot, sender: Sender<Event>) {
-    teloxide::repl(bot, move |bot: Bot, message: Message| {
-        let sender = sender.clone();
+/// Dispatches updates until shut down, or until the listener stops.
+pub async fn run<L>(
+    bot: Bot,
+    listener: L,
+    filter: Filter,
+    names: Arc<Names>,
+    sender: Sender<Event>,
+    mut shutdown: watch::Receiver<()>,
+) where
+    L: UpdateListener + Send,
+    L::Err: Debug,
+{
+    let handler = dptree::entry()
+        .branch(Update::filter_message().endpoint(
+            |downloader: Arc<Downloader>,
+             me: Me,
+             message: Message,
+             filter: Arc<Filter>,
+             names: Arc<Names>,
+             sender: Sender<Event>| {
+                handle(downloader, me, message, filter, names, sender, false)
+            },
+        ))
+        .branch(Update::filter_edited_message().endpoint(
+            |downloader: Arc<Downloader>,
+             me: Me,
+             message: Message,
+             filter: Arc<Filter>,
+             names: Arc<Names>,
+             sender: Sender<Event>| {
+                handle(downloader, me, message, filter, names, sender, true)
+            },
+        ))
+        .branch(Update::filter_message_reaction_updated().endpoint(handle_reaction));
+
+    let downloader = Arc::new(Downloader::new(bot.clone()));
+    let mut dispatcher = Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![downloader, Arc::new(filter), names, sender])
+        .default_handler(|_: Arc<Update>| async {})
+        .build();
+
+    let token = dispatcher.shutdown_token();
+
+    // Updates being handled are finished first.
+    let stop = async {
+        let _ = shutdown.changed().await;
 
-        handle(bot, message, sender)
-    })
-    .await;
+        loop {
+            match token.shutdown() {
+                Ok(stopped) => return stopped.await,
+                // Not dispatching yet.
+                Err(_) => time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = dispatcher.dispatch_with_listener(
+            listener,
+            LoggingErrorHandler::with_custom_text("Error receiving updates"),
+        ) => {}
+        _ = stop => {}
+    }
 }
 
-async fn handle(bot: Bot, message: Message, sender: Sender<Event>) -> Result<(), RequestError> {
+async fn handle(
+    downloader: Arc<Downloader>,
+    me: Me,
+    message: Message,
+    filter: Arc<Filter>,
+    names: Arc<Names>,
+    sender: Sender<Event>,
+    edited: bool,
+) -> Result<(), RequestError> {
+    let reply = message
+        .reply_to_message()
+        .and_then(|reply| quote_message(&names, &me, reply));
+
     let from = match message.from {
         Some(from) => from,
         None => return Ok(()),
     };
 
     let chat_id = message.chat.id;
+    // Messages in reply threads of groups which are not forums have a thread too.
+    let thread_id = match message.is_topic_message {
+        true => message.thread_id,
+        false => None,
+    };
+
     let (user_id, kind) = match message.kind {
+        MessageKind::NewChatMembers(members) => {
+            // Several users can be added at once, each of them joins.
+            for member in members.new_chat_members {
+                if member.id == me.id || !allowed(&filter, &member) {
+                    continue;
+                }
+
+                let event = Event {
+                    chat_id,
+                    thread_id,
+                    user_id: member.id,
+                    kind: EventKind::Join {
+                        user_name: display_name(&names, &member),
+                    },
+                };
+
+                let _ = sender.send(event).await;
+            }
+
+            return Ok(());
+        }
+        MessageKind::Pinned(pinned) if allowed(&filter, &from) => {
+            // Those which are inaccessible are too old to be quoted.
+            let quote = match &*pinned.pinned {
+                MaybeInaccessibleMessage::Regular(pinned) => quote_message(&names, &me, pinned),
+                MaybeInaccessibleMessage::Inaccessible(_) => None,
+            };
+
+            let text = match quote {
+                Some(quote) => format!("pinned a message\n{}", quote),
+                None => "pinned a message".to_owned(),
+            };
+
+            (
+                from.id,
+                EventKind::Message {
+                    message_id: message.id,
+                    user_name: display_name(&names, &from),
+                    text,
+                    reply: None,
+                    attachment: None,
+                    voice: false,
+                    edited,
+                },
+            )
+        }
+        // Users who are filtered out still leave, they may have been bridged before.
         MessageKind::LeftChatMember(member) => (member.left_chat_member.id, EventKind::Leave),
+        MessageKind::Common(_) if !allowed(&filter, &from) => return Ok(()),
         MessageKind::Common(MessageCommon { media_kind, .. }) => match media_kind {
-            MediaKind::Text(MediaText { text, .. }) => (
+            MediaKind::Text(MediaText { text, .. })
+                if filter.ignore_commands && text.starts_with('/') =>
+            {
+                return Ok(())
+            }
+            MediaKind::Text(MediaText { text, entities, .. }) => (
                 from.id,
                 EventKind::Message {
-                    user_name: from.full_name(),
-                    text,
+                    message_id: message.id,
+                    user_name: display_name(&names, &from),
+                    text: markup::from_entities(&text, &entities),
+                    reply,
                     attachment: None,
+                    voice: false,
+                    edited,
                 },
             ),
             MediaKind::Photo(photo) => {
-                let text = photo.caption.unwrap_or_default();
+                let text = caption(photo.caption, &photo.caption_entities);
                 let photo = photo
                     .photo
                     .into_iter()
                     .max_by_key(|photo| photo.width * photo.height);
 
                 let attachment = match photo {
-                    Some(photo) => {
-                        let mut data = Vec::new();
-
-                        let file = bot.get_file(&photo.file.id).await?;
-                        bot.download_file(&file.path, &mut data).await?;
-
-                        Some(data)
-                    }
-                    None => None,
+                    Some(photo) if !edited => Some(downloader.download(&photo.file).await?),
+                    _ => None,
                 };
 
                 (
                     from.id,
                     EventKind::Message {
-                        user_name: from.full_name(),
+                        message_id: message.id,
+                        user_name: display_name(&names, &from),
                         text,
+                        reply,
                         attachment,
+                        voice: false,
+                        edited,
                     },
                 )
             }
             MediaKind::Video(video) => {
-                let mut data = Vec::new();
-
-                let file = bot.get_file(&video.video.file.id).await?;
-                bot.download_file(&file.path, &mut data).await?;
+                let attachment = match edited {
+                    true => None,
+                    false => Some(downloader.download(&video.video.file).await?),
+                };
 
                 (
                     from.id,
                     EventKind::Message {
-                        user_name: from.full_name(),
-                        text: video.caption.unwrap_or_default(),
-                        attachment: Some(data),
+                        message_id: message.id,
+                        user_name: display_name(&names, &from),
+                        text: caption(video.caption, &video.caption_entities),
+                        reply,
+                        attachment,
+                        voice: false,
+                        edited,
                     },
                 )
             }
             MediaKind::Document(document) => {
-                let mut data = Vec::new();
-
-                let file = bot.get_file(&document.document.file.id).await?;
-                bot.download_file(&file.path, &mut data).await?;
+                let attachment = match edited {
+                    true => None,
+                    false => Some(downloader.download(&document.document.file).await?),
+                };
 
                 (
                     from.id,
                     EventKind::Message {
-                        user_name: from.full_name(),
-                        text: document.caption.unwrap_or_default(),
-                        attachment: Some(data),
+                        message_id: message.id,
+                        user_name: display_name(&names, &from),
+                        text: caption(document.caption, &document.caption_entities),
+                        reply,
+                        attachment,
+                        voice: false,
+                        edited,
                     },
                 )
             }
             MediaKind::Voice(voice) => {
-                let mut data = Vec::new();
+                let attachment = match edited {
+                    true => None,
+                    false => Some(downloader.download(&voice.voice.file).await?),
+                };
 
-                let file = bot.get_file(&voice.voice.file.id).await?;
-                bot.download_file(&file.path, &mut data).await?;
+                (
+                    from.id,
+                    EventKind::Message {
+                        message_id: message.id,
+                        user_name: display_name(&names, &from),
+                        text: caption(voice.caption, &voice.caption_entities),
+                        reply,
+                        attachment,
+                        voice: true,
+                        edited,
+                    },
+                )
+            }
+            // Live locations are edited as they move.
+            MediaKind::Location(_) if edited => return Ok(()),
+            media_kind => {
+                let text = match describe(&media_kind) {
+                    Some(text) => text,
+                    None => return Ok(()),
+                };
 
                 (
                     from.id,
                     EventKind::Message {
-                        user_name: from.full_name(),
-                        text: voice.caption.unwrap_or_default(),
-                        attachment: Some(data),
+                        message_id: message.id,
+                        user_name: display_name(&names, &from),
+                        text,
+                        reply,
+                        attachment: None,
+                        voice: false,
+                        edited,
                     },
                 )
             }
-            _ => return Ok(()),
         },
         _ => return Ok(()),
     };
 
     let event = Event {
         chat_id,
+        thread_id,
         user_id,
         kind,
     };
@@ -134,3 +358,263 @@ async fn handle(bot: Bot, message: Message, sender: Sender<Event>) -> Result<(),
 
     Ok(())
 }
+
+async fn handle_reaction(
+    reaction: MessageReactionUpdated,
+    filter: Arc<Filter>,
+    names: Arc<Names>,
+    sender: Sender<Event>,
+) -> Result<(), RequestError> {
+    // Anonymous reactions have no user to send them as.
+    let user = match reaction.user {
+        Some(user) if allowed(&filter, &user) => user,
+        _ => return Ok(()),
+    };
+
+    let reactions: Vec<_> = reaction
+        .new_reaction
+        .iter()
+        .filter(|added| !reaction.old_reaction.contains(added))
+        .filter_map(|added| match added {
+            ReactionType::Emoji { emoji } => Some(emoji.clone()),
+            ReactionType::CustomEmoji { .. } => None,
+        })
+        .collect();
+
+    if reactions.is_empty() {
+        return Ok(());
+    }
+
+    let event = Event {
+        chat_id: reaction.chat.id,
+        thread_id: None,
+        user_id: user.id,
+        kind: EventKind::Reaction {
+            message_id: reaction.message_id,
+            user_name: display_name(&names, &user),
+            reactions,
+        },
+    };
+
+    let _ = sender.send(event).await;
+
+    Ok(())
+}
+
+// Text for messages which have none, or None if they're not bridged.
+fn describe(media_kind: &MediaKind) -> Option<String> {
+    let text = match media_kind {
+        MediaKind::Location(MediaLocation { location }) => {
+            format!("Location: {}", map_link(location))
+        }
+        MediaKind::Venue(MediaVenue { venue }) => format!(
+            "Venue: {}, {}\n{}",
+            venue.title,
+            venue.address,
+            map_link(&venue.location)
+        ),
+        MediaKind::Contact(MediaContact { contact }) => {
+            let mut text = format!("Contact: {}", contact.first_name);
+            if let Some(last_name) = &contact.last_name {
+                text.push(' ');
+                text.push_str(last_name);
+            }
+
+            text.push_str(", ");
+            text.push_str(&contact.phone_number);
+            text
+        }
+        MediaKind::Poll(MediaPoll { poll }) => {
+            let mut text = format!("Poll: {}", poll.question);
+            for option in &poll.options {
+                text.push_str("\n- ");
+                text.push_str(&option.text);
+            }
+
+            text
+        }
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+fn map_link(location: &Location) -> String {
+    format!(
+        "https://www.openstreetmap.org/?mlat={}&mlon={}",
+        location.latitude, location.longitude
+    )
+}
+
+/// Text or caption of a message as it's bridged, with formatting turned into markup.
+pub fn bridged_text(message: &Message) -> Option<String> {
+    match message.text() {
+        Some(text) => Some(markup::from_entities(
+            text,
+            message.entities().unwrap_or_default(),
+        )),
+        None => Some(markup::from_entities(
+            message.caption()?,
+            message.caption_entities().unwrap_or_default(),
+        )),
+    }
+}
+
+fn caption(caption: Option<String>, entities: &[MessageEntity]) -> String {
+    match caption {
+        Some(caption) => markup::from_entities(&caption, entities),
+        None => String::new(),
+    }
+}
+
+fn display_name(names: &Names, user: &User) -> String {
+    if let Some(name) = names.overrides.get(&user.id.0) {
+        return name.clone();
+    }
+
+    match &user.username {
+        Some(username) if names.usernames => format!("{} (@{})", user.full_name(), username),
+        _ => user.full_name(),
+    }
+}
+
+fn allowed(filter: &Filter, user: &User) -> bool {
+    let id = user.id.0;
+
+    if (filter.ignore_bots && user.is_bot) || filter.ignored_users.contains(&id) {
+        return false;
+    }
+
+    match &filter.allowed_users {
+        Some(allowed) => allowed.contains(&id),
+        None => true,
+    }
+}
+
+fn quote_message(names: &Names, me: &Me, message: &Message) -> Option<String> {
+    let text = message.text().or_else(|| message.caption())?;
+
+    // Our own messages already start with the name of the Multichat user.
+    let author = match &message.from {
+        Some(from) if from.id != me.id => Some(display_name(names, from)),
+        _ => None,
+    };
+
+    Some(quote(author.as_deref(), text))
+}
+
+/// Quotes the first line of a message, shortened if it's too long.
+pub fn quote(author: Option<&str>, text: &str) -> String {
+    let line = text.lines().next().unwrap_or_default();
+
+    let mut quote = match author {
+        Some(author) => format!("> {}: ", author),
+        None => "> ".to_owned(),
+    };
+
+    match line.char_indices().nth(MAX_QUOTE_LENGTH) {
+        Some((idx, _)) => {
+            quote.push_str(line[..idx].trim_end());
+            quote.push('…');
+        }
+        None if line.len() != text.len() => {
+            quote.push_str(line);
+            quote.push('…');
+        }
+        None => quote.push_str(line),
+    }
+
+    quote
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use teloxide::types::Contact;
+
+    fn user(id: u64, is_bot: bool) -> User {
+        User {
+            id: UserId(id),
+            is_bot,
+            first_name: "alice".to_owned(),
+            last_name: None,
+            username: None,
+            language_code: None,
+            is_premium: false,
+            added_to_attachment_menu: false,
+        }
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(quote(Some("alice"), "hi"), "> alice: hi");
+        assert_eq!(quote(None, "bob: hi\nall"), "> bob: hi…");
+        assert_eq!(
+            quote(Some("alice"), &"a".repeat(100)),
+            format!("> alice: {}…", "a".repeat(MAX_QUOTE_LENGTH))
+        );
+    }
+
+    #[test]
+    fn names() {
+        let mut user = user(1, false);
+        user.username = Some("alice".to_owned());
+
+        let mut names = Names::default();
+        assert_eq!(display_name(&names, &user), "alice");
+
+        names.usernames = true;
+        assert_eq!(display_name(&names, &user), "alice (@alice)");
+
+        names.overrides.insert(1, "Alice".to_owned());
+        assert_eq!(display_name(&names, &user), "Alice");
+    }
+
+    #[test]
+    fn describes() {
+        let location = Location {
+            longitude: 14.42,
+            latitude: 50.08,
+            horizontal_accuracy: None,
+            live_period: None,
+            heading: None,
+            proximity_alert_radius: None,
+        };
+
+        assert_eq!(
+            describe(&MediaKind::Location(MediaLocation { location })).unwrap(),
+            "Location: https://www.openstreetmap.org/?mlat=50.08&mlon=14.42"
+        );
+
+        let contact = Contact {
+            phone_number: "+420123456789".to_owned(),
+            first_name: "Alice".to_owned(),
+            last_name: Some("Smith".to_owned()),
+            user_id: None,
+            vcard: None,
+        };
+
+        assert_eq!(
+            describe(&MediaKind::Contact(MediaContact { contact })).unwrap(),
+            "Contact: Alice Smith, +420123456789"
+        );
+    }
+
+    #[test]
+    fn filters() {
+        let mut filter = Filter::default();
+        assert!(allowed(&filter, &user(1, true)));
+
+        filter.ignore_bots = true;
+        filter.ignored_users = vec![2];
+        assert!(!allowed(&filter, &user(1, true)));
+        assert!(!allowed(&filter, &user(2, false)));
+        assert!(allowed(&filter, &user(3, false)));
+
+        filter.allowed_users = Some(vec![2, 4]);
+        assert!(!allowed(&filter, &user(2, false)));
+        assert!(!allowed(&filter, &user(3, false)));
+        assert!(allowed(&filter, &user(4, false)));
+    }
+}
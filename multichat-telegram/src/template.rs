@@ -0,0 +1,173 @@
+use crate::config::Templates;
+use crate::markdown_safe::{MarkdownSafeExt, SPECIAL};
+use crate::markup;
+
+impl Templates {
+    /// Templates with the group already filled in.
+    pub fn for_group(&self, group: &str) -> Self {
+        let values = [("group", group)];
+
+        Self {
+            message: render(&self.message, &values),
+            join: render(&self.join, &values),
+            leave: render(&self.leave, &values),
+            rename: render(&self.rename, &values),
+        }
+    }
+}
+
+/// Replaces `{placeholders}` with values made safe for MarkdownV2, unknown ones are kept.
+pub fn render(template: &str, values: &[(&str, &str)]) -> String {
+    substitute(template, &escape(values))
+}
+
+/// Renders the template once for every part of a text too long to be sent at once, so that each
+/// is at most `max_length` long. The markup of the text is turned into formatting.
+pub fn render_split(
+    template: &str,
+    values: &[(&str, &str)],
+    text: &str,
+    max_length: usize,
+) -> Vec<String> {
+    let mut values = escape(values);
+    values.push(("text", String::new()));
+
+    let overhead = length(&substitute(template, &values));
+
+    split(text, max_length.saturating_sub(overhead))
+        .into_iter()
+        .map(|part| {
+            values.last_mut().unwrap().1 = markup::to_markdown(part);
+            substitute(template, &values)
+        })
+        .collect()
+}
+
+/// Length of a text as Telegram counts it, in UTF-16 code units.
+pub fn length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn escape<'a>(values: &[(&'a str, &str)]) -> Vec<(&'a str, String)> {
+    values
+        .iter()
+        .map(|(key, value)| (*key, value.markdown_safe().to_string()))
+        .collect()
+}
+
+// Values are already MarkdownV2.
+fn substitute(template: &str, values: &[(&str, String)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = rest.find('}').and_then(|end| {
+            let (_, value) = values.iter().find(|(key, _)| *key == &rest[1..end])?;
+            Some((end, value))
+        });
+
+        match value {
+            Some((end, value)) => {
+                rendered.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+// Splits a text into parts which are at most `max_length` long once made safe for MarkdownV2,
+// preferably at whitespace. Escaping is done for every part separately, so it's never split.
+fn split(mut text: &str, max_length: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+
+    loop {
+        let mut length = 0;
+        let mut end = None;
+        let mut whitespace = None;
+
+        for (idx, c) in text.char_indices() {
+            if c.is_whitespace() && idx != 0 {
+                whitespace = Some(idx);
+            }
+
+            let escaped = if SPECIAL.contains(&c) { 2 } else { 1 };
+            length += c.len_utf16() * escaped;
+
+            if length > max_length {
+                end = Some(idx);
+                break;
+            }
+        }
+
+        let end = match end {
+            Some(end) => end,
+            None => {
+                parts.push(text);
+                return parts;
+            }
+        };
+
+        // A single character is always sent, even if it's too long.
+        let end = match whitespace {
+            Some(whitespace) => whitespace,
+            None if end == 0 => text.chars().next().unwrap().len_utf8(),
+            None => end,
+        };
+
+        parts.push(text[..end].trim_end());
+        text = text[end..].trim_start();
+
+        if text.is_empty() {
+            return parts;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders() {
+        let values = [("name", "a_b"), ("text", "hi!")];
+
+        assert_eq!(render("*{name}*: {text}", &values), "*a\\_b*: hi\\!");
+        assert_eq!(render("{unknown} {name", &values), "{unknown} {name");
+        assert_eq!(render("{{name}}", &values), "{a\\_b}");
+
+        // Values are not rendered again.
+        let templates = Templates {
+            message: "{group} {text}".to_owned(),
+            ..Templates::default()
+        }
+        .for_group("{text}");
+        assert_eq!(render(&templates.message, &values), "\\{text\\} hi\\!");
+    }
+
+    #[test]
+    fn splits() {
+        assert_eq!(split("", 4), [""]);
+        assert_eq!(split("ab cd ef", 5), ["ab cd", "ef"]);
+        assert_eq!(split("abcdef", 4), ["abcd", "ef"]);
+        assert_eq!(split("a.b.c", 4), ["a.b", ".c"]);
+        assert_eq!(split("ab\n  cd", 4), ["ab", "cd"]);
+        assert_eq!(split("😀😀", 1), ["😀", "😀"]);
+
+        let parts = render_split("*{name}*: {text}", &[("name", "a")], "bc de", 8);
+        assert_eq!(parts, ["*a*: bc", "*a*: de"]);
+        assert!(parts.iter().all(|part| length(part) <= 8));
+
+        let parts = render_split("{text}", &[], "*a.b*", 4096);
+        assert_eq!(parts, ["*a\\.b*"]);
+    }
+}
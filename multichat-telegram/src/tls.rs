@@ -6,12 +6,18 @@ use tokio::fs;
 use tokio_rustls::rustls::{self, ClientConfig, RootCertStore};
 use tokio_rustls::TlsConnector;
 
+#[cfg(feature = "webhook")]
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error(transparent)]
     Rustls(#[from] rustls::Error),
     #[error(transparent)]
     Io(#[from] io::Error),
+    #[cfg(feature = "webhook")]
+    #[error("No private key provided")]
+    NoKeys,
 }
 
 pub async fn configure(certificate: &Path) -> Result<TlsConnector, Error> {
@@ -23,7 +29,8 @@ pub async fn configure(certificate: &Path) -> Result<TlsConnector, Error> {
         store.add(certificate)?;
     }
 
-    let config = ClientConfig::builder()
+    let config = ClientConfig::builder_with_provider(multichat_client::tls::provider())
+        .with_safe_default_protocol_versions()?
         .with_root_certificates(store)
         .with_no_client_auth();
 
@@ -31,3 +38,19 @@ pub async fn configure(certificate: &Path) -> Result<TlsConnector, Error> {
 
     Ok(TlsConnector::from(config))
 }
+
+#[cfg(feature = "webhook")]
+pub async fn acceptor(certificate: &Path, key: &Path) -> Result<TlsAcceptor, Error> {
+    let certificates = fs::read(certificate).await?;
+    let certificates = rustls_pemfile::certs(&mut &*certificates).collect::<Result<_, _>>()?;
+
+    let key = fs::read(key).await?;
+    let key = rustls_pemfile::private_key(&mut &*key)?.ok_or(Error::NoKeys)?;
+
+    let config = ServerConfig::builder_with_provider(multichat_client::tls::provider())
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,45 @@
+use std::io;
+use std::process::{ExitStatus, Stdio};
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Error running ffmpeg: {0}")]
+    Io(#[from] io::Error),
+    #[error("ffmpeg failed with {0}")]
+    Failed(ExitStatus),
+}
+
+/// Converts audio to MP3 with `ffmpeg`, which has to be installed.
+pub async fn to_mp3(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-i", "pipe:0", "-f", "mp3", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // Written while the output is read, ffmpeg would block on a full pipe otherwise.
+    let mut stdin = child.stdin.take().unwrap();
+    let write = async move {
+        stdin.write_all(data).await?;
+        // Closed so that ffmpeg knows the input ended.
+        drop(stdin);
+
+        Ok::<_, io::Error>(())
+    };
+
+    let (written, output) = tokio::join!(write, child.wait_with_output());
+    let output = output?;
+
+    if !output.status.success() {
+        return Err(Error::Failed(output.status));
+    }
+
+    written?;
+
+    Ok(output.stdout)
+}
@@ -0,0 +1,120 @@
+//! Receiving updates through a webhook Telegram posts them to, instead of long polling.
+use crate::config::Webhook;
+use crate::telegram;
+use crate::tls;
+
+use axum::Router;
+use hyper::server::conn::http1;
+use hyper_util::rt::TokioIo;
+use hyper_util::service::TowerToHyperService;
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use teloxide::prelude::Requester;
+use teloxide::update_listeners::webhooks::{self, Options};
+use teloxide::update_listeners::UpdateListener;
+use teloxide::{Bot, RequestError};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Tls(#[from] tls::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Error setting webhook: {0}")]
+    Telegram(#[from] RequestError),
+}
+
+/// Starts listening for updates and sets the webhook, so that Telegram posts them.
+pub async fn listen(
+    bot: Bot,
+    webhook: Webhook,
+) -> Result<impl UpdateListener<Err = Infallible>, Error> {
+    let url = Url::parse(&webhook.url)?;
+    let acceptor = match &webhook.tls {
+        Some(tls) => Some(tls::acceptor(&tls.certificate, &tls.key).await?),
+        None => None,
+    };
+
+    let listener = TcpListener::bind(webhook.address).await?;
+    tracing::info!("Listening for webhook updates on {}", webhook.address);
+
+    let options =
+        Options::new(webhook.address, url.clone()).secret_token(webhook.secret_token.clone());
+
+    // Set up here, teloxide wouldn't ask for reactions which Telegram doesn't send by default.
+    let (updates, stop, router) = webhooks::axum_no_setup(options);
+
+    let mut request = bot.set_webhook(url);
+    request.allowed_updates = Some(telegram::ALLOWED_UPDATES.to_vec());
+    request.secret_token = Some(webhook.secret_token);
+    request.await?;
+
+    tracing::info!("Webhook set");
+
+    match acceptor {
+        Some(acceptor) => {
+            tokio::spawn(serve_tls(listener, acceptor, router, stop));
+        }
+        None => {
+            tokio::spawn(async move {
+                if let Err(err) = axum::serve(listener, router)
+                    .with_graceful_shutdown(stop)
+                    .await
+                {
+                    tracing::error!("Error serving webhook: {}", err);
+                }
+            });
+        }
+    }
+
+    Ok(updates)
+}
+
+async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    router: Router,
+    stop: impl Future<Output = ()>,
+) {
+    tokio::pin!(stop);
+
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!("Error accepting webhook connection: {}", err);
+                    continue;
+                }
+            },
+            _ = &mut stop => return,
+        };
+
+        let acceptor = acceptor.clone();
+        let service = TowerToHyperService::new(router.clone());
+
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    tracing::debug!(%addr, "Webhook TLS handshake failed: {}", err);
+                    return;
+                }
+            };
+
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!(%addr, "Webhook connection failed: {}", err);
+            }
+        });
+    }
+}
@@ -0,0 +1,304 @@
+//! Editing of the `[[clients]]` of a server config, keeping comments and formatting of the rest.
+
+use multichat_proto::AccessToken;
+use multichat_server::config::Config;
+use sha1::{Digest, Sha1};
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+use thiserror::Error;
+use toml_edit::{value, Array, ArrayOfTables, DocumentMut, Item, Table, TomlError};
+
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Error parsing config: {0}")]
+    Parse(#[from] TomlError),
+    #[error("The edited config would be invalid: {0}")]
+    Invalid(toml::de::Error),
+    #[error("Clients are not a list of [[clients]] tables")]
+    NotTables,
+    #[error("The access token is already in the config")]
+    Exists,
+    #[error("No client matches {0:?}")]
+    NotFound(String),
+}
+
+/// Client to add to a config.
+pub struct NewClient {
+    pub access_token: AccessToken,
+    pub name: Option<String>,
+    /// `None` allows all groups.
+    pub groups: Option<Vec<String>>,
+    pub read_only: bool,
+}
+
+/// Client of a config, without its access token.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub fingerprint: String,
+    pub name: Option<String>,
+    pub groups: String,
+    pub scope: String,
+}
+
+/// Short hash identifying an access token, which can be shown without giving the token away.
+pub fn fingerprint(access_token: &AccessToken) -> String {
+    let hash = Sha1::digest(access_token.to_string());
+    hex::encode(&hash[..8])
+}
+
+pub fn load(path: &Path) -> Result<DocumentMut, Error> {
+    Ok(fs::read_to_string(path)?.parse()?)
+}
+
+/// Writes the config if the server would accept it, replacing the old one at once so that it's
+/// never left half written.
+pub fn save(path: &Path, document: &DocumentMut) -> Result<(), Error> {
+    let data = document.to_string();
+    toml::from_str::<Config>(&data).map_err(Error::Invalid)?;
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    // Left behind by a run which failed before renaming it.
+    match fs::remove_file(&temporary) {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+
+    // The config contains access tokens, so the new file is never readable by more than the
+    // original, not even before its permissions are copied.
+    let permissions = fs::metadata(path)?.permissions();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    options.mode(permissions.mode());
+
+    let mut file = options.open(&temporary)?;
+    file.write_all(data.as_bytes())?;
+    file.sync_all()?;
+
+    fs::set_permissions(&temporary, permissions)?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+pub fn list(document: &DocumentMut) -> Result<Vec<Entry>, Error> {
+    let clients = match clients(document)? {
+        Some(clients) => clients,
+        None => return Ok(Vec::new()),
+    };
+
+    let entries = clients
+        .iter()
+        .map(|client| {
+            let access_token = client.get("access-token").and_then(Item::as_str);
+            let fingerprint = access_token
+                .and_then(|token| token.parse().ok())
+                .map(|token| fingerprint(&token))
+                .unwrap_or_else(|| "invalid".to_owned());
+
+            let groups = match client.get("groups") {
+                Some(groups) => match groups.as_array() {
+                    Some(groups) => groups
+                        .iter()
+                        .filter_map(|group| group.as_str())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    None => groups.as_str().unwrap_or_default().to_owned(),
+                },
+                None => String::new(),
+            };
+
+            let scope = client.get("scope").and_then(Item::as_str);
+
+            Entry {
+                fingerprint,
+                name: client.get("name").and_then(Item::as_str).map(From::from),
+                groups,
+                scope: scope.unwrap_or("read-write").to_owned(),
+            }
+        })
+        .collect();
+
+    Ok(entries)
+}
+
+pub fn add(document: &mut DocumentMut, client: &NewClient) -> Result<(), Error> {
+    let access_token = client.access_token.to_string();
+
+    if document.get("clients").is_none() {
+        document["clients"] = Item::ArrayOfTables(ArrayOfTables::new());
+    }
+
+    let clients = document["clients"]
+        .as_array_of_tables_mut()
+        .ok_or(Error::NotTables)?;
+
+    let exists = clients
+        .iter()
+        .any(|table| access_token_of(table).as_ref() == Some(&client.access_token));
+
+    if exists {
+        return Err(Error::Exists);
+    }
+
+    let mut table = Table::new();
+    table["access-token"] = value(access_token);
+
+    if let Some(name) = &client.name {
+        table["name"] = value(name);
+    }
+
+    table["groups"] = match &client.groups {
+        Some(groups) => value(groups.iter().collect::<Array>()),
+        None => value("*"),
+    };
+
+    if client.read_only {
+        table["scope"] = value("read-only");
+    }
+
+    clients.push(table);
+
+    Ok(())
+}
+
+/// Removes clients with an access token, fingerprint or name, returning how many there were.
+pub fn remove(document: &mut DocumentMut, target: &str) -> Result<usize, Error> {
+    let clients = match document.get_mut("clients") {
+        Some(clients) => clients.as_array_of_tables_mut().ok_or(Error::NotTables)?,
+        None => return Err(Error::NotFound(target.to_owned())),
+    };
+
+    let before = clients.len();
+    clients.retain(|table| {
+        let token = access_token_of(table);
+        let matches = token.is_some_and(|token| {
+            token.to_string() == target.to_ascii_lowercase() || fingerprint(&token) == target
+        });
+
+        !(matches || table.get("name").and_then(Item::as_str) == Some(target))
+    });
+
+    match before - clients.len() {
+        0 => Err(Error::NotFound(target.to_owned())),
+        removed => Ok(removed),
+    }
+}
+
+fn clients(document: &DocumentMut) -> Result<Option<&ArrayOfTables>, Error> {
+    match document.get("clients") {
+        Some(clients) => clients
+            .as_array_of_tables()
+            .ok_or(Error::NotTables)
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+fn access_token_of(table: &Table) -> Option<AccessToken> {
+    table.get("access-token")?.as_str()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "# Comment kept as it is\nlisten = \"127.0.0.1:8585\"\nmax-size = \"64 KiB\"\n\n\
+        [[clients]]\naccess-token = \"52f0395327987f07f805c3ac54fe38ac123303fcdb62a61fdfc9b8082195486c\"\n\
+        name = \"example\"\ngroups = \"*\"\n";
+
+    const TOKEN: &str = "07e6a978bbed823e85e51b9702a73b5e1fe5599b01628a7cc076fadc737d071f";
+
+    #[test]
+    fn adds_and_removes() {
+        let mut document: DocumentMut = CONFIG.parse().unwrap();
+        let client = NewClient {
+            access_token: TOKEN.parse().unwrap(),
+            name: Some("bridge".to_owned()),
+            groups: Some(vec!["foo".to_owned(), "bar".to_owned()]),
+            read_only: true,
+        };
+
+        add(&mut document, &client).unwrap();
+        assert!(matches!(add(&mut document, &client), Err(Error::Exists)));
+
+        let data = document.to_string();
+        assert!(data.starts_with("# Comment kept as it is\n"));
+        toml::from_str::<Config>(&data).unwrap();
+
+        let entries = list(&document).unwrap();
+        assert_eq!(
+            entries[1],
+            Entry {
+                fingerprint: fingerprint(&client.access_token),
+                name: Some("bridge".to_owned()),
+                groups: "foo,bar".to_owned(),
+                scope: "read-only".to_owned(),
+            }
+        );
+
+        assert_eq!(remove(&mut document, "example").unwrap(), 1);
+        assert_eq!(
+            remove(&mut document, &fingerprint(&client.access_token)).unwrap(),
+            1
+        );
+        assert!(matches!(
+            remove(&mut document, TOKEN),
+            Err(Error::NotFound(_))
+        ));
+        assert!(list(&document).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions() {
+        let path = std::env::temp_dir().join(format!("multichat-token-{}", std::process::id()));
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        fs::write(&path, CONFIG).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        // Stale and readable by everyone, as if left by a failed run.
+        fs::write(&temporary, "stale").unwrap();
+        fs::set_permissions(&temporary, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut document: DocumentMut = CONFIG.parse().unwrap();
+        let client = NewClient {
+            access_token: TOKEN.parse().unwrap(),
+            name: None,
+            groups: None,
+            read_only: false,
+        };
+
+        add(&mut document, &client).unwrap();
+        save(&path, &document).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let data = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(data, document.to_string());
+        assert!(!Path::new(&temporary).exists());
+    }
+
+    #[test]
+    fn fingerprints() {
+        let token = TOKEN.parse().unwrap();
+
+        assert_eq!(fingerprint(&token).len(), 16);
+        assert_eq!(
+            fingerprint(&token),
+            fingerprint(&TOKEN.to_uppercase().parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,147 @@
+mod clients;
+
+use clap::{Parser, Subcommand};
+use clients::NewClient;
+use multichat_proto::AccessToken;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[clap(about = "Generates access tokens and manages clients of a Multichat server config")]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(about = "Prints a new access token")]
+    Generate,
+    #[clap(about = "Prints the fingerprint of an access token, which can be shared safely")]
+    Fingerprint { access_token: AccessToken },
+    #[clap(about = "Lists clients of a server config by fingerprint")]
+    List {
+        #[clap(help = "Path to the server config")]
+        config: PathBuf,
+    },
+    #[clap(about = "Adds a client to a server config, printing its access token")]
+    Add {
+        #[clap(help = "Path to the server config")]
+        config: PathBuf,
+        #[clap(long, help = "Groups the client can access, \"*\" or comma separated")]
+        groups: String,
+        #[clap(long, help = "Name identifying the client in logs")]
+        name: Option<String>,
+        #[clap(long, help = "Only allow joining groups and receiving updates")]
+        read_only: bool,
+        #[clap(
+            long,
+            help = "Access token to add, a new one is generated if not given"
+        )]
+        access_token: Option<AccessToken>,
+    },
+    #[clap(about = "Removes clients from a server config")]
+    Remove {
+        #[clap(help = "Path to the server config")]
+        config: PathBuf,
+        #[clap(help = "Access token, fingerprint or name of the clients")]
+        target: String,
+    },
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), clients::Error> {
+    match args.command {
+        Command::Generate => println!("{}", generate()),
+        Command::Fingerprint { access_token } => {
+            println!("{}", clients::fingerprint(&access_token))
+        }
+        Command::List { config } => {
+            let document = clients::load(&config)?;
+
+            for entry in clients::list(&document)? {
+                let name = entry.name.as_deref().unwrap_or("-");
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    entry.fingerprint, name, entry.scope, entry.groups
+                );
+            }
+        }
+        Command::Add {
+            config,
+            groups,
+            name,
+            read_only,
+            access_token,
+        } => {
+            let groups = match groups.as_str() {
+                "*" => None,
+                groups => Some(
+                    groups
+                        .split(',')
+                        .map(|group| group.trim().to_owned())
+                        .collect(),
+                ),
+            };
+
+            let client = NewClient {
+                access_token: access_token.unwrap_or_else(generate),
+                name,
+                groups,
+                read_only,
+            };
+
+            let mut document = clients::load(&config)?;
+            clients::add(&mut document, &client)?;
+            clients::save(&config, &document)?;
+
+            println!("{}", client.access_token);
+            eprintln!("Restart the server for the client to be accepted");
+        }
+        Command::Remove { config, target } => {
+            let mut document = clients::load(&config)?;
+            let removed = clients::remove(&mut document, &target)?;
+            clients::save(&config, &document)?;
+
+            eprintln!(
+                "Removed {} clients, restart the server or revoke the access token through the \
+                 admin socket for it to take effect",
+                removed
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// 256 random bits, like the tokens in the example config.
+fn generate() -> AccessToken {
+    hex::encode(rand::random::<[u8; 32]>()).parse().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn args_are_valid() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn generates_distinct() {
+        assert_ne!(generate(), generate());
+    }
+}
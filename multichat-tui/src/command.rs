@@ -1,17 +1,24 @@
 mod args;
 
+use crate::filter::Joins;
+
+use args::Args;
 use multichat_client::proto::AccessToken;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use thiserror::Error;
+use tracing::level_filters::LevelFilter;
 
 #[derive(Debug)]
 pub enum Command<'a> {
     Connect {
+        // Name of a saved server if no access token is given.
         server: Cow<'a, str>,
-        access_token: AccessToken,
+        access_token: Option<Token<'a>>,
     },
     Disconnect,
+    Reconnect,
     Groups,
     Users,
     Join {
@@ -31,6 +38,357 @@ pub enum Command<'a> {
         group: Cow<'a, str>,
         uid: u32,
     },
+    SaveAttachment {
+        id: u32,
+        path: Option<Cow<'a, str>>,
+    },
+    Highlights,
+    AddHighlight {
+        word: Cow<'a, str>,
+    },
+    RemoveHighlight {
+        word: Cow<'a, str>,
+    },
+    Muted,
+    Mute {
+        group: Cow<'a, str>,
+    },
+    Unmute {
+        group: Cow<'a, str>,
+    },
+    Ignored,
+    Ignore {
+        group: Cow<'a, str>,
+        uid: u32,
+    },
+    Unignore {
+        group: Cow<'a, str>,
+        // Ignored users are kept by name, they need not be present.
+        name: Cow<'a, str>,
+    },
+    Filters,
+    FilterJoins {
+        joins: Joins,
+        // The group of the shown buffer if not given.
+        group: Option<Cow<'a, str>>,
+    },
+    Notifications,
+    Notify {
+        group: Cow<'a, str>,
+        enabled: bool,
+    },
+    // Toggled if not given.
+    DoNotDisturb {
+        enabled: Option<bool>,
+    },
+    // Toggled if not given.
+    Markup {
+        enabled: Option<bool>,
+    },
+    // Shown if not given.
+    LogLevel {
+        level: Option<LevelFilter>,
+    },
+    Clear,
+    Export {
+        group: Cow<'a, str>,
+        path: Cow<'a, str>,
+    },
+    Search {
+        // Joined with spaces, so that patterns need no quotes.
+        words: Vec<Cow<'a, str>>,
+    },
+    Theme,
+    SetColor {
+        element: Cow<'a, str>,
+        colors: Vec<Cow<'a, str>>,
+    },
+    Aliases,
+    Alias {
+        name: Cow<'a, str>,
+        // Quoted again and joined with spaces when defining the alias.
+        command: Vec<Cow<'a, str>>,
+    },
+    Unalias {
+        name: Cow<'a, str>,
+    },
+    Help {
+        command: Option<Cow<'a, str>>,
+    },
+    Quit,
+}
+
+#[derive(Debug)]
+pub enum Token<'a> {
+    Inline(AccessToken),
+    // Path of a file containing the token.
+    File(Cow<'a, str>),
+}
+
+/// Name, arguments and description of a command.
+pub struct Info {
+    /// Name without the leading slash.
+    pub name: &'static str,
+    pub args: &'static str,
+    pub description: &'static str,
+}
+
+impl Info {
+    pub fn usage(&self) -> String {
+        match self.args {
+            "" => format!("/{}", self.name),
+            args => format!("/{} {}", self.name, args),
+        }
+    }
+}
+
+pub const COMMANDS: &[Info] = &[
+    Info {
+        name: "connect",
+        args: "<server> [<access token>|--token-file <path>]",
+        description: "Connects to a saved server, or to an address with an access token or a file containing one",
+    },
+    Info {
+        name: "disconnect",
+        args: "",
+        description: "Disconnects from the server",
+    },
+    Info {
+        name: "reconnect",
+        args: "",
+        description: "Connects to the last server again, restoring joined groups and users",
+    },
+    Info {
+        name: "groups",
+        args: "",
+        description: "Lists all groups",
+    },
+    Info {
+        name: "users",
+        args: "",
+        description: "Lists the users of all groups",
+    },
+    Info {
+        name: "join",
+        args: "<group> [user]",
+        description: "Joins a group, creating a user in it if a name is given",
+    },
+    Info {
+        name: "leave",
+        args: "<group> [uid]",
+        description: "Removes one of our users from a group",
+    },
+    Info {
+        name: "rename",
+        args: "<group> <uid> <name>",
+        description: "Renames one of our users",
+    },
+    Info {
+        name: "switch",
+        args: "<group> <uid>",
+        description: "Sends messages as one of our users from now on",
+    },
+    Info {
+        name: "attach",
+        args: "save <id> [path]",
+        description: "Saves an attachment to a file or directory",
+    },
+    Info {
+        name: "highlight",
+        args: "[add|remove <word>]",
+        description: "Lists highlighted words, or adds or removes one",
+    },
+    Info {
+        name: "mute",
+        args: "[group]",
+        description: "Lists muted groups, or hides all messages of a group",
+    },
+    Info {
+        name: "unmute",
+        args: "<group>",
+        description: "Shows messages of a muted group again",
+    },
+    Info {
+        name: "ignore",
+        args: "[<group> <uid>]",
+        description: "Lists ignored users, or hides messages of a user in a group",
+    },
+    Info {
+        name: "unignore",
+        args: "<group> <name>",
+        description: "Shows messages of an ignored user again",
+    },
+    Info {
+        name: "filter",
+        args: "[joins <on|off|smart> [group]]",
+        description: "Lists filters, or sets which joins and leaves are shown in a group",
+    },
+    Info {
+        name: "notify",
+        args: "[<group> <on|off>]",
+        description: "Lists notification settings, or notifies of every message in a group",
+    },
+    Info {
+        name: "dnd",
+        args: "[on|off]",
+        description: "Toggles do not disturb, silencing notifications and the bell",
+    },
+    Info {
+        name: "markup",
+        args: "[on|off]",
+        description: "Toggles styling of *bold*, _italic_ and `code` markup in messages",
+    },
+    Info {
+        name: "loglevel",
+        args: "[off|error|warn|info|debug|trace]",
+        description: "Shows or sets which client events are logged to the status buffer",
+    },
+    Info {
+        name: "clear",
+        args: "",
+        description: "Removes all lines of the shown buffer",
+    },
+    Info {
+        name: "export",
+        args: "<group> <path>",
+        description: "Writes the buffer of a group to a file, as Markdown if it ends with .md",
+    },
+    Info {
+        name: "search",
+        args: "[pattern]",
+        description: "Searches the shown buffer, ends the search without a pattern",
+    },
+    Info {
+        name: "theme",
+        args: "[<element> <color>...]",
+        description: "Lists the colors of the theme, or sets those of an element",
+    },
+    Info {
+        name: "alias",
+        args: "[<name> <command>...]",
+        description: "Lists aliases, or defines one standing for a command and its first arguments",
+    },
+    Info {
+        name: "unalias",
+        args: "<name>",
+        description: "Removes an alias",
+    },
+    Info {
+        name: "help",
+        args: "[command]",
+        description: "Lists all commands, or shows how to use one",
+    },
+    Info {
+        name: "quit",
+        args: "",
+        description: "Disconnects from the server and exits",
+    },
+];
+
+/// Looks up a command by its name, with or without the leading slash.
+pub fn info(name: &str) -> Option<&'static Info> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    COMMANDS.iter().find(|info| info.name == name)
+}
+
+/// Argument being typed at the end of a partial command line.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Completion<'a> {
+    Command(&'a str),
+    Group { prefix: &'a str, joined: bool },
+    User { group: &'a str, prefix: &'a str },
+    // Shortcode of an emoji in a message, without the leading colon.
+    Emoji(&'a str),
+}
+
+impl<'a> Completion<'a> {
+    /// Arguments are split on whitespace only, quoted arguments are not recognized.
+    pub fn parse(line: &'a str) -> Option<Self> {
+        let mut words: Vec<_> = line.split_ascii_whitespace().collect();
+        if line.is_empty() || line.ends_with(|c: char| c.is_ascii_whitespace()) {
+            words.push("");
+        }
+
+        let command = match words.first()?.strip_prefix('/') {
+            Some(command) => command,
+            None => {
+                let prefix = words.last()?.strip_prefix(':')?;
+                if prefix.is_empty() || prefix.contains(':') {
+                    return None;
+                }
+
+                return Some(Completion::Emoji(prefix));
+            }
+        };
+
+        let completion = match (command, &words[1..]) {
+            (command, []) => Completion::Command(command),
+            ("join", [prefix]) => Completion::Group {
+                prefix,
+                joined: false,
+            },
+            ("mute" | "unmute" | "ignore" | "unignore" | "notify" | "export", [prefix]) => {
+                Completion::Group {
+                    prefix,
+                    joined: false,
+                }
+            }
+            ("leave" | "rename" | "switch", [prefix]) => Completion::Group {
+                prefix,
+                joined: true,
+            },
+            ("leave" | "rename" | "switch", [group, prefix]) => Completion::User { group, prefix },
+            _ => return None,
+        };
+
+        Some(completion)
+    }
+}
+
+/// Replaces an alias at the start of a command line with what it stands for.
+///
+/// Aliases are keyed by their name without the leading slash and are not expanded recursively.
+pub fn expand<'a>(line: &'a str, aliases: &BTreeMap<String, String>) -> Cow<'a, str> {
+    let trimmed = line.trim_start();
+    let end = trimmed
+        .find(|c: char| c.is_ascii_whitespace())
+        .unwrap_or(trimmed.len());
+
+    let expansion = match trimmed[..end]
+        .strip_prefix('/')
+        .and_then(|name| aliases.get(name))
+    {
+        Some(expansion) => expansion,
+        None => return Cow::Borrowed(line),
+    };
+
+    let expansion = expansion.strip_prefix('/').unwrap_or(expansion);
+    Cow::Owned(format!("/{}{}", expansion, &trimmed[end..]))
+}
+
+/// Quotes an argument if necessary for it to be parsed back as is.
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    if !arg.is_empty() && !arg.contains(|c: char| c.is_ascii_whitespace() || c == '"' || c == '\\')
+    {
+        return Cow::Borrowed(arg);
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+
+    for c in arg.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\r' => quoted.push_str("\\r"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    Cow::Owned(quoted)
 }
 
 impl<'a> TryFrom<&'a str> for Command<'a> {
@@ -45,61 +403,200 @@ impl<'a> TryFrom<&'a str> for Command<'a> {
             .strip_prefix('/')
             .ok_or(Error::NotACommand)?;
 
-        let command = match &*command {
-            "connect" => Command::Connect {
-                server: args.next().ok_or(Error::MissingArgument)??,
-                access_token: args
+        // Errors in arguments show how the command is used.
+        parse(command, &mut args).map_err(|err| match (err, info(command)) {
+            (
+                error @ (Error::MissingArgument | Error::InvalidArgument | Error::ExtraArgument),
+                Some(info),
+            ) => Error::Usage {
+                error: Box::new(error),
+                usage: info.usage(),
+            },
+            (error, _) => error,
+        })
+    }
+}
+
+fn parse<'a>(command: &str, args: &mut Args<'a>) -> Result<Command<'a>, Error> {
+    let command = match command {
+        "connect" => Command::Connect {
+            server: args.next().ok_or(Error::MissingArgument)??,
+            access_token: match args.next().transpose()? {
+                None => None,
+                Some(flag) if flag == "--token-file" => {
+                    Some(Token::File(args.next().ok_or(Error::MissingArgument)??))
+                }
+                Some(token) => Some(Token::Inline(
+                    token.parse().map_err(|_| Error::InvalidArgument)?,
+                )),
+            },
+        },
+        "disconnect" => Command::Disconnect,
+        "reconnect" => Command::Reconnect,
+        "groups" => Command::Groups,
+        "users" => Command::Users,
+        "join" => Command::Join {
+            group: args.next().ok_or(Error::MissingArgument)??,
+            user: args.next().transpose()?,
+        },
+        "leave" => Command::Leave {
+            group: args.next().ok_or(Error::MissingArgument)??,
+            uid: args
+                .next()
+                .transpose()?
+                .map(|user| user.parse().map_err(|_| Error::InvalidArgument))
+                .transpose()?,
+        },
+        "rename" => Command::Rename {
+            group: args.next().ok_or(Error::MissingArgument)??,
+            uid: args
+                .next()
+                .ok_or(Error::MissingArgument)??
+                .parse()
+                .map_err(|_| Error::InvalidArgument)?,
+            name: args.next().ok_or(Error::MissingArgument)??,
+        },
+        "switch" => Command::Switch {
+            group: args.next().ok_or(Error::MissingArgument)??,
+            uid: args
+                .next()
+                .ok_or(Error::MissingArgument)??
+                .parse()
+                .map_err(|_| Error::InvalidArgument)?,
+        },
+        "attach" => match &*args.next().ok_or(Error::MissingArgument)?? {
+            "save" => Command::SaveAttachment {
+                id: args
                     .next()
                     .ok_or(Error::MissingArgument)??
                     .parse()
                     .map_err(|_| Error::InvalidArgument)?,
+                path: args.next().transpose()?,
             },
-            "disconnect" => Command::Disconnect,
-            "groups" => Command::Groups,
-            "users" => Command::Users,
-            "join" => Command::Join {
-                group: args.next().ok_or(Error::MissingArgument)??,
-                user: args.next().transpose()?,
+            _ => return Err(Error::InvalidArgument),
+        },
+        "highlight" => match args.next().transpose()?.as_deref() {
+            None => Command::Highlights,
+            Some("add") => Command::AddHighlight {
+                word: args.next().ok_or(Error::MissingArgument)??,
             },
-            "leave" => Command::Leave {
-                group: args.next().ok_or(Error::MissingArgument)??,
-                uid: args
-                    .next()
-                    .transpose()?
-                    .map(|user| user.parse().map_err(|_| Error::InvalidArgument))
-                    .transpose()?,
+            Some("remove") => Command::RemoveHighlight {
+                word: args.next().ok_or(Error::MissingArgument)??,
             },
-            "rename" => Command::Rename {
-                group: args.next().ok_or(Error::MissingArgument)??,
+            Some(_) => return Err(Error::InvalidArgument),
+        },
+        "mute" => match args.next().transpose()? {
+            None => Command::Muted,
+            Some(group) => Command::Mute { group },
+        },
+        "unmute" => Command::Unmute {
+            group: args.next().ok_or(Error::MissingArgument)??,
+        },
+        "ignore" => match args.next().transpose()? {
+            None => Command::Ignored,
+            Some(group) => Command::Ignore {
+                group,
                 uid: args
                     .next()
                     .ok_or(Error::MissingArgument)??
                     .parse()
                     .map_err(|_| Error::InvalidArgument)?,
-                name: args.next().ok_or(Error::MissingArgument)??,
             },
-            "switch" => Command::Switch {
-                group: args.next().ok_or(Error::MissingArgument)??,
-                uid: args
+        },
+        "unignore" => Command::Unignore {
+            group: args.next().ok_or(Error::MissingArgument)??,
+            name: args.next().ok_or(Error::MissingArgument)??,
+        },
+        "filter" => match args.next().transpose()?.as_deref() {
+            None => Command::Filters,
+            Some("joins") => Command::FilterJoins {
+                joins: args
                     .next()
                     .ok_or(Error::MissingArgument)??
                     .parse()
                     .map_err(|_| Error::InvalidArgument)?,
+                group: args.next().transpose()?,
             },
-            _ => return Err(Error::InvalidCommand),
-        };
+            Some(_) => return Err(Error::InvalidArgument),
+        },
+        "notify" => match args.next().transpose()? {
+            None => Command::Notifications,
+            Some(group) => Command::Notify {
+                group,
+                enabled: switch(&args.next().ok_or(Error::MissingArgument)??)?,
+            },
+        },
+        "dnd" => Command::DoNotDisturb {
+            enabled: args
+                .next()
+                .transpose()?
+                .map(|arg| switch(&arg))
+                .transpose()?,
+        },
+        "markup" => Command::Markup {
+            enabled: args
+                .next()
+                .transpose()?
+                .map(|arg| switch(&arg))
+                .transpose()?,
+        },
+        "loglevel" => Command::LogLevel {
+            level: args
+                .next()
+                .transpose()?
+                .map(|arg| arg.parse().map_err(|_| Error::InvalidArgument))
+                .transpose()?,
+        },
+        "clear" => Command::Clear,
+        "export" => Command::Export {
+            group: args.next().ok_or(Error::MissingArgument)??,
+            path: args.next().ok_or(Error::MissingArgument)??,
+        },
+        "search" => Command::Search {
+            words: args.by_ref().collect::<Result<_, _>>()?,
+        },
+        "theme" => match args.next().transpose()? {
+            None => Command::Theme,
+            Some(element) => Command::SetColor {
+                element,
+                colors: args.by_ref().collect::<Result<_, _>>()?,
+            },
+        },
+        "alias" => match args.next().transpose()? {
+            None => Command::Aliases,
+            Some(name) => Command::Alias {
+                name,
+                command: args.by_ref().collect::<Result<_, _>>()?,
+            },
+        },
+        "unalias" => Command::Unalias {
+            name: args.next().ok_or(Error::MissingArgument)??,
+        },
+        "help" => Command::Help {
+            command: args.next().transpose()?,
+        },
+        "quit" => Command::Quit,
+        _ => return Err(Error::InvalidCommand),
+    };
 
-        if args.next().is_some() {
-            return Err(Error::ExtraArgument);
-        }
+    if args.next().is_some() {
+        return Err(Error::ExtraArgument);
+    }
+
+    Ok(command)
+}
 
-        Ok(command)
+fn switch(arg: &str) -> Result<bool, Error> {
+    match arg {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(Error::InvalidArgument),
     }
 }
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Invalid command")]
+    #[error("Invalid command, see /help")]
     InvalidCommand,
     #[error("Not a command")]
     NotACommand,
@@ -109,6 +606,145 @@ pub enum Error {
     InvalidArgument,
     #[error("Extra argument")]
     ExtraArgument,
+    #[error("{error}, usage: {usage}")]
+    Usage { error: Box<Error>, usage: String },
     #[error(transparent)]
     Args(#[from] args::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion() {
+        assert_eq!(Completion::parse("/jo"), Some(Completion::Command("jo")));
+        assert_eq!(
+            Completion::parse("/join "),
+            Some(Completion::Group {
+                prefix: "",
+                joined: false
+            })
+        );
+        assert_eq!(
+            Completion::parse("/rename fun al"),
+            Some(Completion::User {
+                group: "fun",
+                prefix: "al"
+            })
+        );
+        assert_eq!(Completion::parse("hello"), None);
+        assert_eq!(
+            Completion::parse("nice :thu"),
+            Some(Completion::Emoji("thu"))
+        );
+        assert_eq!(Completion::parse("nice :tada:"), None);
+        assert_eq!(Completion::parse("/rename fun 0 "), None);
+    }
+
+    #[test]
+    fn usage_errors() {
+        let err = Command::try_from("/join").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing argument, usage: /join <group> [user]"
+        );
+
+        let err = Command::try_from("/groups all").unwrap_err();
+        assert_eq!(err.to_string(), "Extra argument, usage: /groups");
+
+        let err = Command::try_from("/notify fun maybe").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument, usage: /notify [<group> <on|off>]"
+        );
+    }
+
+    #[test]
+    fn markup() {
+        assert!(matches!(
+            Command::try_from("/markup off").unwrap(),
+            Command::Markup {
+                enabled: Some(false)
+            }
+        ));
+        assert!(matches!(
+            Command::try_from("/markup").unwrap(),
+            Command::Markup { enabled: None }
+        ));
+        assert!(Command::try_from("/markup maybe").is_err());
+    }
+
+    #[test]
+    fn log_level() {
+        assert!(matches!(
+            Command::try_from("/loglevel debug").unwrap(),
+            Command::LogLevel {
+                level: Some(LevelFilter::DEBUG)
+            }
+        ));
+        assert!(matches!(
+            Command::try_from("/loglevel").unwrap(),
+            Command::LogLevel { level: None }
+        ));
+        assert!(Command::try_from("/loglevel loud").is_err());
+    }
+
+    #[test]
+    fn connect() {
+        let line = "/connect localhost:8585 52f0395327987f07f805c3ac54fe38ac123303fcdb62a61fdfc9b8082195486c";
+
+        let command = Command::try_from(line).unwrap();
+        assert!(matches!(
+            command,
+            Command::Connect {
+                access_token: Some(Token::Inline(_)),
+                ..
+            }
+        ));
+
+        let command = Command::try_from("/connect localhost:8585 --token-file token").unwrap();
+        assert!(matches!(
+            command,
+            Command::Connect {
+                access_token: Some(Token::File(path)),
+                ..
+            } if path == "token"
+        ));
+
+        let command = Command::try_from("/connect example").unwrap();
+        assert!(matches!(
+            command,
+            Command::Connect {
+                access_token: None,
+                ..
+            }
+        ));
+
+        assert!(Command::try_from("/connect localhost:8585 --token-file").is_err());
+    }
+
+    #[test]
+    fn aliases() {
+        let mut aliases = BTreeMap::new();
+        aliases.insert("j".to_owned(), "/join".to_owned());
+        aliases.insert("fun".to_owned(), "switch fun".to_owned());
+
+        assert_eq!(expand("/j nice alice", &aliases), "/join nice alice");
+        assert_eq!(expand(" /fun 0", &aliases), "/switch fun 0");
+        assert_eq!(expand("/join j", &aliases), "/join j");
+        assert_eq!(expand(" j", &aliases), " j");
+    }
+
+    #[test]
+    fn quote_round_trip() {
+        for arg in ["fun", "", "nice group", "\"quoted\\\"\n"] {
+            let quoted = quote(arg);
+            let parsed = args::args(&quoted)
+                .map(|arg| arg.unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(parsed, [arg]);
+        }
+    }
+}
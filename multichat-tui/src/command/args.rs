@@ -17,7 +17,7 @@ impl<'a> Iterator for Args<'a> {
             Empty,
         }
 
-        let Self { ref data, offset } = self;
+        let Self { data, offset } = self;
 
         let mut quote = false;
         let mut escape = false;
@@ -0,0 +1,242 @@
+use crate::filter::{Filter, Joins};
+use crate::nick::Nicks;
+use crate::theme::Theme;
+
+use multichat_client::proto::AccessToken;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::fs;
+use toml_edit::{Array, DocumentMut, Item, Table, TomlError};
+use tracing::level_filters::LevelFilter;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Name of a server to connect to on startup.
+    pub auto_connect: Option<String>,
+    /// Reconnect after losing the connection, waiting longer after every failed attempt.
+    #[serde(default = "default_auto_reconnect")]
+    pub auto_reconnect: bool,
+    /// File input history is kept in between sessions.
+    pub history_file: Option<PathBuf>,
+    /// Directory to save all attachments to as they arrive.
+    pub download_dir: Option<PathBuf>,
+    /// Words highlighted in messages, besides the names of our users.
+    #[serde(default)]
+    pub highlights: Vec<String>,
+    /// Ring the terminal bell on highlights which are not seen.
+    #[serde(default)]
+    pub bell: bool,
+    /// Show a desktop notification on highlights which are not seen.
+    #[cfg(feature = "notify")]
+    #[serde(default)]
+    pub notify: bool,
+    /// Groups in which every message which is not seen shows a desktop notification.
+    #[serde(default)]
+    pub notify_groups: BTreeSet<String>,
+    /// Expand emoji shortcodes such as `:thumbsup:` in sent messages and complete them.
+    #[serde(default = "default_emoji")]
+    pub emoji: bool,
+    /// Show `*bold*`, `_italic_` and `` `code` `` markup in messages styled, with a preview of
+    /// the message being composed, toggled with /markup.
+    #[serde(default = "default_markup")]
+    pub markup: bool,
+    /// Most verbose level of client events logged to the status buffer, changed with /loglevel.
+    #[serde(default = "default_log_level", deserialize_with = "log_level")]
+    pub log_level: LevelFilter,
+    /// Commands standing for other commands, the leading slash of either is optional.
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Groups whose messages and join/leave noise are not shown.
+    #[serde(default)]
+    pub muted: BTreeSet<String>,
+    /// Names of users whose messages and join/leave noise are not shown, by group.
+    #[serde(default)]
+    pub ignored: BTreeMap<String, BTreeSet<String>>,
+    /// Which joins, leaves and renames are shown, changed per group with /filter.
+    #[serde(default)]
+    pub joins: Joins,
+    /// Show the sidebar listing groups and users on startup, it's toggled with F2.
+    #[serde(default)]
+    pub sidebar: bool,
+    #[serde(default)]
+    pub scrollback: Scrollback,
+    #[serde(default)]
+    pub nicks: Nicks,
+    #[serde(default)]
+    pub theme: Theme,
+    #[serde(default)]
+    pub servers: BTreeMap<String, Server>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Server {
+    pub address: String,
+    pub access_token: AccessToken,
+    /// CA certificate to verify the server with, enables TLS.
+    pub certificate: Option<PathBuf>,
+    /// Groups to join after connecting.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Name of a user to create in every joined group.
+    pub user: Option<String>,
+}
+
+/// Limits of the lines kept by every buffer, the oldest are dropped first.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Scrollback {
+    pub lines: usize,
+    /// Approximate, only the text of lines is counted.
+    pub bytes: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Self {
+            lines: 4096,
+            bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            auto_connect: None,
+            auto_reconnect: default_auto_reconnect(),
+            history_file: None,
+            download_dir: None,
+            highlights: Vec::new(),
+            bell: false,
+            #[cfg(feature = "notify")]
+            notify: false,
+            notify_groups: BTreeSet::new(),
+            emoji: default_emoji(),
+            markup: default_markup(),
+            log_level: default_log_level(),
+            aliases: BTreeMap::new(),
+            muted: BTreeSet::new(),
+            ignored: BTreeMap::new(),
+            joins: Joins::default(),
+            sidebar: false,
+            scrollback: Scrollback::default(),
+            nicks: Nicks::default(),
+            theme: Theme::default(),
+            servers: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_auto_reconnect() -> bool {
+    true
+}
+
+fn default_emoji() -> bool {
+    true
+}
+
+fn default_markup() -> bool {
+    true
+}
+
+fn default_log_level() -> LevelFilter {
+    LevelFilter::WARN
+}
+
+fn log_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LevelFilter, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+/// Default config location, `$XDG_CONFIG_HOME/multichat-tui/config.toml` or `~/.config/multichat-tui/config.toml`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(dir.join("multichat-tui").join("config.toml"))
+}
+
+/// Writes muted groups and ignored users to a config, keeping the rest of it as it is.
+pub async fn save_filter(path: &Path, filter: &Filter) -> Result<(), SaveError> {
+    let data = match fs::read_to_string(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut document: DocumentMut = data.parse()?;
+    document["muted"] = Item::Value(filter.muted.iter().collect::<Array>().into());
+
+    let mut ignored = Table::new();
+    for (group, users) in &filter.ignored {
+        ignored[group] = Item::Value(users.iter().collect::<Array>().into());
+    }
+
+    document["ignored"] = Item::Table(ignored);
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    fs::write(path, document.to_string()).await?;
+
+    Ok(())
+}
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Error parsing config: {0}")]
+    Parse(#[from] TomlError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        toml::from_str::<Config>(config).unwrap();
+    }
+
+    #[test]
+    fn auto_reconnect_by_default() {
+        assert!(toml::from_str::<Config>("").unwrap().auto_reconnect);
+        assert!(Config::default().auto_reconnect);
+    }
+
+    #[tokio::test]
+    async fn filter_saved() {
+        let path = env::temp_dir().join(format!("multichat-tui-{}.toml", std::process::id()));
+        fs::write(&path, "# Comment\nbell = true\nmuted = [\"old\"]\n")
+            .await
+            .unwrap();
+
+        let mut filter = Filter::default();
+        filter.muted.insert("noisy".to_owned());
+        filter.ignore("fun", "bob");
+
+        save_filter(&path, &filter).await.unwrap();
+        let data = fs::read_to_string(&path).await.unwrap();
+        fs::remove_file(&path).await.unwrap();
+
+        assert!(data.starts_with("# Comment\n"));
+
+        let config = toml::from_str::<Config>(&data).unwrap();
+        assert!(config.bell);
+        assert_eq!(config.muted, filter.muted);
+        assert_eq!(config.ignored, filter.ignored);
+    }
+}
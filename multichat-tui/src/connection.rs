@@ -0,0 +1,189 @@
+use multichat_client::proto::ResumeToken;
+use multichat_client::{MaybeTlsClient, ReceivedBytes, Update};
+use std::io::{Error, ErrorKind};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+// Events not handled yet, reading updates waits once this many are queued.
+const EVENT_BUFFER: usize = 64;
+
+/// Client running in a task of its own, so that waiting on the server never blocks the UI.
+///
+/// Requests are sent in order. Their results arrive as events together with updates, in the order
+/// the client saw them.
+pub struct Connection {
+    requests: UnboundedSender<Request>,
+    events: Receiver<Result<Event, Error>>,
+    handle: JoinHandle<Result<(), Error>>,
+    received: ReceivedBytes,
+    resume_token: ResumeToken,
+    resumed: bool,
+}
+
+impl Connection {
+    pub fn new(client: MaybeTlsClient) -> Self {
+        let (requests, requests_receiver) = mpsc::unbounded_channel();
+        let (events_sender, events) = mpsc::channel(EVENT_BUFFER);
+
+        let received = client.received_bytes();
+        let resume_token = client.resume_token();
+        let resumed = client.resumed();
+
+        Self {
+            requests,
+            events,
+            handle: tokio::spawn(serve(client, requests_receiver, events_sender)),
+            received,
+            resume_token,
+            resumed,
+        }
+    }
+
+    /// Queues a request, errors are reported by [`Connection::next`].
+    pub fn send(&self, request: Request) {
+        let _ = self.requests.send(request);
+    }
+
+    /// Waits for the next event, an error is the last one.
+    ///
+    /// This method is cancel-safe.
+    pub async fn next(&mut self) -> Result<Event, Error> {
+        self.events
+            .recv()
+            .await
+            .unwrap_or_else(|| Err(ErrorKind::BrokenPipe.into()))
+    }
+
+    pub fn received_bytes(&self) -> ReceivedBytes {
+        self.received.clone()
+    }
+
+    pub fn resume_token(&self) -> ResumeToken {
+        self.resume_token
+    }
+
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Shuts the client down cleanly, once the requests sent so far are done.
+    pub async fn shutdown(self) -> Result<(), Error> {
+        drop(self.requests);
+        drop(self.events);
+
+        self.handle.await.unwrap()
+    }
+}
+
+pub enum Request {
+    JoinGroup { name: String },
+    InitUser { gid: u32, name: String },
+    DestroyUser { gid: u32, uid: u32 },
+    Rename { gid: u32, uid: u32, name: String },
+    SendMessage { gid: u32, uid: u32, text: String },
+    StartTyping { gid: u32, uid: u32 },
+    StopTyping { gid: u32, uid: u32 },
+    DownloadAttachment { id: u32 },
+    IgnoreAttachment { id: u32 },
+}
+
+pub enum Event {
+    Update(Update),
+    /// Reply to [`Request::JoinGroup`].
+    JoinedGroup {
+        gid: u32,
+        name: String,
+    },
+    /// Reply to [`Request::InitUser`].
+    InitUser {
+        gid: u32,
+        uid: u32,
+    },
+    /// Reply to [`Request::DownloadAttachment`].
+    Attachment {
+        id: u32,
+        data: Vec<u8>,
+    },
+}
+
+// Events are sent as `Err` once the connection fails.
+type EventSender = Sender<Result<Event, Error>>;
+
+async fn serve(
+    mut client: MaybeTlsClient,
+    mut requests: UnboundedReceiver<Request>,
+    events: EventSender,
+) -> Result<(), Error> {
+    let result: Result<(), Error> = async {
+        loop {
+            let request = tokio::select! {
+                request = requests.recv() => request,
+                update = client.read_update() => {
+                    if events.send(Ok(Event::Update(update?))).await.is_err() {
+                        return Ok(());
+                    }
+
+                    continue;
+                }
+            };
+
+            // Closed by shutting down.
+            let request = match request {
+                Some(request) => request,
+                None => return Ok(()),
+            };
+
+            let event = match request {
+                Request::JoinGroup { name } => {
+                    let gid = client.join_group(&name).await?;
+                    Event::JoinedGroup { gid, name }
+                }
+                Request::InitUser { gid, name } => {
+                    let uid = client.init_user(gid, &name).await?;
+                    Event::InitUser { gid, uid }
+                }
+                Request::DownloadAttachment { id } => {
+                    let data = client.download_attachment(id).await?;
+                    Event::Attachment { id, data }
+                }
+                Request::DestroyUser { gid, uid } => {
+                    client.destroy_user(gid, uid).await?;
+                    continue;
+                }
+                Request::Rename { gid, uid, name } => {
+                    client.rename_user(gid, uid, &name).await?;
+                    continue;
+                }
+                Request::SendMessage { gid, uid, text } => {
+                    client.send_message(gid, uid, &text, &[]).await?;
+                    continue;
+                }
+                Request::StartTyping { gid, uid } => {
+                    client.start_typing(gid, uid).await?;
+                    continue;
+                }
+                Request::StopTyping { gid, uid } => {
+                    client.stop_typing(gid, uid).await?;
+                    continue;
+                }
+                Request::IgnoreAttachment { id } => {
+                    client.ignore_attachment(id).await?;
+                    continue;
+                }
+            };
+
+            if events.send(Ok(event)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+    .await;
+
+    match result {
+        Ok(()) => client.shutdown().await,
+        Err(err) => {
+            let _ = events.send(Err(err)).await;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::borrow::Cow;
+
+// Sorted by shortcode, for binary search.
+const EMOJI: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("beer", "🍺"),
+    ("blush", "😊"),
+    ("boom", "💥"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("cake", "🍰"),
+    ("check", "✔️"),
+    ("clap", "👏"),
+    ("coffee", "☕"),
+    ("confused", "😕"),
+    ("cool", "🆒"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fire", "🔥"),
+    ("frowning", "😦"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("hugs", "🤗"),
+    ("innocent", "😇"),
+    ("joy", "😂"),
+    ("kiss", "😘"),
+    ("laughing", "😆"),
+    ("metal", "🤘"),
+    ("neutral_face", "😐"),
+    ("no", "❌"),
+    ("ok", "🆗"),
+    ("ok_hand", "👌"),
+    ("party", "🥳"),
+    ("pensive", "😔"),
+    ("pizza", "🍕"),
+    ("pray", "🙏"),
+    ("question", "❓"),
+    ("rage", "😡"),
+    ("raised_hands", "🙌"),
+    ("relieved", "😌"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("slightly_smiling_face", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("stuck_out_tongue", "😛"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("tired_face", "😫"),
+    ("unamused", "😒"),
+    ("upside_down_face", "🙃"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("weary", "😩"),
+    ("wink", "😉"),
+    ("yum", "😋"),
+    ("zap", "⚡"),
+];
+
+/// Replaces `:shortcode:` with the emoji it stands for, unknown shortcodes are kept as they are.
+pub fn expand(text: &str) -> Cow<'_, str> {
+    if !text.contains(':') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(':') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let emoji = rest[1..]
+            .find(':')
+            .and_then(|end| Some((end, lookup(&rest[1..end + 1])?)));
+
+        match emoji {
+            Some((end, emoji)) => {
+                expanded.push_str(emoji);
+                rest = &rest[end + 2..];
+            }
+            // The closing colon may start a shortcode.
+            None => {
+                expanded.push(':');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    expanded.push_str(rest);
+    Cow::Owned(expanded)
+}
+
+/// Shortcodes starting with a prefix, with their colons.
+pub fn complete(prefix: &str) -> Vec<String> {
+    EMOJI
+        .iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(name, _)| format!(":{}:", name))
+        .collect()
+}
+
+fn lookup(name: &str) -> Option<&'static str> {
+    EMOJI
+        .binary_search_by_key(&name, |(name, _)| name)
+        .ok()
+        .map(|idx| EMOJI[idx].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted() {
+        assert!(EMOJI.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn expansion() {
+        assert_eq!(expand("nice :thumbsup:"), "nice 👍");
+        assert_eq!(expand(":tada::tada:"), "🎉🎉");
+        assert_eq!(expand("at 12:30 :fire:"), "at 12:30 🔥");
+        assert_eq!(expand(":unknown: :"), ":unknown: :");
+        assert_eq!(expand("plain"), "plain");
+    }
+
+    #[test]
+    fn completion() {
+        assert_eq!(complete("thu"), [":thumbsdown:", ":thumbsup:"]);
+        assert!(complete("nothing").is_empty());
+    }
+}
@@ -0,0 +1,85 @@
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Markdown,
+}
+
+impl Format {
+    /// Markdown for `.md` and `.markdown` files, plain text otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("md" | "markdown") => Format::Markdown,
+            _ => Format::Text,
+        }
+    }
+}
+
+/// Formats lines of a buffer, oldest first, with a heading naming the group in Markdown.
+pub fn format(group: &str, lines: &[String], format: Format) -> String {
+    let mut output = String::new();
+
+    match format {
+        Format::Text => {
+            for line in lines {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        Format::Markdown => {
+            output.push_str(&format!("# {}\n\n", escape(group)));
+
+            // Every line is an item, with hard breaks between the lines of multi-line messages.
+            for line in lines {
+                let line: Vec<_> = line.split('\n').map(escape).collect();
+                output.push_str(&format!("- {}\n", line.join("  \n  ")));
+            }
+        }
+    }
+
+    output
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' | '~'
+        ) {
+            escaped.push('\\');
+        }
+
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let lines = ["alice (1): hi *all*".to_owned(), "bob (2): a\nb".to_owned()];
+
+        assert_eq!(
+            format("fun", &lines, Format::Text),
+            "alice (1): hi *all*\nbob (2): a\nb\n"
+        );
+        assert_eq!(
+            format("fun_times", &lines, Format::Markdown),
+            "# fun\\_times\n\n- alice (1): hi \\*all\\*\n- bob (2): a  \n  b\n"
+        );
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(Format::from_path(Path::new("log.md")), Format::Markdown);
+        assert_eq!(Format::from_path(Path::new("log.txt")), Format::Text);
+        assert_eq!(Format::from_path(Path::new("log")), Format::Text);
+    }
+}
@@ -0,0 +1,154 @@
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Users who spoke less than this long ago count as active for smart join filtering.
+const ACTIVE: Duration = Duration::from_secs(10 * 60);
+
+/// Groups and users whose messages and join/leave noise are not shown.
+#[derive(Default)]
+pub struct Filter {
+    pub muted: BTreeSet<String>,
+    /// Names of ignored users, by group. Names are kept rather than IDs as those change between
+    /// connections.
+    pub ignored: BTreeMap<String, BTreeSet<String>>,
+    /// Which joins, leaves and renames are shown in groups without their own setting.
+    pub joins: Joins,
+    pub group_joins: BTreeMap<String, Joins>,
+}
+
+impl Filter {
+    /// Whether anything of a user in a group, or of the group itself if no user is given, is hidden.
+    pub fn hides(&self, group: &str, user: Option<&str>) -> bool {
+        if self.muted.contains(group) {
+            return true;
+        }
+
+        match (user, self.ignored.get(group)) {
+            (Some(user), Some(users)) => users.contains(user),
+            _ => false,
+        }
+    }
+
+    pub fn joins(&self, group: &str) -> Joins {
+        self.group_joins.get(group).copied().unwrap_or(self.joins)
+    }
+
+    /// Whether a user joining, leaving or being renamed is shown, given how long ago it spoke.
+    /// Our own users are always shown.
+    pub fn shows_presence(&self, group: &str, owned: bool, spoke: Option<Duration>) -> bool {
+        match self.joins(group) {
+            Joins::On => true,
+            Joins::Off => owned,
+            Joins::Smart => owned || spoke.is_some_and(|spoke| spoke < ACTIVE),
+        }
+    }
+
+    /// Returns false if the user was already ignored.
+    pub fn ignore(&mut self, group: &str, user: &str) -> bool {
+        self.ignored
+            .entry(group.to_owned())
+            .or_default()
+            .insert(user.to_owned())
+    }
+
+    /// Returns false if the user wasn't ignored.
+    pub fn unignore(&mut self, group: &str, user: &str) -> bool {
+        let users = match self.ignored.get_mut(group) {
+            Some(users) => users,
+            None => return false,
+        };
+
+        let removed = users.remove(user);
+        if users.is_empty() {
+            self.ignored.remove(group);
+        }
+
+        removed
+    }
+}
+
+/// Which joins, leaves and renames of users are shown.
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Joins {
+    #[default]
+    On,
+    Off,
+    /// Only those of users who spoke recently.
+    Smart,
+}
+
+impl FromStr for Joins {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "on" => Ok(Joins::On),
+            "off" => Ok(Joins::Off),
+            "smart" => Ok(Joins::Smart),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for Joins {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Joins::On => "on",
+            Joins::Off => "off",
+            Joins::Smart => "smart",
+        };
+
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hiding() {
+        let mut filter = Filter::default();
+        filter.muted.insert("noisy".to_owned());
+
+        assert!(filter.ignore("fun", "bob"));
+        assert!(!filter.ignore("fun", "bob"));
+
+        assert!(filter.hides("noisy", None));
+        assert!(filter.hides("noisy", Some("alice")));
+        assert!(filter.hides("fun", Some("bob")));
+        assert!(!filter.hides("fun", Some("alice")));
+        assert!(!filter.hides("fun", None));
+        assert!(!filter.hides("other", Some("bob")));
+
+        assert!(filter.unignore("fun", "bob"));
+        assert!(!filter.unignore("fun", "bob"));
+        assert!(!filter.hides("fun", Some("bob")));
+        assert!(filter.ignored.is_empty());
+    }
+
+    #[test]
+    fn presence() {
+        let mut filter = Filter {
+            joins: Joins::Smart,
+            ..Filter::default()
+        };
+        filter.group_joins.insert("quiet".to_owned(), Joins::Off);
+        filter.group_joins.insert("loud".to_owned(), Joins::On);
+
+        let recently = Some(Duration::from_secs(30));
+        let long_ago = Some(Duration::from_secs(3600));
+
+        assert!(filter.shows_presence("fun", false, recently));
+        assert!(!filter.shows_presence("fun", false, long_ago));
+        assert!(!filter.shows_presence("fun", false, None));
+        assert!(filter.shows_presence("fun", true, None));
+        assert!(!filter.shows_presence("quiet", false, recently));
+        assert!(filter.shows_presence("quiet", true, None));
+        assert!(filter.shows_presence("loud", false, None));
+    }
+}
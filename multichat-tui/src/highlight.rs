@@ -0,0 +1,32 @@
+/// Whether text contains a word, ignoring case. Parts of longer words do not count.
+pub fn mentions(text: &str, word: &str) -> bool {
+    let word = word.to_lowercase();
+    if word.is_empty() {
+        return false;
+    }
+
+    let text = text.to_lowercase();
+
+    text.match_indices(&word).any(|(idx, _)| {
+        let before = text[..idx].chars().next_back();
+        let after = text[idx + word.len()..].chars().next();
+
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words() {
+        assert!(mentions("hi alice", "alice"));
+        assert!(mentions("ALICE: hi", "Alice"));
+        assert!(mentions("ping @alice!", "alice"));
+        assert!(!mentions("malice", "alice"));
+        assert!(!mentions("alice2", "alice"));
+        assert!(mentions("alice2 alice", "alice"));
+        assert!(!mentions("anything", ""));
+    }
+}
@@ -0,0 +1,124 @@
+use std::env;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+/// Default history location, `$XDG_DATA_HOME/multichat-tui/history` or `~/.local/share/multichat-tui/history`.
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("share"),
+    };
+
+    Some(dir.join("multichat-tui").join("history"))
+}
+
+/// Reads history entries, oldest first. A missing file is an empty history.
+pub async fn load(path: &Path) -> Result<Vec<String>, Error> {
+    let data = match fs::read_to_string(path).await {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    Ok(data.lines().map(decode).collect())
+}
+
+/// Writes history entries, oldest first, replacing the file.
+pub async fn save(path: &Path, entries: &[String]) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    let mut data = String::new();
+    for entry in entries {
+        data.push_str(&encode(entry));
+        data.push('\n');
+    }
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    // Left behind by a save which failed before renaming it.
+    match fs::remove_file(&temporary).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    // History may contain access tokens given to /connect. A new file is written and renamed over
+    // the old one, so that it doesn't keep looser permissions of an existing file.
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(&temporary).await?;
+    file.write_all(data.as_bytes()).await?;
+    file.sync_all().await?;
+
+    fs::rename(&temporary, path).await
+}
+
+// Entries may span multiple lines, so newlines are escaped to keep one entry per line.
+fn encode(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn decode(line: &str) -> String {
+    let mut entry = String::with_capacity(line.len());
+
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            entry.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => entry.push('\n'),
+            Some(c) => entry.push(c),
+            None => entry.push('\\'),
+        }
+    }
+
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_round_trip() {
+        for entry in ["hello", "", "two\nlines", "back\\slash\\n"] {
+            let encoded = encode(entry);
+
+            assert!(!encoded.contains('\n'));
+            assert_eq!(decode(&encoded), entry);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn save_restricts_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = env::temp_dir().join(format!("multichat-history-{}", std::process::id()));
+        std::fs::write(&path, "old\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let entries = ["/connect example".to_owned(), "two\nlines".to_owned()];
+        save(&path, &entries).await.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        let loaded = load(&path).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(loaded, entries);
+    }
+}
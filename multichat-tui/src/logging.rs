@@ -0,0 +1,149 @@
+use std::fmt::{Debug, Write};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::Registry;
+
+/// Tracing events of the client and the TUI itself, to be shown in the status buffer.
+pub struct Logger {
+    receiver: UnboundedReceiver<Record>,
+    level: Arc<Mutex<LevelFilter>>,
+}
+
+impl Logger {
+    /// Collects events of at most the given level from now on.
+    pub fn init(level: LevelFilter) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let level = Arc::new(Mutex::new(level));
+
+        let layer = ScreenLayer {
+            sender,
+            level: level.clone(),
+        };
+
+        // Fails only if there already is one.
+        let _ = tracing::subscriber::set_global_default(Registry::default().with(layer));
+
+        Self { receiver, level }
+    }
+
+    /// This method is cancel-safe.
+    pub async fn next(&mut self) -> Record {
+        // The sender is kept by the global subscriber, which is never dropped.
+        self.receiver.recv().await.unwrap()
+    }
+
+    pub fn level(&self) -> LevelFilter {
+        *self.level.lock().unwrap()
+    }
+
+    pub fn set_level(&self, level: LevelFilter) {
+        *self.level.lock().unwrap() = level;
+    }
+}
+
+pub struct Record {
+    pub level: Level,
+    pub text: String,
+}
+
+struct ScreenLayer {
+    sender: UnboundedSender<Record>,
+    level: Arc<Mutex<LevelFilter>>,
+}
+
+impl<S: Subscriber> Layer<S> for ScreenLayer {
+    // The level can change, so whether a callsite is enabled is never cached.
+    fn register_callsite(&self, _: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _: Context<'_, S>) -> bool {
+        *metadata.level() <= *self.level.lock().unwrap()
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+
+        let mut visitor = Visitor {
+            message: String::new(),
+            fields: String::new(),
+        };
+
+        event.record(&mut visitor);
+
+        let _ = self.sender.send(Record {
+            level: *metadata.level(),
+            text: format!(
+                "{} {}: {}{}",
+                metadata.level(),
+                metadata.target(),
+                visitor.message,
+                visitor.fields
+            ),
+        });
+    }
+}
+
+// Formats the message of an event followed by its fields.
+struct Visitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for Visitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "message" => self.message.push_str(value),
+            name => {
+                let _ = write!(self.fields, " {}={}", name, value);
+            }
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.message, "{:?}", value),
+            name => write!(self.fields, " {}={:?}", name, value),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn records() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let level = Arc::new(Mutex::new(LevelFilter::INFO));
+
+        let layer = ScreenLayer {
+            sender,
+            level: level.clone(),
+        };
+
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            tracing::debug!("Hidden");
+            tracing::info!(name = "fun", gid = 3, "Joined group");
+
+            *level.lock().unwrap() = LevelFilter::DEBUG;
+            tracing::debug!("Shown");
+        });
+
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.level, Level::INFO);
+        assert_eq!(
+            record.text,
+            "INFO multichat_tui::logging::tests: Joined group name=fun gid=3"
+        );
+
+        let record = receiver.recv().await.unwrap();
+        assert_eq!(record.level, Level::DEBUG);
+        assert!(receiver.try_recv().is_err());
+    }
+}
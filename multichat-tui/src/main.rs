@@ -1,14 +1,88 @@
 mod command;
+mod config;
+mod connection;
+mod emoji;
+mod export;
+mod filter;
+mod highlight;
+mod history;
+mod logging;
+mod markup;
+mod nick;
+#[cfg(feature = "notify")]
+mod notify;
 mod screen;
 mod term_safe;
+mod theme;
+mod tls;
 mod tui;
 
+use config::Config;
+use logging::Logger;
 use screen::Screen;
+use std::io::ErrorKind;
+use std::path::PathBuf;
 use std::process::ExitCode;
+use structopt::StructOpt;
+use tokio::fs;
+
+#[derive(StructOpt)]
+struct Args {
+    #[structopt(
+        long,
+        help = "Path to config file, defaults to ~/.config/multichat-tui/config.toml"
+    )]
+    config: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    let mut screen = match Screen::new() {
+    let args = Args::from_args();
+
+    // A missing config is only an error if it was requested explicitly.
+    let (path, required) = match args.config {
+        Some(path) => (Some(path), true),
+        None => (config::default_path(), false),
+    };
+
+    let config = match &path {
+        Some(path) => match fs::read_to_string(path).await {
+            Ok(config) => match toml::from_str::<Config>(&config) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!("Error parsing config {}: {}", path.display(), err);
+                    return ExitCode::FAILURE;
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound && !required => Config::default(),
+            Err(err) => {
+                eprintln!("Error reading config {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Config::default(),
+    };
+
+    if let Some(name) = &config.auto_connect {
+        if !config.servers.contains_key(name) {
+            eprintln!("Error: auto-connect server {} is not defined", name);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let history_path = config.history_file.clone().or_else(history::default_path);
+    let history = match &history_path {
+        Some(path) => match history::load(path).await {
+            Ok(history) => history,
+            Err(err) => {
+                eprintln!("Error reading history {}: {}", path.display(), err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let mut screen = match Screen::new(config.theme.clone(), config.scrollback) {
         Ok(screen) => screen,
         Err(err) => {
             eprintln!("Error: {}", err);
@@ -16,11 +90,29 @@ async fn main() -> ExitCode {
         }
     };
 
-    match tui::run(&mut screen).await.and_then(|_| screen.close()) {
+    screen.set_history(history);
+    screen.show_sidebar(config.sidebar);
+
+    let logger = Logger::init(config.log_level);
+    let result = tui::run(&mut screen, config, path.as_deref(), logger)
+        .await
+        .and_then(|_| screen.close());
+
+    let mut code = match result {
         Ok(()) => ExitCode::SUCCESS,
         Err(err) => {
             eprintln!("Error: {}", err);
             ExitCode::FAILURE
         }
+    };
+
+    // Saved even if the TUI failed, so that nothing typed is lost.
+    if let Some(path) = &history_path {
+        if let Err(err) = history::save(path, &screen.history()).await {
+            eprintln!("Error saving history {}: {}", path.display(), err);
+            code = ExitCode::FAILURE;
+        }
     }
+
+    code
 }
@@ -0,0 +1,57 @@
+use crate::term_safe::TermSafeExt;
+use crate::theme::Theme;
+
+use crossterm::style::{Color, Stylize};
+use multichat_client::markup;
+
+pub use multichat_client::markup::styled;
+
+/// Replaces markup with escape sequences styling the text, which is made safe to print.
+pub fn render(text: &str, theme: &Theme) -> String {
+    let mut rendered = String::with_capacity(text.len());
+
+    for chunk in markup::parse(text) {
+        let mut styled = chunk.text.term_safe().stylize();
+        if chunk.style.bold {
+            styled = styled.bold();
+        }
+
+        if chunk.style.italic {
+            styled = styled.italic();
+        }
+
+        if chunk.style.code {
+            styled = match theme.code {
+                Color::Reset => styled.dim(),
+                color => styled.with(color),
+            };
+        }
+
+        rendered.push_str(&styled.to_string());
+    }
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders() {
+        let theme = Theme::default();
+        assert_eq!(render("snake_case", &theme), "snake_case");
+        assert_eq!(render("*a*", &theme), format!("{}", "a".bold()));
+        assert_eq!(render("`a`", &theme), format!("{}", "a".dim()));
+    }
+
+    #[test]
+    fn renders_code_colored() {
+        let theme = Theme {
+            code: Color::Cyan,
+            ..Theme::default()
+        };
+
+        assert_eq!(render("`a`", &theme), format!("{}", "a".cyan()));
+    }
+}
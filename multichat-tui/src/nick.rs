@@ -0,0 +1,107 @@
+use crate::theme::Theme;
+
+use serde::Deserialize;
+
+/// How users are shown in buffers.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Nicks {
+    pub show: Show,
+    pub color_by: ColorBy,
+}
+
+impl Nicks {
+    /// Colored nick of a user in a group, with its ID unless set otherwise.
+    pub fn format(&self, theme: &Theme, gid: u32, uid: u32, name: &str) -> String {
+        let nick = self.name(theme, gid, uid, name);
+
+        match self.show {
+            Show::Name => nick,
+            Show::NameUid => format!("{} ({})", nick, uid),
+            Show::ShortUid => format!("{}#{}", nick, uid),
+        }
+    }
+
+    /// Colored name of a user in a group, without its ID.
+    pub fn name(&self, theme: &Theme, gid: u32, uid: u32, name: &str) -> String {
+        match self.color_by {
+            ColorBy::Name => theme.nick(name),
+            ColorBy::User => {
+                let key = [gid.to_le_bytes(), uid.to_le_bytes()].concat();
+                theme.nick_by(name, &key)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Show {
+    Name,
+    /// `alice (3)`
+    #[default]
+    NameUid,
+    /// `alice#3`
+    ShortUid,
+}
+
+/// What picks the color of a nick.
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorBy {
+    #[default]
+    Name,
+    /// Users of the same name, such as those bridged from elsewhere, get different colors.
+    User,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crossterm::style::Stylize;
+
+    #[test]
+    fn shown() {
+        let theme = Theme {
+            nicks: Vec::new(),
+            ..Theme::default()
+        };
+
+        let nicks = |show| Nicks {
+            show,
+            color_by: ColorBy::Name,
+        };
+        let alice = "alice".bold();
+
+        assert_eq!(
+            nicks(Show::Name).format(&theme, 0, 3, "alice"),
+            alice.to_string()
+        );
+        assert_eq!(
+            nicks(Show::NameUid).format(&theme, 0, 3, "alice"),
+            format!("{} (3)", alice)
+        );
+        assert_eq!(
+            nicks(Show::ShortUid).format(&theme, 0, 3, "alice"),
+            format!("{}#3", alice)
+        );
+    }
+
+    #[test]
+    fn colored_by_user() {
+        let theme = Theme::default();
+        let nicks = Nicks {
+            show: Show::Name,
+            color_by: ColorBy::User,
+        };
+
+        assert_eq!(
+            nicks.format(&theme, 1, 2, "bridge"),
+            nicks.format(&theme, 1, 2, "bridge")
+        );
+        assert!((0..4)
+            .map(|uid| nicks.format(&theme, 1, uid, "bridge"))
+            .any(|nick| nick != nicks.format(&theme, 1, 4, "bridge")));
+    }
+}
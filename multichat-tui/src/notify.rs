@@ -0,0 +1,12 @@
+use notify_rust::Notification;
+
+/// Shows a desktop notification in the background, failures are ignored.
+pub fn send(summary: String, body: String) {
+    tokio::task::spawn_blocking(move || {
+        let _ = Notification::new()
+            .appname("multichat")
+            .summary(&summary)
+            .body(&body)
+            .show();
+    });
+}
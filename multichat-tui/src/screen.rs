@@ -1,32 +1,64 @@
+mod buffers;
 mod input;
+mod layout;
 mod log;
+mod sidebar;
+mod wrap;
 
+pub use buffers::Progress;
 pub use log::Level;
+pub use sidebar::Entry;
 
-use crossterm::event::{Event as TermEvent, EventStream, KeyCode, KeyModifiers};
+use crate::config::Scrollback;
+use crate::theme::Theme;
+
+use buffers::Buffers;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{
+    DisableFocusChange, EnableFocusChange, Event as TermEvent, EventStream, KeyCode, KeyEventKind,
+    KeyModifiers,
+};
+use crossterm::style::{Attribute, Print, SetAttribute};
 use crossterm::terminal::{self, DisableLineWrap, EnterAlternateScreen, LeaveAlternateScreen};
 use futures::stream::StreamExt;
 use input::Input;
-use log::Log;
+use layout::{Layout, Rect};
+use sidebar::Sidebar;
 use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
 use std::io::{self, Error, Stdout};
+use std::mem;
+
+// Longer input is scrolled.
+const MAX_INPUT_LINES: u16 = 5;
 
 pub struct Screen {
     stdout: Stdout,
     stream: EventStream,
+    width: u16,
     height: u16,
-    event: Option<TermEvent>,
-    log: Log,
+    // Events to process before reading new ones.
+    pending: VecDeque<TermEvent>,
+    // Terminals which do not report focus changes are assumed to always be focused.
+    focused: bool,
+    theme: Theme,
+    buffers: Buffers,
+    sidebar: Sidebar,
+    // Rendered message being composed.
+    preview: Option<String>,
+    preview_changed: bool,
+    preview_area: Option<Rect>,
     input: Input,
 }
 
 impl Screen {
-    pub fn new() -> Result<Self, Error> {
+    pub fn new(theme: Theme, scrollback: Scrollback) -> Result<Self, Error> {
         // Enter alternate screen so that whatever state the users shell was in
         // will not be trashed. This is what vim does, for example.
         let mut stdout = io::stdout();
         crossterm::execute!(stdout, EnterAlternateScreen)?;
         crossterm::execute!(stdout, DisableLineWrap)?;
+        crossterm::execute!(stdout, EnableFocusChange)?;
 
         let (width, height) = terminal::size()?;
         terminal::enable_raw_mode()?;
@@ -34,78 +66,422 @@ impl Screen {
         Ok(Self {
             stdout,
             stream: EventStream::new(),
+            width,
             height,
-            event: Some(TermEvent::Resize(width, height)),
-            log: Log::new(),
+            pending: VecDeque::from([TermEvent::Resize(width, height)]),
+            focused: true,
+            theme,
+            buffers: Buffers::new(scrollback),
+            sidebar: Sidebar::new(),
+            preview: None,
+            preview_changed: false,
+            preview_area: None,
             input: Input::new(),
         })
     }
 
+    /// Logs to the buffer which is shown.
     pub fn log(&mut self, level: Level, contents: impl Into<Cow<'static, str>>) {
-        self.log.log(level, contents.into());
+        self.buffers.log_current(level, contents.into());
+        self.input.mark_changed();
+    }
+
+    /// Logs to the buffer of a group, or to the status buffer if `group` is `None`.
+    pub fn log_to(
+        &mut self,
+        group: Option<&str>,
+        level: Level,
+        contents: impl Into<Cow<'static, str>>,
+    ) {
+        self.buffers.log(group, level, contents.into());
+        self.input.mark_changed();
+    }
+
+    /// Switches to the buffer of a group.
+    pub fn open_buffer(&mut self, group: &str) {
+        self.buffers.open(group);
+        self.input.mark_changed();
+    }
+
+    pub fn close_buffer(&mut self, group: &str) {
+        self.buffers.close(group);
+        self.input.mark_changed();
+    }
+
+    /// Completes the word before the cursor, in response to [`Event::Complete`].
+    pub fn complete(&mut self, candidates: Vec<String>) {
+        self.input.complete(candidates);
+    }
+
+    /// Name of the group whose buffer is shown, if any.
+    pub fn buffer(&self) -> Option<&str> {
+        self.buffers.current()
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+        self.buffers.mark_changed();
+        self.input.mark_changed();
+    }
+
+    /// Searches the shown buffer, returns false if nothing matches.
+    pub fn search(&mut self, pattern: &str) -> bool {
+        let found = self.buffers.log_mut().search(pattern);
+        self.input.mark_changed();
+        found
+    }
+
+    /// Text of all lines in the buffer of a group, oldest first.
+    pub fn text(&self, group: &str) -> Option<Vec<String>> {
+        self.buffers.group_log(group).map(|log| log.text())
+    }
+
+    /// Removes all lines of the shown buffer.
+    pub fn clear(&mut self) {
+        self.buffers.log_mut().clear();
+        self.input.mark_changed();
+    }
+
+    pub fn end_search(&mut self) {
+        self.buffers.log_mut().end_search();
+        self.input.mark_changed();
+    }
+
+    /// Entries of the input history, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.input.history()
+    }
+
+    pub fn set_history(&mut self, history: Vec<String>) {
+        self.input.set_history(history);
+    }
+
+    /// Whether a message, rather than a command, is being typed.
+    /// Keeps the last input out of the history, so that secrets typed into it are not saved.
+    pub fn forget_input(&mut self) {
+        self.input.forget_last();
+    }
+
+    pub fn composing(&self) -> bool {
+        self.input.composing()
+    }
+
+    /// Sets the names of users typing in a group, shown in the buffer bar with its buffer.
+    pub fn set_typing(&mut self, group: &str, names: Vec<String>) {
+        self.buffers.set_typing(group, names);
+        self.input.mark_changed();
+    }
+
+    /// Forgets who is typing in all groups, after the connection is gone.
+    pub fn clear_typing(&mut self) {
+        self.buffers.clear_typing();
+        self.input.mark_changed();
+    }
+
+    pub fn sidebar_shown(&self) -> bool {
+        self.sidebar.shown()
+    }
+
+    pub fn show_sidebar(&mut self, shown: bool) {
+        self.sidebar.show(shown);
+        self.input.mark_changed();
+    }
+
+    /// Sets the groups listed in the sidebar and the users of each of them, by group name.
+    pub fn set_sidebar(&mut self, groups: Vec<Entry>, users: BTreeMap<String, Vec<Entry>>) {
+        self.sidebar.set(groups, users);
+    }
+
+    /// Whether the terminal window has focus.
+    pub fn focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Rings the terminal bell on the next render.
+    pub fn bell(&mut self) -> Result<(), Error> {
+        crossterm::queue!(self.stdout, Print('\x07'))
+    }
+
+    /// Text of the input.
+    pub fn draft(&self) -> String {
+        self.input.as_ref().iter().collect()
+    }
+
+    /// Shows a line with escape sequences above the input, or hides it.
+    pub fn set_preview(&mut self, preview: Option<String>) {
+        if self.preview != preview {
+            self.preview = preview;
+            self.preview_changed = true;
+        }
+    }
+
+    /// Shows how far along a transfer is in the buffer bar, or hides it.
+    pub fn set_progress(&mut self, progress: Option<Progress>) {
+        self.buffers.set_progress(progress);
         self.input.mark_changed();
     }
 
     pub async fn process(&mut self) -> Result<Option<Event>, Error> {
-        let event = match self.event.take() {
+        let edits = self.input.edits();
+        let event = match self.pending.pop_front() {
             Some(event) => event,
             None => self.stream.next().await.unwrap()?,
         };
 
         let event = match event {
-            TermEvent::Key(key) => match key.code {
-                KeyCode::Char('c' | 'C') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    Some(Event::Quit)
-                }
-                KeyCode::Char(c) => {
-                    self.input.input(c);
-                    None
-                }
-                KeyCode::Backspace => {
-                    self.input.erase();
-                    None
-                }
-                KeyCode::End => {
-                    self.input.last_char();
-                    None
-                }
-                KeyCode::Home => {
-                    self.input.first_char();
-                    None
+            // Only reported on some platforms, presses are handled instead.
+            TermEvent::Key(key) if key.kind == KeyEventKind::Release => None,
+            TermEvent::Key(key) => {
+                if key.code != KeyCode::Tab {
+                    self.input.end_completion();
                 }
-                KeyCode::Enter => Some(Event::Input(self.input.enter())),
-                KeyCode::Left => {
-                    self.input.prev_char();
-                    None
-                }
-                KeyCode::Right => {
-                    self.input.next_char();
-                    None
-                }
-                KeyCode::Up => {
-                    self.input.prev_history();
-                    None
+
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                if self.input.searching() {
+                    match key.code {
+                        KeyCode::Char('r' | 'R') if ctrl => {
+                            self.input.search();
+                            return Ok(None);
+                        }
+                        KeyCode::Char('g' | 'G') if ctrl => {
+                            self.input.cancel_search();
+                            return Ok(None);
+                        }
+                        KeyCode::Esc => {
+                            self.input.cancel_search();
+                            return Ok(None);
+                        }
+                        KeyCode::Char(c) if !ctrl => {
+                            self.input.search_input(c);
+                            return Ok(None);
+                        }
+                        KeyCode::Backspace => {
+                            self.input.search_erase();
+                            return Ok(None);
+                        }
+                        KeyCode::Enter => {
+                            self.input.accept_search();
+                            return Ok(None);
+                        }
+                        // Any other key accepts the match and is then handled as usual.
+                        _ => self.input.accept_search(),
+                    }
                 }
-                KeyCode::Down => {
-                    self.input.next_history();
-                    None
+
+                match key.code {
+                    KeyCode::Char('c' | 'C') if ctrl => Some(Event::Interrupt),
+                    KeyCode::Char('r' | 'R') if ctrl => {
+                        self.input.search();
+                        None
+                    }
+                    KeyCode::Char(c @ '1'..='9') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.buffers.select(c as usize - '1' as usize);
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::Left if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.buffers.prev();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::Right if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.buffers.next();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::Up if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.buffers.log_mut().search_older();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::Down if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.buffers.log_mut().search_newer();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::Left if ctrl => {
+                        self.input.prev_word();
+                        None
+                    }
+                    KeyCode::Right if ctrl => {
+                        self.input.next_word();
+                        None
+                    }
+                    KeyCode::Char('b' | 'B') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.input.prev_word();
+                        None
+                    }
+                    KeyCode::Char('f' | 'F') if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.input.next_word();
+                        None
+                    }
+                    KeyCode::Char('w' | 'W') if ctrl => {
+                        self.input.erase_word();
+                        None
+                    }
+                    KeyCode::Char('u' | 'U') if ctrl => {
+                        self.input.kill_to_start();
+                        None
+                    }
+                    KeyCode::Char('k' | 'K') if ctrl => {
+                        self.input.kill_to_end();
+                        None
+                    }
+                    KeyCode::Esc => {
+                        self.end_search();
+                        None
+                    }
+                    KeyCode::Char(c) => {
+                        self.input.input(c);
+                        None
+                    }
+                    KeyCode::Backspace => {
+                        self.input.erase();
+                        None
+                    }
+                    KeyCode::Delete => {
+                        self.input.delete();
+                        None
+                    }
+                    KeyCode::End if ctrl => {
+                        self.buffers.log_mut().bottom();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::Home if ctrl => {
+                        self.buffers.log_mut().top();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::F(2) => {
+                        self.show_sidebar(!self.sidebar.shown());
+                        None
+                    }
+                    KeyCode::PageUp => {
+                        self.buffers.log_mut().page_up();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::PageDown => {
+                        self.buffers.log_mut().page_down();
+                        self.input.mark_changed();
+                        None
+                    }
+                    KeyCode::End => {
+                        self.input.last_char();
+                        None
+                    }
+                    KeyCode::Home => {
+                        self.input.first_char();
+                        None
+                    }
+                    KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) => {
+                        self.input.input('\n');
+                        None
+                    }
+                    KeyCode::Enter => Some(Event::Input(self.input.enter())),
+                    KeyCode::Left => {
+                        self.input.prev_char();
+                        None
+                    }
+                    KeyCode::Right => {
+                        self.input.next_char();
+                        None
+                    }
+                    KeyCode::Up => {
+                        if !self.input.prev_line() {
+                            self.input.prev_history();
+                        }
+
+                        None
+                    }
+                    KeyCode::Down => {
+                        if !self.input.next_line() {
+                            self.input.next_history();
+                        }
+
+                        None
+                    }
+                    KeyCode::Tab if self.input.next_completion() => None,
+                    KeyCode::Tab => Some(Event::Complete(self.input.before_cursor())),
+                    _ => None,
                 }
-                _ => None,
-            },
-            TermEvent::Mouse(_) => None,
+            }
+            TermEvent::Mouse(_) | TermEvent::Paste(_) => None,
+            TermEvent::FocusGained => {
+                self.focused = true;
+                None
+            }
+            TermEvent::FocusLost => {
+                self.focused = false;
+                None
+            }
             TermEvent::Resize(0..=1, _) | TermEvent::Resize(_, 0..=1) => Some(Event::Quit),
-            TermEvent::Resize(_, height) => {
+            TermEvent::Resize(width, height) => {
+                self.width = width;
                 self.height = height;
                 None
             }
         };
 
+        // Edits are reported so that typing notifications can be sent.
+        let event = match event {
+            None if self.input.edits() != edits => Some(Event::Edit),
+            event => event,
+        };
+
         Ok(event)
     }
 
     pub fn render(&mut self) -> Result<(), Error> {
-        self.log.render(&mut self.stdout, self.height)?;
-        self.input.render(&mut self.stdout, self.height)?;
+        let max = MAX_INPUT_LINES.min(self.height / 2).max(1);
+        let input = self.input.lines(self.width).clamp(1, max as usize) as u16;
+        let layout = Layout::new(
+            self.width,
+            self.height,
+            input,
+            self.sidebar.shown(),
+            self.preview.is_some(),
+        );
+
+        self.buffers
+            .render(&mut self.stdout, &self.theme, layout.log, layout.bar)?;
+
+        if let Some(area) = layout.sidebar {
+            self.sidebar
+                .render(&mut self.stdout, area, self.buffers.current())?;
+        }
+
+        if let (Some(area), Some(preview)) = (layout.preview, &self.preview) {
+            if mem::take(&mut self.preview_changed) || self.preview_area != Some(area) {
+                let width = area.width as usize;
+                let line = wrap::wrap(preview, width).swap_remove(0);
+                let line = &preview[line];
+
+                crossterm::queue!(
+                    self.stdout,
+                    MoveTo(area.x, area.y),
+                    Print(line),
+                    SetAttribute(Attribute::Reset),
+                    Print(" ".repeat(width.saturating_sub(wrap::width(line))))
+                )?;
+
+                self.input.mark_changed();
+            }
+        }
+
+        self.preview_area = layout.preview;
+
+        self.input.render(
+            &mut self.stdout,
+            self.width,
+            layout.input.y,
+            layout.input.height,
+        )?;
 
         crossterm::execute!(&mut self.stdout)?;
 
@@ -114,6 +490,7 @@ impl Screen {
 
     pub fn close(&mut self) -> Result<(), Error> {
         terminal::disable_raw_mode()?;
+        crossterm::execute!(self.stdout, DisableFocusChange)?;
         crossterm::execute!(self.stdout, LeaveAlternateScreen)?;
 
         Ok(())
@@ -122,5 +499,11 @@ impl Screen {
 
 pub enum Event {
     Input(String),
+    // Completion was requested, contains the input before the cursor.
+    Complete(String),
+    // Text of the input changed.
+    Edit,
+    // Ctrl+C was pressed.
+    Interrupt,
     Quit,
 }
@@ -0,0 +1,332 @@
+use super::layout::Rect;
+use super::log::{Level, Log};
+use crate::config::Scrollback;
+use crate::term_safe::TermSafeExt;
+use crate::theme::Theme;
+
+use crossterm::cursor::MoveTo;
+use crossterm::style::{Color, Print, PrintStyledContent, Stylize};
+use crossterm::terminal::{Clear, ClearType};
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::io::{Error, Write};
+
+/// Logs of the status buffer and of every group, only one of which is shown at a time.
+pub struct Buffers {
+    // The status buffer always comes first.
+    buffers: Vec<Buffer>,
+    current: usize,
+    changed: bool,
+    bar: Rect,
+    scrollback: Scrollback,
+    progress: Option<Progress>,
+}
+
+impl Buffers {
+    pub fn new(scrollback: Scrollback) -> Self {
+        Self {
+            buffers: vec![Buffer::new(None, scrollback)],
+            current: 0,
+            changed: true,
+            bar: Rect::default(),
+            scrollback,
+            progress: None,
+        }
+    }
+
+    /// Name of the group whose buffer is shown, if any.
+    pub fn current(&self) -> Option<&str> {
+        self.buffers[self.current].name.as_deref()
+    }
+
+    /// Logs to the buffer of a group, opening it if necessary, or to the status buffer.
+    pub fn log(&mut self, group: Option<&str>, level: Level, contents: Cow<'static, str>) {
+        let idx = match group {
+            Some(group) => self.find_or_insert(group),
+            None => 0,
+        };
+
+        self.log_idx(idx, level, contents);
+    }
+
+    /// Logs to the buffer which is shown.
+    pub fn log_current(&mut self, level: Level, contents: Cow<'static, str>) {
+        self.log_idx(self.current, level, contents);
+    }
+
+    /// Shows the buffer of a group, opening it if necessary.
+    pub fn open(&mut self, group: &str) {
+        let idx = self.find_or_insert(group);
+        self.select(idx);
+    }
+
+    pub fn close(&mut self, group: &str) {
+        let idx = match self.find(group) {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        self.buffers.remove(idx);
+        self.changed = true;
+
+        if self.current >= idx {
+            self.current -= 1;
+            self.buffers[self.current].show();
+        }
+    }
+
+    /// Shows the buffer at a zero-based position in the buffer bar, if there is one.
+    pub fn select(&mut self, idx: usize) {
+        if idx >= self.buffers.len() || idx == self.current {
+            return;
+        }
+
+        self.current = idx;
+        self.buffers[idx].show();
+        self.changed = true;
+    }
+
+    pub fn prev(&mut self) {
+        self.select((self.current + self.buffers.len() - 1) % self.buffers.len());
+    }
+
+    pub fn next(&mut self) {
+        self.select((self.current + 1) % self.buffers.len());
+    }
+
+    /// Sets the names of users typing in a group, does nothing if it has no buffer.
+    pub fn set_typing(&mut self, group: &str, names: Vec<String>) {
+        if let Some(idx) = self.find(group) {
+            let buffer = &mut self.buffers[idx];
+            if buffer.typing != names {
+                buffer.typing = names;
+                self.changed |= idx == self.current;
+            }
+        }
+    }
+
+    pub fn set_progress(&mut self, progress: Option<Progress>) {
+        self.progress = progress;
+        self.changed = true;
+    }
+
+    pub fn clear_typing(&mut self) {
+        for buffer in &mut self.buffers {
+            buffer.typing.clear();
+        }
+
+        self.changed = true;
+    }
+
+    /// Redraws everything, after the theme changed.
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+        self.buffers[self.current].log.mark_changed();
+    }
+
+    /// Log of a group's buffer, if it has one.
+    pub fn group_log(&self, group: &str) -> Option<&Log> {
+        self.find(group).map(|idx| &self.buffers[idx].log)
+    }
+
+    pub fn log_mut(&mut self) -> &mut Log {
+        &mut self.buffers[self.current].log
+    }
+
+    /// Renders the log of the shown buffer and the buffer bar, which takes up a whole row.
+    pub fn render(
+        &mut self,
+        mut writer: impl Write,
+        theme: &Theme,
+        log: Rect,
+        bar: Rect,
+    ) -> Result<(), Error> {
+        self.buffers[self.current]
+            .log
+            .render(&mut writer, theme, log)?;
+
+        if !self.changed && self.bar == bar {
+            return Ok(());
+        }
+
+        self.changed = false;
+        self.bar = bar;
+
+        crossterm::queue!(writer, MoveTo(bar.x, bar.y))?;
+        crossterm::queue!(writer, Clear(ClearType::CurrentLine))?;
+
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            let (label, color) = match &buffer.name {
+                Some(name) => (format!("{}:{}", i + 1, name.term_safe()), theme.group),
+                None => (format!("{}:status", i + 1), Color::Reset),
+            };
+
+            if i == self.current {
+                crossterm::queue!(writer, PrintStyledContent(label.reverse()))?;
+            } else if buffer.highlighted {
+                let label = format!("{}({})", label, buffer.unread);
+                crossterm::queue!(
+                    writer,
+                    PrintStyledContent(label.bold().with(theme.highlight))
+                )?;
+            } else if buffer.unread != 0 {
+                let label = format!("{}({})", label, buffer.unread);
+                crossterm::queue!(writer, PrintStyledContent(label.bold().with(color)))?;
+            } else {
+                crossterm::queue!(writer, PrintStyledContent(label.with(color)))?;
+            }
+
+            crossterm::queue!(writer, Print(" "))?;
+        }
+
+        if let Some(progress) = &self.progress {
+            crossterm::queue!(writer, Print(progress), Print(" "))?;
+        }
+
+        if let Some(typing) = typing(&self.buffers[self.current].typing) {
+            crossterm::queue!(writer, PrintStyledContent(typing.italic()))?;
+        }
+
+        Ok(())
+    }
+
+    fn log_idx(&mut self, idx: usize, level: Level, contents: Cow<'static, str>) {
+        let buffer = &mut self.buffers[idx];
+        buffer.log.log(level, contents);
+
+        if idx != self.current {
+            buffer.unread += 1;
+            buffer.highlighted |= level == Level::Highlight;
+            self.changed = true;
+        }
+    }
+
+    fn find(&self, group: &str) -> Option<usize> {
+        self.buffers
+            .iter()
+            .position(|buffer| buffer.name.as_deref() == Some(group))
+    }
+
+    fn find_or_insert(&mut self, group: &str) -> usize {
+        if let Some(idx) = self.find(group) {
+            return idx;
+        }
+
+        self.buffers
+            .push(Buffer::new(Some(group.to_owned()), self.scrollback));
+        self.changed = true;
+
+        self.buffers.len() - 1
+    }
+}
+
+/// Transfer shown in the buffer bar.
+pub struct Progress {
+    pub label: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        const WIDTH: u64 = 20;
+
+        let done = self.done.min(self.total);
+        let filled = (done * WIDTH / self.total.max(1)) as usize;
+
+        write!(
+            f,
+            "{} [{}{}] {}%",
+            self.label,
+            "#".repeat(filled),
+            " ".repeat(WIDTH as usize - filled),
+            done * 100 / self.total.max(1)
+        )
+    }
+}
+
+// Who is typing, e.g. "alice and bob are typing…".
+fn typing(names: &[String]) -> Option<String> {
+    let names: Vec<_> = names.iter().map(|name| name.term_safe()).collect();
+
+    let typing = match &*names {
+        [] => return None,
+        [name] => format!("{} is typing…", name),
+        [first, second] => format!("{} and {} are typing…", first, second),
+        names => format!("{} users are typing…", names.len()),
+    };
+
+    Some(typing)
+}
+
+struct Buffer {
+    // None for the status buffer.
+    name: Option<String>,
+    log: Log,
+    unread: usize,
+    // Whether any of the unread rows is a highlight.
+    highlighted: bool,
+    // Names of users typing in the group.
+    typing: Vec<String>,
+}
+
+impl Buffer {
+    fn new(name: Option<String>, scrollback: Scrollback) -> Self {
+        Self {
+            name,
+            log: Log::new(scrollback),
+            unread: 0,
+            highlighted: false,
+            typing: Vec::new(),
+        }
+    }
+
+    fn show(&mut self) {
+        self.unread = 0;
+        self.highlighted = false;
+        self.log.mark_changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress() {
+        let progress = Progress {
+            label: "attachment 1".to_owned(),
+            done: 50,
+            total: 200,
+        };
+
+        assert_eq!(
+            progress.to_string(),
+            "attachment 1 [#####               ] 25%"
+        );
+    }
+
+    #[test]
+    fn typing_names() {
+        let names = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(typing(&[]), None);
+        assert_eq!(
+            typing(&names(&["alice"])).as_deref(),
+            Some("alice is typing…")
+        );
+        assert_eq!(
+            typing(&names(&["alice", "bob"])).as_deref(),
+            Some("alice and bob are typing…")
+        );
+        assert_eq!(
+            typing(&names(&["alice", "bob", "carol"])).as_deref(),
+            Some("3 users are typing…")
+        );
+    }
+}
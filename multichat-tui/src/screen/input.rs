@@ -1,8 +1,11 @@
+use super::wrap;
+
 use crossterm::cursor::MoveTo;
 use crossterm::style::Print;
 use crossterm::terminal::{Clear, ClearType};
 use std::collections::VecDeque;
 use std::io::{Error, Write};
+use std::ops::Range;
 
 const MAX_HISTORY: usize = 256;
 
@@ -10,8 +13,14 @@ pub struct Input {
     history: VecDeque<Vec<char>>,
     cursor: usize,
     kind: InputKind,
+    completion: Option<Completion>,
+    search: Option<Search>,
+    // Counts changes of the text, to tell edits apart from cursor movement.
+    edits: u64,
     changed: bool,
-    height: u16,
+    width: u16,
+    // Screen row of the first shown line and the number of shown lines.
+    area: (u16, u16),
 }
 
 impl Input {
@@ -20,13 +29,45 @@ impl Input {
             history: VecDeque::new(),
             cursor: 0,
             kind: InputKind::Owned(Vec::new()),
+            completion: None,
+            search: None,
+            edits: 0,
             changed: true,
-            height: 0,
+            width: 0,
+            area: (0, 0),
         }
     }
 
+    /// Entries of the history, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.history
+            .iter()
+            .map(|entry| entry.iter().collect())
+            .collect()
+    }
+
+    /// Replaces the history, with entries given oldest first.
+    pub fn set_history(&mut self, history: Vec<String>) {
+        let skip = history.len().saturating_sub(MAX_HISTORY);
+
+        self.history = history
+            .into_iter()
+            .skip(skip)
+            .map(|entry| entry.chars().collect())
+            .collect();
+        self.kind = InputKind::Owned(Vec::new());
+        self.edits = self.edits.wrapping_add(1);
+        self.cursor = 0;
+        self.changed = true;
+    }
+
+    /// Removes the newest entry of the history.
+    pub fn forget_last(&mut self) {
+        self.history.pop_back();
+    }
+
     pub fn prev_history(&mut self) {
-        if self.history.len() == 0 {
+        if self.history.is_empty() {
             return;
         }
 
@@ -34,13 +75,14 @@ impl Input {
             InputKind::History(idx) => InputKind::History(idx.wrapping_sub(1) % self.history.len()),
             InputKind::Owned(_) => InputKind::History(0),
         };
+        self.edits = self.edits.wrapping_add(1);
 
         self.cursor = self.as_ref().len();
         self.changed = true;
     }
 
     pub fn next_history(&mut self) {
-        if self.history.len() == 0 {
+        if self.history.is_empty() {
             return;
         }
 
@@ -48,6 +90,7 @@ impl Input {
             InputKind::History(idx) => InputKind::History((idx + 1) % self.history.len()),
             InputKind::Owned(_) => InputKind::History(0),
         };
+        self.edits = self.edits.wrapping_add(1);
 
         self.cursor = self.as_ref().len();
         self.changed = true;
@@ -76,6 +119,20 @@ impl Input {
         self.cursor = cursor;
     }
 
+    /// Moves the cursor to the start of the word before it.
+    pub fn prev_word(&mut self) {
+        let cursor = word_start(self.as_ref(), self.cursor);
+        self.changed = self.cursor != cursor;
+        self.cursor = cursor;
+    }
+
+    /// Moves the cursor to the end of the word after it.
+    pub fn next_word(&mut self) {
+        let cursor = word_end(self.as_ref(), self.cursor);
+        self.changed = self.cursor != cursor;
+        self.cursor = cursor;
+    }
+
     pub fn input(&mut self, c: char) {
         let cursor = self.cursor;
 
@@ -85,7 +142,7 @@ impl Input {
     }
 
     pub fn enter(&mut self) -> String {
-        let data: Vec<_> = self.as_ref().iter().copied().collect();
+        let data: Vec<_> = self.as_ref().to_vec();
 
         if self.history.len() == MAX_HISTORY {
             self.history.pop_front();
@@ -93,6 +150,7 @@ impl Input {
 
         self.history.push_back(data.clone());
         self.kind = InputKind::Owned(Vec::new());
+        self.edits = self.edits.wrapping_add(1);
         self.cursor = 0;
         self.changed = true;
 
@@ -112,6 +170,154 @@ impl Input {
         self.changed = true;
     }
 
+    /// Erases the character under the cursor.
+    pub fn delete(&mut self) {
+        if self.cursor == self.as_ref().len() {
+            return;
+        }
+
+        let cursor = self.cursor;
+
+        self.as_mut().remove(cursor);
+        self.changed = true;
+    }
+
+    /// Erases the word before the cursor.
+    pub fn erase_word(&mut self) {
+        let start = word_start(self.as_ref(), self.cursor);
+        self.kill(start..self.cursor);
+    }
+
+    /// Erases everything before the cursor.
+    pub fn kill_to_start(&mut self) {
+        self.kill(0..self.cursor);
+    }
+
+    /// Erases everything after the cursor.
+    pub fn kill_to_end(&mut self) {
+        let end = self.as_ref().len();
+        self.kill(self.cursor..end);
+    }
+
+    /// Number of changes of the text so far, wrapping around.
+    pub fn edits(&self) -> u64 {
+        self.edits
+    }
+
+    /// Whether the text is a message, rather than empty or a command.
+    pub fn composing(&self) -> bool {
+        self.as_ref()
+            .iter()
+            .find(|c| !c.is_whitespace())
+            .is_some_and(|c| *c != '/')
+    }
+
+    /// Text before the cursor.
+    pub fn before_cursor(&self) -> String {
+        self.as_ref()[..self.cursor].iter().collect()
+    }
+
+    /// Replaces the word before the cursor with the first of `candidates`, the rest are cycled
+    /// through by [`Input::next_completion`].
+    pub fn complete(&mut self, candidates: Vec<String>) {
+        if candidates.is_empty() {
+            return;
+        }
+
+        let start = self.as_ref()[..self.cursor]
+            .iter()
+            .rposition(|c| c.is_ascii_whitespace())
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+
+        let completion = Completion {
+            start,
+            candidates,
+            idx: 0,
+        };
+
+        self.insert_completion(&completion);
+
+        // Nothing to cycle through, the next completion continues with the following word.
+        if completion.candidates.len() > 1 {
+            self.completion = Some(completion);
+        }
+    }
+
+    /// Cycles to the next candidate, returns false if no completion is in progress.
+    pub fn next_completion(&mut self) -> bool {
+        let mut completion = match self.completion.take() {
+            Some(completion) => completion,
+            None => return false,
+        };
+
+        completion.idx = (completion.idx + 1) % completion.candidates.len();
+
+        self.insert_completion(&completion);
+        self.completion = Some(completion);
+
+        true
+    }
+
+    pub fn end_completion(&mut self) {
+        self.completion = None;
+    }
+
+    pub fn searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Starts a reverse search of the history, or continues it with the next older match.
+    pub fn search(&mut self) {
+        let search = self.search.get_or_insert(Search {
+            query: Vec::new(),
+            matched: None,
+        });
+
+        let end = search.matched.unwrap_or(self.history.len());
+        if let Some(idx) = find(&self.history, &search.query, end) {
+            search.matched = Some(idx);
+        }
+
+        self.changed = true;
+    }
+
+    pub fn search_input(&mut self, c: char) {
+        if let Some(search) = &mut self.search {
+            search.query.push(c);
+            search.matched = find(&self.history, &search.query, self.history.len());
+            self.changed = true;
+        }
+    }
+
+    pub fn search_erase(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            search.matched = find(&self.history, &search.query, self.history.len());
+            self.changed = true;
+        }
+    }
+
+    /// Ends the search, replacing the input with the match if there is one.
+    pub fn accept_search(&mut self) {
+        if let Some(Search {
+            matched: Some(idx), ..
+        }) = self.search.take()
+        {
+            self.kind = InputKind::History(idx);
+            self.edits = self.edits.wrapping_add(1);
+            self.cursor = self.as_ref().len();
+        }
+
+        self.changed = true;
+    }
+
+    /// Ends the search, keeping the input as it was.
+    pub fn cancel_search(&mut self) {
+        self.search = None;
+        self.changed = true;
+    }
+
     pub fn as_ref(&self) -> &[char] {
         match &self.kind {
             InputKind::History(idx) => &self.history[*idx],
@@ -119,22 +325,73 @@ impl Input {
         }
     }
 
-    pub fn render(&mut self, mut writer: impl Write, height: u16) -> Result<(), Error> {
-        if !self.changed && self.height == height {
+    /// Moves the cursor one line up, returns false if it's already on the first line.
+    pub fn prev_line(&mut self) -> bool {
+        let (lines, (row, column)) = self.layout(self.width);
+        if row == 0 {
+            return false;
+        }
+
+        self.move_to(&lines[row - 1], column);
+
+        true
+    }
+
+    /// Moves the cursor one line down, returns false if it's already on the last line.
+    pub fn next_line(&mut self) -> bool {
+        let (lines, (row, column)) = self.layout(self.width);
+        if row + 1 == lines.len() {
+            return false;
+        }
+
+        self.move_to(&lines[row + 1], column);
+
+        true
+    }
+
+    /// Number of screen lines the input takes up at the given width.
+    pub fn lines(&self, width: u16) -> usize {
+        if self.search.is_some() {
+            return 1;
+        }
+
+        self.layout(width).0.len()
+    }
+
+    /// Renders up to `height` lines of the input starting at screen row `top`, keeping the cursor in view.
+    pub fn render(
+        &mut self,
+        mut writer: impl Write,
+        width: u16,
+        top: u16,
+        height: u16,
+    ) -> Result<(), Error> {
+        if !self.changed && self.width == width && self.area == (top, height) {
             return Ok(());
         }
 
         self.changed = false;
-        self.height = height;
+        self.width = width;
+        self.area = (top, height);
 
-        crossterm::queue!(writer, MoveTo(0, height))?;
-        crossterm::queue!(writer, Clear(ClearType::CurrentLine))?;
+        if let Some(search) = &self.search {
+            return self.render_search(writer, search, top, height);
+        }
+
+        let (lines, (row, column)) = self.layout(width);
+        let first = (row + 1).saturating_sub(height as usize);
+
+        for i in 0..height {
+            crossterm::queue!(writer, MoveTo(0, top + i))?;
+            crossterm::queue!(writer, Clear(ClearType::CurrentLine))?;
 
-        for c in self.as_ref() {
-            crossterm::queue!(writer, Print(c))?;
+            if let Some(line) = lines.get(first + i as usize) {
+                let line: String = self.as_ref()[line.clone()].iter().collect();
+                crossterm::queue!(writer, Print(line))?;
+            }
         }
 
-        crossterm::queue!(writer, MoveTo(self.cursor as u16, height - 1))?;
+        crossterm::queue!(writer, MoveTo(column as u16, top + (row - first) as u16))?;
 
         Ok(())
     }
@@ -143,11 +400,151 @@ impl Input {
         self.changed = true;
     }
 
+    // Shows the query and the matching entry on a single line, with the cursor after the query.
+    fn render_search(
+        &self,
+        mut writer: impl Write,
+        search: &Search,
+        top: u16,
+        height: u16,
+    ) -> Result<(), Error> {
+        for i in 0..height {
+            crossterm::queue!(writer, MoveTo(0, top + i))?;
+            crossterm::queue!(writer, Clear(ClearType::CurrentLine))?;
+        }
+
+        let prompt = match search.matched {
+            Some(_) => "(reverse-i-search)`",
+            None => "(failed reverse-i-search)`",
+        };
+
+        let query: String = search.query.iter().collect();
+        let entry: String = match search.matched {
+            Some(idx) => self.history[idx]
+                .iter()
+                .map(|c| if *c == '\n' { ' ' } else { *c })
+                .collect(),
+            None => String::new(),
+        };
+
+        let column = prompt.len()
+            + search
+                .query
+                .iter()
+                .map(|c| wrap::char_width(*c))
+                .sum::<usize>();
+
+        crossterm::queue!(
+            writer,
+            MoveTo(0, top),
+            Print(prompt),
+            Print(&query),
+            Print("': "),
+            Print(entry),
+            MoveTo(column as u16, top)
+        )?;
+
+        Ok(())
+    }
+
+    // Character ranges of screen lines and the row and column of the cursor.
+    fn layout(&self, width: u16) -> (Vec<Range<usize>>, (usize, usize)) {
+        let width = (width as usize).max(1);
+        let input = self.as_ref();
+
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut column = 0;
+
+        for (idx, c) in input.iter().enumerate() {
+            if *c == '\n' {
+                lines.push(start..idx);
+                start = idx + 1;
+                column = 0;
+                continue;
+            }
+
+            let c_width = wrap::char_width(*c);
+            if column + c_width > width && column != 0 {
+                lines.push(start..idx);
+                start = idx;
+                column = 0;
+            }
+
+            column += c_width;
+        }
+
+        // A full last line leaves the cursor at the start of the next one.
+        if column >= width {
+            lines.push(start..input.len());
+            start = input.len();
+        }
+
+        lines.push(start..input.len());
+
+        let row = lines
+            .iter()
+            .rposition(|line| line.start <= self.cursor)
+            .unwrap_or(0);
+
+        let column = input[lines[row].start..self.cursor]
+            .iter()
+            .map(|c| wrap::char_width(*c))
+            .sum();
+
+        (lines, (row, column))
+    }
+
+    // Moves the cursor to a column of a line, or to the end of the line if it's shorter.
+    fn move_to(&mut self, line: &Range<usize>, column: usize) {
+        // The end of a wrapped line is shown at the start of the next one.
+        let wrapped = self.as_ref().get(line.end).is_some_and(|c| *c != '\n');
+        let end = if wrapped { line.end - 1 } else { line.end };
+
+        let input = self.as_ref();
+        let mut cursor = line.start;
+        let mut width = 0;
+
+        while cursor < end && width + wrap::char_width(input[cursor]) <= column {
+            width += wrap::char_width(input[cursor]);
+            cursor += 1;
+        }
+
+        self.cursor = cursor;
+        self.changed = true;
+    }
+
+    fn kill(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        self.cursor = range.start;
+        self.as_mut().drain(range);
+        self.changed = true;
+    }
+
+    fn insert_completion(&mut self, completion: &Completion) {
+        let candidate = &completion.candidates[completion.idx];
+        let cursor = self.cursor;
+
+        // A sole candidate is final, so it's followed by a space.
+        let mut replacement: Vec<_> = candidate.chars().collect();
+        if completion.candidates.len() == 1 {
+            replacement.push(' ');
+        }
+
+        self.cursor = completion.start + replacement.len();
+        self.as_mut().splice(completion.start..cursor, replacement);
+        self.changed = true;
+    }
+
     fn as_mut(&mut self) -> &mut Vec<char> {
         self.kind = match std::mem::replace(&mut self.kind, InputKind::History(0)) {
             InputKind::History(idx) => InputKind::Owned(self.history[idx].clone()),
             InputKind::Owned(data) => InputKind::Owned(data),
         };
+        self.edits = self.edits.wrapping_add(1);
 
         match &mut self.kind {
             InputKind::Owned(data) => data,
@@ -156,7 +553,124 @@ impl Input {
     }
 }
 
+// Start of the word before `cursor`, skipping any whitespace right before it.
+fn word_start(input: &[char], cursor: usize) -> usize {
+    let input = &input[..cursor];
+    let end = input
+        .iter()
+        .rposition(|c| !c.is_whitespace())
+        .map(|idx| idx + 1)
+        .unwrap_or(0);
+
+    input[..end]
+        .iter()
+        .rposition(|c| c.is_whitespace())
+        .map(|idx| idx + 1)
+        .unwrap_or(0)
+}
+
+// End of the word after `cursor`, skipping any whitespace right after it.
+fn word_end(input: &[char], cursor: usize) -> usize {
+    let start = input[cursor..]
+        .iter()
+        .position(|c| !c.is_whitespace())
+        .map(|idx| cursor + idx)
+        .unwrap_or(input.len());
+
+    input[start..]
+        .iter()
+        .position(|c| c.is_whitespace())
+        .map(|idx| start + idx)
+        .unwrap_or(input.len())
+}
+
+// Index of the newest history entry before `end` which contains the query.
+fn find(history: &VecDeque<Vec<char>>, query: &[char], end: usize) -> Option<usize> {
+    history
+        .iter()
+        .take(end)
+        .rposition(|entry| query.is_empty() || entry.windows(query.len()).any(|w| w == query))
+}
+
 enum InputKind {
     History(usize),
     Owned(Vec<char>),
 }
+
+struct Search {
+    query: Vec<char>,
+    // History entry shown, None if nothing matches.
+    matched: Option<usize>,
+}
+
+struct Completion {
+    // Position where the completed word starts.
+    start: usize,
+    candidates: Vec<String>,
+    idx: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(text: &str, cursor: usize) -> Input {
+        let mut input = Input::new();
+        for c in text.chars() {
+            input.input(c);
+        }
+
+        input.cursor = cursor;
+        input
+    }
+
+    fn text(input: &Input) -> String {
+        input.as_ref().iter().collect()
+    }
+
+    #[test]
+    fn word_movement() {
+        let mut input = input("/join  fun alice", 9);
+
+        input.prev_word();
+        assert_eq!(input.cursor, 7);
+        input.prev_word();
+        assert_eq!(input.cursor, 0);
+        input.prev_word();
+        assert_eq!(input.cursor, 0);
+
+        input.next_word();
+        assert_eq!(input.cursor, 5);
+        input.next_word();
+        assert_eq!(input.cursor, 10);
+        input.next_word();
+        assert_eq!(input.cursor, 16);
+        input.next_word();
+        assert_eq!(input.cursor, 16);
+    }
+
+    #[test]
+    fn killing() {
+        let mut input = input("hello  there world", 13);
+
+        input.erase_word();
+        assert_eq!(text(&input), "hello  world");
+        assert_eq!(input.cursor, 7);
+
+        input.erase_word();
+        assert_eq!(text(&input), "world");
+        assert_eq!(input.cursor, 0);
+
+        input.delete();
+        assert_eq!(text(&input), "orld");
+
+        input.cursor = 2;
+        input.kill_to_end();
+        assert_eq!(text(&input), "or");
+
+        input.cursor = 1;
+        input.kill_to_start();
+        assert_eq!(text(&input), "r");
+        assert_eq!(input.cursor, 0);
+    }
+}
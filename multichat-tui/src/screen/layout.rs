@@ -0,0 +1,116 @@
+// The sidebar is only shown if the log keeps at least this many columns.
+const MIN_LOG_WIDTH: u16 = 40;
+const SIDEBAR_WIDTH: u16 = 24;
+
+/// Part of the screen a component renders to.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Areas of the screen, from the top: the log with the sidebar to its right, the buffer bar, the
+/// preview of the message being composed and the input.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub log: Rect,
+    pub sidebar: Option<Rect>,
+    pub bar: Rect,
+    pub preview: Option<Rect>,
+    pub input: Rect,
+}
+
+impl Layout {
+    /// Lays out a screen at least two rows high with `input` rows of input. The preview is only
+    /// shown if the log keeps at least one row.
+    pub fn new(width: u16, height: u16, input: u16, sidebar: bool, preview: bool) -> Self {
+        let preview = preview && height > input + 2;
+        let top = height - input - 1 - preview as u16;
+
+        let sidebar = (sidebar && width >= MIN_LOG_WIDTH + SIDEBAR_WIDTH).then(|| Rect {
+            x: width - SIDEBAR_WIDTH,
+            y: 0,
+            width: SIDEBAR_WIDTH,
+            height: top,
+        });
+
+        Self {
+            log: Rect {
+                x: 0,
+                y: 0,
+                width: width - sidebar.map(|sidebar| sidebar.width).unwrap_or(0),
+                height: top,
+            },
+            sidebar,
+            bar: Rect {
+                x: 0,
+                y: top,
+                width,
+                height: 1,
+            },
+            preview: preview.then(|| Rect {
+                x: 0,
+                y: top + 1,
+                width,
+                height: 1,
+            }),
+            input: Rect {
+                x: 0,
+                y: height - input,
+                width,
+                height: input,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u16, y: u16, width: u16, height: u16) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn sidebar() {
+        let layout = Layout::new(80, 24, 2, true, false);
+
+        assert_eq!(layout.log, rect(0, 0, 56, 21));
+        assert_eq!(layout.sidebar, Some(rect(56, 0, 24, 21)));
+        assert_eq!(layout.bar, rect(0, 21, 80, 1));
+        assert_eq!(layout.preview, None);
+        assert_eq!(layout.input, rect(0, 22, 80, 2));
+    }
+
+    #[test]
+    fn preview() {
+        let layout = Layout::new(80, 24, 2, false, true);
+
+        assert_eq!(layout.log, rect(0, 0, 80, 20));
+        assert_eq!(layout.bar, rect(0, 20, 80, 1));
+        assert_eq!(layout.preview, Some(rect(0, 21, 80, 1)));
+        assert_eq!(layout.input, rect(0, 22, 80, 2));
+
+        let layout = Layout::new(80, 3, 1, false, true);
+        assert_eq!(layout.preview, None);
+        assert_eq!(layout.input, rect(0, 2, 80, 1));
+    }
+
+    #[test]
+    fn narrow() {
+        let layout = Layout::new(60, 2, 1, true, true);
+
+        assert_eq!(layout.log, rect(0, 0, 60, 0));
+        assert_eq!(layout.sidebar, None);
+        assert_eq!(layout.bar, rect(0, 0, 60, 1));
+        assert_eq!(layout.input, rect(0, 1, 60, 1));
+    }
+}
@@ -1,79 +1,500 @@
+use super::layout::Rect;
+use super::wrap;
+use crate::config::Scrollback;
+use crate::theme::Theme;
+
 use crossterm::cursor::MoveTo;
-use crossterm::style::{Color, Print, PrintStyledContent, Stylize};
-use crossterm::terminal::{Clear, ClearType};
+use crossterm::style::{Attribute, Print, PrintStyledContent, SetAttribute, Stylize};
 use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::io::{Error, Write};
+use std::ops::Range;
 
-const MAX_ROWS: usize = 256;
+// Width of the level prefix, continuation lines are indented by as much.
+const PREFIX_WIDTH: usize = 4;
 
 pub struct Log {
     rows: VecDeque<(Level, Cow<'static, str>)>,
+    // Total length of all rows.
+    bytes: usize,
+    scrollback: Scrollback,
+    // Number of screen lines hidden below the view, zero while following new rows.
+    scroll: usize,
+    search: Option<Search>,
     changed: bool,
-    height: u16,
+    area: Rect,
 }
 
 impl Log {
-    pub fn new() -> Self {
+    pub fn new(scrollback: Scrollback) -> Self {
         Self {
             rows: VecDeque::new(),
+            bytes: 0,
+            scrollback,
+            scroll: 0,
+            search: None,
             changed: true,
-            height: 0,
+            area: Rect::default(),
         }
     }
 
     pub fn log(&mut self, level: Level, contents: Cow<'static, str>) {
-        if self.rows.len() == MAX_ROWS {
-            self.rows.pop_front();
+        // The newest row is always kept, even if it's larger than the limit by itself.
+        while !self.rows.is_empty()
+            && (self.rows.len() >= self.scrollback.lines.max(1)
+                || self.bytes + contents.len() > self.scrollback.bytes)
+        {
+            let (_, oldest) = self.rows.pop_front().unwrap();
+            self.bytes -= oldest.len();
+
+            if let Some(search) = &mut self.search {
+                search.row = search.row.and_then(|row| row.checked_sub(1));
+            }
+        }
+
+        self.bytes += contents.len();
+
+        // Keep the view in place while scrolled up.
+        if self.scroll != 0 {
+            self.scroll += self.wrap(&contents).len();
         }
 
         self.rows.push_back((level, contents));
         self.changed = true;
     }
 
-    pub fn render(&mut self, mut writer: impl Write, height: u16) -> Result<(), Error> {
-        if !self.changed && self.height == height {
+    /// Text of all rows without escape sequences, oldest first.
+    pub fn text(&self) -> Vec<String> {
+        self.rows
+            .iter()
+            .map(|(_, contents)| {
+                wrap::text_ranges(contents)
+                    .into_iter()
+                    .map(|range| &contents[range])
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+        self.bytes = 0;
+        self.scroll = 0;
+        self.search = None;
+        self.changed = true;
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_to(self.scroll.saturating_add(self.page()));
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_to(self.scroll.saturating_sub(self.page()));
+    }
+
+    pub fn top(&mut self) {
+        self.scroll_to(usize::MAX);
+    }
+
+    pub fn bottom(&mut self) {
+        self.scroll_to(0);
+    }
+
+    /// Starts searching for text, ignoring ASCII case, and shows the newest matching row.
+    /// Returns false if no row matches.
+    pub fn search(&mut self, pattern: &str) -> bool {
+        let search = self.search.insert(Search {
+            pattern: pattern.to_ascii_lowercase(),
+            row: None,
+        });
+
+        let row = self
+            .rows
+            .iter()
+            .rposition(|(_, contents)| !find(contents, &search.pattern).is_empty());
+
+        search.row = row;
+        self.changed = true;
+
+        match row {
+            Some(row) => {
+                self.show(row);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Shows the previous matching row, returns false if there is none.
+    pub fn search_older(&mut self) -> bool {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return false,
+        };
+
+        let end = search.row.unwrap_or(self.rows.len());
+        let row = self
+            .rows
+            .range(..end)
+            .rposition(|(_, contents)| !find(contents, &search.pattern).is_empty());
+
+        self.select(row)
+    }
+
+    /// Shows the next matching row, returns false if there is none.
+    pub fn search_newer(&mut self) -> bool {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return false,
+        };
+
+        let start = search.row.map(|row| row + 1).unwrap_or(0);
+        let row = self
+            .rows
+            .range(start..)
+            .position(|(_, contents)| !find(contents, &search.pattern).is_empty())
+            .map(|row| start + row);
+
+        self.select(row)
+    }
+
+    pub fn end_search(&mut self) {
+        self.changed |= self.search.take().is_some();
+    }
+
+    /// Renders the log to an area of the screen, overwriting all of it.
+    pub fn render(
+        &mut self,
+        mut writer: impl Write,
+        theme: &Theme,
+        area: Rect,
+    ) -> Result<(), Error> {
+        if !self.changed && self.area == area {
             return Ok(());
         }
 
         self.changed = false;
-        self.height = height;
 
-        for (i, (level, contents)) in self.last((height - 1) as usize).enumerate() {
-            crossterm::queue!(&mut writer, MoveTo(0, i as u16))?;
-            crossterm::queue!(&mut writer, Clear(ClearType::CurrentLine))?;
+        // Rows are wrapped again for the new width, the newest row in view is kept in place.
+        if self.area.width != area.width && self.scroll != 0 {
+            let anchor = self.anchor();
+
+            self.area = area;
+            self.scroll = match anchor {
+                Some((row, offset)) => self.scroll_from(row, offset),
+                None => 0,
+            };
+        }
+
+        self.area = area;
+
+        let lines = self.lines();
+        self.scroll = self.scroll.min(self.max_scroll(lines.len()));
+
+        let visible = self.visible();
+        let end = lines.len().saturating_sub(self.scroll);
+        let start = end.saturating_sub(visible);
 
-            let (prefix, color) = match level {
-                Level::Error => ("[-]", Color::Red),
-                Level::Info => ("[+]", Color::Green),
+        let width = area.width as usize;
+
+        for i in 0..self.page() {
+            crossterm::queue!(&mut writer, MoveTo(area.x, area.y + i as u16))?;
+
+            // Lines are padded with spaces so that the rest of the screen is left alone.
+            let (idx, range) = match lines.get(start + i) {
+                Some(line) if start + i < end => line,
+                _ => {
+                    crossterm::queue!(&mut writer, Print(" ".repeat(width)))?;
+                    continue;
+                }
             };
 
+            let (level, contents) = &self.rows[*idx];
+
+            if range.start == 0 {
+                let (prefix, color) = match level {
+                    Level::Error => ("[-]", theme.error),
+                    Level::Info => ("[+]", theme.info),
+                    Level::Highlight => ("[!]", theme.highlight),
+                };
+
+                crossterm::queue!(
+                    &mut writer,
+                    PrintStyledContent(prefix.with(color)),
+                    Print(" ")
+                )?;
+            } else {
+                // Restore the style the line starts with.
+                crossterm::queue!(
+                    &mut writer,
+                    Print(" ".repeat(PREFIX_WIDTH)),
+                    Print(wrap::escapes(&contents[..range.start]))
+                )?;
+            }
+
+            // Matches are underlined, those of the selected row reversed.
+            let (matches, (start, end)) = match &self.search {
+                Some(search) if search.row == Some(*idx) => (
+                    find(contents, &search.pattern),
+                    (Attribute::Reverse, Attribute::NoReverse),
+                ),
+                Some(search) => (
+                    find(contents, &search.pattern),
+                    (Attribute::Underlined, Attribute::NoUnderline),
+                ),
+                None => (Vec::new(), (Attribute::Reset, Attribute::Reset)),
+            };
+
+            let mut pos = range.start;
+            for m in matches {
+                let m = m.start.max(range.start)..m.end.min(range.end);
+                if m.is_empty() {
+                    continue;
+                }
+
+                crossterm::queue!(
+                    &mut writer,
+                    Print(&contents[pos..m.start]),
+                    SetAttribute(start),
+                    Print(&contents[m.clone()]),
+                    SetAttribute(end)
+                )?;
+
+                pos = m.end;
+            }
+
+            let padding =
+                width.saturating_sub(PREFIX_WIDTH + wrap::width(&contents[range.clone()]));
+
+            crossterm::queue!(
+                &mut writer,
+                Print(&contents[pos..range.end]),
+                SetAttribute(Attribute::Reset),
+                Print(" ".repeat(padding))
+            )?;
+        }
+
+        if self.scroll != 0 {
+            let indicator = match self.scroll {
+                1 => "-- 1 line below --".to_owned(),
+                scroll => format!("-- {} lines below --", scroll),
+            };
+
+            let indicator = wrap::truncate(&indicator, width);
+
             crossterm::queue!(
                 &mut writer,
-                PrintStyledContent(prefix.with(color)),
-                Print(" "),
-                Print(contents)
+                MoveTo(area.x, area.y + visible as u16),
+                PrintStyledContent(indicator.reverse()),
+                Print(" ".repeat(width - indicator.len()))
             )?;
         }
 
         Ok(())
     }
 
-    fn last(&self, num: usize) -> impl Iterator<Item = (Level, &str)> {
-        let offset = if self.rows.len() >= num {
-            self.rows.len() - num
-        } else {
-            0
+    pub fn mark_changed(&mut self) {
+        self.changed = true;
+    }
+
+    fn select(&mut self, row: Option<usize>) -> bool {
+        let row = match row {
+            Some(row) => row,
+            None => return false,
+        };
+
+        if let Some(search) = &mut self.search {
+            search.row = Some(row);
+        }
+
+        self.changed = true;
+        self.show(row);
+
+        true
+    }
+
+    // Scrolls a row into the middle of the view, unless it's already in view.
+    fn show(&mut self, row: usize) {
+        let lines = self.lines();
+        let line = match lines.iter().position(|(idx, _)| *idx == row) {
+            Some(line) => line,
+            None => return,
         };
 
+        let end = lines.len().saturating_sub(self.scroll);
+        if (end.saturating_sub(self.visible())..end).contains(&line) {
+            return;
+        }
+
+        let end = line + 1 + self.page().saturating_sub(1) / 2;
+        self.scroll_to(lines.len().saturating_sub(end));
+    }
+
+    // Row and byte offset of the newest line in view.
+    fn anchor(&self) -> Option<(usize, usize)> {
+        let lines = self.lines();
+        let end = lines.len().checked_sub(self.scroll)?;
+        let (row, range) = lines.get(end.checked_sub(1)?)?;
+
+        Some((*row, range.start))
+    }
+
+    // Scroll which shows the line containing a byte offset of a row as the newest line in view.
+    fn scroll_from(&self, row: usize, offset: usize) -> usize {
+        let lines = self.lines();
+        match lines
+            .iter()
+            .rposition(|(idx, range)| *idx == row && range.start <= offset)
+        {
+            Some(line) => lines.len() - line - 1,
+            None => 0,
+        }
+    }
+
+    fn scroll_to(&mut self, scroll: usize) {
+        let scroll = scroll.min(self.max_scroll(self.lines().len()));
+
+        self.changed |= self.scroll != scroll;
+        self.scroll = scroll;
+    }
+
+    // Screen lines of all rows, as the index of the row and the range of its contents.
+    fn lines(&self) -> Vec<(usize, Range<usize>)> {
         self.rows
-            .range(offset..)
-            .map(|(level, contents)| (*level, contents.as_ref()))
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, (_, contents))| {
+                self.wrap(contents)
+                    .into_iter()
+                    .map(move |range| (idx, range))
+            })
+            .collect()
     }
+
+    fn wrap(&self, contents: &str) -> Vec<Range<usize>> {
+        wrap::wrap(
+            contents,
+            (self.area.width as usize).saturating_sub(PREFIX_WIDTH),
+        )
+    }
+
+    // Number of screen rows available to the log.
+    fn page(&self) -> usize {
+        self.area.height as usize
+    }
+
+    // Number of lines shown, the scroll indicator takes up the last row.
+    fn visible(&self) -> usize {
+        match self.scroll {
+            0 => self.page(),
+            _ => self.page().saturating_sub(1),
+        }
+    }
+
+    fn max_scroll(&self, lines: usize) -> usize {
+        (lines + 1).saturating_sub(self.page())
+    }
+}
+
+struct Search {
+    // Lowercase.
+    pattern: String,
+    // Row of the current match.
+    row: Option<usize>,
+}
+
+// Byte ranges of occurrences of a lowercase pattern, escape sequences are not searched.
+fn find(contents: &str, pattern: &str) -> Vec<Range<usize>> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+
+    wrap::text_ranges(contents)
+        .into_iter()
+        .flat_map(|range| {
+            contents[range.clone()]
+                .to_ascii_lowercase()
+                .match_indices(pattern)
+                .map(|(idx, _)| range.start + idx..range.start + idx + pattern.len())
+                .collect::<Vec<_>>()
+        })
+        .collect()
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum Level {
     Info,
     Error,
+    // Messages mentioning one of our users or a highlight keyword.
+    Highlight,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(lines: usize, bytes: usize) -> Log {
+        Log::new(Scrollback { lines, bytes })
+    }
+
+    fn rows(log: &Log) -> Vec<&str> {
+        log.rows.iter().map(|(_, contents)| &**contents).collect()
+    }
+
+    #[test]
+    fn line_limit() {
+        let mut log = log(2, usize::MAX);
+        for row in ["a", "b", "c"] {
+            log.log(Level::Info, row.into());
+        }
+
+        assert_eq!(rows(&log), ["b", "c"]);
+    }
+
+    #[test]
+    fn byte_limit() {
+        let mut log = log(usize::MAX, 6);
+        for row in ["aaa", "bb", "cc", "dddddddd"] {
+            log.log(Level::Info, row.into());
+        }
+
+        assert_eq!(rows(&log), ["dddddddd"]);
+        assert_eq!(log.bytes, 8);
+
+        log.log(Level::Info, "e".into());
+        assert_eq!(rows(&log), ["e"]);
+
+        log.clear();
+        assert!(rows(&log).is_empty());
+        assert_eq!(log.bytes, 0);
+    }
+
+    #[test]
+    fn reflow() {
+        let mut log = log(usize::MAX, usize::MAX);
+        for i in 0..5 {
+            log.log(Level::Info, format!("row{} xxxx", i).into());
+        }
+
+        let theme = Theme::default();
+        let area = |width| Rect {
+            x: 0,
+            y: 0,
+            width,
+            height: 3,
+        };
+
+        // Every row fits on a line, scrolling up shows the second row at the bottom.
+        log.render(Vec::new(), &theme, area(14)).unwrap();
+        log.page_up();
+        assert_eq!(log.anchor(), Some((1, 0)));
+
+        // Every row takes up two lines, the second row stays at the bottom.
+        log.render(Vec::new(), &theme, area(9)).unwrap();
+        assert_eq!(log.scroll, 7);
+        assert_eq!(log.anchor(), Some((1, 0)));
+
+        log.render(Vec::new(), &theme, area(14)).unwrap();
+        assert_eq!(log.anchor(), Some((1, 0)));
+    }
 }
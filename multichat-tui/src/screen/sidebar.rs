@@ -0,0 +1,148 @@
+use super::layout::Rect;
+use super::wrap;
+use crate::term_safe::TermSafeExt;
+
+use crossterm::cursor::MoveTo;
+use crossterm::style::{Print, PrintStyledContent, StyledContent, Stylize};
+use std::collections::BTreeMap;
+use std::io::{Error, Write};
+
+/// Group or user listed in the sidebar.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub name: String,
+    /// Joined groups and our own users are marked with an asterisk.
+    pub marked: bool,
+}
+
+/// Lists all groups and the users of the group whose buffer is shown.
+pub struct Sidebar {
+    shown: bool,
+    groups: Vec<Entry>,
+    // Users of every group, by its name.
+    users: BTreeMap<String, Vec<Entry>>,
+    changed: bool,
+    area: Rect,
+    // Group whose users were rendered last.
+    current: Option<String>,
+}
+
+impl Sidebar {
+    pub fn new() -> Self {
+        Self {
+            shown: false,
+            groups: Vec::new(),
+            users: BTreeMap::new(),
+            changed: true,
+            area: Rect::default(),
+            current: None,
+        }
+    }
+
+    pub fn shown(&self) -> bool {
+        self.shown
+    }
+
+    pub fn show(&mut self, shown: bool) {
+        self.shown = shown;
+        self.changed = true;
+    }
+
+    pub fn set(&mut self, groups: Vec<Entry>, users: BTreeMap<String, Vec<Entry>>) {
+        if self.groups == groups && self.users == users {
+            return;
+        }
+
+        self.groups = groups;
+        self.users = users;
+        self.changed = true;
+    }
+
+    /// Renders to an area of the screen, with a border on its left.
+    pub fn render(
+        &mut self,
+        mut writer: impl Write,
+        area: Rect,
+        current: Option<&str>,
+    ) -> Result<(), Error> {
+        if !self.changed && self.area == area && self.current.as_deref() == current {
+            return Ok(());
+        }
+
+        self.changed = false;
+        self.area = area;
+        self.current = current.map(str::to_owned);
+
+        let mut lines = vec![Line::heading("Groups")];
+        for group in &self.groups {
+            let mut line = Line::entry(group);
+            line.reverse = Some(&*group.name) == current;
+
+            lines.push(line);
+        }
+
+        let users = current.and_then(|group| self.users.get(group));
+        if let Some(users) = users {
+            lines.push(Line::default());
+            lines.push(Line::heading("Users"));
+            lines.extend(users.iter().map(Line::entry));
+        }
+
+        let width = area.width.saturating_sub(2) as usize;
+
+        for i in 0..area.height {
+            crossterm::queue!(writer, MoveTo(area.x, area.y + i), Print("│ "))?;
+
+            let line = lines.get(i as usize).cloned().unwrap_or_default();
+            let text = wrap::truncate(&line.text, width);
+
+            crossterm::queue!(
+                writer,
+                PrintStyledContent(line.style(text)),
+                Print(" ".repeat(width - wrap::width(text)))
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct Line {
+    text: String,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Line {
+    fn heading(text: &str) -> Self {
+        Self {
+            text: text.to_owned(),
+            bold: true,
+            reverse: false,
+        }
+    }
+
+    fn entry(entry: &Entry) -> Self {
+        let marker = if entry.marked { '*' } else { ' ' };
+
+        Self {
+            text: format!("{} {}", marker, entry.name.term_safe()),
+            bold: false,
+            reverse: false,
+        }
+    }
+
+    fn style<'a>(&self, text: &'a str) -> StyledContent<&'a str> {
+        let mut styled = text.stylize();
+        if self.bold {
+            styled = styled.bold();
+        }
+
+        if self.reverse {
+            styled = styled.reverse();
+        }
+
+        styled
+    }
+}
@@ -0,0 +1,210 @@
+use std::iter::Peekable;
+use std::ops::Range;
+use std::str::CharIndices;
+use unicode_width::UnicodeWidthChar;
+
+/// Splits text into byte ranges of lines at most `width` columns wide, breaking after spaces where possible.
+///
+/// Newlines always break a line and are not part of any range. Escape sequences take up no space.
+pub fn wrap(text: &str, width: usize) -> Vec<Range<usize>> {
+    let width = width.max(1);
+
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut column = 0;
+    // Position after the last space of the current line and the column there.
+    let mut space = None;
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\x1b' => {
+                skip_escape(&mut chars);
+                continue;
+            }
+            '\n' => {
+                lines.push(start..idx);
+                start = idx + 1;
+                column = 0;
+                space = None;
+                continue;
+            }
+            _ => {}
+        }
+
+        let c_width = char_width(c);
+        if column + c_width > width && column != 0 {
+            // A space at the break is dropped.
+            if c == ' ' {
+                lines.push(start..idx);
+                start = idx + 1;
+                column = 0;
+                space = None;
+                continue;
+            }
+
+            match space.take() {
+                Some((end, end_column)) => {
+                    lines.push(start..end);
+                    start = end;
+                    column -= end_column;
+                }
+                None => {
+                    lines.push(start..idx);
+                    start = idx;
+                    column = 0;
+                }
+            }
+        }
+
+        column += c_width;
+
+        if c == ' ' {
+            space = Some((idx + 1, column));
+        }
+    }
+
+    lines.push(start..text.len());
+    lines
+}
+
+/// Escape sequences contained in text, which restore its style when printed.
+pub fn escapes(text: &str) -> String {
+    let mut escapes = String::new();
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if c != '\x1b' {
+            continue;
+        }
+
+        skip_escape(&mut chars);
+
+        let end = chars.peek().map(|(idx, _)| *idx).unwrap_or(text.len());
+        escapes.push_str(&text[idx..end]);
+    }
+
+    escapes
+}
+
+/// Byte ranges of the text in between escape sequences.
+pub fn text_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if c != '\x1b' {
+            continue;
+        }
+
+        skip_escape(&mut chars);
+
+        if start != idx {
+            ranges.push(start..idx);
+        }
+
+        start = chars.peek().map(|(idx, _)| *idx).unwrap_or(text.len());
+    }
+
+    if start != text.len() {
+        ranges.push(start..text.len());
+    }
+
+    ranges
+}
+
+/// Number of columns text takes up, escape sequences take up none.
+pub fn width(text: &str) -> usize {
+    text_ranges(text)
+        .into_iter()
+        .flat_map(|range| text[range].chars())
+        .map(char_width)
+        .sum()
+}
+
+/// Longest start of text without escape sequences which fits in `width` columns.
+pub fn truncate(text: &str, width: usize) -> &str {
+    let mut column = 0;
+
+    for (idx, c) in text.char_indices() {
+        column += char_width(c);
+        if column > width {
+            return &text[..idx];
+        }
+    }
+
+    text
+}
+
+/// Number of columns a character takes up, wide characters such as CJK or emoji take up two.
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+// Skips the rest of a CSI sequence after the escape character.
+fn skip_escape(chars: &mut Peekable<CharIndices<'_>>) {
+    if chars.next_if(|(_, c)| *c == '[').is_none() {
+        return;
+    }
+
+    for (_, c) in chars {
+        if ('@'..='~').contains(&c) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(text: &str, width: usize, output: &[&str]) {
+        let lines: Vec<_> = wrap(text, width)
+            .into_iter()
+            .map(|range| &text[range])
+            .collect();
+
+        assert_eq!(lines, output);
+    }
+
+    #[test]
+    fn words() {
+        check("hello world", 20, &["hello world"]);
+        check("hello world", 8, &["hello ", "world"]);
+        check("hello world", 5, &["hello", "world"]);
+        check("", 5, &[""]);
+    }
+
+    #[test]
+    fn long_word() {
+        check("abcdefgh ij", 3, &["abc", "def", "gh ", "ij"]);
+    }
+
+    #[test]
+    fn wide_chars() {
+        check("日本語です", 5, &["日本", "語で", "す"]);
+        check("ab 😀😀", 4, &["ab ", "😀😀"]);
+    }
+
+    #[test]
+    fn newlines() {
+        check("a\nb\n", 5, &["a", "b", ""]);
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let text = "\x1b[1mbold\x1b[0m text";
+        check(text, 5, &["\x1b[1mbold\x1b[0m ", "text"]);
+        assert_eq!(escapes(text), "\x1b[1m\x1b[0m");
+        assert_eq!(text_ranges(text), [4..8, 12..17]);
+        assert_eq!(width(text), 9);
+    }
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("hello", 3), "hel");
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("日本語", 3), "日");
+    }
+}
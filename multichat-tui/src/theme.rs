@@ -0,0 +1,209 @@
+use crate::term_safe::TermSafeExt;
+
+use crossterm::style::{Color, Stylize};
+use serde::{Deserialize, Deserializer};
+use std::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Theme {
+    /// Prefix of informational lines.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub info: Color,
+    /// Prefix of errors.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub error: Color,
+    /// Prefix of highlighted messages and buffers with unread highlights.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub highlight: Color,
+    /// Groups in the buffer bar.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub group: Color,
+    /// `code` in messages, which is dimmed without a color.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub code: Color,
+    /// Colors nicks are picked from by their name, nicks are not colored if empty.
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub nicks: Vec<Color>,
+}
+
+impl Theme {
+    /// Nick colored by its name, so that the same name always gets the same color.
+    pub fn nick(&self, name: &str) -> String {
+        self.nick_by(name, name.as_bytes())
+    }
+
+    /// Nick colored by a key, so that the same key always gets the same color.
+    pub fn nick_by(&self, name: &str, key: &[u8]) -> String {
+        let nick = name.term_safe().bold();
+        if self.nicks.is_empty() {
+            return nick.to_string();
+        }
+
+        // FNV-1a, which unlike the standard hasher is stable between builds.
+        let hash = key.iter().fold(0xcbf29ce484222325u64, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+
+        nick.with(self.nicks[(hash % self.nicks.len() as u64) as usize])
+            .to_string()
+    }
+
+    /// Sets the color of an element, `nicks` takes any number of colors and the others exactly one.
+    pub fn set(&mut self, element: &str, colors: &[&str]) -> Result<(), Error> {
+        let colors = colors
+            .iter()
+            .map(|color| parse(color))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let color = match element {
+            "nicks" => {
+                self.nicks = colors;
+                return Ok(());
+            }
+            "info" => &mut self.info,
+            "error" => &mut self.error,
+            "highlight" => &mut self.highlight,
+            "group" => &mut self.group,
+            "code" => &mut self.code,
+            _ => return Err(Error::UnknownElement),
+        };
+
+        match *colors {
+            [single] => *color = single,
+            _ => return Err(Error::SingleColor),
+        }
+
+        Ok(())
+    }
+
+    /// Names of all elements and their colors.
+    pub fn elements(&self) -> Vec<(&'static str, String)> {
+        let nicks = self
+            .nicks
+            .iter()
+            .map(|color| format(*color))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        vec![
+            ("info", format(self.info)),
+            ("error", format(self.error)),
+            ("highlight", format(self.highlight)),
+            ("group", format(self.group)),
+            ("code", format(self.code)),
+            ("nicks", nicks),
+        ]
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            info: Color::Green,
+            error: Color::Red,
+            highlight: Color::Yellow,
+            group: Color::Reset,
+            code: Color::Reset,
+            nicks: vec![
+                Color::Cyan,
+                Color::Magenta,
+                Color::Blue,
+                Color::DarkCyan,
+                Color::DarkMagenta,
+                Color::DarkYellow,
+            ],
+        }
+    }
+}
+
+/// Parses a color name such as `dark-red`, an ANSI color number or a `#rrggbb` hex color.
+pub fn parse(color: &str) -> Result<Color, Error> {
+    if let Some(hex) = color.strip_prefix('#') {
+        let value = match hex.len() {
+            6 => u32::from_str_radix(hex, 16).map_err(|_| Error::InvalidColor)?,
+            _ => return Err(Error::InvalidColor),
+        };
+
+        return Ok(Color::Rgb {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        });
+    }
+
+    if let Ok(value) = color.parse() {
+        return Ok(Color::AnsiValue(value));
+    }
+
+    match color {
+        "default" => Ok(Color::Reset),
+        color => Color::try_from(&*color.replace('-', "_")).map_err(|_| Error::InvalidColor),
+    }
+}
+
+/// Formats a color so that it can be parsed back.
+pub fn format(color: Color) -> String {
+    let name = match color {
+        Color::Reset => "default",
+        Color::Black => "black",
+        Color::DarkGrey => "dark-grey",
+        Color::Red => "red",
+        Color::DarkRed => "dark-red",
+        Color::Green => "green",
+        Color::DarkGreen => "dark-green",
+        Color::Yellow => "yellow",
+        Color::DarkYellow => "dark-yellow",
+        Color::Blue => "blue",
+        Color::DarkBlue => "dark-blue",
+        Color::Magenta => "magenta",
+        Color::DarkMagenta => "dark-magenta",
+        Color::Cyan => "cyan",
+        Color::DarkCyan => "dark-cyan",
+        Color::White => "white",
+        Color::Grey => "grey",
+        Color::Rgb { r, g, b } => return format!("#{:02x}{:02x}{:02x}", r, g, b),
+        Color::AnsiValue(value) => return value.to_string(),
+    };
+
+    name.to_owned()
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let color = String::deserialize(deserializer)?;
+    parse(&color).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_colors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Color>, D::Error> {
+    let colors = Vec::<String>::deserialize(deserializer)?;
+    colors
+        .iter()
+        .map(|color| parse(color).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Invalid color")]
+    InvalidColor,
+    #[error("Unknown element")]
+    UnknownElement,
+    #[error("Element takes a single color")]
+    SingleColor,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_round_trip() {
+        for color in ["dark-red", "default", "#12abef", "208"] {
+            assert_eq!(format(parse(color).unwrap()), color);
+        }
+
+        assert!(parse("#12abe").is_err());
+        assert!(parse("purple").is_err());
+    }
+}
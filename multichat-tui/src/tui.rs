@@ -1,17 +1,47 @@
-use crate::command::{Command, Error as CommandError};
-use crate::screen::{Event as ScreenEvent, Level, Screen};
+use crate::command::{self, Command, Completion, Error as CommandError, Token, COMMANDS};
+use crate::config::{self, Config, Server};
+use crate::connection::{Connection, Event as ConnectionEvent, Request};
+use crate::emoji;
+use crate::export::{self, Format};
+use crate::filter::Filter;
+use crate::highlight;
+use crate::logging::{Logger, Record};
+use crate::markup;
+#[cfg(feature = "notify")]
+use crate::notify;
+use crate::screen::{Entry, Event as ScreenEvent, Level, Progress, Screen};
 use crate::term_safe::TermSafeExt;
+use crate::tls;
 
-use crossterm::style::Stylize;
-use multichat_client::proto::Version;
-use multichat_client::{BasicClient, BasicConnectError, ClientBuilder, Update, UpdateKind};
-use std::collections::{BTreeMap, HashSet};
+use multichat_client::proto::{AccessToken, Config as ProtoConfig, ResumeToken, Version};
+use multichat_client::{ClientBuilder, MaybeTlsClient, UpdateKind};
+use std::borrow::Cow;
+use std::collections::btree_map::Entry as MapEntry;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::convert::TryFrom;
-use std::io::Error;
+use std::io::{self, Error};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{future, mem};
+use thiserror::Error;
+use tokio::fs;
 use tokio::sync::mpsc;
+use tokio::time::{self, Instant, MissedTickBehavior};
 
-pub async fn run(screen: &mut Screen) -> Result<(), Error> {
+const MAX_PENDING_ATTACHMENTS: usize = 32;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// Longest wait for requests to be sent when disconnecting, in case the server stalls.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+// Typing stops after this long without an edit.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn run(
+    screen: &mut Screen,
+    config: Config,
+    config_path: Option<&Path>,
+    mut logger: Logger,
+) -> Result<(), Error> {
     screen.log(
         Level::Info,
         format!(
@@ -21,22 +51,76 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
         ),
     );
 
-    let mut connecting = false;
+    let mut highlights = config.highlights.clone();
+    let mut notify_groups = config.notify_groups.clone();
+    let mut dnd = false;
+    let mut styling = config.markup;
+    let mut aliases: BTreeMap<_, _> = config
+        .aliases
+        .iter()
+        .map(|(name, command)| (name.trim_start_matches('/').to_owned(), command.clone()))
+        .collect();
+    let mut filter = Filter {
+        muted: config.muted.clone(),
+        ignored: config.ignored.clone(),
+        joins: config.joins,
+        group_joins: BTreeMap::new(),
+    };
+    let mut connecting = None::<Server>;
     let mut state = None::<State>;
+    // Connection which was lost, and when to try to get it back.
+    let mut lost = None::<Lost>;
+    let mut retry_at = None::<Instant>;
+    let mut typing_until = None::<Instant>;
     let (sender, mut receiver) = mpsc::channel(1);
+    let mut progress = time::interval(PROGRESS_INTERVAL);
+    progress.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    if let Some(name) = &config.auto_connect {
+        let server = config.servers[name].clone();
+
+        screen.log(
+            Level::Info,
+            format!("Attempting to connect to server {}", name),
+        );
+        connect(&server, None, sender.clone());
+        connecting = Some(server);
+    }
 
     loop {
+        if screen.sidebar_shown() {
+            update_sidebar(screen, state.as_ref());
+        }
+
         screen.render()?;
 
-        let update = async {
+        let downloading = state
+            .as_ref()
+            .is_some_and(|state| !state.downloads.is_empty());
+
+        let connection = async {
             match &mut state {
-                Some(state) => state.client.read_update().await,
+                Some(state) => state.connection.next().await,
+                None => future::pending().await,
+            }
+        };
+
+        let retry = async {
+            match retry_at {
+                Some(at) => time::sleep_until(at).await,
+                None => future::pending().await,
+            }
+        };
+
+        let typing_timeout = async {
+            match typing_until {
+                Some(at) => time::sleep_until(at).await,
                 None => future::pending().await,
             }
         };
 
         let event = tokio::select! {
-            update = update => Event::Update(update),
+            event = connection => Event::Connection(event),
             event = screen.process() => {
                 match event? {
                     Some(event) => Event::Screen(event),
@@ -44,17 +128,39 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                 }
             },
             event = receiver.recv() => Event::Connect(event.unwrap()),
+            _ = retry => Event::Reconnect,
+            _ = typing_timeout => Event::TypingTimeout,
+            _ = progress.tick(), if downloading => Event::Progress,
+            record = logger.next() => Event::Log(record),
         };
 
         match event {
             Event::Screen(event) => match event {
                 ScreenEvent::Input(input) => {
-                    let command = match Command::try_from(&*input) {
+                    screen.set_preview(None);
+
+                    if let Some(state) = &mut state {
+                        state.set_typing(None);
+                    }
+
+                    typing_until = None;
+
+                    let expanded = command::expand(&input, &aliases);
+                    let command = match Command::try_from(&*expanded) {
                         Ok(command) => command,
                         Err(CommandError::NotACommand) => {
                             if let Some(state) = &mut state {
-                                if let Some((gid, uid)) = state.current {
-                                    state.client.send_message(gid, uid, &input, &[]).await?;
+                                if let Some((gid, uid)) = state.sender(screen.buffer()) {
+                                    let text = match config.emoji {
+                                        true => emoji::expand(&input),
+                                        false => Cow::Borrowed(&*input),
+                                    };
+
+                                    state.connection.send(Request::SendMessage {
+                                        gid,
+                                        uid,
+                                        text: text.into_owned(),
+                                    });
                                 } else {
                                     screen.log(Level::Error, "No active user");
                                 }
@@ -73,29 +179,63 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                             server,
                             access_token,
                         } => {
-                            if connecting {
+                            if connecting.is_some() {
                                 screen.log(Level::Error, "Already connecting");
                                 continue;
                             }
 
-                            state = None;
-                            connecting = true;
+                            let access_token = match access_token {
+                                Some(Token::Inline(access_token)) => {
+                                    screen.forget_input();
+                                    Some(access_token)
+                                }
+                                Some(Token::File(path)) => {
+                                    match read_token(Path::new(&*path)).await {
+                                        Ok(access_token) => Some(access_token),
+                                        Err(err) => {
+                                            screen.log(
+                                                Level::Error,
+                                                format!(
+                                                    "Error reading token file {}: {}",
+                                                    path, err
+                                                ),
+                                            );
+                                            continue;
+                                        }
+                                    }
+                                }
+                                None => None,
+                            };
 
-                            let server = server.into_owned();
-                            let sender = sender.clone();
+                            let server = match access_token {
+                                Some(access_token) => Server {
+                                    address: server.into_owned(),
+                                    access_token,
+                                    certificate: None,
+                                    groups: Vec::new(),
+                                    user: None,
+                                },
+                                None => match config.servers.get(&*server) {
+                                    Some(server) => server.clone(),
+                                    None => {
+                                        screen.log(Level::Error, "Unknown server");
+                                        continue;
+                                    }
+                                },
+                            };
 
-                            screen.log(Level::Info, "Attempting to connect to server");
+                            if let Some(state) = state.take() {
+                                disconnect(state.connection).await;
+                            }
 
-                            tokio::spawn(async move {
-                                let builder = ClientBuilder::basic();
+                            lost = None;
+                            retry_at = None;
+                            screen.clear_typing();
+                            screen.set_progress(None);
 
-                                tokio::select! {
-                                    result = builder.connect(&*server, access_token) => {
-                                        let _ = sender.send(result).await;
-                                    }
-                                    _ = sender.closed() => {}
-                                }
-                            });
+                            screen.log(Level::Info, "Attempting to connect to server");
+                            connect(&server, None, sender.clone());
+                            connecting = Some(server);
 
                             continue;
                         }
@@ -137,10 +277,37 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                         }
                         Command::Disconnect => {
                             if let Some(state) = state.take() {
-                                let _ = state.client.shutdown().await;
+                                disconnect(state.connection).await;
                             }
 
-                            connecting = false;
+                            screen.clear_typing();
+                            screen.set_progress(None);
+                            connecting = None;
+                            lost = None;
+                            retry_at = None;
+                        }
+                        Command::Reconnect => {
+                            if connecting.is_some() {
+                                screen.log(Level::Error, "Already connecting");
+                                continue;
+                            }
+
+                            if let Some(state) = state.take() {
+                                let (new, connection) = Lost::new(state);
+                                disconnect(connection).await;
+
+                                lost = Some(new);
+                                screen.clear_typing();
+                                screen.set_progress(None);
+                            }
+
+                            match &mut lost {
+                                Some(lost) => {
+                                    lost.attempt = 0;
+                                    retry_at = Some(Instant::now());
+                                }
+                                None => screen.log(Level::Error, "Not connected to server"),
+                            }
                         }
                         Command::Join { group, user } => {
                             let state = match state.as_mut() {
@@ -151,41 +318,7 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                                 }
                             };
 
-                            let (gid, group) =
-                                match state.groups.iter_mut().find(|(_, g)| group == g.name) {
-                                    Some((gid, group)) => (*gid, group),
-                                    None => {
-                                        let gid = state.client.join_group(&group).await?;
-                                        let group = state.groups.entry(gid).or_insert(Group {
-                                            name: group.into_owned(),
-                                            users: BTreeMap::new(),
-                                            owned: HashSet::new(),
-                                            joined: true,
-                                        });
-
-                                        screen.log(
-                                            Level::Info,
-                                            format!("Joined group {}", group.name.term_safe()),
-                                        );
-
-                                        (gid, group)
-                                    }
-                                };
-
-                            if !group.joined {
-                                state.client.join_group(&group.name).await?;
-                                group.joined = true;
-
-                                screen.log(
-                                    Level::Info,
-                                    format!("Joined group {}", group.name.term_safe()),
-                                );
-                            }
-
-                            if let Some(user) = user {
-                                let uid = state.client.init_user(gid, &*user).await?;
-                                group.owned.insert(uid);
-                            }
+                            state.join(&group, user.as_deref());
                         }
                         Command::Leave { group, uid } => {
                             let state = match state.as_mut() {
@@ -219,7 +352,7 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                                     continue;
                                 }
 
-                                state.client.destroy_user(gid, uid).await?;
+                                state.connection.send(Request::DestroyUser { gid, uid });
                             }
                         }
                         Command::Rename { group, uid, name } => {
@@ -253,7 +386,11 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                                 continue;
                             }
 
-                            state.client.rename_user(gid, uid, &*name).await?;
+                            state.connection.send(Request::Rename {
+                                gid,
+                                uid,
+                                name: name.into_owned(),
+                            });
                         }
                         Command::Switch { group, uid } => {
                             let state = match state.as_mut() {
@@ -286,185 +423,782 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
                                 continue;
                             }
 
+                            screen.open_buffer(&group.name);
                             state.current = Some((gid, uid));
                         }
+                        Command::SaveAttachment { id, path } => {
+                            let state = match state.as_mut() {
+                                Some(state) => state,
+                                None => {
+                                    screen.log(Level::Error, "Not connected to server");
+                                    continue;
+                                }
+                            };
+
+                            let idx = match state.attachments.iter().position(|a| a.id == id) {
+                                Some(idx) => idx,
+                                None => {
+                                    screen.log(Level::Error, "Unknown attachment");
+                                    continue;
+                                }
+                            };
+
+                            let attachment = state.attachments.remove(idx).unwrap();
+
+                            let mut path = match path {
+                                Some(path) => PathBuf::from(&*path),
+                                None => config.download_dir.clone().unwrap_or_default(),
+                            };
+
+                            if path.as_os_str().is_empty() || path.is_dir() {
+                                path.push(file_name(id));
+                            }
+
+                            state.download(attachment, path);
+                        }
+                        Command::Highlights => {
+                            for word in &highlights {
+                                screen.log(Level::Info, format!("* {}", word.term_safe()));
+                            }
+                        }
+                        Command::AddHighlight { word } => {
+                            if !highlights.iter().any(|w| *w == word) {
+                                highlights.push(word.into_owned());
+                            }
+                        }
+                        Command::RemoveHighlight { word } => {
+                            highlights.retain(|w| *w != word);
+                        }
+                        Command::Muted => {
+                            for group in &filter.muted {
+                                screen.log(Level::Info, format!("* {}", group.term_safe()));
+                            }
+                        }
+                        Command::Mute { group } => {
+                            if !filter.muted.insert(group.into_owned()) {
+                                screen.log(Level::Error, "Group is already muted");
+                                continue;
+                            }
+
+                            save_filter(screen, config_path, &filter).await;
+                        }
+                        Command::Unmute { group } => {
+                            if !filter.muted.remove(&*group) {
+                                screen.log(Level::Error, "Group is not muted");
+                                continue;
+                            }
+
+                            save_filter(screen, config_path, &filter).await;
+                        }
+                        Command::Ignored => {
+                            for (group, users) in &filter.ignored {
+                                screen.log(Level::Info, format!("* {}", group.term_safe()));
+
+                                for user in users {
+                                    screen.log(Level::Info, format!("  * {}", user.term_safe()));
+                                }
+                            }
+                        }
+                        Command::Ignore { group, uid } => {
+                            let state = match &state {
+                                Some(state) => state,
+                                None => {
+                                    screen.log(Level::Error, "Not connected to server");
+                                    continue;
+                                }
+                            };
+
+                            let group = match state.groups.values().find(|g| group == g.name) {
+                                Some(group) => group,
+                                None => {
+                                    screen.log(Level::Error, "Unknown group");
+                                    continue;
+                                }
+                            };
+
+                            let user = match group.users.get(&uid) {
+                                Some(user) => user,
+                                None => {
+                                    screen.log(Level::Error, "Unknown user");
+                                    continue;
+                                }
+                            };
+
+                            if user.owned {
+                                screen.log(Level::Error, "Cannot ignore own user");
+                                continue;
+                            }
+
+                            if !filter.ignore(&group.name, &user.name) {
+                                screen.log(Level::Error, "User is already ignored");
+                                continue;
+                            }
+
+                            save_filter(screen, config_path, &filter).await;
+                        }
+                        Command::Unignore { group, name } => {
+                            if !filter.unignore(&group, &name) {
+                                screen.log(Level::Error, "User is not ignored");
+                                continue;
+                            }
+
+                            save_filter(screen, config_path, &filter).await;
+                        }
+                        Command::Filters => {
+                            screen.log(Level::Info, format!("* joins: {}", filter.joins));
+
+                            for (group, joins) in &filter.group_joins {
+                                screen.log(
+                                    Level::Info,
+                                    format!("* joins in {}: {}", group.term_safe(), joins),
+                                );
+                            }
+                        }
+                        Command::FilterJoins { joins, group } => {
+                            let group = match group.as_deref().or(screen.buffer()) {
+                                Some(group) => group.to_owned(),
+                                None => {
+                                    screen.log(Level::Error, "No group given");
+                                    continue;
+                                }
+                            };
+
+                            filter.group_joins.insert(group, joins);
+                        }
+                        #[cfg(feature = "notify")]
+                        Command::Notifications => {
+                            let highlights = if config.notify { "on" } else { "off" };
+                            let dnd = if dnd { "on" } else { "off" };
+
+                            screen.log(Level::Info, format!("* highlights: {}", highlights));
+                            screen.log(Level::Info, format!("* do not disturb: {}", dnd));
+
+                            for group in &notify_groups {
+                                screen.log(Level::Info, format!("* group {}", group.term_safe()));
+                            }
+                        }
+                        Command::Notify { group, enabled } => {
+                            if cfg!(not(feature = "notify")) {
+                                screen.log(
+                                    Level::Error,
+                                    "Desktop notifications require the notify feature",
+                                );
+                                continue;
+                            }
+
+                            if enabled {
+                                notify_groups.insert(group.into_owned());
+                            } else {
+                                notify_groups.remove(&*group);
+                            }
+                        }
+                        #[cfg(not(feature = "notify"))]
+                        Command::Notifications => {
+                            screen.log(
+                                Level::Error,
+                                "Desktop notifications require the notify feature",
+                            );
+                        }
+                        Command::DoNotDisturb { enabled } => {
+                            dnd = enabled.unwrap_or(!dnd);
+
+                            let message = if dnd {
+                                "Do not disturb is on"
+                            } else {
+                                "Do not disturb is off"
+                            };
+
+                            screen.log(Level::Info, message);
+                        }
+                        Command::Markup { enabled } => {
+                            styling = enabled.unwrap_or(!styling);
+
+                            // Lines already in the log keep how they were shown.
+                            let message = if styling {
+                                "Markup is styled in new messages"
+                            } else {
+                                "Markup is shown as plain text in new messages"
+                            };
+
+                            screen.log(Level::Info, message);
+                        }
+                        Command::LogLevel { level: Some(level) } => {
+                            logger.set_level(level);
+                            screen.log(Level::Info, format!("Log level set to {}", level));
+                        }
+                        Command::LogLevel { level: None } => {
+                            screen.log(Level::Info, format!("Log level is {}", logger.level()));
+                        }
+                        Command::Clear => screen.clear(),
+                        Command::Export { group, path } => {
+                            let lines = match screen.text(&group) {
+                                Some(lines) => lines,
+                                None => {
+                                    screen.log(Level::Error, "Unknown group");
+                                    continue;
+                                }
+                            };
+
+                            let path = Path::new(&*path);
+                            let data = export::format(&group, &lines, Format::from_path(path));
+
+                            match fs::write(path, data).await {
+                                Ok(()) => screen.log(
+                                    Level::Info,
+                                    format!("Exported {} lines to {}", lines.len(), path.display()),
+                                ),
+                                Err(err) => screen.log(
+                                    Level::Error,
+                                    format!("Error exporting to {}: {}", path.display(), err),
+                                ),
+                            }
+                        }
+                        Command::Search { words } => {
+                            if words.is_empty() {
+                                screen.end_search();
+                            } else if !screen.search(&words.join(" ")) {
+                                screen.log(Level::Error, "No matches");
+                            }
+                        }
+                        Command::Aliases => {
+                            for (name, command) in &aliases {
+                                screen.log(
+                                    Level::Info,
+                                    format!("* /{} = {}", name.term_safe(), command.term_safe()),
+                                );
+                            }
+                        }
+                        Command::Alias { name, command } => {
+                            let name = name.trim_start_matches('/');
+                            if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+                                screen.log(Level::Error, "Invalid alias name");
+                                continue;
+                            }
+
+                            if command.is_empty() {
+                                screen.log(Level::Error, "Missing command");
+                                continue;
+                            }
+
+                            let command: Vec<_> =
+                                command.iter().map(|arg| command::quote(arg)).collect();
+
+                            aliases.insert(name.to_owned(), command.join(" "));
+                        }
+                        Command::Unalias { name } => {
+                            if aliases.remove(name.trim_start_matches('/')).is_none() {
+                                screen.log(Level::Error, "Unknown alias");
+                            }
+                        }
+                        Command::Help { command } => match command {
+                            Some(name) => match command::info(&name) {
+                                Some(info) => {
+                                    screen.log(Level::Info, info.usage());
+                                    screen.log(Level::Info, format!("  {}", info.description));
+                                }
+                                None => screen.log(Level::Error, "Unknown command"),
+                            },
+                            None => {
+                                for info in COMMANDS {
+                                    screen.log(
+                                        Level::Info,
+                                        format!("* {} - {}", info.usage(), info.description),
+                                    );
+                                }
+                            }
+                        },
+                        // The protocol has no way to tell others why we left.
+                        Command::Quit => {
+                            if let Some(state) = state.take() {
+                                disconnect(state.connection).await;
+                            }
+
+                            return Ok(());
+                        }
+                        Command::Theme => {
+                            for (element, colors) in screen.theme().elements() {
+                                screen.log(Level::Info, format!("* {}: {}", element, colors));
+                            }
+                        }
+                        Command::SetColor { element, colors } => {
+                            let colors: Vec<_> = colors.iter().map(|color| &**color).collect();
+
+                            let mut theme = screen.theme().clone();
+                            match theme.set(&element, &colors) {
+                                Ok(()) => screen.set_theme(theme),
+                                Err(err) => screen.log(Level::Error, format!("{}", err)),
+                            }
+                        }
                     }
                 }
+                ScreenEvent::Edit => {
+                    // Messages with markup are previewed as they will be shown.
+                    let draft = screen.draft();
+                    let preview = styling && screen.composing() && markup::styled(&draft);
+                    let preview = preview.then(|| match config.emoji {
+                        true => markup::render(&emoji::expand(&draft), screen.theme()),
+                        false => markup::render(&draft, screen.theme()),
+                    });
+
+                    screen.set_preview(preview);
+
+                    let state = match &mut state {
+                        Some(state) => state,
+                        None => continue,
+                    };
+
+                    let typing = match screen.composing() {
+                        true => state.sender(screen.buffer()),
+                        false => None,
+                    };
+
+                    state.set_typing(typing);
+                    typing_until = typing.map(|_| Instant::now() + TYPING_TIMEOUT);
+                }
+                ScreenEvent::Complete(input) => {
+                    screen.complete(candidates(state.as_ref(), &aliases, config.emoji, &input));
+                }
+                // Cancels a download if there is one, quits otherwise.
+                ScreenEvent::Interrupt => {
+                    let download = state.as_mut().and_then(|state| {
+                        state
+                            .downloads
+                            .iter_mut()
+                            .find(|download| !download.cancelled)
+                    });
+
+                    let download = match download {
+                        Some(download) => download,
+                        None => {
+                            if let Some(state) = state.take() {
+                                disconnect(state.connection).await;
+                            }
+
+                            return Ok(());
+                        }
+                    };
+
+                    download.cancelled = true;
+
+                    let attachment = &download.attachment;
+                    screen.log_to(
+                        Some(&attachment.group),
+                        Level::Info,
+                        format!("Cancelled download of attachment {}", attachment.id),
+                    );
+
+                    screen.set_progress(state.as_ref().and_then(State::progress));
+                }
                 ScreenEvent::Quit => {
                     if let Some(state) = state.take() {
-                        let _ = state.client.shutdown().await;
+                        disconnect(state.connection).await;
                     }
 
                     return Ok(());
                 }
             },
             Event::Connect(result) => {
-                if !connecting {
-                    if let Ok(client) = result {
-                        let _ = client.shutdown().await;
-                    }
-
-                    continue;
-                }
+                let server = match connecting.take() {
+                    Some(server) => server,
+                    None => {
+                        if let Ok(client) = result {
+                            tokio::spawn(client.shutdown());
+                        }
 
-                connecting = false;
+                        continue;
+                    }
+                };
 
                 match result {
                     Ok(client) => {
-                        screen.log(Level::Info, "Connected to server");
+                        screen.log_to(None, Level::Info, "Connected to server");
 
-                        state = Some(State {
+                        let connection = Connection::new(client);
+                        let resumed = connection.resumed();
+                        let state = state.insert(State {
+                            server,
                             groups: BTreeMap::new(),
-                            client,
+                            connection,
+                            joining: BTreeMap::new(),
                             current: None,
+                            typing: None,
+                            attachments: VecDeque::new(),
+                            downloads: VecDeque::new(),
                         });
+
+                        match lost.take() {
+                            Some(lost) if resumed => {
+                                screen.log_to(None, Level::Info, "Resumed session");
+                                state.resume(lost);
+                            }
+                            Some(lost) => state.rejoin(lost),
+                            None => {
+                                for group in &state.server.groups.clone() {
+                                    let user = state.server.user.clone();
+                                    state.join(group, user.as_deref());
+                                }
+                            }
+                        }
                     }
                     Err(err) => {
-                        screen.log(Level::Error, format!("Error connecting to server: {}", err));
+                        screen.log_to(
+                            None,
+                            Level::Error,
+                            format!("Error connecting to server: {}", err),
+                        );
+
+                        if let Some(lost) = &mut lost {
+                            if config.auto_reconnect {
+                                lost.attempt += 1;
+                                retry_at = Some(schedule(screen, lost.attempt));
+                            }
+                        }
                     }
                 }
             }
-            Event::Update(update) => {
-                let update = match update {
-                    Ok(update) => update,
+            Event::TypingTimeout => {
+                typing_until = None;
+
+                if let Some(state) = &mut state {
+                    state.set_typing(None);
+                }
+            }
+            Event::Progress => {
+                screen.set_progress(state.as_ref().and_then(State::progress));
+            }
+            Event::Log(record) => {
+                let level = match record.level {
+                    tracing::Level::ERROR | tracing::Level::WARN => Level::Error,
+                    _ => Level::Info,
+                };
+
+                // Fields may come from the server.
+                screen.log_to(None, level, record.text.term_safe().to_string());
+            }
+            Event::Reconnect => {
+                retry_at = None;
+
+                let lost = match &lost {
+                    Some(lost) => lost,
+                    None => continue,
+                };
+
+                screen.log_to(None, Level::Info, "Attempting to reconnect to server");
+                connect(&lost.server, Some(lost.resume_token), sender.clone());
+                connecting = Some(lost.server.clone());
+            }
+            Event::Connection(event) => {
+                let event = match event {
+                    Ok(event) => event,
                     Err(err) => {
-                        screen.log(Level::Error, format!("Disconnected: {}", err));
-                        state = None;
+                        screen.log_to(None, Level::Error, format!("Disconnected: {}", err));
+
+                        let (new, _) = Lost::new(state.take().unwrap());
+                        lost = Some(new);
+                        screen.clear_typing();
+                        screen.set_progress(None);
+
+                        if config.auto_reconnect {
+                            retry_at = Some(schedule(screen, 0));
+                        }
+
                         continue;
                     }
                 };
 
                 let state = state.as_mut().unwrap();
 
+                let update = match event {
+                    ConnectionEvent::Update(update) => update,
+                    ConnectionEvent::JoinedGroup { gid, name } => {
+                        let group = state.groups.entry(gid).or_insert(Group {
+                            name: name.clone(),
+                            users: BTreeMap::new(),
+                            owned: HashSet::new(),
+                            typing: BTreeSet::new(),
+                            joined: false,
+                        });
+
+                        group.joined = true;
+
+                        screen.open_buffer(&group.name);
+                        screen.log(
+                            Level::Info,
+                            format!("Joined group {}", group.name.term_safe()),
+                        );
+
+                        for user in state.joining.remove(&name).unwrap_or_default() {
+                            state.connection.send(Request::InitUser { gid, name: user });
+                        }
+
+                        continue;
+                    }
+                    ConnectionEvent::InitUser { gid, uid } => {
+                        if let Some(group) = state.groups.get_mut(&gid) {
+                            group.owned.insert(uid);
+                        }
+
+                        continue;
+                    }
+                    ConnectionEvent::Attachment { id, data } => {
+                        let download = state.downloads.pop_front().unwrap();
+                        debug_assert_eq!(download.attachment.id, id);
+
+                        // Attachments arrive in the order they were requested.
+                        if let Some(next) = state.downloads.front_mut() {
+                            next.start = state.connection.received_bytes().get();
+                        }
+
+                        screen.set_progress(state.progress());
+
+                        if !download.cancelled {
+                            save(screen, &download, data).await;
+                        }
+
+                        continue;
+                    }
+                };
+
                 match update.kind {
                     UpdateKind::InitGroup { name } => {
                         let group = state.groups.entry(update.gid).or_insert(Group {
                             name,
                             users: BTreeMap::new(),
                             owned: HashSet::new(),
+                            typing: BTreeSet::new(),
                             joined: false,
                         });
 
-                        screen.log(Level::Info, format!("[{}] created", group.name.term_safe()));
+                        screen.log_to(
+                            None,
+                            Level::Info,
+                            format!("[{}] created", group.name.term_safe()),
+                        );
                     }
                     UpdateKind::DestroyGroup => {
                         let group = state.groups.remove(&update.gid).unwrap();
 
-                        screen.log(
+                        screen.log_to(
+                            None,
                             Level::Info,
                             format!("[{}] destroyed", group.name.term_safe()),
                         );
+                        screen.close_buffer(&group.name);
                     }
                     UpdateKind::InitUser { uid, name } => {
                         let group = state.groups.get_mut(&update.gid).unwrap();
 
-                        screen.log(
-                            Level::Info,
-                            format!(
-                                "[{}] {} ({}): joined",
-                                group.name.term_safe(),
-                                name.term_safe().bold(),
-                                uid,
-                            ),
-                        );
-
                         let owned = group.owned.remove(&uid);
+
+                        if !filter.hides(&group.name, Some(&name))
+                            && filter.shows_presence(&group.name, owned, None)
+                        {
+                            screen.log_to(
+                                Some(&group.name),
+                                Level::Info,
+                                format!(
+                                    "{}: joined",
+                                    config.nicks.format(screen.theme(), update.gid, uid, &name)
+                                ),
+                            );
+                        }
+
                         if owned && state.current.is_none() {
                             state.current = Some((update.gid, uid));
                         }
 
-                        group.users.insert(uid, User { name, owned });
+                        group.users.insert(
+                            uid,
+                            User {
+                                name,
+                                owned,
+                                spoke: None,
+                            },
+                        );
                     }
                     UpdateKind::DestroyUser { uid } => {
                         let group = state.groups.get_mut(&update.gid).unwrap();
-                        let name = group.users.remove(&uid).unwrap().name;
+                        let user = group.users.remove(&uid).unwrap();
+                        let name = user.name;
 
-                        screen.log(
-                            Level::Info,
-                            format!(
-                                "[{}] {} ({}): left",
-                                group.name.term_safe(),
-                                name.term_safe().bold(),
-                                uid
-                            ),
-                        );
+                        if group.typing.remove(&uid) {
+                            show_typing(screen, group, &filter);
+                        }
+
+                        let spoke = user.spoke.map(|at| at.elapsed());
+                        if !filter.hides(&group.name, Some(&name))
+                            && filter.shows_presence(&group.name, user.owned, spoke)
+                        {
+                            screen.log_to(
+                                Some(&group.name),
+                                Level::Info,
+                                format!(
+                                    "{}: left",
+                                    config.nicks.format(screen.theme(), update.gid, uid, &name)
+                                ),
+                            );
+                        }
                     }
                     UpdateKind::Rename { uid, name } => {
                         let group = state.groups.get_mut(&update.gid).unwrap();
-                        let old_name = mem::replace(
-                            &mut group.users.get_mut(&uid).unwrap().name,
-                            name.clone(),
-                        );
+                        let user = group.users.get_mut(&uid).unwrap();
+                        let old_name = mem::replace(&mut user.name, name.clone());
+                        let (owned, spoke) = (user.owned, user.spoke.map(|at| at.elapsed()));
 
-                        screen.log(
+                        if group.typing.contains(&uid) {
+                            show_typing(screen, group, &filter);
+                        }
+
+                        // Hidden if either name is ignored, so that renames don't reveal users.
+                        if filter.hides(&group.name, Some(&old_name))
+                            || filter.hides(&group.name, Some(&name))
+                            || !filter.shows_presence(&group.name, owned, spoke)
+                        {
+                            continue;
+                        }
+
+                        screen.log_to(
+                            Some(&group.name),
                             Level::Info,
                             format!(
-                                "[{}] {} ({}): renamed to {}",
-                                group.name.term_safe(),
-                                old_name.term_safe().bold(),
-                                uid,
-                                name.term_safe().bold()
+                                "{}: renamed to {}",
+                                config
+                                    .nicks
+                                    .format(screen.theme(), update.gid, uid, &old_name),
+                                config.nicks.name(screen.theme(), update.gid, uid, &name)
                             ),
                         );
                     }
                     UpdateKind::Message { uid, message } => {
                         let group = state.groups.get_mut(&update.gid).unwrap();
-                        let user = &group.users.get(&uid).unwrap().name;
 
-                        screen.log(
-                            Level::Info,
+                        // A message ends typing, even if its sender doesn't say so.
+                        if group.typing.remove(&uid) {
+                            show_typing(screen, group, &filter);
+                        }
+
+                        group.users.get_mut(&uid).unwrap().spoke = Some(Instant::now());
+
+                        let sender = group.users.get(&uid).unwrap();
+                        let user = &sender.name;
+
+                        // Attachments of hidden messages are never downloaded.
+                        if filter.hides(&group.name, Some(user)) {
+                            for attachment in message.attachments {
+                                state
+                                    .connection
+                                    .send(Request::IgnoreAttachment { id: attachment.id });
+                            }
+
+                            continue;
+                        }
+
+                        // Our own messages are never highlighted.
+                        let highlight = !sender.owned
+                            && group
+                                .users
+                                .values()
+                                .filter(|user| user.owned)
+                                .map(|user| &user.name)
+                                .chain(&highlights)
+                                .any(|word| highlight::mentions(&message.text, word));
+
+                        // Messages are unseen if the terminal is unfocused or the buffer hidden.
+                        let unseen =
+                            !dnd && (!screen.focused() || screen.buffer() != Some(&group.name));
+
+                        if unseen && highlight && config.bell {
+                            screen.bell()?;
+                        }
+
+                        #[cfg(feature = "notify")]
+                        if unseen
+                            && (highlight && config.notify || notify_groups.contains(&group.name))
+                        {
+                            notify::send(
+                                format!("{} in {}", user, group.name),
+                                message.text.clone(),
+                            );
+                        }
+
+                        // Newlines are kept, the log breaks lines at them.
+                        let text = message
+                            .text
+                            .split('\n')
+                            .map(|line| match styling {
+                                true => markup::render(line, screen.theme()),
+                                false => line.term_safe().to_string(),
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n");
+
+                        let level = if highlight {
+                            Level::Highlight
+                        } else {
+                            Level::Info
+                        };
+
+                        screen.log_to(
+                            Some(&group.name),
+                            level,
                             format!(
-                                "[{}] {} ({}): {}",
-                                group.name.term_safe(),
-                                user.term_safe().bold(),
-                                uid,
-                                message.text.term_safe()
+                                "{}: {}",
+                                config.nicks.format(screen.theme(), update.gid, uid, user),
+                                text
                             ),
                         );
 
+                        // Downloads need all of the state.
+                        let (group, user) = (group.name.clone(), user.clone());
+
                         for attachment in message.attachments {
-                            screen.log(
+                            screen.log_to(
+                                Some(&group),
                                 Level::Info,
                                 format!(
-                                    "[{}] {} ({}): attachment {}, size {} b",
-                                    group.name.term_safe(),
-                                    user.term_safe().bold(),
-                                    uid,
+                                    "{}: attachment {}, size {} b",
+                                    config.nicks.format(screen.theme(), update.gid, uid, &user),
                                     attachment.id,
                                     attachment.size
                                 ),
                             );
 
-                            state.client.ignore_attachment(attachment.id).await?;
+                            let attachment = PendingAttachment {
+                                id: attachment.id,
+                                size: attachment.size,
+                                group: group.clone(),
+                            };
+
+                            if let Some(dir) = &config.download_dir {
+                                let path = dir.join(file_name(attachment.id));
+                                state.download(attachment, path);
+                                continue;
+                            }
+
+                            // Attachments are kept by the server until downloaded or ignored.
+                            if state.attachments.len() == MAX_PENDING_ATTACHMENTS {
+                                let oldest = state.attachments.pop_front().unwrap();
+                                state
+                                    .connection
+                                    .send(Request::IgnoreAttachment { id: oldest.id });
+                            }
+
+                            state.attachments.push_back(attachment);
                         }
                     }
                     UpdateKind::StartTyping { uid } => {
-                        let group = state.groups.get(&update.gid).unwrap();
-                        let user = &group.users.get(&uid).unwrap().name;
+                        let group = state.groups.get_mut(&update.gid).unwrap();
 
-                        screen.log(
-                            Level::Info,
-                            format!(
-                                "[{}] {} ({}): typing",
-                                group.name.term_safe(),
-                                user.term_safe().bold(),
-                                uid
-                            ),
-                        );
+                        if group.typing.insert(uid) {
+                            show_typing(screen, group, &filter);
+                        }
                     }
                     UpdateKind::StopTyping { uid } => {
-                        let group = state.groups.get(&update.gid).unwrap();
-                        let user = &group.users.get(&uid).unwrap().name;
+                        let group = state.groups.get_mut(&update.gid).unwrap();
 
-                        screen.log(
-                            Level::Info,
-                            format!(
-                                "[{}] {} ({}): stopped typing",
-                                group.name.term_safe(),
-                                user.term_safe().bold(),
-                                uid
-                            ),
-                        );
+                        if group.typing.remove(&uid) {
+                            show_typing(screen, group, &filter);
+                        }
                     }
                 }
             }
@@ -472,26 +1206,441 @@ pub async fn run(screen: &mut Screen) -> Result<(), Error> {
     }
 }
 
+fn connect(
+    server: &Server,
+    resume: Option<ResumeToken>,
+    sender: mpsc::Sender<Result<MaybeTlsClient, ConnectError>>,
+) {
+    let server = server.clone();
+
+    tokio::spawn(async move {
+        let connect = async {
+            let connector = match &server.certificate {
+                Some(certificate) => Some(tls::configure(certificate).await?),
+                None => None,
+            };
+
+            let mut proto_config = ProtoConfig::default();
+            proto_config.max_size(512 * 1024 * 1024); // 512 MiB, for attachments.
+
+            let client = ClientBuilder::maybe_tls(connector)
+                .config(proto_config)
+                .resume(resume)
+                .connect(&*server.address, server.access_token)
+                .await?;
+
+            Ok(client)
+        };
+
+        tokio::select! {
+            result = connect => {
+                let _ = sender.send(result).await;
+            }
+            _ = sender.closed() => {}
+        }
+    });
+}
+
+// Lists all groups and their users in the sidebar, marking joined groups and our users.
+fn update_sidebar(screen: &mut Screen, state: Option<&State>) {
+    let groups = state.into_iter().flat_map(|state| state.groups.values());
+
+    let entries = groups
+        .clone()
+        .map(|group| Entry {
+            name: group.name.clone(),
+            marked: group.joined,
+        })
+        .collect();
+
+    let users = groups
+        .map(|group| {
+            let users = group
+                .users
+                .iter()
+                .map(|(uid, user)| Entry {
+                    name: format!("{} ({})", user.name, uid),
+                    marked: user.owned,
+                })
+                .collect();
+
+            (group.name.clone(), users)
+        })
+        .collect();
+
+    screen.set_sidebar(entries, users);
+}
+
+// Shows who is typing in a group, leaving out our own and hidden users.
+fn show_typing(screen: &mut Screen, group: &Group, filter: &Filter) {
+    let names = group
+        .typing
+        .iter()
+        .filter_map(|uid| group.users.get(uid))
+        .filter(|user| !user.owned && !filter.hides(&group.name, Some(&user.name)))
+        .map(|user| user.name.clone())
+        .collect();
+
+    screen.set_typing(&group.name, names);
+}
+
+// Saves muted groups and ignored users to the config, if there is one.
+async fn save_filter(screen: &mut Screen, path: Option<&Path>, filter: &Filter) {
+    let path = match path {
+        Some(path) => path,
+        None => return,
+    };
+
+    if let Err(err) = config::save_filter(path, filter).await {
+        screen.log(
+            Level::Error,
+            format!("Error saving config {}: {}", path.display(), err),
+        );
+    }
+}
+
+// Picks when to reconnect, waiting twice as long after every failed attempt.
+fn schedule(screen: &mut Screen, attempt: u32) -> Instant {
+    let delay = Duration::from_secs(1 << attempt.min(6)).min(MAX_RECONNECT_DELAY);
+
+    screen.log_to(
+        None,
+        Level::Info,
+        format!("Reconnecting in {} s", delay.as_secs()),
+    );
+
+    Instant::now() + delay
+}
+
+// Reads an access token from a file, surrounding whitespace is ignored.
+async fn read_token(path: &Path) -> Result<AccessToken, Error> {
+    fs::read_to_string(path)
+        .await?
+        .trim()
+        .parse()
+        .map_err(|_| Error::new(io::ErrorKind::InvalidData, "Invalid access token"))
+}
+
+// Shuts a connection down, giving up on the server after a while.
+async fn disconnect(connection: Connection) {
+    let _ = time::timeout(SHUTDOWN_TIMEOUT, connection.shutdown()).await;
+}
+
+// Writes a downloaded attachment to its file.
+async fn save(screen: &mut Screen, download: &Download, data: Vec<u8>) {
+    let attachment = &download.attachment;
+    let group = Some(&*attachment.group);
+
+    match fs::write(&download.path, data).await {
+        Ok(()) => screen.log_to(
+            group,
+            Level::Info,
+            format!(
+                "Saved attachment {} to {}",
+                attachment.id,
+                download.path.display()
+            ),
+        ),
+        Err(err) => screen.log_to(
+            group,
+            Level::Error,
+            format!("Error saving attachment {}: {}", attachment.id, err),
+        ),
+    }
+}
+
+// Attachments carry no name, so one is made up. IDs are reused, hence the timestamp.
+fn file_name(id: u32) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    format!("attachment-{}-{}", timestamp, id)
+}
+
+fn candidates(
+    state: Option<&State>,
+    aliases: &BTreeMap<String, String>,
+    emoji: bool,
+    input: &str,
+) -> Vec<String> {
+    // Arguments of aliases are completed as those of the commands they stand for.
+    let input = if input
+        .trim_start()
+        .contains(|c: char| c.is_ascii_whitespace())
+    {
+        command::expand(input, aliases)
+    } else {
+        Cow::Borrowed(input)
+    };
+
+    let completion = match Completion::parse(&input) {
+        Some(completion) => completion,
+        None => return Vec::new(),
+    };
+
+    let groups = state.into_iter().flat_map(|state| state.groups.values());
+    let mut candidates: Vec<_> = match completion {
+        Completion::Command(prefix) => COMMANDS
+            .iter()
+            .map(|info| info.name)
+            .chain(aliases.keys().map(|name| &**name))
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| format!("/{}", name))
+            .collect(),
+        Completion::Group { prefix, joined } => groups
+            .filter(|group| (group.joined || !joined) && group.name.starts_with(prefix))
+            .map(|group| command::quote(&group.name).into_owned())
+            .collect(),
+        // Users are matched by both name and uid, but only our own can be acted upon.
+        Completion::User { group, prefix } => groups
+            .filter(|g| g.name == group)
+            .flat_map(|group| &group.users)
+            .filter(|(uid, user)| {
+                user.owned && (user.name.starts_with(prefix) || uid.to_string().starts_with(prefix))
+            })
+            .map(|(uid, _)| uid.to_string())
+            .collect(),
+        Completion::Emoji(prefix) if emoji => emoji::complete(prefix),
+        Completion::Emoji(_) => Vec::new(),
+    };
+
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
 enum Event {
     Screen(ScreenEvent),
-    Connect(Result<BasicClient, BasicConnectError>),
-    Update(Result<Update, Error>),
+    Connect(Result<MaybeTlsClient, ConnectError>),
+    Connection(Result<ConnectionEvent, Error>),
+    Reconnect,
+    TypingTimeout,
+    // Progress of the current download is shown again.
+    Progress,
+    Log(Record),
 }
 
 struct State {
+    server: Server,
     groups: BTreeMap<u32, Group>,
-    client: BasicClient,
+    connection: Connection,
+    // Groups being joined, with names of users to create in them once they are.
+    joining: BTreeMap<String, Vec<String>>,
     current: Option<(u32, u32)>, // (gid, uid)
+    // User of ours we told the server is typing.
+    typing: Option<(u32, u32)>,
+    // Oldest first.
+    attachments: VecDeque<PendingAttachment>,
+    // In the order they were requested, the first is being received.
+    downloads: VecDeque<Download>,
+}
+
+struct PendingAttachment {
+    id: u32,
+    size: u64,
+    group: String,
+}
+
+struct Download {
+    attachment: PendingAttachment,
+    path: PathBuf,
+    // Bytes received before the download started.
+    start: u64,
+    // Received data is thrown away.
+    cancelled: bool,
+}
+
+impl State {
+    // Joins a group unless it's joined already, creating a user in it if a name is given.
+    fn join(&mut self, name: &str, user: Option<&str>) {
+        let joined = self
+            .groups
+            .iter()
+            .find(|(_, g)| name == g.name && g.joined)
+            .map(|(gid, _)| *gid);
+
+        if let Some(gid) = joined {
+            if let Some(user) = user {
+                self.connection.send(Request::InitUser {
+                    gid,
+                    name: user.to_owned(),
+                });
+            }
+
+            return;
+        }
+
+        // Joining a group twice gets us disconnected.
+        let users = match self.joining.entry(name.to_owned()) {
+            MapEntry::Occupied(entry) => entry.into_mut(),
+            MapEntry::Vacant(entry) => {
+                self.connection.send(Request::JoinGroup {
+                    name: name.to_owned(),
+                });
+
+                entry.insert(Vec::new())
+            }
+        };
+
+        users.extend(user.map(str::to_owned));
+    }
+
+    // Requests an attachment to be saved to a file once it's downloaded.
+    fn download(&mut self, attachment: PendingAttachment, path: PathBuf) {
+        self.connection
+            .send(Request::DownloadAttachment { id: attachment.id });
+
+        self.downloads.push_back(Download {
+            attachment,
+            path,
+            start: self.connection.received_bytes().get(),
+            cancelled: false,
+        });
+    }
+
+    // How far along the download being received is.
+    fn progress(&self) -> Option<Progress> {
+        let download = self.downloads.front().filter(|d| !d.cancelled)?;
+        let received = self.connection.received_bytes().get();
+
+        Some(Progress {
+            label: format!("attachment {}", download.attachment.id),
+            done: received - download.start,
+            total: download.attachment.size,
+        })
+    }
+
+    // Tells the server which of our users is typing, if any, stopping the previous one.
+    fn set_typing(&mut self, typing: Option<(u32, u32)>) {
+        if self.typing == typing {
+            return;
+        }
+
+        // The user may be gone already, stopping its typing would be an error.
+        if let Some((gid, uid)) = self.typing.take() {
+            let exists = self
+                .groups
+                .get(&gid)
+                .is_some_and(|group| group.users.contains_key(&uid));
+
+            if exists {
+                self.connection.send(Request::StopTyping { gid, uid });
+            }
+        }
+
+        if let Some((gid, uid)) = typing {
+            self.connection.send(Request::StartTyping { gid, uid });
+            self.typing = typing;
+        }
+    }
+
+    // Takes over the groups and users of a resumed session, whose IDs are kept.
+    fn resume(&mut self, lost: Lost) {
+        // Every existing group is announced again, only memberships need to be carried over.
+        for (gid, mut group) in lost.groups.into_iter().filter(|(_, group)| group.joined) {
+            group.typing.clear();
+
+            // Users are sent again, our own are recognized by their IDs.
+            group.owned = mem::take(&mut group.users)
+                .into_iter()
+                .filter(|(_, user)| user.owned)
+                .map(|(uid, _)| uid)
+                .chain(group.owned)
+                .collect();
+
+            self.groups.insert(gid, group);
+        }
+
+        self.current = lost.current;
+    }
+
+    // Joins the groups of a session which couldn't be resumed again and recreates our users.
+    fn rejoin(&mut self, lost: Lost) {
+        for group in lost.groups.values().filter(|group| group.joined) {
+            let users: Vec<_> = group
+                .users
+                .values()
+                .filter(|user| user.owned)
+                .map(|user| &*user.name)
+                .collect();
+
+            if users.is_empty() {
+                self.join(&group.name, None);
+            }
+
+            for user in users {
+                self.join(&group.name, Some(user));
+            }
+        }
+    }
+
+    // User to send messages as. Prefers the active user, unless a buffer of another group is
+    // shown, in which case any user of ours in that group is used.
+    fn sender(&self, buffer: Option<&str>) -> Option<(u32, u32)> {
+        let name = match buffer {
+            Some(name) => name,
+            None => return self.current,
+        };
+
+        let (gid, group) = self.groups.iter().find(|(_, g)| g.name == name)?;
+        match self.current {
+            Some((current, uid)) if current == *gid => Some((current, uid)),
+            _ => group
+                .users
+                .iter()
+                .find(|(_, user)| user.owned)
+                .map(|(uid, _)| (*gid, *uid)),
+        }
+    }
+}
+
+// What is needed to connect again after losing the connection.
+struct Lost {
+    server: Server,
+    resume_token: ResumeToken,
+    groups: BTreeMap<u32, Group>,
+    current: Option<(u32, u32)>,
+    // Number of failed attempts to reconnect.
+    attempt: u32,
+}
+
+impl Lost {
+    // Keeps what is needed from a connection's state and hands back its connection.
+    fn new(state: State) -> (Self, Connection) {
+        let lost = Self {
+            server: state.server,
+            resume_token: state.connection.resume_token(),
+            groups: state.groups,
+            current: state.current,
+            attempt: 0,
+        };
+
+        (lost, state.connection)
+    }
 }
 
 struct Group {
     name: String,
     users: BTreeMap<u32, User>,
     owned: HashSet<u32>,
+    // Users which started typing and haven't stopped yet.
+    typing: BTreeSet<u32>,
     joined: bool,
 }
 
 struct User {
     name: String,
     owned: bool, // Did we create this user?
+    // When the user last sent a message.
+    spoke: Option<Instant>,
+}
+
+#[derive(Error, Debug)]
+enum ConnectError {
+    #[error("Error configuring TLS: {0}")]
+    Tls(#[from] tls::Error),
+    #[error(transparent)]
+    Connect(#[from] multichat_client::ConnectError<io::Error>),
 }
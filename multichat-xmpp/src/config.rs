@@ -0,0 +1,56 @@
+use multichat_client::proto::AccessToken;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    pub xmpp: Xmpp,
+    pub multichat: Multichat,
+    pub rooms: Vec<Room>,
+}
+
+/// The bridge connects to the XMPP server as an external component.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Xmpp {
+    /// Address the server accepts components on.
+    pub server: String,
+    /// Domain of the component, Multichat users are occupants of rooms with addresses in it.
+    pub domain: String,
+    pub secret: String,
+    /// Nick of the bridge itself in rooms.
+    #[serde(default = "default_nick")]
+    pub nick: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Multichat {
+    pub server: String,
+    pub access_token: AccessToken,
+    pub certificate: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Room {
+    pub multichat_group: String,
+    /// Bare address of a multi-user chat room.
+    pub xmpp_room: String,
+}
+
+fn default_nick() -> String {
+    "multichat".to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_parses() {
+        let config = include_str!("../example/config.toml");
+        toml::from_str::<Config>(config).unwrap();
+    }
+}
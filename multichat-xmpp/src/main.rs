@@ -0,0 +1,183 @@
+mod config;
+mod multichat;
+mod tls;
+mod xhtml;
+mod xml;
+mod xmpp;
+
+use clap::Parser;
+use config::Config;
+use multichat::Addresses;
+use multichat_client::proto::Config as ProtoConfig;
+use multichat_client::ClientBuilder;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tokio::fs;
+use tokio::sync::watch;
+use tracing::subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+use xmpp::Component;
+
+#[derive(Parser)]
+struct Args {
+    #[clap(help = "Path to config file")]
+    config: PathBuf,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().without_time().with_target(false));
+
+    subscriber::set_global_default(registry).unwrap();
+
+    let args = Args::parse();
+
+    tracing::info!("Reading config from {}", args.config.display());
+
+    let config = match fs::read_to_string(&args.config).await {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error reading config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let config = match toml::from_str::<Config>(&config) {
+        Ok(config) => config,
+        Err(err) => {
+            tracing::error!("Error parsing config: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let connector = match config.multichat.certificate {
+        Some(certificate) => match tls::configure(&certificate).await {
+            Ok(connector) => Some(connector),
+            Err(err) => {
+                tracing::error!("Error configuring TLS: {}", err);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    let mut proto_config = ProtoConfig::default();
+    proto_config.max_size(512 * 1024 * 1024); // 512 MiB
+
+    let mut client = match ClientBuilder::maybe_tls(connector)
+        .config(proto_config)
+        .connect(&config.multichat.server, config.multichat.access_token)
+        .await
+    {
+        Ok(client) => client,
+        Err(err) => {
+            tracing::error!("Error connecting to multichat: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing::info!("Connected to Multichat");
+
+    let mut room_to_group = HashMap::new();
+    let mut group_to_room = HashMap::new();
+
+    for room in config.rooms {
+        let gid = match client.join_group(&room.multichat_group).await {
+            Ok(gid) => gid,
+            Err(err) => {
+                tracing::error!("Error joining group: {}", err);
+                return ExitCode::FAILURE;
+            }
+        };
+
+        if room_to_group.contains_key(&room.xmpp_room) {
+            tracing::error!(
+                "XMPP room {} is already associated with a Multichat group",
+                room.xmpp_room
+            );
+
+            return ExitCode::FAILURE;
+        }
+
+        if group_to_room.contains_key(&gid) {
+            tracing::error!(
+                "Multichat group {} is already associated with an XMPP room",
+                room.multichat_group
+            );
+
+            return ExitCode::FAILURE;
+        }
+
+        room_to_group.insert(room.xmpp_room.clone(), gid);
+        group_to_room.insert(gid, room.xmpp_room);
+    }
+
+    let xmpp = config.xmpp;
+    let component = match Component::connect(&xmpp.server, &xmpp.domain, &xmpp.secret).await {
+        Ok(component) => component,
+        Err(err) => {
+            tracing::error!("Error connecting to XMPP server: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    tracing::info!("Connected to XMPP server");
+
+    let addresses = Addresses {
+        bridge: format!("bridge@{}", xmpp.domain),
+        nick: xmpp.nick,
+        domain: xmpp.domain,
+    };
+
+    let shutdown_signal = match multichat_client::shutdown_signal() {
+        Ok(signal) => signal,
+        Err(err) => {
+            tracing::error!("Error handling signals: {}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (shutdown_sender, shutdown) = watch::channel(());
+
+    let mut multichat = tokio::spawn(async move {
+        multichat::run(
+            client,
+            component,
+            &addresses,
+            &room_to_group,
+            &group_to_room,
+            shutdown,
+        )
+        .await
+    });
+
+    let result = tokio::select! {
+        result = &mut multichat => Some(result.unwrap()),
+        _ = shutdown_signal => None,
+    };
+
+    tracing::info!("Shutting down");
+
+    let _ = shutdown_sender.send(());
+    let result = match result {
+        Some(result) => result,
+        None => multichat.await.unwrap(),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            tracing::error!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
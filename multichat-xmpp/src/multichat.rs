@@ -0,0 +1,319 @@
+use multichat_client::{MaybeTlsClient, Update, UpdateKind};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use thiserror::Error;
+use tokio::sync::watch;
+
+use crate::xhtml::{self, XHTML, XHTML_IM};
+use crate::xml::Element;
+use crate::xmpp::{self, Component, DELAY};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Xmpp(#[from] xmpp::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Addresses the bridge uses in the component's domain.
+pub struct Addresses {
+    /// Occupant of every room, which receives what happens in them.
+    pub bridge: String,
+    pub nick: String,
+    pub domain: String,
+}
+
+impl Addresses {
+    // Every Multichat user joins the room of their group as an occupant of their own.
+    fn puppet(&self, gid: u32, uid: u32) -> String {
+        format!("{}.{}@{}", gid, uid, self.domain)
+    }
+}
+
+pub async fn run(
+    mut client: MaybeTlsClient,
+    mut component: Component,
+    addresses: &Addresses,
+    room_to_group: &HashMap<String, u32>,
+    group_to_room: &HashMap<u32, String>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<(), Error> {
+    let mut occupants = HashMap::<(String, String), u32>::new();
+    let mut owned = HashSet::new();
+    let mut groups = group_to_room
+        .keys()
+        .map(|gid| (*gid, HashMap::new()))
+        .collect::<HashMap<_, HashMap<u32, MultichatUser>>>();
+
+    for room in group_to_room.values() {
+        let join = xmpp::join(&addresses.bridge, room, &addresses.nick);
+        component.send(&join).await?;
+    }
+
+    loop {
+        let event = tokio::select! {
+            stanza = component.read() => Event::Xmpp(stanza?),
+            update = client.read_update() => Event::Multichat(update?),
+            _ = shutdown.changed() => break,
+        };
+
+        let stanza = match event {
+            Event::Xmpp(stanza) => stanza,
+            Event::Multichat(Update {
+                kind: UpdateKind::InitGroup { .. } | UpdateKind::DestroyGroup,
+                ..
+            }) => continue,
+            Event::Multichat(update) => {
+                let gid = update.gid;
+                let group = groups.get_mut(&gid).unwrap();
+                let room = &group_to_room[&gid];
+
+                match update.kind {
+                    UpdateKind::InitUser { uid, name } => {
+                        let owned = owned.remove(&(gid, uid));
+                        if !owned {
+                            let join = xmpp::join(&addresses.puppet(gid, uid), room, &name);
+                            component.send(&join).await?;
+                        }
+
+                        group.insert(
+                            uid,
+                            MultichatUser {
+                                name,
+                                owned,
+                                joined: false,
+                            },
+                        );
+                    }
+                    UpdateKind::DestroyUser { uid } => {
+                        let user = group.remove(&uid).unwrap();
+                        if !user.owned {
+                            let leave = xmpp::leave(&addresses.puppet(gid, uid), room, &user.name);
+                            component.send(&leave).await?;
+                        }
+                    }
+                    UpdateKind::Rename { uid, name } => {
+                        let user = group.get_mut(&uid).unwrap();
+                        if !user.owned {
+                            let change =
+                                xmpp::change_nick(&addresses.puppet(gid, uid), room, &name);
+                            component.send(&change).await?;
+                        }
+
+                        user.name = name;
+                    }
+                    UpdateKind::Message { uid, message } => {
+                        let user = &group[&uid];
+
+                        // Files would have to be uploaded to the server (XEP-0363), which
+                        // components can't do on behalf of users.
+                        for attachment in &message.attachments {
+                            client.ignore_attachment(attachment.id).await?;
+                        }
+
+                        if user.owned {
+                            continue;
+                        }
+
+                        let mut text = message.text;
+                        if !message.attachments.is_empty() {
+                            if !text.is_empty() {
+                                text.push('\n');
+                            }
+
+                            text.push_str(&format!(
+                                "({} attachments not bridged)",
+                                message.attachments.len()
+                            ));
+                        }
+
+                        // Messages are sent by the bridge if the user couldn't join, such as
+                        // when their name is taken.
+                        let (from, text) = match user.joined {
+                            true => (addresses.puppet(gid, uid), text),
+                            false => (
+                                addresses.bridge.clone(),
+                                format!("*{}*: {}", user.name, text),
+                            ),
+                        };
+
+                        component.send(&groupchat(&from, room, &text)).await?;
+                    }
+                    UpdateKind::InitGroup { .. }
+                    | UpdateKind::DestroyGroup
+                    | UpdateKind::StartTyping { .. }
+                    | UpdateKind::StopTyping { .. } => {}
+                }
+
+                continue;
+            }
+        };
+
+        let to = stanza.get_attr("to").unwrap_or_default();
+        let from = stanza.get_attr("from").unwrap_or_default();
+        let (room, nick) = match xmpp::split_jid(from) {
+            (room, Some(nick)) => (room, nick),
+            (_, None) => continue,
+        };
+
+        let gid = match room_to_group.get(room) {
+            Some(gid) => *gid,
+            None => continue,
+        };
+
+        let group = groups.get_mut(&gid).unwrap();
+
+        // Puppets only care about whether they joined, the bridge receives everything else too.
+        let to = xmpp::split_jid(to).0;
+        if to != addresses.bridge {
+            let user = group
+                .iter_mut()
+                .find(|(uid, user)| !user.owned && addresses.puppet(gid, **uid) == to);
+
+            if let (Some((_, user)), "presence") = (user, stanza.name.as_str()) {
+                match stanza.get_attr("type") {
+                    None if xmpp::status_codes(&stanza).contains(&"110") => user.joined = true,
+                    Some("error") => {
+                        tracing::warn!(%room, name = %user.name, "User couldn't join room");
+                        user.joined = false;
+                    }
+                    _ => {}
+                }
+            }
+
+            continue;
+        }
+
+        // Echoes of the bridge and the users it joined.
+        let puppet =
+            nick == addresses.nick || group.values().any(|user| !user.owned && user.name == nick);
+
+        if puppet {
+            if stanza.name == "presence" && stanza.get_attr("type") == Some("error") {
+                tracing::error!(%room, "Error joining room");
+            }
+
+            continue;
+        }
+
+        let key = (room.to_owned(), nick.to_owned());
+
+        match (stanza.name.as_str(), stanza.get_attr("type")) {
+            ("presence", None) => {
+                occupant(&mut client, &mut occupants, &mut owned, gid, key).await?;
+            }
+            ("presence", Some("unavailable")) => {
+                let uid = match occupants.remove(&key) {
+                    Some(uid) => uid,
+                    None => continue,
+                };
+
+                // Occupants changing their nick leave with status 303 and the new nick.
+                let new_nick = stanza
+                    .get_child("x", xmpp::MUC_USER)
+                    .and_then(|x| x.get_child("item", xmpp::MUC_USER))
+                    .and_then(|item| item.get_attr("nick"))
+                    .filter(|_| xmpp::status_codes(&stanza).contains(&"303"));
+
+                match new_nick {
+                    Some(new_nick) => {
+                        client.rename_user(gid, uid, new_nick).await?;
+                        occupants.insert((room.to_owned(), new_nick.to_owned()), uid);
+                    }
+                    None => client.destroy_user(gid, uid).await?,
+                }
+            }
+            ("message", Some("groupchat")) => {
+                // History is sent with a delay, even though none is requested.
+                if stanza.get_child("delay", DELAY).is_some() {
+                    continue;
+                }
+
+                let body = match stanza.get_child("body", "jabber:component:accept") {
+                    Some(body) => body,
+                    None => continue,
+                };
+
+                let text = stanza
+                    .get_child("html", XHTML_IM)
+                    .and_then(|html| html.get_child("body", XHTML))
+                    .map(xhtml::to_markup)
+                    .unwrap_or_else(|| body.text_content());
+
+                let uid = occupant(&mut client, &mut occupants, &mut owned, gid, key).await?;
+
+                client.send_message(gid, uid, &text, &[]).await?;
+            }
+            _ => {}
+        }
+    }
+
+    for (gid, users) in &groups {
+        let room = &group_to_room[gid];
+        for (uid, user) in users.iter().filter(|(_, user)| !user.owned) {
+            let leave = xmpp::leave(&addresses.puppet(*gid, *uid), room, &user.name);
+            component.send(&leave).await?;
+        }
+
+        let leave = xmpp::leave(&addresses.bridge, room, &addresses.nick);
+        component.send(&leave).await?;
+    }
+
+    component.close().await?;
+
+    for ((room, _), uid) in occupants {
+        client.destroy_user(room_to_group[&room], uid).await?;
+    }
+
+    client.shutdown().await?;
+
+    Ok(())
+}
+
+// Multichat user of an occupant of a room, keyed by the room and their nick.
+async fn occupant(
+    client: &mut MaybeTlsClient,
+    occupants: &mut HashMap<(String, String), u32>,
+    owned: &mut HashSet<(u32, u32)>,
+    gid: u32,
+    key: (String, String),
+) -> Result<u32, Error> {
+    let uid = match occupants.entry(key) {
+        Entry::Occupied(entry) => *entry.get(),
+        Entry::Vacant(entry) => {
+            let uid = client.init_user(gid, &entry.key().1).await?;
+            owned.insert((gid, uid));
+            *entry.insert(uid)
+        }
+    };
+
+    Ok(uid)
+}
+
+fn groupchat(from: &str, room: &str, text: &str) -> Element {
+    let mut message = Element::new("message")
+        .attr("from", from)
+        .attr("to", room)
+        .attr("type", "groupchat")
+        .child(Element::new("body").text(text));
+
+    if let Some(html) = xhtml::from_markup(text) {
+        message = message.child(html);
+    }
+
+    message
+}
+
+enum Event {
+    Xmpp(Element),
+    Multichat(Update),
+}
+
+struct MultichatUser {
+    name: String,
+    owned: bool,
+    // Whether the user is an occupant of the room, only known for those not owned.
+    joined: bool,
+}
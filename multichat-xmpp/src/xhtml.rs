@@ -0,0 +1,135 @@
+//! Conversion between XHTML-IM (XEP-0071) and Multichat markup.
+
+use crate::xml::{Element, Node};
+
+use multichat_client::markup;
+
+pub const XHTML_IM: &str = "http://jabber.org/protocol/xhtml-im";
+pub const XHTML: &str = "http://www.w3.org/1999/xhtml";
+
+/// Converts the XHTML body of a message to text with markup, styles which can't be expressed
+/// in it are dropped.
+pub fn to_markup(body: &Element) -> String {
+    let mut text = String::new();
+    push_markup(&mut text, body);
+    text.trim_end_matches('\n').to_owned()
+}
+
+/// XHTML-IM of a message, only if it's styled, since plain messages don't need any.
+pub fn from_markup(text: &str) -> Option<Element> {
+    if !markup::styled(text) {
+        return None;
+    }
+
+    let mut body = Element::new("body").attr("xmlns", XHTML);
+
+    for chunk in markup::parse(text) {
+        let mut lines = chunk.text.split('\n');
+        let mut content = vec![Node::Text(lines.next().unwrap().to_owned())];
+        for line in lines {
+            content.push(Node::Element(Element::new("br")));
+            content.push(Node::Text(line.to_owned()));
+        }
+
+        let styles = [
+            (chunk.style.code, "code"),
+            (chunk.style.italic, "em"),
+            (chunk.style.bold, "strong"),
+        ];
+
+        for (_, name) in styles.iter().filter(|(enabled, _)| *enabled) {
+            let mut element = Element::new(name);
+            element.children = content;
+            content = vec![Node::Element(element)];
+        }
+
+        body.children.extend(content);
+    }
+
+    Some(Element::new("html").attr("xmlns", XHTML_IM).child(body))
+}
+
+fn push_markup(text: &mut String, element: &Element) {
+    for child in &element.children {
+        let element = match child {
+            Node::Text(t) => {
+                text.push_str(t);
+                continue;
+            }
+            Node::Element(element) => element,
+        };
+
+        let marker = match element.name.as_str() {
+            "strong" | "b" => Some('*'),
+            "em" | "i" | "cite" => Some('_'),
+            "code" => Some('`'),
+            "br" => {
+                text.push('\n');
+                continue;
+            }
+            _ => None,
+        };
+
+        match marker {
+            Some(marker) => {
+                text.push(marker);
+                push_markup(text, element);
+                text.push(marker);
+            }
+            None => push_markup(text, element),
+        }
+
+        match element.name.as_str() {
+            "p" | "div" | "li" | "blockquote" | "pre" => text.push('\n'),
+            "a" => {
+                let href = element.get_attr("href");
+                if let Some(href) = href.filter(|href| *href != element.text_content()) {
+                    text.push_str(&format!(" ({})", href));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_markup() {
+        let body = Element::new("body")
+            .child(
+                Element::new("p")
+                    .child(Element::new("strong").text("hi"))
+                    .text(" there"),
+            )
+            .child(
+                Element::new("p")
+                    .child(
+                        Element::new("a")
+                            .attr("href", "https://example.com")
+                            .text("site"),
+                    )
+                    .child(Element::new("br"))
+                    .child(Element::new("em").text("bye")),
+            );
+
+        assert_eq!(
+            to_markup(&body),
+            "*hi* there\nsite (https://example.com)\n_bye_"
+        );
+    }
+
+    #[test]
+    fn converts_from_markup() {
+        assert_eq!(from_markup("snake_case"), None);
+
+        let html = from_markup("*hi*\n_there_").unwrap();
+        let body = html.get_child("body", XHTML).unwrap();
+        assert_eq!(
+            body.to_string(),
+            "<body xmlns='http://www.w3.org/1999/xhtml'><strong>hi</strong><br/><em>there</em></body>"
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Elements of XMPP streams, which are a single document whose top level children are stanzas,
+//! sent as they come.
+//!
+//! Namespace prefixes are kept as a part of names and `xmlns` attributes are ordinary
+//! attributes.
+
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fmt::{self, Display, Formatter};
+use std::str;
+use thiserror::Error;
+use tokio::io::AsyncBufRead;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Xml(#[from] quick_xml::Error),
+    #[error("Invalid UTF-8")]
+    Utf8(#[from] str::Utf8Error),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Element {
+    pub name: String,
+    pub attrs: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn attr(mut self, name: &str, value: &str) -> Self {
+        self.attrs.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    pub fn child(mut self, child: Element) -> Self {
+        self.children.push(Node::Element(child));
+        self
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.children.push(Node::Text(text.to_owned()));
+        self
+    }
+
+    pub fn get_attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(attr, _)| attr == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// First child element with a name in a namespace, which children without `xmlns` are
+    /// assumed to be in.
+    pub fn get_child(&self, name: &str, namespace: &str) -> Option<&Element> {
+        self.elements().find(|child| {
+            child.name == name
+                && child
+                    .get_attr("xmlns")
+                    .is_none_or(|xmlns| xmlns == namespace)
+        })
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// Text of the element and all of its descendants.
+    pub fn text_content(&self) -> String {
+        let mut text = String::new();
+        push_text(&mut text, self);
+        text
+    }
+}
+
+impl Display for Element {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<{}", self.name)?;
+        for (name, value) in &self.attrs {
+            write!(f, " {}='{}'", name, escape(value))?;
+        }
+
+        if self.children.is_empty() {
+            return write!(f, "/>");
+        }
+
+        write!(f, ">")?;
+        for child in &self.children {
+            match child {
+                Node::Element(element) => write!(f, "{}", element)?,
+                Node::Text(text) => write!(f, "{}", escape(text))?,
+            }
+        }
+
+        write!(f, "</{}>", self.name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Item {
+    /// Opening tag of the stream, with its attributes.
+    StreamStart(Element),
+    Stanza(Element),
+    StreamEnd,
+}
+
+/// Parses a stream as it's read.
+pub struct Parser<R> {
+    reader: Reader<R>,
+    buffer: Vec<u8>,
+    // Elements being parsed, the stream element first.
+    open: Vec<Element>,
+}
+
+impl<R: AsyncBufRead + Unpin> Parser<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Reader::from_reader(reader),
+            buffer: Vec::new(),
+            open: Vec::new(),
+        }
+    }
+
+    /// Next item of the stream, none once there's nothing more to read.
+    ///
+    /// Not cancel safe, part of the item could be lost.
+    pub async fn next(&mut self) -> Result<Option<Item>, Error> {
+        loop {
+            self.buffer.clear();
+
+            match self.reader.read_event_into_async(&mut self.buffer).await? {
+                Event::Start(start) => {
+                    let element = element(&start)?;
+                    if self.open.is_empty() {
+                        self.open.push(element.clone());
+                        return Ok(Some(Item::StreamStart(element)));
+                    }
+
+                    self.open.push(element);
+                }
+                Event::Empty(start) => {
+                    if let Some(item) = close(&mut self.open, element(&start)?) {
+                        return Ok(Some(item));
+                    }
+                }
+                Event::End(_) => {
+                    // The reader checks that names of closing tags match.
+                    let element = match self.open.pop() {
+                        Some(element) => element,
+                        None => continue,
+                    };
+
+                    if self.open.is_empty() {
+                        return Ok(Some(Item::StreamEnd));
+                    }
+
+                    if let Some(item) = close(&mut self.open, element) {
+                        return Ok(Some(item));
+                    }
+                }
+                // Whitespace between stanzas is only for keeping the connection alive.
+                Event::Text(text) if self.open.len() > 1 => {
+                    let text = text.unescape()?.into_owned();
+                    let parent = self.open.last_mut().unwrap();
+                    parent.children.push(Node::Text(text));
+                }
+                Event::Eof => return Ok(None),
+                // There are no comments, DTDs or CDATA in XMPP, the declaration is skipped.
+                _ => {}
+            }
+        }
+    }
+}
+
+// Adds a complete element to its parent, or returns it if it's a stanza.
+fn close(open: &mut [Element], element: Element) -> Option<Item> {
+    if open.len() == 1 {
+        return Some(Item::Stanza(element));
+    }
+
+    let parent = open.last_mut()?;
+    parent.children.push(Node::Element(element));
+
+    None
+}
+
+fn element(start: &BytesStart) -> Result<Element, Error> {
+    let mut element = Element::new(str::from_utf8(start.name().as_ref())?);
+    for attr in start.attributes() {
+        let attr = attr.map_err(quick_xml::Error::from)?;
+        element.attrs.push((
+            str::from_utf8(attr.key.as_ref())?.to_owned(),
+            attr.unescape_value()?.into_owned(),
+        ));
+    }
+
+    Ok(element)
+}
+
+fn push_text(text: &mut String, element: &Element) {
+    for child in &element.children {
+        match child {
+            Node::Element(element) => push_text(text, element),
+            Node::Text(t) => text.push_str(t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn parses_stream() {
+        let first: &[u8] = b"<?xml version='1.0'?><stream:stream xmlns='jabber:component:accept' \
+            id='a>b'><handshake/> <message from='x'><body>1 &lt; 2 &#x1F600;</bo";
+        let second: &[u8] = b"dy></message></stream:stream>";
+
+        // Received in pieces, the message is split between them.
+        let mut parser = Parser::new(first.chain(second));
+        assert_eq!(
+            parser.next().await.unwrap(),
+            Some(Item::StreamStart(
+                Element::new("stream:stream")
+                    .attr("xmlns", "jabber:component:accept")
+                    .attr("id", "a>b")
+            ))
+        );
+
+        assert_eq!(
+            parser.next().await.unwrap(),
+            Some(Item::Stanza(Element::new("handshake")))
+        );
+
+        assert_eq!(
+            parser.next().await.unwrap(),
+            Some(Item::Stanza(
+                Element::new("message")
+                    .attr("from", "x")
+                    .child(Element::new("body").text("1 < 2 \u{1F600}"))
+            ))
+        );
+
+        assert_eq!(parser.next().await.unwrap(), Some(Item::StreamEnd));
+        assert_eq!(parser.next().await.unwrap(), None);
+    }
+
+    #[test]
+    fn serializes() {
+        let element = Element::new("message")
+            .attr("to", "a'b")
+            .child(Element::new("body").text("<hi> & bye"))
+            .child(Element::new("active"));
+
+        assert_eq!(
+            element.to_string(),
+            "<message to='a&apos;b'><body>&lt;hi&gt; &amp; bye</body><active/></message>"
+        );
+    }
+}
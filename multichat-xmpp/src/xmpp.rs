@@ -0,0 +1,183 @@
+use crate::xml::{self, Element, Item, Parser};
+
+use quick_xml::escape::escape;
+use sha1::{Digest, Sha1};
+use std::io;
+use thiserror::Error;
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::task::JoinHandle;
+
+pub const MUC: &str = "http://jabber.org/protocol/muc";
+pub const MUC_USER: &str = "http://jabber.org/protocol/muc#user";
+pub const DELAY: &str = "urn:xmpp:delay";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Xml(#[from] xml::Error),
+    #[error("Server rejected the component secret")]
+    Auth,
+    #[error("Stream error: {0}")]
+    Stream(String),
+    #[error("Stream ended")]
+    Ended,
+}
+
+/// Connection of an external component (XEP-0114), which gets all stanzas sent to its domain and
+/// can send them from any address in it.
+pub struct Component {
+    writer: OwnedWriteHalf,
+    // Parsing isn't cancel safe, so it's done by a task of its own.
+    items: Receiver<Result<Item, xml::Error>>,
+    reader: JoinHandle<()>,
+}
+
+impl Component {
+    pub async fn connect(server: &str, domain: &str, secret: &str) -> Result<Self, Error> {
+        let (reader, writer) = TcpStream::connect(server).await?.into_split();
+        let (sender, items) = mpsc::channel(1);
+
+        let reader = tokio::spawn(async move {
+            let mut parser = Parser::new(BufReader::new(reader));
+
+            loop {
+                let item = match parser.next().await {
+                    Ok(Some(item)) => Ok(item),
+                    Ok(None) => break,
+                    Err(err) => Err(err),
+                };
+
+                let failed = item.is_err();
+                if sender.send(item).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let mut component = Self {
+            writer,
+            items,
+            reader,
+        };
+
+        let header = format!(
+            "<?xml version='1.0'?><stream:stream xmlns='jabber:component:accept' \
+             xmlns:stream='http://etherx.jabber.org/streams' to='{}'>",
+            escape(domain)
+        );
+
+        component.writer.write_all(header.as_bytes()).await?;
+
+        let id = match component.read_item().await? {
+            Item::StreamStart(header) => header.get_attr("id").unwrap_or_default().to_owned(),
+            _ => return Err(Error::Ended),
+        };
+
+        let hash = Sha1::digest(format!("{}{}", id, secret));
+        component
+            .send(&Element::new("handshake").text(&hex::encode(hash)))
+            .await?;
+
+        match component.read().await {
+            Ok(reply) if reply.name == "handshake" => Ok(component),
+            Ok(_) | Err(Error::Stream(_)) => Err(Error::Auth),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub async fn send(&mut self, stanza: &Element) -> Result<(), Error> {
+        self.writer.write_all(stanza.to_string().as_bytes()).await?;
+
+        Ok(())
+    }
+
+    /// Reads the next stanza, cancel safe.
+    pub async fn read(&mut self) -> Result<Element, Error> {
+        match self.read_item().await? {
+            Item::Stanza(stanza) if stanza.name == "stream:error" => {
+                let condition = stanza.elements().next().map(|e| e.name.clone());
+                Err(Error::Stream(condition.unwrap_or_default()))
+            }
+            Item::Stanza(stanza) => Ok(stanza),
+            Item::StreamStart(_) | Item::StreamEnd => Err(Error::Ended),
+        }
+    }
+
+    pub async fn close(mut self) -> Result<(), Error> {
+        self.writer.write_all(b"</stream:stream>").await?;
+        self.writer.shutdown().await?;
+        self.reader.abort();
+
+        Ok(())
+    }
+
+    async fn read_item(&mut self) -> Result<Item, Error> {
+        match self.items.recv().await {
+            Some(item) => Ok(item?),
+            None => Err(Error::Ended),
+        }
+    }
+}
+
+/// Splits a JID into its bare part and its resource, which is the nick of an occupant of a room.
+pub fn split_jid(jid: &str) -> (&str, Option<&str>) {
+    match jid.split_once('/') {
+        Some((bare, resource)) => (bare, Some(resource)),
+        None => (jid, None),
+    }
+}
+
+/// Presence joining a room, without the history of messages sent before.
+pub fn join(from: &str, room: &str, nick: &str) -> Element {
+    Element::new("presence")
+        .attr("from", from)
+        .attr("to", &format!("{}/{}", room, nick))
+        .child(
+            Element::new("x")
+                .attr("xmlns", MUC)
+                .child(Element::new("history").attr("maxstanzas", "0")),
+        )
+}
+
+/// Presence changing the nick in a room joined already.
+pub fn change_nick(from: &str, room: &str, nick: &str) -> Element {
+    Element::new("presence")
+        .attr("from", from)
+        .attr("to", &format!("{}/{}", room, nick))
+}
+
+pub fn leave(from: &str, room: &str, nick: &str) -> Element {
+    change_nick(from, room, nick).attr("type", "unavailable")
+}
+
+/// Status codes of a presence in a room, such as 110 for presences of the receiver itself.
+pub fn status_codes(presence: &Element) -> Vec<&str> {
+    match presence.get_child("x", MUC_USER) {
+        Some(x) => x
+            .elements()
+            .filter(|e| e.name == "status")
+            .filter_map(|e| e.get_attr("code"))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_jids() {
+        assert_eq!(
+            split_jid("room@conference.example.com/alice/phone"),
+            ("room@conference.example.com", Some("alice/phone"))
+        );
+
+        assert_eq!(split_jid("example.com"), ("example.com", None));
+    }
+}